@@ -39,7 +39,15 @@ fn main() {
     .flag("-fno-strict-aliasing")
     .flag("-Werror")
     //.include("kernels")
+    .file("kernels/binary_map.c")
+    .file("kernels/clip.c")
+    .file("kernels/linear.c")
+    .file("kernels/loss.c")
+    .file("kernels/norm.c")
+    .file("kernels/reduce.c")
+    .file("kernels/softmax.c")
     .file("kernels/special_map.c")
+    .file("kernels/transform.c")
     .compile("libarraydiff_kernels.a");
 
   let mut cuda_kernels_src_dir = PathBuf::from(manifest_dir.clone());
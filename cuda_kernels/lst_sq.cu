@@ -33,7 +33,7 @@ __global__ void lst_sq1_fwd_f32_kernel(
     float delta = x_i - t_i;
     if (do_clip) {
       if (fabs(delta) > 1.0f) {
-        loss[idx] = fabs(delta);
+        loss[idx] = fabs(delta) - 0.5f;
       } else {
         loss[idx] = 0.5f * delta * delta;
       }
@@ -74,7 +74,7 @@ __global__ void lst_sq_block_fwd_f32_kernel(
     float delta = x_i - t_i;
     if (do_clip) {
       if (fabs(delta) > 1.0f) {
-        cache[tid] = fabs(delta);
+        cache[tid] = fabs(delta) - 0.5f;
       } else {
         cache[tid] = 0.5f * delta * delta;
       }
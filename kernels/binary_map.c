@@ -0,0 +1,337 @@
+/*
+Copyright 2017 the arraydiff authors
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+#include <math.h>
+#include <stdlib.h>
+
+/* Broadcast binary map kernels.
+
+All arrays are packed column-major 4d arrays; lower rank arrays pad their
+shape with trailing 1s. An axis of an input with size 1 is broadcast along
+the corresponding axis of the output. In the backward pass, gradients are
+summed over the broadcast axes. */
+
+enum {
+  BINARY_SUB = 0,
+  BINARY_MUL,
+  BINARY_DIV,
+  BINARY_MAX,
+  BINARY_MIN,
+  BINARY_POW,
+};
+
+static void bcast_strides(const size_t *dim, size_t *stride) {
+  size_t s = 1;
+  for (size_t k = 0; k < 4; k++) {
+    stride[k] = dim[k] == 1 ? 0 : s;
+    s *= dim[k];
+  }
+}
+
+static float binary_fwd(int op, float x1, float x2) {
+  switch (op) {
+    case BINARY_SUB: return x1 - x2;
+    case BINARY_MUL: return x1 * x2;
+    case BINARY_DIV: return x1 / x2;
+    case BINARY_MAX: return x1 >= x2 ? x1 : x2;
+    case BINARY_MIN: return x1 <= x2 ? x1 : x2;
+    case BINARY_POW: return powf(x1, x2);
+    default: abort();
+  }
+}
+
+static float binary_x1_bwd(int op, float x1, float x2) {
+  switch (op) {
+    case BINARY_SUB: return 1.0f;
+    case BINARY_MUL: return x2;
+    case BINARY_DIV: return 1.0f / x2;
+    case BINARY_MAX: return (float)(x1 >= x2);
+    case BINARY_MIN: return (float)(x1 <= x2);
+    case BINARY_POW: return x2 * powf(x1, x2 - 1.0f);
+    default: abort();
+  }
+}
+
+static float binary_x2_bwd(int op, float x1, float x2) {
+  switch (op) {
+    case BINARY_SUB: return -1.0f;
+    case BINARY_MUL: return x1;
+    case BINARY_DIV: return -x1 / (x2 * x2);
+    case BINARY_MAX: return (float)(x1 < x2);
+    case BINARY_MIN: return (float)(x1 > x2);
+    /* The gradient w.r.t. the exponent is only defined for positive bases. */
+    case BINARY_POW: return x1 > 0.0f ? powf(x1, x2) * logf(x1) : 0.0f;
+    default: abort();
+  }
+}
+
+static void bcast_binary_fwd(
+    int op,
+    const size_t *x1_dim, const float *x1,
+    const size_t *x2_dim, const float *x2,
+    const size_t *y_dim, float *y)
+{
+  size_t x1_stride[4];
+  size_t x2_stride[4];
+  bcast_strides(x1_dim, x1_stride);
+  bcast_strides(x2_dim, x2_stride);
+  size_t idx = 0;
+  for (size_t i3 = 0; i3 < y_dim[3]; i3++) {
+    for (size_t i2 = 0; i2 < y_dim[2]; i2++) {
+      for (size_t i1 = 0; i1 < y_dim[1]; i1++) {
+        for (size_t i0 = 0; i0 < y_dim[0]; i0++) {
+          size_t x1_idx = i0 * x1_stride[0] + i1 * x1_stride[1] + i2 * x1_stride[2] + i3 * x1_stride[3];
+          size_t x2_idx = i0 * x2_stride[0] + i1 * x2_stride[1] + i2 * x2_stride[2] + i3 * x2_stride[3];
+          y[idx] = binary_fwd(op, x1[x1_idx], x2[x2_idx]);
+          idx++;
+        }
+      }
+    }
+  }
+}
+
+static void bcast_binary_bwd(
+    int op,
+    const size_t *x1_dim, const float *x1,
+    const size_t *x2_dim, const float *x2,
+    const size_t *y_dim, const float *dy,
+    float *dx1, float *dx2)
+{
+  size_t x1_stride[4];
+  size_t x2_stride[4];
+  bcast_strides(x1_dim, x1_stride);
+  bcast_strides(x2_dim, x2_stride);
+  size_t idx = 0;
+  for (size_t i3 = 0; i3 < y_dim[3]; i3++) {
+    for (size_t i2 = 0; i2 < y_dim[2]; i2++) {
+      for (size_t i1 = 0; i1 < y_dim[1]; i1++) {
+        for (size_t i0 = 0; i0 < y_dim[0]; i0++) {
+          size_t x1_idx = i0 * x1_stride[0] + i1 * x1_stride[1] + i2 * x1_stride[2] + i3 * x1_stride[3];
+          size_t x2_idx = i0 * x2_stride[0] + i1 * x2_stride[1] + i2 * x2_stride[2] + i3 * x2_stride[3];
+          float x1_i = x1[x1_idx];
+          float x2_i = x2[x2_idx];
+          if (NULL != dx1) {
+            dx1[x1_idx] += dy[idx] * binary_x1_bwd(op, x1_i, x2_i);
+          }
+          if (NULL != dx2) {
+            dx2[x2_idx] += dy[idx] * binary_x2_bwd(op, x1_i, x2_i);
+          }
+          idx++;
+        }
+      }
+    }
+  }
+}
+
+static void bcast_binary_rfwd(
+    int op,
+    const size_t *x1_dim, const float *x1,
+    const size_t *x2_dim, const float *x2,
+    const size_t *y_dim,
+    const float *r_x1, const float *r_x2,
+    float *r_y)
+{
+  size_t x1_stride[4];
+  size_t x2_stride[4];
+  bcast_strides(x1_dim, x1_stride);
+  bcast_strides(x2_dim, x2_stride);
+  size_t idx = 0;
+  for (size_t i3 = 0; i3 < y_dim[3]; i3++) {
+    for (size_t i2 = 0; i2 < y_dim[2]; i2++) {
+      for (size_t i1 = 0; i1 < y_dim[1]; i1++) {
+        for (size_t i0 = 0; i0 < y_dim[0]; i0++) {
+          size_t x1_idx = i0 * x1_stride[0] + i1 * x1_stride[1] + i2 * x1_stride[2] + i3 * x1_stride[3];
+          size_t x2_idx = i0 * x2_stride[0] + i1 * x2_stride[1] + i2 * x2_stride[2] + i3 * x2_stride[3];
+          float x1_i = x1[x1_idx];
+          float x2_i = x2[x2_idx];
+          r_y[idx] = r_x1[x1_idx] * binary_x1_bwd(op, x1_i, x2_i)
+                   + r_x2[x2_idx] * binary_x2_bwd(op, x1_i, x2_i);
+          idx++;
+        }
+      }
+    }
+  }
+}
+
+void arraydiff_kernel_bcast_sub_fwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, float *y) {
+  bcast_binary_fwd(BINARY_SUB, x1_dim, x1, x2_dim, x2, y_dim, y);
+}
+
+void arraydiff_kernel_bcast_sub_bwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, const float *dy, float *dx1, float *dx2) {
+  bcast_binary_bwd(BINARY_SUB, x1_dim, x1, x2_dim, x2, y_dim, dy, dx1, dx2);
+}
+
+void arraydiff_kernel_bcast_sub_rfwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, const float *r_x1, const float *r_x2, float *r_y) {
+  bcast_binary_rfwd(BINARY_SUB, x1_dim, x1, x2_dim, x2, y_dim, r_x1, r_x2, r_y);
+}
+
+void arraydiff_kernel_bcast_mul_fwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, float *y) {
+  bcast_binary_fwd(BINARY_MUL, x1_dim, x1, x2_dim, x2, y_dim, y);
+}
+
+void arraydiff_kernel_bcast_mul_bwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, const float *dy, float *dx1, float *dx2) {
+  bcast_binary_bwd(BINARY_MUL, x1_dim, x1, x2_dim, x2, y_dim, dy, dx1, dx2);
+}
+
+void arraydiff_kernel_bcast_mul_rfwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, const float *r_x1, const float *r_x2, float *r_y) {
+  bcast_binary_rfwd(BINARY_MUL, x1_dim, x1, x2_dim, x2, y_dim, r_x1, r_x2, r_y);
+}
+
+void arraydiff_kernel_bcast_div_fwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, float *y) {
+  bcast_binary_fwd(BINARY_DIV, x1_dim, x1, x2_dim, x2, y_dim, y);
+}
+
+void arraydiff_kernel_bcast_div_bwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, const float *dy, float *dx1, float *dx2) {
+  bcast_binary_bwd(BINARY_DIV, x1_dim, x1, x2_dim, x2, y_dim, dy, dx1, dx2);
+}
+
+void arraydiff_kernel_bcast_div_rfwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, const float *r_x1, const float *r_x2, float *r_y) {
+  bcast_binary_rfwd(BINARY_DIV, x1_dim, x1, x2_dim, x2, y_dim, r_x1, r_x2, r_y);
+}
+
+void arraydiff_kernel_bcast_max_fwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, float *y) {
+  bcast_binary_fwd(BINARY_MAX, x1_dim, x1, x2_dim, x2, y_dim, y);
+}
+
+void arraydiff_kernel_bcast_max_bwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, const float *dy, float *dx1, float *dx2) {
+  bcast_binary_bwd(BINARY_MAX, x1_dim, x1, x2_dim, x2, y_dim, dy, dx1, dx2);
+}
+
+void arraydiff_kernel_bcast_max_rfwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, const float *r_x1, const float *r_x2, float *r_y) {
+  bcast_binary_rfwd(BINARY_MAX, x1_dim, x1, x2_dim, x2, y_dim, r_x1, r_x2, r_y);
+}
+
+void arraydiff_kernel_bcast_min_fwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, float *y) {
+  bcast_binary_fwd(BINARY_MIN, x1_dim, x1, x2_dim, x2, y_dim, y);
+}
+
+void arraydiff_kernel_bcast_min_bwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, const float *dy, float *dx1, float *dx2) {
+  bcast_binary_bwd(BINARY_MIN, x1_dim, x1, x2_dim, x2, y_dim, dy, dx1, dx2);
+}
+
+void arraydiff_kernel_bcast_min_rfwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, const float *r_x1, const float *r_x2, float *r_y) {
+  bcast_binary_rfwd(BINARY_MIN, x1_dim, x1, x2_dim, x2, y_dim, r_x1, r_x2, r_y);
+}
+
+void arraydiff_kernel_bcast_pow_fwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, float *y) {
+  bcast_binary_fwd(BINARY_POW, x1_dim, x1, x2_dim, x2, y_dim, y);
+}
+
+void arraydiff_kernel_bcast_pow_bwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, const float *dy, float *dx1, float *dx2) {
+  bcast_binary_bwd(BINARY_POW, x1_dim, x1, x2_dim, x2, y_dim, dy, dx1, dx2);
+}
+
+void arraydiff_kernel_bcast_pow_rfwd_f32(const size_t *x1_dim, const float *x1, const size_t *x2_dim, const float *x2, const size_t *y_dim, const float *r_x1, const float *r_x2, float *r_y) {
+  bcast_binary_rfwd(BINARY_POW, x1_dim, x1, x2_dim, x2, y_dim, r_x1, r_x2, r_y);
+}
+
+/* Channel broadcast kernels.
+
+The input is viewed as a packed 3d array `[inner, chan, outer]`; the scale
+`a` and the shift `b` have one entry per channel. `b` may be NULL. Parameter
+gradients are reduced in a fixed order and are deterministic. */
+
+void arraydiff_kernel_bcast_add_fwd_f32(
+    size_t inner_dim,
+    size_t chan_dim,
+    size_t outer_dim,
+    const float *a,
+    const float *x,
+    float *y)
+{
+  size_t idx = 0;
+  for (size_t o = 0; o < outer_dim; o++) {
+    for (size_t c = 0; c < chan_dim; c++) {
+      for (size_t i = 0; i < inner_dim; i++) {
+        y[idx] = x[idx] + a[c];
+        idx++;
+      }
+    }
+  }
+}
+
+void arraydiff_kernel_bcast_mult_add_fwd_f32(
+    size_t inner_dim,
+    size_t chan_dim,
+    size_t outer_dim,
+    const float *x,
+    const float *a,
+    const float *b,
+    float *y)
+{
+  size_t idx = 0;
+  for (size_t o = 0; o < outer_dim; o++) {
+    for (size_t c = 0; c < chan_dim; c++) {
+      float a_c = a[c];
+      float b_c = NULL != b ? b[c] : 0.0f;
+      for (size_t i = 0; i < inner_dim; i++) {
+        y[idx] = a_c * x[idx] + b_c;
+        idx++;
+      }
+    }
+  }
+}
+
+void arraydiff_kernel_bcast_mult_add_param_bwd_f32(
+    size_t inner_dim,
+    size_t chan_dim,
+    size_t outer_dim,
+    const float *x,
+    const float *dy,
+    float *da,
+    float *db)
+{
+  for (size_t c = 0; c < chan_dim; c++) {
+    double da_c = 0.0;
+    double db_c = 0.0;
+    for (size_t o = 0; o < outer_dim; o++) {
+      size_t offset = (o * chan_dim + c) * inner_dim;
+      for (size_t i = 0; i < inner_dim; i++) {
+        float dy_i = dy[offset + i];
+        if (NULL != x) {
+          da_c += (double)(dy_i * x[offset + i]);
+        }
+        db_c += (double)dy_i;
+      }
+    }
+    if (NULL != da) {
+      da[c] += (float)da_c;
+    }
+    if (NULL != db) {
+      db[c] += (float)db_c;
+    }
+  }
+}
+
+void arraydiff_kernel_bcast_mult_add_input_bwd_f32(
+    size_t inner_dim,
+    size_t chan_dim,
+    size_t outer_dim,
+    const float *a,
+    const float *dy,
+    float *dx)
+{
+  size_t idx = 0;
+  for (size_t o = 0; o < outer_dim; o++) {
+    for (size_t c = 0; c < chan_dim; c++) {
+      float a_c = NULL != a ? a[c] : 1.0f;
+      for (size_t i = 0; i < inner_dim; i++) {
+        dx[idx] += a_c * dy[idx];
+        idx++;
+      }
+    }
+  }
+}
@@ -0,0 +1,115 @@
+/*
+Copyright 2017 the arraydiff authors
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+#include <stdlib.h>
+
+/* Symmetric unit clip kernels.
+
+The input is viewed as a packed 3d array `[inner, chan, outer]` and `clip` has
+one slope per channel; a scalar clip is the case `chan_dim == 1`. */
+
+void arraydiff_kernel_symm_unit_clip_fwd_f32(
+    size_t inner_dim,
+    size_t chan_dim,
+    size_t outer_dim,
+    const float *clip,
+    const float *x,
+    float *y)
+{
+  size_t idx = 0;
+  for (size_t o = 0; o < outer_dim; o++) {
+    for (size_t c = 0; c < chan_dim; c++) {
+      float a = clip[c];
+      for (size_t i = 0; i < inner_dim; i++) {
+        float x_i = x[idx];
+        y[idx] = x_i * ((x_i > 0.0f) + a * (x_i < 0.0f));
+        idx++;
+      }
+    }
+  }
+}
+
+/* Unlike the CUDA version, the parameter gradient is reduced in a fixed order
+and is deterministic. */
+
+void arraydiff_kernel_symm_unit_clip_param_bwd_f32(
+    size_t inner_dim,
+    size_t chan_dim,
+    size_t outer_dim,
+    const float *clip,
+    const float *x,
+    const float *dy,
+    float *grad)
+{
+  (void)clip;
+  for (size_t c = 0; c < chan_dim; c++) {
+    double g = 0.0;
+    for (size_t o = 0; o < outer_dim; o++) {
+      size_t offset = (o * chan_dim + c) * inner_dim;
+      for (size_t i = 0; i < inner_dim; i++) {
+        float x_i = x[offset + i];
+        g += (double)(dy[offset + i] * x_i * (x_i < 0.0f));
+      }
+    }
+    grad[c] += (float)g;
+  }
+}
+
+void arraydiff_kernel_symm_unit_clip_input_bwd_f32(
+    size_t inner_dim,
+    size_t chan_dim,
+    size_t outer_dim,
+    const float *clip,
+    const float *x,
+    const float *dy,
+    float *dx)
+{
+  size_t idx = 0;
+  for (size_t o = 0; o < outer_dim; o++) {
+    for (size_t c = 0; c < chan_dim; c++) {
+      float a = clip[c];
+      for (size_t i = 0; i < inner_dim; i++) {
+        float x_i = x[idx];
+        dx[idx] += dy[idx] * ((x_i > 0.0f) + a * (x_i < 0.0f));
+        idx++;
+      }
+    }
+  }
+}
+
+void arraydiff_kernel_symm_unit_clip_rfwd_f32(
+    size_t inner_dim,
+    size_t chan_dim,
+    size_t outer_dim,
+    const float *clip,
+    const float *x,
+    const float *r_clip,
+    const float *r_x,
+    float *r_y)
+{
+  size_t idx = 0;
+  for (size_t o = 0; o < outer_dim; o++) {
+    for (size_t c = 0; c < chan_dim; c++) {
+      float a = clip[c];
+      float r_a = r_clip[c];
+      for (size_t i = 0; i < inner_dim; i++) {
+        float x_i = x[idx];
+        r_y[idx] = r_x[idx] * ((x_i > 0.0f) + a * (x_i < 0.0f)) + r_a * x_i * (x_i < 0.0f);
+        idx++;
+      }
+    }
+  }
+}
@@ -0,0 +1,60 @@
+/*
+Copyright 2017 the arraydiff authors
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+#include <stdint.h>
+#include <stdlib.h>
+
+/* Batched matrix product kernel.
+
+Computes `c_i = alpha op(a_i) op(b_i) + beta c_i` for `num_mats` column-major
+matrices, where `op(a_i)` is `m x k`, `op(b_i)` is `k x n`, and `c_i` is
+`m x n`. A nonzero `a_trans` (`b_trans`) means that `a_i` (`b_i`) is stored
+transposed, i.e. as a `k x m` (`n x k`) matrix. */
+
+void arraydiff_kernel_batch_gemm_f32(
+    size_t m,
+    size_t n,
+    size_t k,
+    size_t num_mats,
+    float alpha,
+    const float *a,
+    uint32_t a_trans,
+    const float *b,
+    uint32_t b_trans,
+    float beta,
+    float *c)
+{
+  for (size_t p = 0; p < num_mats; p++) {
+    const float *a_p = a + p * m * k;
+    const float *b_p = b + p * k * n;
+    float *c_p = c + p * m * n;
+    for (size_t j = 0; j < n; j++) {
+      for (size_t i = 0; i < m; i++) {
+        double acc = 0.0;
+        for (size_t l = 0; l < k; l++) {
+          float a_il = a_trans ? a_p[l + i * k] : a_p[i + l * m];
+          float b_lj = b_trans ? b_p[j + l * n] : b_p[l + j * k];
+          acc += a_il * b_lj;
+        }
+        float c_ij = alpha * (float)acc;
+        if (0.0f != beta) {
+          c_ij += beta * c_p[i + j * m];
+        }
+        c_p[i + j * m] = c_ij;
+      }
+    }
+  }
+}
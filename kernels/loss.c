@@ -0,0 +1,180 @@
+/*
+Copyright 2017 the arraydiff authors
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+#include <math.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/* Least squares loss kernels.
+
+The inputs are packed `[dim, batch]` arrays and the loss of each example is
+summed over `dim`. With `do_clip` set, this is the Huber loss with threshold
+1: residuals larger than 1 in magnitude are penalized by `|d| - 0.5`, which
+continues the quadratic with matching value and slope. */
+
+void arraydiff_kernel_lst_sq_fwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *x,
+    const float *target,
+    float *loss,
+    uint32_t do_clip)
+{
+  for (size_t b = 0; b < batch_sz; b++) {
+    float l = 0.0f;
+    for (size_t i = 0; i < dim; i++) {
+      size_t idx = i + dim * b;
+      float delta = x[idx] - target[idx];
+      if (do_clip && fabsf(delta) > 1.0f) {
+        l += fabsf(delta) - 0.5f;
+      } else {
+        l += 0.5f * delta * delta;
+      }
+    }
+    loss[b] = l;
+  }
+}
+
+void arraydiff_kernel_lst_sq_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *x,
+    const float *target,
+    const float *df,
+    float *dx,
+    uint32_t do_clip)
+{
+  for (size_t b = 0; b < batch_sz; b++) {
+    for (size_t i = 0; i < dim; i++) {
+      size_t idx = i + dim * b;
+      float delta = x[idx] - target[idx];
+      if (do_clip) {
+        delta = fmaxf(-1.0f, fminf(delta, 1.0f));
+      }
+      dx[idx] += df[b] * delta;
+    }
+  }
+}
+
+/* Directional derivative of the loss along `(r_x, r_target)`. */
+void arraydiff_kernel_lst_sq_rfwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *x,
+    const float *target,
+    const float *r_x,
+    const float *r_target,
+    float *r_loss,
+    uint32_t do_clip)
+{
+  for (size_t b = 0; b < batch_sz; b++) {
+    float r_l = 0.0f;
+    for (size_t i = 0; i < dim; i++) {
+      size_t idx = i + dim * b;
+      float delta = x[idx] - target[idx];
+      if (do_clip) {
+        delta = fmaxf(-1.0f, fminf(delta, 1.0f));
+      }
+      r_l += delta * (r_x[idx] - r_target[idx]);
+    }
+    r_loss[b] = r_l;
+  }
+}
+
+/* Curvature-vector products: the Gauss-Newton kernel accumulates `df * H r_x`
+for the Hessian `H` of the loss with respect to `x` (the identity, masked
+where the residual is clipped), which is also the Fisher matrix of a unit
+variance Gaussian. The empirical Fisher kernel accumulates `df * g <g, r_x>`
+for the gradient `g` of the loss of each example. */
+
+void arraydiff_kernel_lst_sq_gauss_newton_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *x,
+    const float *target,
+    const float *r_x,
+    const float *df,
+    float *dx,
+    uint32_t do_clip)
+{
+  for (size_t b = 0; b < batch_sz; b++) {
+    for (size_t i = 0; i < dim; i++) {
+      size_t idx = i + dim * b;
+      float delta = x[idx] - target[idx];
+      if (do_clip && fabsf(delta) > 1.0f) {
+        continue;
+      }
+      dx[idx] += df[b] * r_x[idx];
+    }
+  }
+}
+
+void arraydiff_kernel_lst_sq_empirical_fisher_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *x,
+    const float *target,
+    const float *r_x,
+    const float *df,
+    float *dx,
+    uint32_t do_clip)
+{
+  for (size_t b = 0; b < batch_sz; b++) {
+    float s = 0.0f;
+    for (size_t i = 0; i < dim; i++) {
+      size_t idx = i + dim * b;
+      float delta = x[idx] - target[idx];
+      if (do_clip) {
+        delta = fmaxf(-1.0f, fminf(delta, 1.0f));
+      }
+      s += delta * r_x[idx];
+    }
+    for (size_t i = 0; i < dim; i++) {
+      size_t idx = i + dim * b;
+      float delta = x[idx] - target[idx];
+      if (do_clip) {
+        delta = fmaxf(-1.0f, fminf(delta, 1.0f));
+      }
+      dx[idx] += df[b] * s * delta;
+    }
+  }
+}
+
+/* Becker-LeCun diagonal: accumulates `df2 * g^2 + df * h` into `dx2`, for the
+gradient `g` of the loss of each example and the diagonal `h` of its Hessian. */
+void arraydiff_kernel_lst_sq_bwd2_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *x,
+    const float *target,
+    const float *df,
+    const float *df2,
+    float *dx2,
+    uint32_t do_clip)
+{
+  for (size_t b = 0; b < batch_sz; b++) {
+    for (size_t i = 0; i < dim; i++) {
+      size_t idx = i + dim * b;
+      float delta = x[idx] - target[idx];
+      float h = 1.0f;
+      if (do_clip && fabsf(delta) > 1.0f) {
+        delta = delta > 0.0f ? 1.0f : -1.0f;
+        h = 0.0f;
+      }
+      dx2[idx] += df2[b] * delta * delta + df[b] * h;
+    }
+  }
+}
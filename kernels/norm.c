@@ -0,0 +1,218 @@
+/*
+Copyright 2017 the arraydiff authors
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+#include <math.h>
+#include <stdlib.h>
+
+/* Group normalization kernels.
+
+The input is viewed as a packed 3d array `[inner, chan, outer]` where `outer`
+is the batch. The channels of each example are split into `num_groups`
+contiguous groups, each normalized with its own mean and variance; layer
+normalization is the case `num_groups == 1`. `scale` and `shift` have one
+entry per channel, and either parameter gradient may be NULL. Statistics are reduced in double precision and in a fixed
+order, so the results do not depend on the batch size. */
+
+static void group_stats(
+    size_t group_len,
+    double epsilon,
+    const float *x,
+    double *mean,
+    double *istd)
+{
+  double sum = 0.0;
+  for (size_t k = 0; k < group_len; k++) {
+    sum += x[k];
+  }
+  double m = sum / (double)group_len;
+  double sq = 0.0;
+  for (size_t k = 0; k < group_len; k++) {
+    double d = x[k] - m;
+    sq += d * d;
+  }
+  *mean = m;
+  *istd = 1.0 / sqrt(sq / (double)group_len + epsilon);
+}
+
+void arraydiff_kernel_group_norm_fwd_f32(
+    size_t inner_dim,
+    size_t chan_dim,
+    size_t num_groups,
+    size_t outer_dim,
+    double epsilon,
+    const float *x,
+    const float *scale,
+    const float *shift,
+    float *y)
+{
+  size_t group_chans = chan_dim / num_groups;
+  size_t group_len = inner_dim * group_chans;
+  for (size_t o = 0; o < outer_dim; o++) {
+    for (size_t g = 0; g < num_groups; g++) {
+      size_t offset = (o * num_groups + g) * group_len;
+      double mean, istd;
+      group_stats(group_len, epsilon, x + offset, &mean, &istd);
+      for (size_t c = g * group_chans; c < (g + 1) * group_chans; c++) {
+        size_t idx = (o * chan_dim + c) * inner_dim;
+        for (size_t i = 0; i < inner_dim; i++) {
+          float xhat = (float)((x[idx] - mean) * istd);
+          y[idx] = scale[c] * xhat + shift[c];
+          idx++;
+        }
+      }
+    }
+  }
+}
+
+void arraydiff_kernel_group_norm_param_bwd_f32(
+    size_t inner_dim,
+    size_t chan_dim,
+    size_t num_groups,
+    size_t outer_dim,
+    double epsilon,
+    const float *x,
+    const float *dy,
+    float *dscale,
+    float *dshift)
+{
+  size_t group_chans = chan_dim / num_groups;
+  size_t group_len = inner_dim * group_chans;
+  double *dscale_acc = calloc(chan_dim, sizeof(double));
+  double *dshift_acc = calloc(chan_dim, sizeof(double));
+  if (NULL == dscale_acc || NULL == dshift_acc) {
+    abort();
+  }
+  for (size_t o = 0; o < outer_dim; o++) {
+    for (size_t g = 0; g < num_groups; g++) {
+      size_t offset = (o * num_groups + g) * group_len;
+      double mean, istd;
+      group_stats(group_len, epsilon, x + offset, &mean, &istd);
+      for (size_t c = g * group_chans; c < (g + 1) * group_chans; c++) {
+        size_t idx = (o * chan_dim + c) * inner_dim;
+        for (size_t i = 0; i < inner_dim; i++) {
+          double xhat = (x[idx] - mean) * istd;
+          dscale_acc[c] += dy[idx] * xhat;
+          dshift_acc[c] += dy[idx];
+          idx++;
+        }
+      }
+    }
+  }
+  for (size_t c = 0; c < chan_dim; c++) {
+    if (NULL != dscale) {
+      dscale[c] += (float)dscale_acc[c];
+    }
+    if (NULL != dshift) {
+      dshift[c] += (float)dshift_acc[c];
+    }
+  }
+  free(dscale_acc);
+  free(dshift_acc);
+}
+
+void arraydiff_kernel_group_norm_input_bwd_f32(
+    size_t inner_dim,
+    size_t chan_dim,
+    size_t num_groups,
+    size_t outer_dim,
+    double epsilon,
+    const float *x,
+    const float *scale,
+    const float *dy,
+    float *dx)
+{
+  size_t group_chans = chan_dim / num_groups;
+  size_t group_len = inner_dim * group_chans;
+  for (size_t o = 0; o < outer_dim; o++) {
+    for (size_t g = 0; g < num_groups; g++) {
+      size_t offset = (o * num_groups + g) * group_len;
+      double mean, istd;
+      group_stats(group_len, epsilon, x + offset, &mean, &istd);
+      double dxhat_sum = 0.0;
+      double dxhat_xhat_sum = 0.0;
+      for (size_t c = g * group_chans; c < (g + 1) * group_chans; c++) {
+        size_t idx = (o * chan_dim + c) * inner_dim;
+        for (size_t i = 0; i < inner_dim; i++) {
+          double xhat = (x[idx] - mean) * istd;
+          double dxhat = dy[idx] * scale[c];
+          dxhat_sum += dxhat;
+          dxhat_xhat_sum += dxhat * xhat;
+          idx++;
+        }
+      }
+      double dxhat_mean = dxhat_sum / (double)group_len;
+      double dxhat_xhat_mean = dxhat_xhat_sum / (double)group_len;
+      for (size_t c = g * group_chans; c < (g + 1) * group_chans; c++) {
+        size_t idx = (o * chan_dim + c) * inner_dim;
+        for (size_t i = 0; i < inner_dim; i++) {
+          double xhat = (x[idx] - mean) * istd;
+          double dxhat = dy[idx] * scale[c];
+          dx[idx] += (float)(istd * (dxhat - dxhat_mean - xhat * dxhat_xhat_mean));
+          idx++;
+        }
+      }
+    }
+  }
+}
+
+void arraydiff_kernel_group_norm_rfwd_f32(
+    size_t inner_dim,
+    size_t chan_dim,
+    size_t num_groups,
+    size_t outer_dim,
+    double epsilon,
+    const float *x,
+    const float *scale,
+    const float *r_x,
+    const float *r_scale,
+    const float *r_shift,
+    float *r_y)
+{
+  size_t group_chans = chan_dim / num_groups;
+  size_t group_len = inner_dim * group_chans;
+  for (size_t o = 0; o < outer_dim; o++) {
+    for (size_t g = 0; g < num_groups; g++) {
+      size_t offset = (o * num_groups + g) * group_len;
+      double mean, istd;
+      group_stats(group_len, epsilon, x + offset, &mean, &istd);
+      double r_x_sum = 0.0;
+      double r_x_xhat_sum = 0.0;
+      for (size_t k = 0; k < group_len; k++) {
+        double xhat = (x[offset + k] - mean) * istd;
+        r_x_sum += r_x[offset + k];
+        r_x_xhat_sum += r_x[offset + k] * xhat;
+      }
+      double r_x_mean = r_x_sum / (double)group_len;
+      double r_x_xhat_mean = r_x_xhat_sum / (double)group_len;
+      for (size_t c = g * group_chans; c < (g + 1) * group_chans; c++) {
+        size_t idx = (o * chan_dim + c) * inner_dim;
+        for (size_t i = 0; i < inner_dim; i++) {
+          double xhat = (x[idx] - mean) * istd;
+          double r_xhat = istd * (r_x[idx] - r_x_mean - xhat * r_x_xhat_mean);
+          double r = scale[c] * r_xhat;
+          if (NULL != r_scale) {
+            r += r_scale[c] * xhat;
+          }
+          if (NULL != r_shift) {
+            r += r_shift[c];
+          }
+          r_y[idx] = (float)r;
+          idx++;
+        }
+      }
+    }
+  }
+}
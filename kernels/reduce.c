@@ -0,0 +1,321 @@
+/*
+Copyright 2017 the arraydiff authors
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+#include <math.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/* Axis reduction kernels.
+
+The input is a packed column-major 4d array. The output has the same shape
+except that reduced axes have size 1. */
+
+enum {
+  REDUCE_SUM = 0,
+  REDUCE_MEAN,
+  REDUCE_MAX,
+  REDUCE_MIN,
+  REDUCE_L2_NORM,
+  REDUCE_LOGSUMEXP,
+};
+
+static size_t reduce_strides(const size_t *dim, size_t *stride) {
+  size_t s = 1;
+  for (size_t k = 0; k < 4; k++) {
+    stride[k] = dim[k] == 1 ? 0 : s;
+    s *= dim[k];
+  }
+  return s;
+}
+
+static void reduce_fwd(int op, const size_t *x_dim, const float *x, const size_t *y_dim, float *y) {
+  size_t y_stride[4];
+  size_t x_len = x_dim[0] * x_dim[1] * x_dim[2] * x_dim[3];
+  size_t y_len = reduce_strides(y_dim, y_stride);
+  float *tmp = NULL;
+  for (size_t j = 0; j < y_len; j++) {
+    switch (op) {
+      case REDUCE_MAX:
+      case REDUCE_LOGSUMEXP:
+        y[j] = -INFINITY;
+        break;
+      case REDUCE_MIN:
+        y[j] = INFINITY;
+        break;
+      default:
+        y[j] = 0.0f;
+    }
+  }
+  size_t idx = 0;
+  for (size_t i3 = 0; i3 < x_dim[3]; i3++) {
+    for (size_t i2 = 0; i2 < x_dim[2]; i2++) {
+      for (size_t i1 = 0; i1 < x_dim[1]; i1++) {
+        for (size_t i0 = 0; i0 < x_dim[0]; i0++) {
+          size_t y_idx = i0 * y_stride[0] + i1 * y_stride[1] + i2 * y_stride[2] + i3 * y_stride[3];
+          float x_i = x[idx];
+          switch (op) {
+            case REDUCE_SUM:
+            case REDUCE_MEAN:
+              y[y_idx] += x_i;
+              break;
+            case REDUCE_MAX:
+            case REDUCE_LOGSUMEXP:
+              y[y_idx] = fmaxf(y[y_idx], x_i);
+              break;
+            case REDUCE_MIN:
+              y[y_idx] = fminf(y[y_idx], x_i);
+              break;
+            case REDUCE_L2_NORM:
+              y[y_idx] += x_i * x_i;
+              break;
+            default:
+              abort();
+          }
+          idx++;
+        }
+      }
+    }
+  }
+  switch (op) {
+    case REDUCE_MEAN:
+      for (size_t j = 0; j < y_len; j++) {
+        y[j] /= (float)(x_len / y_len);
+      }
+      break;
+    case REDUCE_L2_NORM:
+      for (size_t j = 0; j < y_len; j++) {
+        y[j] = sqrtf(y[j]);
+      }
+      break;
+    case REDUCE_LOGSUMEXP:
+      /* Second pass: accumulate exp(x - max) for numerical stability. */
+      tmp = calloc(y_len, sizeof(float));
+      if (NULL == tmp && 0 != y_len) {
+        abort();
+      }
+      idx = 0;
+      for (size_t i3 = 0; i3 < x_dim[3]; i3++) {
+        for (size_t i2 = 0; i2 < x_dim[2]; i2++) {
+          for (size_t i1 = 0; i1 < x_dim[1]; i1++) {
+            for (size_t i0 = 0; i0 < x_dim[0]; i0++) {
+              size_t y_idx = i0 * y_stride[0] + i1 * y_stride[1] + i2 * y_stride[2] + i3 * y_stride[3];
+              if (isfinite(y[y_idx])) {
+                tmp[y_idx] += expf(x[idx] - y[y_idx]);
+              }
+              idx++;
+            }
+          }
+        }
+      }
+      for (size_t j = 0; j < y_len; j++) {
+        if (isfinite(y[j])) {
+          y[j] += logf(tmp[j]);
+        }
+      }
+      free(tmp);
+      break;
+    default:
+      break;
+  }
+}
+
+static void reduce_bwd(int op, const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *dy, float *dx) {
+  size_t y_stride[4];
+  size_t x_len = x_dim[0] * x_dim[1] * x_dim[2] * x_dim[3];
+  size_t y_len = reduce_strides(y_dim, y_stride);
+  /* For max and min, only the first extremal element receives the gradient. */
+  char *taken = NULL;
+  if (REDUCE_MAX == op || REDUCE_MIN == op) {
+    taken = calloc(y_len, sizeof(char));
+    if (NULL == taken && 0 != y_len) {
+      abort();
+    }
+  }
+  size_t idx = 0;
+  for (size_t i3 = 0; i3 < x_dim[3]; i3++) {
+    for (size_t i2 = 0; i2 < x_dim[2]; i2++) {
+      for (size_t i1 = 0; i1 < x_dim[1]; i1++) {
+        for (size_t i0 = 0; i0 < x_dim[0]; i0++) {
+          size_t y_idx = i0 * y_stride[0] + i1 * y_stride[1] + i2 * y_stride[2] + i3 * y_stride[3];
+          float x_i = x[idx];
+          float y_i = y[y_idx];
+          float dy_i = dy[y_idx];
+          switch (op) {
+            case REDUCE_SUM:
+              dx[idx] += dy_i;
+              break;
+            case REDUCE_MEAN:
+              dx[idx] += dy_i / (float)(x_len / y_len);
+              break;
+            case REDUCE_MAX:
+            case REDUCE_MIN:
+              if (!taken[y_idx] && x_i == y_i) {
+                dx[idx] += dy_i;
+                taken[y_idx] = 1;
+              }
+              break;
+            case REDUCE_L2_NORM:
+              if (y_i > 0.0f) {
+                dx[idx] += dy_i * x_i / y_i;
+              }
+              break;
+            case REDUCE_LOGSUMEXP:
+              if (isfinite(y_i)) {
+                dx[idx] += dy_i * expf(x_i - y_i);
+              }
+              break;
+            default:
+              abort();
+          }
+          idx++;
+        }
+      }
+    }
+  }
+  free(taken);
+}
+
+/* Forward-mode (R-operator) pass: `r_y` is the derivative of `y` along
+`r_x`, using the same partial derivatives as the backward pass. */
+static void reduce_rfwd(int op, const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *r_x, float *r_y) {
+  size_t y_stride[4];
+  size_t x_len = x_dim[0] * x_dim[1] * x_dim[2] * x_dim[3];
+  size_t y_len = reduce_strides(y_dim, y_stride);
+  char *taken = NULL;
+  if (REDUCE_MAX == op || REDUCE_MIN == op) {
+    taken = calloc(y_len, sizeof(char));
+    if (NULL == taken && 0 != y_len) {
+      abort();
+    }
+  }
+  for (size_t j = 0; j < y_len; j++) {
+    r_y[j] = 0.0f;
+  }
+  size_t idx = 0;
+  for (size_t i3 = 0; i3 < x_dim[3]; i3++) {
+    for (size_t i2 = 0; i2 < x_dim[2]; i2++) {
+      for (size_t i1 = 0; i1 < x_dim[1]; i1++) {
+        for (size_t i0 = 0; i0 < x_dim[0]; i0++) {
+          size_t y_idx = i0 * y_stride[0] + i1 * y_stride[1] + i2 * y_stride[2] + i3 * y_stride[3];
+          float x_i = x[idx];
+          float y_i = y[y_idx];
+          float r_x_i = r_x[idx];
+          switch (op) {
+            case REDUCE_SUM:
+              r_y[y_idx] += r_x_i;
+              break;
+            case REDUCE_MEAN:
+              r_y[y_idx] += r_x_i / (float)(x_len / y_len);
+              break;
+            case REDUCE_MAX:
+            case REDUCE_MIN:
+              if (!taken[y_idx] && x_i == y_i) {
+                r_y[y_idx] += r_x_i;
+                taken[y_idx] = 1;
+              }
+              break;
+            case REDUCE_L2_NORM:
+              if (y_i > 0.0f) {
+                r_y[y_idx] += r_x_i * x_i / y_i;
+              }
+              break;
+            case REDUCE_LOGSUMEXP:
+              if (isfinite(y_i)) {
+                r_y[y_idx] += r_x_i * expf(x_i - y_i);
+              }
+              break;
+            default:
+              abort();
+          }
+          idx++;
+        }
+      }
+    }
+  }
+  free(taken);
+}
+
+void arraydiff_kernel_reduce_sum_fwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, float *y) {
+  reduce_fwd(REDUCE_SUM, x_dim, x, y_dim, y);
+}
+
+void arraydiff_kernel_reduce_sum_bwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *dy, float *dx) {
+  reduce_bwd(REDUCE_SUM, x_dim, x, y_dim, y, dy, dx);
+}
+
+void arraydiff_kernel_reduce_sum_rfwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *r_x, float *r_y) {
+  reduce_rfwd(REDUCE_SUM, x_dim, x, y_dim, y, r_x, r_y);
+}
+
+void arraydiff_kernel_reduce_mean_fwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, float *y) {
+  reduce_fwd(REDUCE_MEAN, x_dim, x, y_dim, y);
+}
+
+void arraydiff_kernel_reduce_mean_bwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *dy, float *dx) {
+  reduce_bwd(REDUCE_MEAN, x_dim, x, y_dim, y, dy, dx);
+}
+
+void arraydiff_kernel_reduce_mean_rfwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *r_x, float *r_y) {
+  reduce_rfwd(REDUCE_MEAN, x_dim, x, y_dim, y, r_x, r_y);
+}
+
+void arraydiff_kernel_reduce_max_fwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, float *y) {
+  reduce_fwd(REDUCE_MAX, x_dim, x, y_dim, y);
+}
+
+void arraydiff_kernel_reduce_max_bwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *dy, float *dx) {
+  reduce_bwd(REDUCE_MAX, x_dim, x, y_dim, y, dy, dx);
+}
+
+void arraydiff_kernel_reduce_max_rfwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *r_x, float *r_y) {
+  reduce_rfwd(REDUCE_MAX, x_dim, x, y_dim, y, r_x, r_y);
+}
+
+void arraydiff_kernel_reduce_min_fwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, float *y) {
+  reduce_fwd(REDUCE_MIN, x_dim, x, y_dim, y);
+}
+
+void arraydiff_kernel_reduce_min_bwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *dy, float *dx) {
+  reduce_bwd(REDUCE_MIN, x_dim, x, y_dim, y, dy, dx);
+}
+
+void arraydiff_kernel_reduce_min_rfwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *r_x, float *r_y) {
+  reduce_rfwd(REDUCE_MIN, x_dim, x, y_dim, y, r_x, r_y);
+}
+
+void arraydiff_kernel_reduce_l2_norm_fwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, float *y) {
+  reduce_fwd(REDUCE_L2_NORM, x_dim, x, y_dim, y);
+}
+
+void arraydiff_kernel_reduce_l2_norm_bwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *dy, float *dx) {
+  reduce_bwd(REDUCE_L2_NORM, x_dim, x, y_dim, y, dy, dx);
+}
+
+void arraydiff_kernel_reduce_l2_norm_rfwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *r_x, float *r_y) {
+  reduce_rfwd(REDUCE_L2_NORM, x_dim, x, y_dim, y, r_x, r_y);
+}
+
+void arraydiff_kernel_reduce_logsumexp_fwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, float *y) {
+  reduce_fwd(REDUCE_LOGSUMEXP, x_dim, x, y_dim, y);
+}
+
+void arraydiff_kernel_reduce_logsumexp_bwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *dy, float *dx) {
+  reduce_bwd(REDUCE_LOGSUMEXP, x_dim, x, y_dim, y, dy, dx);
+}
+
+void arraydiff_kernel_reduce_logsumexp_rfwd_f32(const size_t *x_dim, const float *x, const size_t *y_dim, const float *y, const float *r_x, float *r_y) {
+  reduce_rfwd(REDUCE_LOGSUMEXP, x_dim, x, y_dim, y, r_x, r_y);
+}
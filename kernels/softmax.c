@@ -0,0 +1,552 @@
+/*
+Copyright 2017 the arraydiff authors
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+#include <math.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/* Softmax kernels.
+
+The input is a sequence of `num_rows` contiguous rows of length `dim`, and
+each row is normalized independently. */
+
+void arraydiff_kernel_softmax_fwd_f32(
+    size_t dim,
+    size_t num_rows,
+    const float *x,
+    float *y)
+{
+  for (size_t r = 0; r < num_rows; r++) {
+    const float *x_r = x + r * dim;
+    float *y_r = y + r * dim;
+    float x_max = -INFINITY;
+    for (size_t j = 0; j < dim; j++) {
+      x_max = x_r[j] > x_max ? x_r[j] : x_max;
+    }
+    double z = 0.0;
+    for (size_t j = 0; j < dim; j++) {
+      y_r[j] = expf(x_r[j] - x_max);
+      z += y_r[j];
+    }
+    for (size_t j = 0; j < dim; j++) {
+      y_r[j] = (float)(y_r[j] / z);
+    }
+  }
+}
+
+void arraydiff_kernel_softmax_bwd_f32(
+    size_t dim,
+    size_t num_rows,
+    const float *y,
+    const float *dy,
+    float *dx)
+{
+  for (size_t r = 0; r < num_rows; r++) {
+    const float *y_r = y + r * dim;
+    const float *dy_r = dy + r * dim;
+    float *dx_r = dx + r * dim;
+    double dot = 0.0;
+    for (size_t j = 0; j < dim; j++) {
+      dot += dy_r[j] * y_r[j];
+    }
+    for (size_t j = 0; j < dim; j++) {
+      dx_r[j] += (float)(y_r[j] * (dy_r[j] - dot));
+    }
+  }
+}
+
+/* Backward of the softmax tangent `y_tng = J(y) x_tng` with respect to the
+softmax input, i.e. the second-order term `dx += J(y) u` where
+`u = dy_tng * (x_tng - <y, x_tng>) - <dy_tng, y> x_tng`. */
+
+void arraydiff_kernel_softmax_tangent_bwd_f32(
+    size_t dim,
+    size_t num_rows,
+    const float *y,
+    const float *x_tng,
+    const float *dy_tng,
+    float *dx)
+{
+  for (size_t r = 0; r < num_rows; r++) {
+    const float *y_r = y + r * dim;
+    const float *x_tng_r = x_tng + r * dim;
+    const float *dy_tng_r = dy_tng + r * dim;
+    float *dx_r = dx + r * dim;
+    double s = 0.0;
+    double g = 0.0;
+    for (size_t j = 0; j < dim; j++) {
+      s += y_r[j] * x_tng_r[j];
+      g += y_r[j] * dy_tng_r[j];
+    }
+    double dot = 0.0;
+    for (size_t j = 0; j < dim; j++) {
+      double u_j = dy_tng_r[j] * (x_tng_r[j] - s) - g * x_tng_r[j];
+      dot += y_r[j] * u_j;
+    }
+    for (size_t j = 0; j < dim; j++) {
+      double u_j = dy_tng_r[j] * (x_tng_r[j] - s) - g * x_tng_r[j];
+      dx_r[j] += (float)(y_r[j] * (u_j - dot));
+    }
+  }
+}
+
+/* Softmax loss kernels.
+
+These take the softmax output `y` of a batch of `batch_sz` rows of length
+`dim`, and the backward kernels accumulate into the gradient of the softmax
+input. The tangent kernels compute the directional derivative of the loss
+along `x_tng` given the softmax tangent `y_tng`, and the backward of that
+directional derivative with respect to the softmax input. */
+
+void arraydiff_kernel_softmax_nll_loss_fwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const uint32_t *t,
+    float *loss)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    size_t t_i = t[idx];
+    loss[idx] = -logf(y[t_i + dim * idx]);
+  }
+}
+
+void arraydiff_kernel_softmax_nll_loss_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const uint32_t *t,
+    const float *df,
+    float *dx)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    size_t t_i = t[idx];
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      dx[k] += df[idx] * (y[k] - (float)(j == t_i));
+    }
+  }
+}
+
+void arraydiff_kernel_softmax_nll_loss_tangent_fwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const uint32_t *t,
+    const float *x_tng,
+    float *loss_tng)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    size_t t_i = t[idx];
+    double s = 0.0;
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      s += y[k] * x_tng[k];
+    }
+    loss_tng[idx] = (float)(s - x_tng[t_i + dim * idx]);
+  }
+}
+
+void arraydiff_kernel_softmax_nll_loss_tangent_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y_tng,
+    const float *dloss_tng,
+    float *dx)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      dx[k] += dloss_tng[idx] * y_tng[k];
+    }
+  }
+}
+
+void arraydiff_kernel_softmax_kl2_loss_fwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const float *t,
+    float *loss)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    double kl = 0.0;
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      float t_k = t[k];
+      if (t_k > 0.0f) {
+        kl += t_k * (logf(t_k) - logf(y[k]));
+      } else {
+        kl -= t_k * logf(y[k]);
+      }
+    }
+    loss[idx] = (float)kl;
+  }
+}
+
+void arraydiff_kernel_softmax_kl2_loss_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const float *t,
+    const float *df,
+    float *dx)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      dx[k] += df[idx] * (y[k] - t[k]);
+    }
+  }
+}
+
+void arraydiff_kernel_softmax_kl2_loss_tangent_fwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const float *t,
+    const float *x_tng,
+    float *loss_tng)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    double s = 0.0;
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      s += (y[k] - t[k]) * x_tng[k];
+    }
+    loss_tng[idx] = (float)s;
+  }
+}
+
+void arraydiff_kernel_softmax_kl2_loss_tangent_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y_tng,
+    const float *dloss_tng,
+    float *dx)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      dx[k] += dloss_tng[idx] * y_tng[k];
+    }
+  }
+}
+
+void arraydiff_kernel_softmax_lr_loss_fwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const uint32_t *index,
+    const float *t,
+    float *loss,
+    float lr_clip)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    size_t index_i = index[idx];
+    float lr_i = y[index_i + dim * idx] / t[idx];
+    loss[idx] = lr_i < lr_clip ? lr_i : lr_clip;
+  }
+}
+
+void arraydiff_kernel_softmax_lr_loss_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const uint32_t *index,
+    const float *t,
+    const float *df,
+    float *dx,
+    float lr_clip)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    size_t index_i = index[idx];
+    float lr_i = y[index_i + dim * idx] / t[idx];
+    if (lr_i < lr_clip) {
+      for (size_t j = 0; j < dim; j++) {
+        size_t k = j + dim * idx;
+        dx[k] += df[idx] * lr_i * ((float)(j == index_i) - y[k]);
+      }
+    }
+  }
+}
+
+void arraydiff_kernel_softmax_lr_loss_tangent_fwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const uint32_t *index,
+    const float *t,
+    const float *x_tng,
+    float *loss_tng,
+    float lr_clip)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    size_t index_i = index[idx];
+    float lr_i = y[index_i + dim * idx] / t[idx];
+    if (lr_i < lr_clip) {
+      double s = 0.0;
+      for (size_t j = 0; j < dim; j++) {
+        size_t k = j + dim * idx;
+        s += y[k] * x_tng[k];
+      }
+      loss_tng[idx] = (float)(lr_i * (x_tng[index_i + dim * idx] - s));
+    } else {
+      loss_tng[idx] = 0.0f;
+    }
+  }
+}
+
+void arraydiff_kernel_softmax_lr_loss_tangent_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const uint32_t *index,
+    const float *t,
+    const float *x_tng,
+    const float *y_tng,
+    const float *dloss_tng,
+    float *dx,
+    float lr_clip)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    size_t index_i = index[idx];
+    float lr_i = y[index_i + dim * idx] / t[idx];
+    if (lr_i < lr_clip) {
+      double s = 0.0;
+      for (size_t j = 0; j < dim; j++) {
+        size_t k = j + dim * idx;
+        s += y[k] * x_tng[k];
+      }
+      float d_i = (float)(x_tng[index_i + dim * idx] - s);
+      for (size_t j = 0; j < dim; j++) {
+        size_t k = j + dim * idx;
+        dx[k] += dloss_tng[idx] * lr_i * (d_i * ((float)(j == index_i) - y[k]) - y_tng[k]);
+      }
+    }
+  }
+}
+
+/* Curvature-vector products of the softmax losses.
+
+The Gauss-Newton kernel accumulates `df * H r_x`, where `H = diag(y) - y y^T`
+is the Hessian of the NLL and KL2 losses with respect to the softmax input;
+for these losses it is also the Fisher matrix of the predictive distribution.
+The empirical Fisher kernels accumulate `df * g <g, r_x>`, where `g` is the
+gradient of the loss of each row. */
+
+void arraydiff_kernel_softmax_gauss_newton_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const float *r_x,
+    const float *df,
+    float *dx)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    const float *y_r = y + dim * idx;
+    const float *r_x_r = r_x + dim * idx;
+    float *dx_r = dx + dim * idx;
+    double dot = 0.0;
+    for (size_t j = 0; j < dim; j++) {
+      dot += y_r[j] * r_x_r[j];
+    }
+    for (size_t j = 0; j < dim; j++) {
+      dx_r[j] += (float)(df[idx] * y_r[j] * (r_x_r[j] - dot));
+    }
+  }
+}
+
+void arraydiff_kernel_softmax_nll_loss_empirical_fisher_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const uint32_t *t,
+    const float *r_x,
+    const float *df,
+    float *dx)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    size_t t_i = t[idx];
+    double s = 0.0;
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      s += (y[k] - (float)(j == t_i)) * r_x[k];
+    }
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      dx[k] += (float)(df[idx] * s * (y[k] - (float)(j == t_i)));
+    }
+  }
+}
+
+void arraydiff_kernel_softmax_kl2_loss_empirical_fisher_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const float *t,
+    const float *r_x,
+    const float *df,
+    float *dx)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    double s = 0.0;
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      s += (y[k] - t[k]) * r_x[k];
+    }
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      dx[k] += (float)(df[idx] * s * (y[k] - t[k]));
+    }
+  }
+}
+
+/* The likelihood ratio loss `lr = y_i / t` has the gradient
+`g = lr (e_i - y)` and the Hessian `H = lr ((e_i - y) (e_i - y)^T - diag(y) +
+y y^T)` with respect to the softmax input. `H` is indefinite, so the
+Gauss-Newton product of this loss need not be positive semidefinite. Rows
+whose ratio is clipped have zero curvature. */
+
+void arraydiff_kernel_softmax_lr_loss_gauss_newton_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const uint32_t *index,
+    const float *t,
+    const float *r_x,
+    const float *df,
+    float *dx,
+    float lr_clip)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    size_t index_i = index[idx];
+    float lr_i = y[index_i + dim * idx] / t[idx];
+    if (lr_i < lr_clip) {
+      double s = 0.0;
+      for (size_t j = 0; j < dim; j++) {
+        size_t k = j + dim * idx;
+        s += y[k] * r_x[k];
+      }
+      double d_i = r_x[index_i + dim * idx] - s;
+      for (size_t j = 0; j < dim; j++) {
+        size_t k = j + dim * idx;
+        double h_r = d_i * ((double)(j == index_i) - y[k]) - y[k] * (r_x[k] - s);
+        dx[k] += (float)(df[idx] * lr_i * h_r);
+      }
+    }
+  }
+}
+
+void arraydiff_kernel_softmax_lr_loss_empirical_fisher_bwd_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const uint32_t *index,
+    const float *t,
+    const float *r_x,
+    const float *df,
+    float *dx,
+    float lr_clip)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    size_t index_i = index[idx];
+    float lr_i = y[index_i + dim * idx] / t[idx];
+    if (lr_i < lr_clip) {
+      double s = 0.0;
+      for (size_t j = 0; j < dim; j++) {
+        size_t k = j + dim * idx;
+        s += y[k] * r_x[k];
+      }
+      double d_i = r_x[index_i + dim * idx] - s;
+      for (size_t j = 0; j < dim; j++) {
+        size_t k = j + dim * idx;
+        dx[k] += (float)(df[idx] * lr_i * lr_i * d_i * ((double)(j == index_i) - y[k]));
+      }
+    }
+  }
+}
+
+/* Becker-LeCun diagonal of the NLL and KL2 losses: accumulates
+`df2 * g^2 + df * y (1 - y)` into `dx2`, where `g` is the gradient of the loss
+of each row and `y (1 - y)` is the diagonal of its Hessian. */
+
+void arraydiff_kernel_softmax_nll_loss_bwd2_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const uint32_t *t,
+    const float *df,
+    const float *df2,
+    float *dx2)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    size_t t_i = t[idx];
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      float g = y[k] - (float)(j == t_i);
+      dx2[k] += df2[idx] * g * g + df[idx] * y[k] * (1.0f - y[k]);
+    }
+  }
+}
+
+void arraydiff_kernel_softmax_kl2_loss_bwd2_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const float *t,
+    const float *df,
+    const float *df2,
+    float *dx2)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    for (size_t j = 0; j < dim; j++) {
+      size_t k = j + dim * idx;
+      float g = y[k] - t[k];
+      dx2[k] += df2[idx] * g * g + df[idx] * y[k] * (1.0f - y[k]);
+    }
+  }
+}
+
+/* Becker-LeCun diagonal of the likelihood ratio loss. The gradient of each
+row is `g = lr (e_i - y)` and the diagonal of its Hessian is
+`lr ((e_i - y)^2 - y (1 - y))`. Rows whose ratio is clipped contribute
+nothing. */
+
+void arraydiff_kernel_softmax_lr_loss_bwd2_f32(
+    size_t dim,
+    size_t batch_sz,
+    const float *y,
+    const uint32_t *index,
+    const float *t,
+    const float *df,
+    const float *df2,
+    float *dx2,
+    float lr_clip)
+{
+  for (size_t idx = 0; idx < batch_sz; idx++) {
+    size_t index_i = index[idx];
+    float lr_i = y[index_i + dim * idx] / t[idx];
+    if (lr_i < lr_clip) {
+      for (size_t j = 0; j < dim; j++) {
+        size_t k = j + dim * idx;
+        float d = (float)(j == index_i) - y[k];
+        float g = lr_i * d;
+        dx2[k] += df2[idx] * g * g + df[idx] * lr_i * (d * d - y[k] * (1.0f - y[k]));
+      }
+    }
+  }
+}
@@ -31,6 +31,68 @@ void arraydiff_kernel_rect_bwd_f32(size_t dim, const float *x, const float *dy,
   }
 }
 
+void arraydiff_kernel_rect_bwd2_f32(size_t dim, const float *x, const float *dy2, float *dx2) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    dx2[i] += dy2[i] * (x_i > 0.0f);
+  }
+}
+
+void arraydiff_kernel_leak_rect_fwd_f32(size_t dim, float c, const float *x, float *y) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    y[i] = x_i > 0.0f ? x_i : c * x_i;
+  }
+}
+
+void arraydiff_kernel_leak_rect_bwd_f32(size_t dim, float c, const float *x, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    dx[i] += dy[i] * (x_i > 0.0f ? 1.0f : c);
+  }
+}
+
+void arraydiff_kernel_leak_rect_bwd2_f32(size_t dim, float c, const float *x, const float *dy2, float *dx2) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float d = x_i > 0.0f ? 1.0f : c;
+    dx2[i] += dy2[i] * (d * d);
+  }
+}
+
+void arraydiff_kernel_elu_fwd_f32(size_t dim, float c, const float *x, float *y) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    y[i] = x_i > 0.0f ? x_i : c * expm1f(x_i);
+  }
+}
+
+void arraydiff_kernel_elu_bwd_f32(size_t dim, float c, const float *x, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    dx[i] += dy[i] * (x_i > 0.0f ? 1.0f : c * expf(x_i));
+  }
+}
+
+void arraydiff_kernel_elu_tangent_bwd_f32(size_t dim, float c, const float *x, const float *x_tng, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    dx[i] += dy[i] * x_tng[i] * (x_i > 0.0f ? 0.0f : c * expf(x_i));
+  }
+}
+
+void arraydiff_kernel_elu_bwd2_f32(size_t dim, float c, const float *x, const float *dy, const float *dy2, float *dx2) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    if (x_i > 0.0f) {
+      dx2[i] += dy2[i];
+    } else {
+      float d = c * expf(x_i);
+      dx2[i] += dy2[i] * (d * d) + dy[i] * d;
+    }
+  }
+}
+
 void arraydiff_kernel_logistic_fwd_f32(size_t dim, const float *x, float *y) {
   for (size_t i = 0; i < dim; i++) {
     float x_i = x[i];
@@ -43,7 +105,15 @@ void arraydiff_kernel_logistic_bwd_f32(size_t dim, const float *x, const float *
   for (size_t i = 0; i < dim; i++) {
     float x_i = x[i];
     float y_i = 1.0f / (1.0f + expf(-x_i));
-    dx[i] += y_i * (1.0f - y_i);
+    dx[i] += dy[i] * y_i * (1.0f - y_i);
+  }
+}
+
+void arraydiff_kernel_logistic_tangent_bwd_f32(size_t dim, const float *x, const float *x_tng, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float y_i = 1.0f / (1.0f + expf(-x_i));
+    dx[i] += dy[i] * x_tng[i] * (y_i * (1.0f - y_i) * (1.0f - 2.0f * y_i));
   }
 }
 
@@ -85,6 +155,17 @@ void arraydiff_kernel_tanh_bwd_f32(size_t dim, const float *x, const float *dy,
   }
 }
 
+void arraydiff_kernel_tanh_tangent_bwd_f32(size_t dim, const float *x, const float *x_tng, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float hi = expf(x_i);
+    float lo = expf(-x_i);
+    float t = (hi - lo) / (hi + lo);
+    float s = 2.0f / (hi + lo);
+    dx[i] += dy[i] * x_tng[i] * (-2.0f * t * s * s);
+  }
+}
+
 void arraydiff_kernel_tanh_rbwd_f32(size_t dim, const float *x, const float *r_x, const float *dy, const float *r_dy, float *r_dx) {
   for (size_t i = 0; i < dim; i++) {
     float x_i = x[i];
@@ -96,3 +177,199 @@ void arraydiff_kernel_tanh_rbwd_f32(size_t dim, const float *x, const float *r_x
     r_dx[i] += (r_dy[i] + dy[i] * r_x[i] * (-2.0f * t)) * (s * s);
   }
 }
+
+void arraydiff_kernel_tanh_bwd2_f32(size_t dim, const float *x, const float *dy, const float *dy2, float *dx2) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float hi = expf(x_i);
+    float lo = expf(-x_i);
+    float t = (hi - lo) / (hi + lo);
+    float s = 2.0f / (hi + lo);
+    dx2[i] += (dy2[i] * (s * s) + dy[i] * (-2.0f * t)) * (s * s);
+  }
+}
+
+void arraydiff_kernel_exp_fwd_f32(size_t dim, const float *x, float *y) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    y[i] = expf(x_i);
+  }
+}
+
+void arraydiff_kernel_exp_bwd_f32(size_t dim, const float *x, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    dx[i] += dy[i] * expf(x_i);
+  }
+}
+
+void arraydiff_kernel_exp_tangent_bwd_f32(size_t dim, const float *x, const float *x_tng, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    dx[i] += dy[i] * x_tng[i] * expf(x_i);
+  }
+}
+
+void arraydiff_kernel_exp_bwd2_f32(size_t dim, const float *x, const float *dy, const float *dy2, float *dx2) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float y_i = expf(x_i);
+    dx2[i] += (dy2[i] * y_i + dy[i]) * y_i;
+  }
+}
+
+void arraydiff_kernel_log_fwd_f32(size_t dim, const float *x, float *y) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    y[i] = logf(x_i);
+  }
+}
+
+void arraydiff_kernel_log_bwd_f32(size_t dim, const float *x, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    dx[i] += dy[i] / x_i;
+  }
+}
+
+void arraydiff_kernel_log_tangent_bwd_f32(size_t dim, const float *x, const float *x_tng, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    dx[i] -= dy[i] * x_tng[i] / (x_i * x_i);
+  }
+}
+
+void arraydiff_kernel_log_bwd2_f32(size_t dim, const float *x, const float *dy, const float *dy2, float *dx2) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    dx2[i] += (dy2[i] - dy[i]) / (x_i * x_i);
+  }
+}
+
+void arraydiff_kernel_sqrt_fwd_f32(size_t dim, const float *x, float *y) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    y[i] = sqrtf(x_i);
+  }
+}
+
+void arraydiff_kernel_sqrt_bwd_f32(size_t dim, const float *x, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    dx[i] += dy[i] * 0.5f / sqrtf(x_i);
+  }
+}
+
+void arraydiff_kernel_sqrt_tangent_bwd_f32(size_t dim, const float *x, const float *x_tng, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    dx[i] -= dy[i] * x_tng[i] * 0.25f / (x_i * sqrtf(x_i));
+  }
+}
+
+void arraydiff_kernel_sqrt_bwd2_f32(size_t dim, const float *x, const float *dy, const float *dy2, float *dx2) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    dx2[i] += (dy2[i] - dy[i] / sqrtf(x_i)) * 0.25f / x_i;
+  }
+}
+
+void arraydiff_kernel_softplus_fwd_f32(size_t dim, const float *x, float *y) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    /* Stable for large |x|: softplus(x) = max(x, 0) + log(1 + exp(-|x|)). */
+    y[i] = fmaxf(x_i, 0.0f) + log1pf(expf(-fabsf(x_i)));
+  }
+}
+
+void arraydiff_kernel_softplus_bwd_f32(size_t dim, const float *x, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float y_i = 1.0f / (1.0f + expf(-x_i));
+    dx[i] += dy[i] * y_i;
+  }
+}
+
+void arraydiff_kernel_softplus_tangent_bwd_f32(size_t dim, const float *x, const float *x_tng, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float y_i = 1.0f / (1.0f + expf(-x_i));
+    dx[i] += dy[i] * x_tng[i] * (y_i * (1.0f - y_i));
+  }
+}
+
+void arraydiff_kernel_softplus_bwd2_f32(size_t dim, const float *x, const float *dy, const float *dy2, float *dx2) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float y_i = 1.0f / (1.0f + expf(-x_i));
+    dx2[i] += dy2[i] * (y_i * y_i) + dy[i] * (y_i * (1.0f - y_i));
+  }
+}
+
+void arraydiff_kernel_gelu_fwd_f32(size_t dim, const float *x, float *y) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float cdf = 0.5f * (1.0f + erff(x_i * (float)M_SQRT1_2));
+    y[i] = x_i * cdf;
+  }
+}
+
+void arraydiff_kernel_gelu_bwd_f32(size_t dim, const float *x, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float cdf = 0.5f * (1.0f + erff(x_i * (float)M_SQRT1_2));
+    float pdf = 0.5f * (float)M_2_SQRTPI * (float)M_SQRT1_2 * expf(-0.5f * x_i * x_i);
+    dx[i] += dy[i] * (cdf + x_i * pdf);
+  }
+}
+
+void arraydiff_kernel_gelu_tangent_bwd_f32(size_t dim, const float *x, const float *x_tng, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float pdf = 0.5f * (float)M_2_SQRTPI * (float)M_SQRT1_2 * expf(-0.5f * x_i * x_i);
+    dx[i] += dy[i] * x_tng[i] * (pdf * (2.0f - x_i * x_i));
+  }
+}
+
+void arraydiff_kernel_gelu_bwd2_f32(size_t dim, const float *x, const float *dy, const float *dy2, float *dx2) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float cdf = 0.5f * (1.0f + erff(x_i * (float)M_SQRT1_2));
+    float pdf = 0.5f * (float)M_2_SQRTPI * (float)M_SQRT1_2 * expf(-0.5f * x_i * x_i);
+    float d = cdf + x_i * pdf;
+    dx2[i] += dy2[i] * (d * d) + dy[i] * (pdf * (2.0f - x_i * x_i));
+  }
+}
+
+void arraydiff_kernel_swish_fwd_f32(size_t dim, const float *x, float *y) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float s = 1.0f / (1.0f + expf(-x_i));
+    y[i] = x_i * s;
+  }
+}
+
+void arraydiff_kernel_swish_bwd_f32(size_t dim, const float *x, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float s = 1.0f / (1.0f + expf(-x_i));
+    dx[i] += dy[i] * (s + x_i * s * (1.0f - s));
+  }
+}
+
+void arraydiff_kernel_swish_tangent_bwd_f32(size_t dim, const float *x, const float *x_tng, const float *dy, float *dx) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float s = 1.0f / (1.0f + expf(-x_i));
+    dx[i] += dy[i] * x_tng[i] * (s * (1.0f - s) * (2.0f + x_i * (1.0f - 2.0f * s)));
+  }
+}
+
+void arraydiff_kernel_swish_bwd2_f32(size_t dim, const float *x, const float *dy, const float *dy2, float *dx2) {
+  for (size_t i = 0; i < dim; i++) {
+    float x_i = x[i];
+    float s = 1.0f / (1.0f + expf(-x_i));
+    float d = s + x_i * s * (1.0f - s);
+    dx2[i] += dy2[i] * (d * d) + dy[i] * (s * (1.0f - s) * (2.0f + x_i * (1.0f - 2.0f * s)));
+  }
+}
@@ -0,0 +1,212 @@
+/*
+Copyright 2017 the arraydiff authors
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+#include <stdint.h>
+#include <stdlib.h>
+
+/* Axis slice kernels.
+
+Both arrays are viewed as packed 3d arrays `[inner, axis, outer]`. The slice
+`[x_offset, x_offset + length)` of the axis of `x` is copied (or added) into
+the slice `[y_offset, y_offset + length)` of the axis of `y`. */
+
+void arraydiff_kernel_axis_slice_copy_f32(
+    size_t inner_dim,
+    size_t outer_dim,
+    size_t length,
+    size_t x_axis_dim,
+    size_t x_offset,
+    const float *x,
+    size_t y_axis_dim,
+    size_t y_offset,
+    float *y)
+{
+  for (size_t o = 0; o < outer_dim; o++) {
+    for (size_t k = 0; k < length; k++) {
+      const float *x_row = x + (o * x_axis_dim + x_offset + k) * inner_dim;
+      float *y_row = y + (o * y_axis_dim + y_offset + k) * inner_dim;
+      for (size_t i = 0; i < inner_dim; i++) {
+        y_row[i] = x_row[i];
+      }
+    }
+  }
+}
+
+void arraydiff_kernel_axis_slice_add_f32(
+    size_t inner_dim,
+    size_t outer_dim,
+    size_t length,
+    size_t x_axis_dim,
+    size_t x_offset,
+    const float *x,
+    size_t y_axis_dim,
+    size_t y_offset,
+    float *y)
+{
+  for (size_t o = 0; o < outer_dim; o++) {
+    for (size_t k = 0; k < length; k++) {
+      const float *x_row = x + (o * x_axis_dim + x_offset + k) * inner_dim;
+      float *y_row = y + (o * y_axis_dim + y_offset + k) * inner_dim;
+      for (size_t i = 0; i < inner_dim; i++) {
+        y_row[i] += x_row[i];
+      }
+    }
+  }
+}
+
+void arraydiff_kernel_copy_f32(
+    size_t len,
+    const float *x,
+    float *y)
+{
+  for (size_t i = 0; i < len; i++) {
+    y[i] = x[i];
+  }
+}
+
+void arraydiff_kernel_add_f32(
+    size_t len,
+    const float *x,
+    float *y)
+{
+  for (size_t i = 0; i < len; i++) {
+    y[i] += x[i];
+  }
+}
+
+void arraydiff_kernel_scale_add_f32(
+    size_t len,
+    float alpha,
+    const float *x,
+    float *y)
+{
+  for (size_t i = 0; i < len; i++) {
+    y[i] += alpha * x[i];
+  }
+}
+
+void arraydiff_kernel_square_f32(
+    size_t len,
+    const float *x,
+    float *y)
+{
+  for (size_t i = 0; i < len; i++) {
+    y[i] = x[i] * x[i];
+  }
+}
+
+void arraydiff_kernel_cast_u8_to_f32(
+    size_t len,
+    const uint8_t *x,
+    float *y)
+{
+  for (size_t i = 0; i < len; i++) {
+    y[i] = (float)x[i];
+  }
+}
+
+/* Gather kernels.
+
+`x` is a packed `[dim, batch]` array and `index` holds `k` indices per
+example, packed as `[k, batch]`. The backward pass scatter-adds into `dx`. */
+
+void arraydiff_kernel_gather_fwd_f32(
+    size_t dim,
+    size_t k,
+    size_t batch_sz,
+    const float *x,
+    const uint32_t *index,
+    float *y)
+{
+  for (size_t b = 0; b < batch_sz; b++) {
+    for (size_t j = 0; j < k; j++) {
+      y[j + k * b] = x[index[j + k * b] + dim * b];
+    }
+  }
+}
+
+void arraydiff_kernel_gather_bwd_f32(
+    size_t dim,
+    size_t k,
+    size_t batch_sz,
+    const float *dy,
+    const uint32_t *index,
+    float *dx)
+{
+  for (size_t b = 0; b < batch_sz; b++) {
+    for (size_t j = 0; j < k; j++) {
+      dx[index[j + k * b] + dim * b] += dy[j + k * b];
+    }
+  }
+}
+
+/* Embedding lookup kernel.
+
+The table `w` holds `num_rows` contiguous rows of length `row_len`. Output
+example `b` is the sum of the rows `index[offsets[b] .. offsets[b + 1]]`;
+when `offsets` is NULL every example looks up exactly one row. */
+
+void arraydiff_kernel_embed_lookup_fwd_f32(
+    size_t row_len,
+    size_t num_rows,
+    size_t batch_sz,
+    const float *w,
+    const uint32_t *index,
+    const uint32_t *offsets,
+    float *y)
+{
+  for (size_t b = 0; b < batch_sz; b++) {
+    size_t start = NULL != offsets ? offsets[b] : b;
+    size_t end = NULL != offsets ? offsets[b + 1] : b + 1;
+    float *y_b = y + b * row_len;
+    for (size_t j = 0; j < row_len; j++) {
+      y_b[j] = 0.0f;
+    }
+    for (size_t k = start; k < end; k++) {
+      size_t row = index[k];
+      if (row >= num_rows) {
+        abort();
+      }
+      const float *w_r = w + row * row_len;
+      for (size_t j = 0; j < row_len; j++) {
+        y_b[j] += w_r[j];
+      }
+    }
+  }
+}
+
+/* Writes the output gradient of each lookup to its own slot of `dvalues`, so
+the caller can pair `dvalues` with `index` as a sparse row gradient. */
+
+void arraydiff_kernel_embed_lookup_bwd_f32(
+    size_t row_len,
+    size_t batch_sz,
+    const uint32_t *offsets,
+    const float *dy,
+    float *dvalues)
+{
+  for (size_t b = 0; b < batch_sz; b++) {
+    size_t start = NULL != offsets ? offsets[b] : b;
+    size_t end = NULL != offsets ? offsets[b + 1] : b + 1;
+    const float *dy_b = dy + b * row_len;
+    for (size_t k = start; k < end; k++) {
+      float *dv_k = dvalues + k * row_len;
+      for (size_t j = 0; j < row_len; j++) {
+        dv_k[j] = dy_b[j];
+      }
+    }
+  }
+}
@@ -22,13 +22,159 @@ extern "C" {
   // Special map functions.
   pub fn arraydiff_kernel_rect_fwd_f32(dim: usize, x: *const f32, y: *mut f32);
   pub fn arraydiff_kernel_rect_bwd_f32(dim: usize, x: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_rect_bwd2_f32(dim: usize, x: *const f32, dy2: *const f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_leak_rect_fwd_f32(dim: usize, c: f32, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_leak_rect_bwd_f32(dim: usize, c: f32, x: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_leak_rect_bwd2_f32(dim: usize, c: f32, x: *const f32, dy2: *const f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_elu_fwd_f32(dim: usize, c: f32, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_elu_bwd_f32(dim: usize, c: f32, x: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_elu_tangent_bwd_f32(dim: usize, c: f32, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_elu_bwd2_f32(dim: usize, c: f32, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32);
   pub fn arraydiff_kernel_logistic_fwd_f32(dim: usize, x: *const f32, y: *mut f32);
   pub fn arraydiff_kernel_logistic_bwd_f32(dim: usize, x: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_logistic_tangent_bwd_f32(dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32);
   pub fn arraydiff_kernel_logistic_rbwd_f32(dim: usize, x: *const f32, r_x: *const f32, dy: *const f32, r_dy: *const f32, r_dx: *mut f32);
   pub fn arraydiff_kernel_logistic_bwd2_f32(dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32);
   pub fn arraydiff_kernel_tanh_fwd_f32(dim: usize, x: *const f32, y: *mut f32);
   pub fn arraydiff_kernel_tanh_bwd_f32(dim: usize, x: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_tanh_tangent_bwd_f32(dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32);
   pub fn arraydiff_kernel_tanh_rbwd_f32(dim: usize, x: *const f32, r_x: *const f32, dy: *const f32, r_dy: *const f32, r_dx: *mut f32);
+  pub fn arraydiff_kernel_tanh_bwd2_f32(dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_exp_fwd_f32(dim: usize, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_exp_bwd_f32(dim: usize, x: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_exp_tangent_bwd_f32(dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_exp_bwd2_f32(dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_log_fwd_f32(dim: usize, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_log_bwd_f32(dim: usize, x: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_log_tangent_bwd_f32(dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_log_bwd2_f32(dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_sqrt_fwd_f32(dim: usize, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_sqrt_bwd_f32(dim: usize, x: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_sqrt_tangent_bwd_f32(dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_sqrt_bwd2_f32(dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_softplus_fwd_f32(dim: usize, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_softplus_bwd_f32(dim: usize, x: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_softplus_tangent_bwd_f32(dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_softplus_bwd2_f32(dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_gelu_fwd_f32(dim: usize, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_gelu_bwd_f32(dim: usize, x: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_gelu_tangent_bwd_f32(dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_gelu_bwd2_f32(dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_swish_fwd_f32(dim: usize, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_swish_bwd_f32(dim: usize, x: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_swish_tangent_bwd_f32(dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_swish_bwd2_f32(dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32);
+
+  // Broadcast binary map functions.
+  pub fn arraydiff_kernel_bcast_sub_fwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, y: *mut f32);
+  pub fn arraydiff_kernel_bcast_sub_bwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, dy: *const f32, dx1: *mut f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_bcast_sub_rfwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, r_x1: *const f32, r_x2: *const f32, r_y: *mut f32);
+  pub fn arraydiff_kernel_bcast_mul_fwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, y: *mut f32);
+  pub fn arraydiff_kernel_bcast_mul_bwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, dy: *const f32, dx1: *mut f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_bcast_mul_rfwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, r_x1: *const f32, r_x2: *const f32, r_y: *mut f32);
+  pub fn arraydiff_kernel_bcast_div_fwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, y: *mut f32);
+  pub fn arraydiff_kernel_bcast_div_bwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, dy: *const f32, dx1: *mut f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_bcast_div_rfwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, r_x1: *const f32, r_x2: *const f32, r_y: *mut f32);
+  pub fn arraydiff_kernel_bcast_max_fwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, y: *mut f32);
+  pub fn arraydiff_kernel_bcast_max_bwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, dy: *const f32, dx1: *mut f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_bcast_max_rfwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, r_x1: *const f32, r_x2: *const f32, r_y: *mut f32);
+  pub fn arraydiff_kernel_bcast_min_fwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, y: *mut f32);
+  pub fn arraydiff_kernel_bcast_min_bwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, dy: *const f32, dx1: *mut f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_bcast_min_rfwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, r_x1: *const f32, r_x2: *const f32, r_y: *mut f32);
+  pub fn arraydiff_kernel_bcast_pow_fwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, y: *mut f32);
+  pub fn arraydiff_kernel_bcast_pow_bwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, dy: *const f32, dx1: *mut f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_bcast_pow_rfwd_f32(x1_dim: *const usize, x1: *const f32, x2_dim: *const usize, x2: *const f32, y_dim: *const usize, r_x1: *const f32, r_x2: *const f32, r_y: *mut f32);
+  pub fn arraydiff_kernel_bcast_add_fwd_f32(inner_dim: usize, chan_dim: usize, outer_dim: usize, a: *const f32, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_bcast_mult_add_fwd_f32(inner_dim: usize, chan_dim: usize, outer_dim: usize, x: *const f32, a: *const f32, b: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_bcast_mult_add_param_bwd_f32(inner_dim: usize, chan_dim: usize, outer_dim: usize, x: *const f32, dy: *const f32, da: *mut f32, db: *mut f32);
+  pub fn arraydiff_kernel_bcast_mult_add_input_bwd_f32(inner_dim: usize, chan_dim: usize, outer_dim: usize, a: *const f32, dy: *const f32, dx: *mut f32);
+
+  // Axis reduction functions.
+  pub fn arraydiff_kernel_reduce_sum_fwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *mut f32);
+  pub fn arraydiff_kernel_reduce_sum_bwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_reduce_sum_rfwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *const f32, r_x: *const f32, r_y: *mut f32);
+  pub fn arraydiff_kernel_reduce_mean_fwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *mut f32);
+  pub fn arraydiff_kernel_reduce_mean_bwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_reduce_mean_rfwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *const f32, r_x: *const f32, r_y: *mut f32);
+  pub fn arraydiff_kernel_reduce_max_fwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *mut f32);
+  pub fn arraydiff_kernel_reduce_max_bwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_reduce_max_rfwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *const f32, r_x: *const f32, r_y: *mut f32);
+  pub fn arraydiff_kernel_reduce_min_fwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *mut f32);
+  pub fn arraydiff_kernel_reduce_min_bwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_reduce_min_rfwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *const f32, r_x: *const f32, r_y: *mut f32);
+  pub fn arraydiff_kernel_reduce_l2_norm_fwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *mut f32);
+  pub fn arraydiff_kernel_reduce_l2_norm_bwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_reduce_l2_norm_rfwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *const f32, r_x: *const f32, r_y: *mut f32);
+  pub fn arraydiff_kernel_reduce_logsumexp_fwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *mut f32);
+  pub fn arraydiff_kernel_reduce_logsumexp_bwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_reduce_logsumexp_rfwd_f32(x_dim: *const usize, x: *const f32, y_dim: *const usize, y: *const f32, r_x: *const f32, r_y: *mut f32);
+
+  // Axis slice functions.
+  pub fn arraydiff_kernel_axis_slice_copy_f32(inner_dim: usize, outer_dim: usize, length: usize, x_axis_dim: usize, x_offset: usize, x: *const f32, y_axis_dim: usize, y_offset: usize, y: *mut f32);
+  pub fn arraydiff_kernel_axis_slice_add_f32(inner_dim: usize, outer_dim: usize, length: usize, x_axis_dim: usize, x_offset: usize, x: *const f32, y_axis_dim: usize, y_offset: usize, y: *mut f32);
+
+  // Flat copy and cast functions.
+  pub fn arraydiff_kernel_copy_f32(len: usize, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_add_f32(len: usize, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_scale_add_f32(len: usize, alpha: f32, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_square_f32(len: usize, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_cast_u8_to_f32(len: usize, x: *const u8, y: *mut f32);
+
+  // Gather functions.
+  pub fn arraydiff_kernel_gather_fwd_f32(dim: usize, k: usize, batch_sz: usize, x: *const f32, index: *const u32, y: *mut f32);
+  pub fn arraydiff_kernel_gather_bwd_f32(dim: usize, k: usize, batch_sz: usize, dy: *const f32, index: *const u32, dx: *mut f32);
+
+  // Embedding functions.
+  pub fn arraydiff_kernel_embed_lookup_fwd_f32(row_len: usize, num_rows: usize, batch_sz: usize, w: *const f32, index: *const u32, offsets: *const u32, y: *mut f32);
+  pub fn arraydiff_kernel_embed_lookup_bwd_f32(row_len: usize, batch_sz: usize, offsets: *const u32, dy: *const f32, dvalues: *mut f32);
+
+  // Clip functions.
+  pub fn arraydiff_kernel_symm_unit_clip_fwd_f32(inner_dim: usize, chan_dim: usize, outer_dim: usize, clip: *const f32, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_symm_unit_clip_param_bwd_f32(inner_dim: usize, chan_dim: usize, outer_dim: usize, clip: *const f32, x: *const f32, dy: *const f32, grad: *mut f32);
+  pub fn arraydiff_kernel_symm_unit_clip_input_bwd_f32(inner_dim: usize, chan_dim: usize, outer_dim: usize, clip: *const f32, x: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_symm_unit_clip_rfwd_f32(inner_dim: usize, chan_dim: usize, outer_dim: usize, clip: *const f32, x: *const f32, r_clip: *const f32, r_x: *const f32, r_y: *mut f32);
+
+  // Linear functions.
+  pub fn arraydiff_kernel_batch_gemm_f32(m: usize, n: usize, k: usize, num_mats: usize, alpha: f32, a: *const f32, a_trans: u32, b: *const f32, b_trans: u32, beta: f32, c: *mut f32);
+
+  // Softmax functions.
+  pub fn arraydiff_kernel_softmax_fwd_f32(dim: usize, num_rows: usize, x: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_softmax_bwd_f32(dim: usize, num_rows: usize, y: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_softmax_tangent_bwd_f32(dim: usize, num_rows: usize, y: *const f32, x_tng: *const f32, dy_tng: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_softmax_nll_loss_fwd_f32(dim: usize, batch_sz: usize, y: *const f32, t: *const u32, loss: *mut f32);
+  pub fn arraydiff_kernel_softmax_nll_loss_bwd_f32(dim: usize, batch_sz: usize, y: *const f32, t: *const u32, df: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_softmax_nll_loss_tangent_fwd_f32(dim: usize, batch_sz: usize, y: *const f32, t: *const u32, x_tng: *const f32, loss_tng: *mut f32);
+  pub fn arraydiff_kernel_softmax_nll_loss_tangent_bwd_f32(dim: usize, batch_sz: usize, y_tng: *const f32, dloss_tng: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_softmax_kl2_loss_fwd_f32(dim: usize, batch_sz: usize, y: *const f32, t: *const f32, loss: *mut f32);
+  pub fn arraydiff_kernel_softmax_kl2_loss_bwd_f32(dim: usize, batch_sz: usize, y: *const f32, t: *const f32, df: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_softmax_kl2_loss_tangent_fwd_f32(dim: usize, batch_sz: usize, y: *const f32, t: *const f32, x_tng: *const f32, loss_tng: *mut f32);
+  pub fn arraydiff_kernel_softmax_kl2_loss_tangent_bwd_f32(dim: usize, batch_sz: usize, y_tng: *const f32, dloss_tng: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_softmax_lr_loss_fwd_f32(dim: usize, batch_sz: usize, y: *const f32, index: *const u32, t: *const f32, loss: *mut f32, lr_clip: f32);
+  pub fn arraydiff_kernel_softmax_lr_loss_bwd_f32(dim: usize, batch_sz: usize, y: *const f32, index: *const u32, t: *const f32, df: *const f32, dx: *mut f32, lr_clip: f32);
+  pub fn arraydiff_kernel_softmax_lr_loss_tangent_fwd_f32(dim: usize, batch_sz: usize, y: *const f32, index: *const u32, t: *const f32, x_tng: *const f32, loss_tng: *mut f32, lr_clip: f32);
+  pub fn arraydiff_kernel_softmax_lr_loss_tangent_bwd_f32(dim: usize, batch_sz: usize, y: *const f32, index: *const u32, t: *const f32, x_tng: *const f32, y_tng: *const f32, dloss_tng: *const f32, dx: *mut f32, lr_clip: f32);
+  pub fn arraydiff_kernel_softmax_gauss_newton_bwd_f32(dim: usize, batch_sz: usize, y: *const f32, r_x: *const f32, df: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_softmax_nll_loss_empirical_fisher_bwd_f32(dim: usize, batch_sz: usize, y: *const f32, t: *const u32, r_x: *const f32, df: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_softmax_kl2_loss_empirical_fisher_bwd_f32(dim: usize, batch_sz: usize, y: *const f32, t: *const f32, r_x: *const f32, df: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_softmax_lr_loss_gauss_newton_bwd_f32(dim: usize, batch_sz: usize, y: *const f32, index: *const u32, t: *const f32, r_x: *const f32, df: *const f32, dx: *mut f32, lr_clip: f32);
+  pub fn arraydiff_kernel_softmax_lr_loss_empirical_fisher_bwd_f32(dim: usize, batch_sz: usize, y: *const f32, index: *const u32, t: *const f32, r_x: *const f32, df: *const f32, dx: *mut f32, lr_clip: f32);
+  pub fn arraydiff_kernel_softmax_nll_loss_bwd2_f32(dim: usize, batch_sz: usize, y: *const f32, t: *const u32, df: *const f32, df2: *const f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_softmax_kl2_loss_bwd2_f32(dim: usize, batch_sz: usize, y: *const f32, t: *const f32, df: *const f32, df2: *const f32, dx2: *mut f32);
+  pub fn arraydiff_kernel_softmax_lr_loss_bwd2_f32(dim: usize, batch_sz: usize, y: *const f32, index: *const u32, t: *const f32, df: *const f32, df2: *const f32, dx2: *mut f32, lr_clip: f32);
+
+  // Group normalization functions.
+  pub fn arraydiff_kernel_group_norm_fwd_f32(inner_dim: usize, chan_dim: usize, num_groups: usize, outer_dim: usize, epsilon: f64, x: *const f32, scale: *const f32, shift: *const f32, y: *mut f32);
+  pub fn arraydiff_kernel_group_norm_param_bwd_f32(inner_dim: usize, chan_dim: usize, num_groups: usize, outer_dim: usize, epsilon: f64, x: *const f32, dy: *const f32, dscale: *mut f32, dshift: *mut f32);
+  pub fn arraydiff_kernel_group_norm_input_bwd_f32(inner_dim: usize, chan_dim: usize, num_groups: usize, outer_dim: usize, epsilon: f64, x: *const f32, scale: *const f32, dy: *const f32, dx: *mut f32);
+  pub fn arraydiff_kernel_group_norm_rfwd_f32(inner_dim: usize, chan_dim: usize, num_groups: usize, outer_dim: usize, epsilon: f64, x: *const f32, scale: *const f32, r_x: *const f32, r_scale: *const f32, r_shift: *const f32, r_y: *mut f32);
+
+  // Loss functions.
+  pub fn arraydiff_kernel_lst_sq_fwd_f32(dim: usize, batch_sz: usize, x: *const f32, target: *const f32, loss: *mut f32, do_clip: u32);
+  pub fn arraydiff_kernel_lst_sq_bwd_f32(dim: usize, batch_sz: usize, x: *const f32, target: *const f32, df: *const f32, dx: *mut f32, do_clip: u32);
+  pub fn arraydiff_kernel_lst_sq_rfwd_f32(dim: usize, batch_sz: usize, x: *const f32, target: *const f32, r_x: *const f32, r_target: *const f32, r_loss: *mut f32, do_clip: u32);
+  pub fn arraydiff_kernel_lst_sq_gauss_newton_bwd_f32(dim: usize, batch_sz: usize, x: *const f32, target: *const f32, r_x: *const f32, df: *const f32, dx: *mut f32, do_clip: u32);
+  pub fn arraydiff_kernel_lst_sq_empirical_fisher_bwd_f32(dim: usize, batch_sz: usize, x: *const f32, target: *const f32, r_x: *const f32, df: *const f32, dx: *mut f32, do_clip: u32);
+  pub fn arraydiff_kernel_lst_sq_bwd2_f32(dim: usize, batch_sz: usize, x: *const f32, target: *const f32, df: *const f32, df2: *const f32, dx2: *mut f32, do_clip: u32);
 }
 
 #[cfg(feature = "cuda")]
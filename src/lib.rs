@@ -434,20 +434,36 @@ pub trait AOp {
   fn _copy_val(&self, _dst_txn: TxnId, _dst_vars: &mut VarSet, _src_txn: TxnId, _src_vars: &mut VarSet, offset: usize, _src: &AOp) -> usize { offset }
   fn _load_val(&self, _txn: TxnId, _vars: &mut VarSet, offset: usize, _reader: &mut Any) -> usize { offset }
   fn _load_grad(&self, _txn: TxnId, _vars: &mut VarSet, offset: usize, _reader: &mut Any) -> usize { offset }
-  //fn _load_r_val(&self, _txn: TxnId, _vars: &mut VarSet, offset: usize, _reader: &mut Any) -> usize { offset }
+  fn _load_r_val(&self, _txn: TxnId, _vars: &mut VarSet, offset: usize, _reader: &mut Any) -> usize { offset }
   fn _store_val(&self, _txn: TxnId, _vars: &mut VarSet, offset: usize, _writer: &mut Any) -> usize { offset }
   fn _store_grad(&self, _txn: TxnId, _vars: &mut VarSet, offset: usize, _writer: &mut Any) -> usize { offset }
+  fn _store_r_val(&self, _txn: TxnId, _vars: &mut VarSet, offset: usize, _writer: &mut Any) -> usize { offset }
   //fn _store_r_grad(&self, _txn: TxnId, _vars: &mut VarSet, offset: usize, _writer: &mut Any) -> usize { offset }
-  //fn _store_grad2(&self, _txn: TxnId, _vars: &mut VarSet, offset: usize, _writer: &mut Any) -> usize { offset }
+  fn _store_grad2(&self, _txn: TxnId, _vars: &mut VarSet, offset: usize, _writer: &mut Any) -> usize { offset }
   fn _persist(&self, _txn: TxnId, _vars: &mut VarSet) {}
 
   fn _init(&self, _txn: TxnId, _seed_rng: Rc<RefCell<ChaChaRng>>) {}
   fn _forward(&self, txn: TxnId);
   fn _backward(&self, _txn: TxnId) { unimplemented!(); }
   fn _backward_store_grad(&self, _txn: TxnId, _vars: &mut VarSet, _offset: usize, _writer: &mut Any) -> usize { unimplemented!(); }
-  /*fn _r_forward(&self, _txn: TxnId) { unimplemented!(); }
-  fn _r_backward(&self, _txn: TxnId) { unimplemented!(); }
-  fn _backward2(&self, _txn: TxnId) { unimplemented!(); }*/
+  /// Pearlmutter R-operator: propagates the directional derivative
+  /// `r_val` of the inputs to `r_val` of the output. Requires `_forward` to
+  /// have already run in the same txn.
+  fn _r_forward(&self, _txn: TxnId) { unimplemented!(); }
+  /// Whether the op implements `_r_forward` (see `supports_r_eval`).
+  fn _has_r_forward(&self) -> bool { false }
+  /// Backward pass of the generalized Gauss-Newton product: a loss
+  /// backpropagates `H r_x`, for the Hessian `H` of the loss w.r.t. its
+  /// input, in place of its gradient. Other ops backpropagate as usual.
+  fn _backward_gauss_newton(&self, txn: TxnId) { self._backward(txn); }
+  /// Backward pass of the empirical Fisher product: a loss backpropagates
+  /// `g <g, r_x>` for the gradient `g` of each example's loss.
+  fn _backward_empirical_fisher(&self, txn: TxnId) { self._backward(txn); }
+  /// Becker-LeCun backward pass: propagates the diagonal `grad2` of the
+  /// Hessian of the output to the inputs, dropping the off-diagonal terms.
+  /// Requires `_backward` to have already run in the same txn.
+  fn _backward2(&self, _txn: TxnId) { unimplemented!(); }
+  //fn _r_backward(&self, _txn: TxnId) { unimplemented!(); }
 
   /*fn _reset_clock(&self) {}
   fn _set_clock(&self, _clk: usize) { unimplemented!(); }*/
@@ -533,17 +549,17 @@ pub trait AOp {
     offset
   }
 
-  /*fn load_r_val(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, reader: &mut SerialIoBuf) -> usize {
+  fn load_r_val(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, reader: &mut Any) -> usize {
     let epoch = Epoch::new(self._id());
     vars.unmask_all();
-    reader.reset();
+    //reader.reset();
     self._push(epoch, &mut |_op| {});
     self._pop(epoch, &mut |op| {
-      offset = op._load_r_val(txn, vars, offset, reader.as_any());
+      offset = op._load_r_val(txn, vars, offset, reader);
     });
     vars.unmask_all();
     offset
-  }*/
+  }
 
   fn store_val(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, writer: &mut Any) -> usize {
     let epoch = Epoch::new(self._id());
@@ -569,6 +585,18 @@ pub trait AOp {
     offset
   }
 
+  fn store_r_val(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, writer: &mut Any) -> usize {
+    let epoch = Epoch::new(self._id());
+    vars.unmask_all();
+    //writer.reset();
+    self._push(epoch, &mut |_op| {});
+    self._pop(epoch, &mut |op| {
+      offset = op._store_r_val(txn, vars, offset, writer);
+    });
+    vars.unmask_all();
+    offset
+  }
+
   /*fn store_r_grad(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, writer: &mut SerialIoBuf) -> usize {
     let epoch = Epoch::new(self._id());
     vars.unmask_all();
@@ -581,17 +609,17 @@ pub trait AOp {
     offset
   }*/
 
-  /*fn store_grad2(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, writer: &mut SerialIoBuf) -> usize {
+  fn store_grad2(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, writer: &mut Any) -> usize {
     let epoch = Epoch::new(self._id());
     vars.unmask_all();
-    writer.reset();
+    //writer.reset();
     self._push(epoch, &mut |_op| {});
     self._pop(epoch, &mut |op| {
-      offset = op._store_grad2(txn, vars, offset, writer.as_any());
+      offset = op._store_grad2(txn, vars, offset, writer);
     });
     vars.unmask_all();
     offset
-  }*/
+  }
 
   fn init(&self, txn: TxnId, seed_rng: Rc<RefCell<ChaChaRng>>) {
     let epoch = Epoch::new(self._id());
@@ -625,6 +653,22 @@ pub trait AOp {
   fn eval(&self, txn: TxnId) {
     self._traverse_fwd(&mut |op| { op._forward(txn); });
   }
+
+  /// Evaluates the graph together with its Jacobian-vector product: the
+  /// direction is read from the `r_val` loaded into the sources (see
+  /// `load_r_val`), and sources without a loaded direction count as zero.
+  fn r_eval(&self, txn: TxnId) {
+    assert!(self.supports_r_eval(), "r_eval: some op in the graph has no forward-mode R-operator");
+    self._traverse_fwd(&mut |op| { op._forward(txn); op._r_forward(txn); });
+  }
+
+  /// Whether every op in the graph implements the R-operator, so that
+  /// `r_eval` can run.
+  fn supports_r_eval(&self) -> bool {
+    let mut supported = true;
+    self._traverse_fwd(&mut |op| { supported &= op._has_r_forward(); });
+    supported
+  }
 }
 
 pub trait GradientSinkExt {
@@ -635,8 +679,29 @@ pub trait HessianSinkExt {
   fn eval_hessian_vector_product(&self, txn: TxnId);
 }
 
+/// Curvature-vector products for Hessian-free and natural gradient methods.
+/// The vector is read from the `r_val` loaded into the sources (see
+/// `load_r_val`), and the product is accumulated into their `grad`.
 pub trait GaussNewtonSinkExt {
   fn eval_gauss_newton_vector_product(&self, txn: TxnId);
+
+  /// For the softmax NLL/KL2 and least squares losses, the Fisher matrix of
+  /// the predictive distribution coincides with the Gauss-Newton matrix.
+  fn eval_fisher_vector_product(&self, txn: TxnId) {
+    self.eval_gauss_newton_vector_product(txn);
+  }
+
+  fn eval_empirical_fisher_vector_product(&self, txn: TxnId);
+}
+
+/// Becker-LeCun approximation of the Hessian diagonal: a second backward pass
+/// that keeps only the diagonal terms of the chain rule. It costs about one
+/// extra gradient, but is biased; `HutchinsonEstimator` is an unbiased
+/// alternative built on Hessian-vector products. The diagonal is accumulated
+/// into the `grad2` of the sources (see `store_grad2`), and the gradient into
+/// their `grad`.
+pub trait HessianDiagSinkExt {
+  fn eval_hessian_diagonal(&self, txn: TxnId);
 }
 
 //pub trait AutodiffSink<Op>: Deref<Target=Op> where Op: AOp {
@@ -718,16 +783,20 @@ impl<Op> AOp for Op where Op: AutodiffSink {
     self._op()._backward(txn);
   }
 
-  /*default fn _r_forward(&self, txn: TxnId) {
+  default fn _r_forward(&self, txn: TxnId) {
     self._op()._r_forward(txn);
   }
 
-  default fn _r_backward(&self, txn: TxnId) {
-    self._op()._r_backward(txn);
+  default fn _has_r_forward(&self) -> bool {
+    self._op()._has_r_forward()
   }
 
   default fn _backward2(&self, txn: TxnId) {
     self._op()._backward2(txn);
+  }
+
+  /*default fn _r_backward(&self, txn: TxnId) {
+    self._op()._r_backward(txn);
   }*/
 }
 
@@ -777,6 +846,93 @@ pub trait AVar<Out>: AOp where Out: AVarOutput {
 
   fn _make_tangent(&self) -> Rc<AVar<Out>> { unimplemented!(); }
   fn tangent(&self) -> Rc<AVar<Out>> { unimplemented!(); }
+
+  /// Adds the adjoints of the inputs of this var to `adjs`, built as new
+  /// vars from its own adjoint `adj_` (see `ops::gradient_graph`).
+  fn _adjoint(&self, _adj_: Rc<AVar<Out>>, _adjs: &mut AdjointMap) { unimplemented!(); }
+}
+
+/// The arrays whose adjoints can be accumulated in an `AdjointMap`. The
+/// contributions are summed by an op, so the impl lives with the ops.
+pub trait AdjointArray: 'static + Sized {
+  fn sum_adjoints(x_: &Rc<AVar<AData<Self>>>, adjs_: Vec<Rc<AVar<AData<Self>>>>) -> Rc<AVar<AData<Self>>>;
+}
+
+trait AdjointNode {
+  fn _as_any(&self) -> &Any;
+  fn _as_any_mut(&mut self) -> &mut Any;
+  fn _propagate(&mut self, adjs: &mut AdjointMap);
+}
+
+struct AdjointEntry<A> {
+  x_:       Rc<AVar<AData<A>>>,
+  adjs_:    Vec<Rc<AVar<AData<A>>>>,
+  sum_:     Option<Rc<AVar<AData<A>>>>,
+}
+
+impl<A> AdjointNode for AdjointEntry<A> where A: AdjointArray {
+  fn _as_any(&self) -> &Any {
+    self
+  }
+
+  fn _as_any_mut(&mut self) -> &mut Any {
+    self
+  }
+
+  fn _propagate(&mut self, adjs: &mut AdjointMap) {
+    assert!(self.sum_.is_none());
+    let sum_ = if self.adjs_.len() == 1 {
+      self.adjs_[0].clone()
+    } else {
+      A::sum_adjoints(&self.x_, self.adjs_.clone())
+    };
+    self.sum_ = Some(sum_.clone());
+    self.x_._adjoint(sum_, adjs);
+  }
+}
+
+/// The adjoints of the vars of a graph, i.e. the backward pass built out of
+/// vars (see `ops::gradient_graph`). The contributions to the adjoint of a var
+/// are summed once all of its consumers have been visited.
+pub struct AdjointMap {
+  entries:  FnvHashMap<NodeId, Box<AdjointNode>>,
+}
+
+impl AdjointMap {
+  pub fn new() -> Self {
+    AdjointMap{entries: FnvHashMap::default()}
+  }
+
+  /// Adds `adj_` to the adjoint of `x_`.
+  pub fn accumulate<A>(&mut self, x_: Rc<AVar<AData<A>>>, adj_: Rc<AVar<AData<A>>>) where A: AdjointArray {
+    let node = x_._id();
+    if !self.entries.contains_key(&node) {
+      let entry: Box<AdjointNode> = Box::new(AdjointEntry{
+        x_:       x_,
+        adjs_:    vec![],
+        sum_:     None,
+      });
+      self.entries.insert(node, entry);
+    }
+    let entry = self.entries.get_mut(&node).unwrap()._as_any_mut().downcast_mut::<AdjointEntry<A>>().unwrap();
+    assert!(entry.sum_.is_none(), "adjoint of a var was accumulated after it was visited");
+    entry.adjs_.push(adj_);
+  }
+
+  pub fn _propagate(&mut self, node: NodeId) {
+    if let Some(mut entry) = self.entries.remove(&node) {
+      entry._propagate(self);
+      self.entries.insert(node, entry);
+    }
+  }
+
+  /// The adjoint of `x_`, or `None` if the graph does not depend on it.
+  pub fn adjoint<Op, A>(&self, x_: &Rc<Op>) -> Option<Rc<AVar<AData<A>>>> where Op: AVar<AData<A>>, A: 'static {
+    self.entries.get(&x_._id()).map(|entry| {
+      let entry = entry._as_any().downcast_ref::<AdjointEntry<A>>().unwrap();
+      entry.sum_.as_ref().unwrap().clone()
+    })
+  }
 }
 
 /*impl<Op> AVar<()> for Op where Op: AVar<()> {
@@ -887,6 +1043,14 @@ impl ArrayStorage<usize> for Vec<f32> {
   }
 }
 
+impl ArrayStorage<(usize, usize)> for Vec<f32> {
+  fn alloc(dim: (usize, usize)) -> Self {
+    let mut buf = Vec::with_capacity(dim.0 * dim.1);
+    buf.resize(dim.0 * dim.1, 0.0);
+    buf
+  }
+}
+
 pub trait BatchArrayStorage<Idx> {
   fn alloc(dim: Idx, batch_sz: usize) -> Self where Self: Sized;
 }
@@ -1365,6 +1529,8 @@ pub struct AData<A> {
   pub alloc:    Rc<Fn(TxnId, NodeId) -> A>,
   pub val:      TxnVar<A>,
   pub grad:     TxnVar<A>,
+  pub r_val:    TxnVar<A>,
+  pub grad2:    TxnVar<A>,
 }
 
 impl<A> Clone for AData<A> {
@@ -1376,6 +1542,8 @@ impl<A> Clone for AData<A> {
       alloc:    self.alloc.clone(),
       val:      self.val.dup(new_symbol),
       grad:     self.grad.dup(new_symbol),
+      r_val:    self.r_val.dup(new_symbol),
+      grad2:    self.grad2.dup(new_symbol),
     }
   }
 }
@@ -1385,11 +1553,15 @@ impl<A> AVarOutput for AData<A> {
     VarSet::empty()
       .add(self.val.var())
       .add(self.grad.var())
+      .add(self.r_val.var())
+      .add(self.grad2.var())
   }
 
   fn rollover_all(&self, txn: TxnId, vars: &mut VarSet) {
     self.val.rollover(txn, vars);
     self.grad.rollover(txn, vars);
+    self.r_val.rollover(txn, vars);
+    self.grad2.rollover(txn, vars);
   }
 }
 
@@ -1404,6 +1576,8 @@ impl<A> AData<A> {
       alloc:    alloc.clone(),
       val:      TxnVar::new(symbol, Val,  clock.clone(), alloc.clone()),
       grad:     TxnVar::new(symbol, Grad, clock.clone(), alloc.clone()),
+      r_val:    TxnVar::new(symbol, RVal, clock.clone(), alloc.clone()),
+      grad2:    TxnVar::new(symbol, Grad2, clock.clone(), alloc.clone()),
     }
   }
 
@@ -1414,6 +1588,8 @@ impl<A> AData<A> {
       alloc:    self.alloc.clone(),
       val:      self.val.dup(self.symbol),
       grad:     self.grad.dup(self.symbol),
+      r_val:    self.r_val.dup(self.symbol),
+      grad2:    self.grad2.dup(self.symbol),
     }
   }
 }
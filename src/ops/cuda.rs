@@ -1296,31 +1296,125 @@ impl AOp for BranchOp<Rc<CopyConstant<bool>>, Rc<AVar<AData<DeviceArray1d<f32>>>
   }
 }
 
+fn _device_batch_array1d_map_alloc(x: AData<DeviceBatchArray1d<f32>>) -> Rc<impl Fn(TxnId, NodeId) -> DeviceBatchArray1d<f32>> {
+  Rc::new(move |txn, node| {
+    let batch_cap = x.val.get(txn, node).batch_capacity();
+    let x_dim = x.val.get(txn, node).dim();
+    DeviceBatchArray1d::zeros(x_dim, batch_cap, DeviceStream::implicit().conn())
+  })
+}
+
 impl SpecialMapExt<DeviceBatchArray1d<f32>> for Rc<AVar<AData<DeviceBatchArray1d<f32>>>> {
   fn rect(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, RectMapKernel>> {
     //let clk_horizon = self.data().horizon();
-    MapOp::new(RectMapKernel, self.clone(), /*clk_horizon,*/ {
-      let x = self.data();
-      Rc::new(move |txn, node| {
-        let batch_cap = x.val.get(txn, node).batch_capacity();
-        let x_dim = x.val.get(txn, node).dim();
-        DeviceBatchArray1d::zeros(x_dim, batch_cap, DeviceStream::implicit().conn())
-      })
-    })
+    MapOp::new(RectMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn exp(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, ExpMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(ExpMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn log(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, LogMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(LogMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn sqrt(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, SqrtMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(SqrtMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn softplus(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, SoftplusMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(SoftplusMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn leaky_rect(&self, c: f32) -> Rc<MapOp<DeviceBatchArray1d<f32>, LeakRectMapKernel<f32>>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(LeakRectMapKernel{c: c}, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn elu(&self, alpha: f32) -> Rc<MapOp<DeviceBatchArray1d<f32>, EluMapKernel<f32>>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(EluMapKernel{c: alpha}, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn gelu(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, GeluMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(GeluMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn swish(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, SwishMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(SwishMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn logistic(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, LogisticMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(LogisticMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn tanh(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, TanhMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(TanhMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
   }
 }
 
 impl<Op> SpecialMapExt</*f32,*/ DeviceBatchArray1d<f32>> for Rc<Op> where Op: 'static + AVar<AData<DeviceBatchArray1d<f32>>> {
   fn rect(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, RectMapKernel>> {
     //let clk_horizon = self.data().horizon();
-    MapOp::new(RectMapKernel, self.clone(), /*clk_horizon,*/ {
-      let x = self.data();
-      Rc::new(move |txn, node| {
-        let batch_cap = x.val.get(txn, node).batch_capacity();
-        let x_dim = x.val.get(txn, node).dim();
-        DeviceBatchArray1d::zeros(x_dim, batch_cap, DeviceStream::implicit().conn())
-      })
-    })
+    MapOp::new(RectMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn exp(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, ExpMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(ExpMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn log(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, LogMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(LogMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn sqrt(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, SqrtMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(SqrtMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn softplus(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, SoftplusMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(SoftplusMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn leaky_rect(&self, c: f32) -> Rc<MapOp<DeviceBatchArray1d<f32>, LeakRectMapKernel<f32>>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(LeakRectMapKernel{c: c}, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn elu(&self, alpha: f32) -> Rc<MapOp<DeviceBatchArray1d<f32>, EluMapKernel<f32>>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(EluMapKernel{c: alpha}, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn gelu(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, GeluMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(GeluMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn swish(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, SwishMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(SwishMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn logistic(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, LogisticMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(LogisticMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
+  }
+
+  fn tanh(&self) -> Rc<MapOp<DeviceBatchArray1d<f32>, TanhMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(TanhMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array1d_map_alloc(self.data()))
   }
 }
 
@@ -1473,31 +1567,125 @@ impl AOp for MapOp<DeviceBatchArray1d<f32>, RectMapKernel> {
   }*/
 }
 
+fn _device_batch_array3d_map_alloc(x: AData<DeviceBatchArray3d<f32>>) -> Rc<impl Fn(TxnId, NodeId) -> DeviceBatchArray3d<f32>> {
+  Rc::new(move |txn, node| {
+    let batch_cap = x.val.get(txn, node).batch_capacity();
+    let x_dim = x.val.get(txn, node).dim();
+    DeviceBatchArray3d::zeros(x_dim, batch_cap, DeviceStream::implicit().conn())
+  })
+}
+
 impl SpecialMapExt<DeviceBatchArray3d<f32>> for Rc<AVar<AData<DeviceBatchArray3d<f32>>>> {
   fn rect(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, RectMapKernel>> {
     //let clk_horizon = self.data().horizon();
-    MapOp::new(RectMapKernel, self.clone(), /*clk_horizon,*/ {
-      let x = self.data();
-      Rc::new(move |txn, node| {
-        let batch_cap = x.val.get(txn, node).batch_capacity();
-        let x_dim = x.val.get(txn, node).dim();
-        DeviceBatchArray3d::zeros(x_dim, batch_cap, DeviceStream::implicit().conn())
-      })
-    })
+    MapOp::new(RectMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn exp(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, ExpMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(ExpMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn log(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, LogMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(LogMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn sqrt(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, SqrtMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(SqrtMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn softplus(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, SoftplusMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(SoftplusMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn leaky_rect(&self, c: f32) -> Rc<MapOp<DeviceBatchArray3d<f32>, LeakRectMapKernel<f32>>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(LeakRectMapKernel{c: c}, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn elu(&self, alpha: f32) -> Rc<MapOp<DeviceBatchArray3d<f32>, EluMapKernel<f32>>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(EluMapKernel{c: alpha}, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn gelu(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, GeluMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(GeluMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn swish(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, SwishMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(SwishMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn logistic(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, LogisticMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(LogisticMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn tanh(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, TanhMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(TanhMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
   }
 }
 
 impl<Op> SpecialMapExt<DeviceBatchArray3d<f32>> for Rc<Op> where Op: 'static + AVar<AData<DeviceBatchArray3d<f32>>> {
   fn rect(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, RectMapKernel>> {
     //let clk_horizon = self.data().horizon();
-    MapOp::new(RectMapKernel, self.clone(), /*clk_horizon,*/ {
-      let x = self.data();
-      Rc::new(move |txn, node| {
-        let batch_cap = x.val.get(txn, node).batch_capacity();
-        let x_dim = x.val.get(txn, node).dim();
-        DeviceBatchArray3d::zeros(x_dim, batch_cap, DeviceStream::implicit().conn())
-      })
-    })
+    MapOp::new(RectMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn exp(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, ExpMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(ExpMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn log(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, LogMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(LogMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn sqrt(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, SqrtMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(SqrtMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn softplus(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, SoftplusMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(SoftplusMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn leaky_rect(&self, c: f32) -> Rc<MapOp<DeviceBatchArray3d<f32>, LeakRectMapKernel<f32>>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(LeakRectMapKernel{c: c}, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn elu(&self, alpha: f32) -> Rc<MapOp<DeviceBatchArray3d<f32>, EluMapKernel<f32>>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(EluMapKernel{c: alpha}, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn gelu(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, GeluMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(GeluMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn swish(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, SwishMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(SwishMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn logistic(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, LogisticMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(LogisticMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
+  }
+
+  fn tanh(&self) -> Rc<MapOp<DeviceBatchArray3d<f32>, TanhMapKernel>> {
+    //let clk_horizon = self.data().horizon();
+    MapOp::new(TanhMapKernel, self.clone(), /*clk_horizon,*/ _device_batch_array3d_map_alloc(self.data()))
   }
 }
 
@@ -2522,6 +2710,265 @@ impl AOp for JoinOp<DeviceBatchArray3d<f32>, SumJoinKernel> {
   }
 }
 
+impl AOp for GradScaleOp<DeviceMem<f32>> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      self.y.val.get_excl(txn, node).as_mut()
+        .copy(self.x.val.get(txn, node).as_ref(), DeviceStream::implicit().conn());
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.x.grad.accumulate(txn, node, |grad| grad.as_mut().set_constant(0.0, DeviceStream::implicit().conn())) {
+      if self.scale != 0.0 {
+        let len = self.y.grad.get(txn, node).len();
+        self.x.grad.get_mut(txn, node).as_mut().reshape_mut(len)
+          .add(self.scale, self.y.grad.get(txn, node).as_ref().reshape(len), DeviceStream::implicit().conn());
+      }
+    }
+  }
+}
+
+impl AOp for GradScaleOp<DeviceArray1d<f32>> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      self.y.val.get_excl(txn, node).as_view_mut()
+        .copy(self.x.val.get(txn, node).as_view(), DeviceStream::implicit().conn());
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0, DeviceStream::implicit().conn())) {
+      if self.scale != 0.0 {
+        self.x.grad.get_mut(txn, node).as_view_mut()
+          .add(self.scale, self.y.grad.get(txn, node).as_view(), DeviceStream::implicit().conn());
+      }
+    }
+  }
+}
+
+impl AOp for GradScaleOp<DeviceArray2d<f32>> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      self.y.val.get_excl(txn, node).as_view_mut()
+        .copy(self.x.val.get(txn, node).as_view(), DeviceStream::implicit().conn());
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0, DeviceStream::implicit().conn())) {
+      if self.scale != 0.0 {
+        self.x.grad.get_mut(txn, node).as_view_mut().flatten_mut()
+          .add(self.scale, self.y.grad.get(txn, node).as_view().flatten(), DeviceStream::implicit().conn());
+      }
+    }
+  }
+}
+
+impl AOp for GradScaleOp<DeviceIoBatch<f32>> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      self.y.val.get_excl(txn, node).set_batch_size(batch_sz).as_mut()
+        .copy(self.x.val.get(txn, node).as_ref(), DeviceStream::implicit().conn());
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| grad.set_batch_size(batch_sz).as_mut().set_constant(0.0, DeviceStream::implicit().conn())) {
+      if self.scale != 0.0 {
+        self.x.grad.get_mut(txn, node).as_mut().flatten_mut()
+          .add(self.scale, self.y.grad.get(txn, node).as_ref().flatten(), DeviceStream::implicit().conn());
+      }
+    }
+  }
+}
+
+impl AOp for GradScaleOp<DeviceBatchArray1d<f32>> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      self.y.val.get_excl(txn, node).set_batch_size(batch_sz).as_view_mut()
+        .copy(self.x.val.get(txn, node).as_view(), DeviceStream::implicit().conn());
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| grad.set_batch_size(batch_sz).as_view_mut().set_constant(0.0, DeviceStream::implicit().conn())) {
+      if self.scale != 0.0 {
+        self.x.grad.get_mut(txn, node).as_view_mut().flatten_mut()
+          .add(self.scale, self.y.grad.get(txn, node).as_view().flatten(), DeviceStream::implicit().conn());
+      }
+    }
+  }
+}
+
+impl AOp for GradScaleOp<DeviceBatchArray3d<f32>> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      self.y.val.get_excl(txn, node).set_batch_size(batch_sz).as_view_mut()
+        .copy(self.x.val.get(txn, node).as_view(), DeviceStream::implicit().conn());
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| grad.set_batch_size(batch_sz).as_view_mut().set_constant(0.0, DeviceStream::implicit().conn())) {
+      if self.scale != 0.0 {
+        self.x.grad.get_mut(txn, node).as_view_mut().flatten_mut()
+          .add(self.scale, self.y.grad.get(txn, node).as_view().flatten(), DeviceStream::implicit().conn());
+      }
+    }
+  }
+}
+
 impl<Op> SymmClipExt<DeviceBatchArray1d<f32>, DeviceMem<f32>> for Rc<Op> where Op: 'static + AVar<AData<DeviceBatchArray1d<f32>>> {
   fn symm_unit_clip(&self, c_: Rc<AVar<AData<DeviceMem<f32>>>>) -> Rc<ClipOp<DeviceBatchArray1d<f32>, DeviceMem<f32>, SymmUnitClipKernel>> {
     let x = self.data();
@@ -2700,6 +3147,7 @@ impl AOp for ClipOp<DeviceBatchArray3d<f32>, DeviceMem<f32>, SymmUnitClipKernel>
 
 impl BroadcastAddExt<DeviceBatchArray1d<f32>, DeviceBatchArray3d<f32>> for Rc<AVar<AData<DeviceBatchArray1d<f32>>>> {
   fn broadcast_add(&self, axes: Vec<usize>, x_: Rc<AVar<AData<DeviceBatchArray3d<f32>>>>) -> Rc<BroadcastAddOp<DeviceBatchArray1d<f32>, DeviceBatchArray3d<f32>>> {
+    _check_bcast_axes(&axes, &[0, 1]);
     //let clk_horizon = x_.data().horizon();
     BroadcastAddOp::new(axes, self.clone(), x_.clone(), /*clk_horizon,*/ {
       let a = self.data();
@@ -2718,6 +3166,7 @@ impl BroadcastAddExt<DeviceBatchArray1d<f32>, DeviceBatchArray3d<f32>> for Rc<AV
 
 impl<Op> BroadcastAddExt<DeviceBatchArray1d<f32>, DeviceBatchArray3d<f32>> for Rc<Op> where Op: 'static + AVar<AData<DeviceBatchArray1d<f32>>> {
   fn broadcast_add(&self, axes: Vec<usize>, x_: Rc<AVar<AData<DeviceBatchArray3d<f32>>>>) -> Rc<BroadcastAddOp<DeviceBatchArray1d<f32>, DeviceBatchArray3d<f32>>> {
+    _check_bcast_axes(&axes, &[0, 1]);
     //let clk_horizon = x_.data().horizon();
     BroadcastAddOp::new(axes, self.clone(), x_.clone(), /*clk_horizon,*/ {
       let a = self.data();
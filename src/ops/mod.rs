@@ -18,6 +18,7 @@ use prelude::*;
 use ffi::*;
 
 use densearray::prelude::*;
+use fnv::{FnvHashMap};
 use rng::xorshift::*;
 
 use rand::{Rng, SeedableRng};
@@ -28,8 +29,10 @@ use rand::distributions::range::{Range};
 use std::any::{Any, /*TypeId*/};
 use std::cell::{Cell, RefCell};
 use std::cmp::{max};
+use std::f32;
 use std::marker::{PhantomData};
 use std::ops::{Deref, DerefMut};
+use std::ptr::{null, null_mut};
 use std::rc::{Rc, Weak};
 
 #[cfg(feature = "cuda")] pub mod cuda;
@@ -46,6 +49,9 @@ impl IoBuf for Array1d<f32> {
     let buf_len = dst.dim();
     if reader.downcast_mut::<NullIo>().is_some() {
       offset += buf_len;
+    } else if reader.downcast_mut::<ZeroIo>().is_some() {
+      dst.as_view_mut().set_constant(0.0);
+      offset += buf_len;
     } else if reader.downcast_mut::<Vec<f32>>().is_some() {
       let reader = reader.downcast_mut::<Vec<f32>>().unwrap();
       dst.as_view_mut().copy(reader[offset .. offset + buf_len].flatten());
@@ -76,6 +82,9 @@ impl IoBuf for Array2d<f32> {
     let buf_len = dst.dim().flat_len();
     if reader.downcast_mut::<NullIo>().is_some() {
       offset += buf_len;
+    } else if reader.downcast_mut::<ZeroIo>().is_some() {
+      dst.as_view_mut().flatten_mut().set_constant(0.0);
+      offset += buf_len;
     } else if reader.downcast_mut::<Vec<f32>>().is_some() {
       let reader = reader.downcast_mut::<Vec<f32>>().unwrap();
       dst.as_view_mut().flatten_mut().copy(reader[offset .. offset + buf_len].flatten());
@@ -106,6 +115,9 @@ impl IoBuf for Array4d<f32> {
     let buf_len = dst.dim().flat_len();
     if reader.downcast_mut::<NullIo>().is_some() {
       offset += buf_len;
+    } else if reader.downcast_mut::<ZeroIo>().is_some() {
+      dst.as_view_mut().flatten_mut().set_constant(0.0);
+      offset += buf_len;
     } else if reader.downcast_mut::<Vec<f32>>().is_some() {
       let reader = reader.downcast_mut::<Vec<f32>>().unwrap();
       dst.as_view_mut().flatten_mut().copy(reader[offset .. offset + buf_len].flatten());
@@ -184,6 +196,10 @@ impl<A> AVar<AData<A>> for SrcOp<A> where A: 'static, SrcOp<A>: AOp {
     }
     self.tng.borrow().as_ref().unwrap().clone()
   }
+
+  default fn _adjoint(&self, _adj_: Rc<AVar<AData<A>>>, _adjs: &mut AdjointMap) {
+    // Sources are the leaves of the adjoint graph.
+  }
 }
 
 impl AOp for SrcOp<f32> {
@@ -264,6 +280,16 @@ impl AOp for SrcOp<f32> {
   fn _backward(&self, _txn: TxnId) {
   }
 
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  fn _backward2(&self, _txn: TxnId) {
+  }
+
   /*fn _reset_clock(&self) {
     if self.clock {
       self.data.reset_clock_all();
@@ -354,6 +380,16 @@ impl AOp for SrcOp<Batch<u32>> {
   fn _backward(&self, _txn: TxnId) {
   }
 
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  fn _backward2(&self, _txn: TxnId) {
+  }
+
   /*fn _reset_clock(&self) {
     if self.clock {
       self.data.reset_clock_all();
@@ -422,6 +458,16 @@ impl AOp for SrcOp<Array1d<f32>> {
   fn _backward(&self, _txn: TxnId) {
   }
 
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  fn _backward2(&self, _txn: TxnId) {
+  }
+
   /*fn _reset_clock(&self) {
     if self.clock {
       self.data.reset_clock_all();
@@ -490,6 +536,16 @@ impl AOp for SrcOp<Array2d<f32>> {
   fn _backward(&self, _txn: TxnId) {
   }
 
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  fn _backward2(&self, _txn: TxnId) {
+  }
+
   /*fn _reset_clock(&self) {
     if self.clock {
       self.data.reset_clock_all();
@@ -564,6 +620,16 @@ impl AOp for SrcOp<Array4d<f32>> {
   fn _backward(&self, _txn: TxnId) {
   }
 
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  fn _backward2(&self, _txn: TxnId) {
+  }
+
   /*fn _reset_clock(&self) {
     if self.clock {
       self.data.reset_clock_all();
@@ -649,6 +715,16 @@ impl<A> AOp for NoOp<A> where A: 'static {
 
   default fn _backward(&self, _txn: TxnId) {
   }
+
+  default fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  default fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  default fn _backward2(&self, _txn: TxnId) {
+  }
 }
 
 pub fn io<A, In>(x_: In) -> Rc<IoOp<A>> where In: IoExt<A> {
@@ -711,6 +787,16 @@ impl<A> AVar<AData<A>> for IoOp<A> where A: 'static, Self: AOp {
   }
 }
 
+impl<A> AVar<AData<A>> for IoOp<A> where A: 'static + PackedArray, Self: AOp {
+  fn _owned_data(&self) -> &AData<A> {
+    &self.data
+  }
+
+  fn _adjoint(&self, adj_: Rc<AVar<AData<A>>>, adjs: &mut AdjointMap) {
+    adjs.accumulate(self.x_.clone(), adj_);
+  }
+}
+
 impl<A> AOp for IoOp<A> where A: 'static + IoBuf {
   default fn _load_val(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, reader: &mut Any) -> usize {
     let node = self._id();
@@ -749,6 +835,47 @@ impl<A> AOp for IoOp<A> where A: 'static + IoBuf {
     }
     offset
   }
+
+  default fn _load_r_val(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, reader: &mut Any) -> usize {
+    let node = self._id();
+    if vars.mask(self.data.r_val.var()) {
+      assert!(self.data.r_val.overwrite(txn, node));
+      let mut r_val = self.data.r_val.get_excl(txn, node);
+      offset = IoBuf::load(&mut *r_val, offset, reader);
+    }
+    offset
+  }
+
+  default fn _store_r_val(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, writer: &mut Any) -> usize {
+    let node = self._id();
+    if vars.mask(self.data.r_val.var()) {
+      let r_val = self.data.r_val.get(txn, node);
+      offset = IoBuf::store(&*r_val, offset, writer);
+    }
+    offset
+  }
+
+  default fn _store_grad2(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, writer: &mut Any) -> usize {
+    let node = self._id();
+    if vars.mask(self.data.grad2.var()) {
+      let grad2 = self.data.grad2.get(txn, node);
+      offset = IoBuf::store(&*grad2, offset, writer);
+    }
+    offset
+  }
+
+  default fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  default fn _r_forward(&self, txn: TxnId) {
+    // A source without a loaded direction is held constant.
+    let node = self._id();
+    if self.data.r_val.overwrite(txn, node) {
+      let mut r_val = self.data.r_val.get_excl(txn, node);
+      IoBuf::load(&mut *r_val, 0, &mut ZeroIo);
+    }
+  }
 }
 
 impl<A> AOp for IoOp<A> where A: 'static {
@@ -779,6 +906,16 @@ impl<A> AOp for IoOp<A> where A: 'static {
 
   default fn _backward(&self, _txn: TxnId) {
   }
+
+  default fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  default fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  default fn _backward2(&self, _txn: TxnId) {
+  }
 }
 
 pub fn unpack2<A1, A2>(x_: Rc<AVar<(A1, A2)>>) -> (Rc<Unpack2Out1Op<A1, A2>>, Rc<Unpack2Out2Op<A1, A2>>) where A1: AVarOutput, A2: AVarOutput {
@@ -854,6 +991,16 @@ impl<A1, A2> AOp for Unpack2Out1Op<A1, A2> where A1: AVarOutput, A2: AVarOutput
 
   default fn _backward(&self, _txn: TxnId) {
   }
+
+  default fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  default fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  default fn _backward2(&self, _txn: TxnId) {
+  }
 }
 
 pub struct Unpack2Out2Op<A1, A2> {
@@ -924,6 +1071,16 @@ impl<A1, A2> AOp for Unpack2Out2Op<A1, A2> where A1: AVarOutput, A2: AVarOutput
 
   default fn _backward(&self, _txn: TxnId) {
   }
+
+  default fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  default fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  default fn _backward2(&self, _txn: TxnId) {
+  }
 }
 
 //pub fn pass<A, Op>(x_: Rc<Op>) -> Rc<PassOp<A>> where Op: 'static + AVar<AData<A>> {
@@ -940,6 +1097,7 @@ pub struct PassOp<Pre, A> {
   x_:       RefCell<Option<Rc<AVar<Pre>>>>,
   data:     AData<A>,
   tng:      RefCell<Option<Rc<AVar<AData<A>>>>>,
+  adj:      RefCell<Option<Rc<Fn(Rc<AVar<AData<A>>>, &mut AdjointMap)>>>,
 }
 
 //impl<A> PassOp<A> {
@@ -953,6 +1111,7 @@ impl<Pre, A> PassOp<Pre, A> {
       x_:       RefCell::new(x_),
       data:     data,
       tng:      RefCell::new(None),
+      adj:      RefCell::new(None),
     })
   }
 }
@@ -964,10 +1123,26 @@ impl<Pre, A> AVar<AData<A>> for PassOp<Pre, A> where Pre: 'static + AVarOutput,
   }
 
   default fn _make_tangent(&self) -> Rc<AVar<AData<A>>> {
-    let tng_op = PassOp::new(self.x_.borrow().as_ref().map(|op| op.tangent()), self.data.clone());
+    let pre_tng_ = self.x_.borrow().as_ref().map(|op| op.tangent());
+    // Ops with several outputs (e.g. `SoftmaxLoss`) may have installed the
+    // tangent of this op while making their own.
+    if let Some(tng_op) = self.tng.borrow_mut().take() {
+      return tng_op;
+    }
+    let tng_op = PassOp::new(pre_tng_, self.data.clone());
     tng_op
   }
 
+  default fn _adjoint(&self, adj_: Rc<AVar<AData<A>>>, adjs: &mut AdjointMap) {
+    // Like the tangent, the adjoint of an output of an op with several
+    // outputs is installed by that op.
+    let adj = self.adj.borrow().clone();
+    match adj {
+      None      => unimplemented!(),
+      Some(adj) => (adj)(adj_, adjs),
+    }
+  }
+
   default fn tangent(&self) -> Rc<AVar<AData<A>>> {
     if self.tng.borrow().is_none() {
       *self.tng.borrow_mut() = Some(self._make_tangent());
@@ -1007,6 +1182,16 @@ impl<Pre, A> AOp for PassOp<Pre, A> where Pre: 'static + AVarOutput, A: 'static
 
   default fn _backward(&self, _txn: TxnId) {
   }
+
+  default fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  default fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  default fn _backward2(&self, _txn: TxnId) {
+  }
 }
 
 pub fn no_pass<A, Op>(x_: Rc<Op>) -> Rc<NoPassOp<A>> where Op: 'static + AVar<AData<A>> {
@@ -1067,6 +1252,233 @@ impl<A> AOp for NoPassOp<A> {
 
   default fn _backward(&self, _txn: TxnId) {
   }
+
+  default fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  default fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  default fn _backward2(&self, _txn: TxnId) {
+  }
+}
+
+pub trait GradScaleExt<A> {
+  fn scale_gradient(&self, c: f32) -> Rc<GradScaleOp<A>>;
+
+  fn stop_gradient(&self) -> Rc<GradScaleOp<A>> {
+    self.scale_gradient(0.0)
+  }
+
+  /// The gradient reversal layer used for domain adaptation.
+  fn reverse_gradient(&self) -> Rc<GradScaleOp<A>> {
+    self.scale_gradient(-1.0)
+  }
+}
+
+pub fn stop_gradient<Op, A>(x_: Rc<Op>) -> Rc<GradScaleOp<A>> where Rc<Op>: GradScaleExt<A> {
+  x_.stop_gradient()
+}
+
+pub fn scale_gradient<Op, A>(x_: Rc<Op>, c: f32) -> Rc<GradScaleOp<A>> where Rc<Op>: GradScaleExt<A> {
+  x_.scale_gradient(c)
+}
+
+pub fn reverse_gradient<Op, A>(x_: Rc<Op>) -> Rc<GradScaleOp<A>> where Rc<Op>: GradScaleExt<A> {
+  x_.reverse_gradient()
+}
+
+impl<Op, A> GradScaleExt<A> for Rc<Op> where Op: 'static + AVar<AData<A>>, A: 'static {
+  fn scale_gradient(&self, c: f32) -> Rc<GradScaleOp<A>> {
+    let alloc = self.data().alloc.clone();
+    GradScaleOp::new(self.clone(), c, alloc)
+  }
+}
+
+/// Forwards the value of `x_` unchanged, but scales the gradient flowing back
+/// into `x_` by a constant. Unlike `PassOp` and `NoPassOp`, which alias the
+/// data of `x_`, this op owns its output, so its gradient is kept apart from
+/// that of `x_`.
+///
+/// A zero scale still accumulates a zero gradient into `x_`, so the ops
+/// upstream backpropagate as usual. In the adjoint graph, however, a zero
+/// scale adds no adjoint to `x_` at all (see `gradient_graph`).
+pub struct GradScaleOp<A> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  x_:       Rc<AVar<AData<A>>>,
+  x:        AData<A>,
+  y:        AData<A>,
+  scale:    f32,
+  val_scale:    f32,
+  tng_:     RefCell<Option<Rc<AVar<AData<A>>>>>,
+}
+
+impl<A> GradScaleOp<A> {
+  pub fn new<Op>(x_: Rc<Op>, scale: f32, alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<Self> where Op: 'static + AVar<AData<A>> {
+    Self::_new(x_, 1.0, scale, alloc)
+  }
+
+  /// Multiplies the value by `val_scale` as well. The adjoint of a
+  /// `GradScaleOp` is one of these, with both scales equal.
+  fn _new(x_: Rc<AVar<AData<A>>>, val_scale: f32, scale: f32, alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<Self> {
+    let node = NodeId::new();
+    let x = x_.data();
+    Rc::new(GradScaleOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 1),
+      x_:       x_,
+      x:        x,
+      y:        AData::new(alloc),
+      scale:    scale,
+      val_scale:    val_scale,
+      tng_:     RefCell::new(None),
+    })
+  }
+}
+
+impl<A> AVar<AData<A>> for GradScaleOp<A> where GradScaleOp<A>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+
+  default fn tangent(&self) -> Rc<AVar<AData<A>>> {
+    if self.tng_.borrow().is_none() {
+      *self.tng_.borrow_mut() = Some(self._make_tangent());
+    }
+    self.tng_.borrow().as_ref().unwrap().clone()
+  }
+}
+
+impl<A> AVar<AData<A>> for GradScaleOp<A> where A: 'static + PackedArray, GradScaleOp<A>: AOp {
+  fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+
+  fn _make_tangent(&self) -> Rc<AVar<AData<A>>> {
+    // The tangent of `x_` is passed through with the same scales, so the
+    // Hessian-vector product sees the scaled gradient too.
+    GradScaleOp::_new(self.x_.tangent(), self.val_scale, self.scale, _shared_alloc(&self.y))
+  }
+
+  fn _adjoint(&self, adj_: Rc<AVar<AData<A>>>, adjs: &mut AdjointMap) {
+    if self.scale != 0.0 {
+      let x_adj_: Rc<AVar<AData<A>>> = GradScaleOp::_new(adj_, self.scale, self.scale, _shared_alloc(&self.x));
+      adjs.accumulate(self.x_.clone(), x_adj_);
+    }
+  }
+
+  fn tangent(&self) -> Rc<AVar<AData<A>>> {
+    if self.tng_.borrow().is_none() {
+      *self.tng_.borrow_mut() = Some(self._make_tangent());
+    }
+    self.tng_.borrow().as_ref().unwrap().clone()
+  }
+}
+
+/// Writes `alpha x` into `y`, which must already have the batch size of `x`.
+fn _packed_scale_copy<A>(alpha: f32, x: &A, y: &mut A) where A: PackedArray {
+  let len = x._packed_dim().iter().product();
+  if alpha == 1.0 {
+    unsafe { arraydiff_kernel_copy_f32(
+        len,
+        x._as_ptr(),
+        y._as_mut_ptr(),
+    ) };
+  } else {
+    y._set_zero();
+    unsafe { arraydiff_kernel_scale_add_f32(
+        len,
+        alpha,
+        x._as_ptr(),
+        y._as_mut_ptr(),
+    ) };
+  }
+}
+
+impl<A> AOp for GradScaleOp<A> where A: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let x_val = self.x.val.get(txn, node);
+      let mut y_val = self.y.val.get_excl(txn, node);
+      y_val._set_batch_size(x_val._batch_size());
+      _packed_scale_copy(self.val_scale, &*x_val, &mut *y_val);
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      if self.scale != 0.0 {
+        let y_grad = self.y.grad.get(txn, node);
+        let mut x_grad = self.x.grad.get_mut(txn, node);
+        let len = y_grad._packed_dim().iter().product();
+        unsafe { arraydiff_kernel_scale_add_f32(
+            len,
+            self.scale,
+            y_grad._as_ptr(),
+            x_grad._as_mut_ptr(),
+        ) };
+      }
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      let x_r_val = self.x.r_val.get(txn, node);
+      let mut y_r_val = self.y.r_val.get_excl(txn, node);
+      y_r_val._set_batch_size(x_r_val._batch_size());
+      _packed_scale_copy(self.val_scale, &*x_r_val, &mut *y_r_val);
+    }
+  }
+
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.x.grad2.accumulate(txn, node, |grad2| { grad2._set_batch_size(batch_sz); grad2._set_zero(); }) {
+      if self.scale != 0.0 {
+        let y_grad2 = self.y.grad2.get(txn, node);
+        let mut x_grad2 = self.x.grad2.get_mut(txn, node);
+        let len = y_grad2._packed_dim().iter().product();
+        unsafe { arraydiff_kernel_scale_add_f32(
+            len,
+            self.scale * self.scale,
+            y_grad2._as_ptr(),
+            x_grad2._as_mut_ptr(),
+        ) };
+      }
+    }
+  }
 }
 
 /*pub struct IoOp<A> {
@@ -1298,6 +1710,16 @@ impl AOp for InitializeOp<f32, Rc<Fn(TxnId, NodeId, Rc<RefCell<ChaChaRng>>, ADat
 
   fn _backward(&self, _txn: TxnId) {
   }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  fn _backward2(&self, _txn: TxnId) {
+  }
 }
 
 //impl<S, F> AOp for InitializeOp<Array1d<f32, S>, Rc<F>> where S: DerefMut<Target=[f32]>, F: Fn(Rc<RefCell<ChaChaRng>>, &mut Array1d<f32, S>) {
@@ -1338,6 +1760,16 @@ impl<S> AOp for InitializeOp<Array1d<f32, S>, Rc<Fn(TxnId, NodeId, Rc<RefCell<Ch
 
   fn _backward(&self, _txn: TxnId) {
   }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, _txn: TxnId) {
+  }
+
+  fn _backward2(&self, _txn: TxnId) {
+  }
 }
 
 pub struct BranchOp<Cond, Off, On, Data> {
@@ -1385,686 +1817,650 @@ impl<Cond, On, Off, A> AVar<AData<A>> for BranchOp<Cond, On, Off, AData<A>> wher
   }
 }
 
-pub struct ExpMapKernel;
-pub struct RectMapKernel;
-pub struct LeakRectMapKernel<T>{c: T}
-pub struct LogisticMapKernel;
-pub struct TanhMapKernel;
+impl<A> AOp for BranchOp<Rc<CopyConstant<bool>>, Rc<AVar<AData<A>>>, Rc<AVar<AData<A>>>, AData<A>> where A: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
 
-pub trait SpecialMapKernel {}
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.off_._push(epoch, apply);
+      self.on_._push(epoch, apply);
+      apply(self);
+    }
+  }
 
-impl SpecialMapKernel for ExpMapKernel {}
-impl SpecialMapKernel for RectMapKernel {}
-impl<T> SpecialMapKernel for LeakRectMapKernel<T> {}
-impl SpecialMapKernel for TanhMapKernel {}
-impl SpecialMapKernel for LogisticMapKernel {}
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.on_._pop(epoch, apply);
+      self.off_._pop(epoch, apply);
+    }
+  }
 
-pub trait SpecialMapExt</*T,*/ A> {
-  //fn exp(&self) -> Rc<MapOp<A, ExpMapKernel>>;
-  fn rect(&self) -> Rc<MapOp<A, RectMapKernel>>;
-  //fn leak_rect(&self, c: T) -> Rc<MapOp<A, LeakRectMapKernel<T>>>;
-  //fn logistic(&self) -> Rc<MapOp<A, LogisticMapKernel>>;
-  //fn tanh(&self) -> Rc<MapOp<A, TanhMapKernel>>;
-}
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.output.rollover_all(txn, vars);
+  }
 
-impl<Op, S> SpecialMapExt</*f32,*/ Array1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
-  fn rect(&self) -> Rc<MapOp<Array1d<f32, S>, RectMapKernel>> {
-    //let clk_horizon = self.data().horizon();
-    MapOp::new(RectMapKernel, self.clone(), /*clk_horizon,*/ {
-      let x = self.clone().data();
-      Rc::new(move |txn, node| {
-        let dim = x.val.get(txn, node).dim();
-        let buf = <S as ArrayStorage<usize>>::alloc(dim);
-        Array1d::from_storage(dim, buf)
-      })
-    })
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.output.val.overwrite(txn, node) {
+      match self.cond.var.get(txn) {
+        false => _packed_copy_fwd(&self.off, &self.output, txn, node),
+        true  => _packed_copy_fwd(&self.on, &self.output, txn, node),
+      }
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let (active, inactive) = match self.cond.var.get(txn) {
+      false => (&self.off, &self.on),
+      true  => (&self.on, &self.off),
+    };
+    _packed_copy_bwd(active, &self.output, txn, node);
+    let batch_sz = inactive.val.get(txn, node)._batch_size();
+    if inactive.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      // Do nothing.
+    }
   }
 }
 
-pub struct MapOp<A, MapF> {
+pub struct SwitchOp<Cond, A> {
   node_id:  NodeId,
   stack:    OperatorStack,
-  x_:   Rc<AVar<AData<A>>>,
-  x:    AData<A>,
-  y:    AData<A>,
-  kernel:   MapF,
-  tng_: RefCell<Option<Rc<AVar<AData<A>>>>>,
+  cond:     Cond,
+  xs_:      Vec<Rc<AVar<AData<A>>>>,
+  xs:       Vec<AData<A>>,
+  output:   AData<A>,
 }
 
-impl<A, MapF> MapOp<A, MapF> {
-  pub fn new<F>(kernel: MapF, x_: Rc<AVar<AData<A>>>, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<Self> where F: 'static + Fn(TxnId, NodeId) -> A {
+impl<Cond, A> SwitchOp<Cond, A> {
+  pub fn new(cond: Cond, xs_: Vec<Rc<AVar<AData<A>>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<Self> {
     let node = NodeId::new();
-    let x = x_.data();
-    Rc::new(MapOp{
+    let in_degree = xs_.len();
+    let mut xs = Vec::with_capacity(in_degree);
+    for x_ in xs_.iter() {
+      xs.push(x_.data());
+    }
+    Rc::new(SwitchOp{
       node_id:  node,
-      stack:    OperatorStack::new(node, 1),
-      x_:   x_,
-      x:    x,
-      y:    AData::new(/*clk_horizon,*/ alloc),
-      kernel:   kernel,
-      tng_: RefCell::new(None),
+      stack:    OperatorStack::new(node, in_degree),
+      cond:     cond,
+      xs_:      xs_,
+      xs:       xs,
+      output:   AData::new(/*clk_horizon,*/ alloc),
     })
   }
 }
 
-impl<A, MapF> AVar<AData<A>> for MapOp<A, MapF> where MapOp<A, MapF>: AOp {
+impl<Cond, A> AVar<AData<A>> for SwitchOp<Cond, A> where SwitchOp<Cond, A>: AOp {
   default fn _owned_data(&self) -> &AData<A> {
-    &self.y
+    &self.output
   }
 }
 
-impl<S, MapF> AOp for MapOp<Array1d<f32, S>, MapF> where S: DerefMut<Target=[f32]>, MapF: SpecialMapKernel {
-  default fn _id(&self) -> NodeId {
+pub trait SwitchExt<A> {
+  fn switch(cond: Rc<CopyConstant<usize>>, xs_: Vec<Rc<AVar<AData<A>>>>) -> Rc<SwitchOp<Rc<CopyConstant<usize>>, A>>;
+}
+
+/// Selects the input whose index is the value of `cond` in each txn.
+pub fn switch<A>(cond: Rc<CopyConstant<usize>>, xs_: Vec<Rc<AVar<AData<A>>>>) -> Rc<SwitchOp<Rc<CopyConstant<usize>>, A>> where Rc<SwitchOp<Rc<CopyConstant<usize>>, A>>: SwitchExt<A> {
+  <Rc<SwitchOp<Rc<CopyConstant<usize>>, A>> as SwitchExt<A>>::switch(cond, xs_)
+}
+
+impl<A> SwitchExt<A> for Rc<SwitchOp<Rc<CopyConstant<usize>>, A>> where A: 'static + PackedArray {
+  fn switch(cond: Rc<CopyConstant<usize>>, xs_: Vec<Rc<AVar<AData<A>>>>) -> Rc<SwitchOp<Rc<CopyConstant<usize>>, A>> {
+    assert!(!xs_.is_empty());
+    // The inputs share a shape, so the output takes the first one's buffers.
+    let x = xs_[0].data();
+    //let clk_horizon = x.horizon();
+    SwitchOp::new(cond, xs_, /*clk_horizon,*/ _shared_alloc(&x))
+  }
+}
+
+impl<A> AOp for SwitchOp<Rc<CopyConstant<usize>>, A> where A: PackedArray {
+  fn _id(&self) -> NodeId {
     self.node_id
   }
 
-  default fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if 1 == self.stack.push(epoch) {
-      self.x_._push(epoch, apply);
+      for x_ in self.xs_.iter() {
+        x_._push(epoch, apply);
+      }
       apply(self);
     }
   }
 
-  default fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if self.stack.degree(epoch) == self.stack.pop(epoch) {
       apply(self);
-      self.x_._pop(epoch, apply);
+      for x_ in self.xs_.iter().rev() {
+        x_._pop(epoch, apply);
+      }
     }
   }
 
-  default fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
-    self.y.rollover_all(txn, vars);
-  }
-
-  default fn _forward(&self, txn: TxnId) {
-    unimplemented!();
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.output.rollover_all(txn, vars);
   }
-}
 
-impl<S> AOp for MapOp<Array1d<f32, S>, RectMapKernel> where S: DerefMut<Target=[f32]> {
   fn _forward(&self, txn: TxnId) {
     let node = self._id();
-    if self.y.val.overwrite(txn, node) {
-      let x_dim = self.x.val.get(txn, node).dim();
-      unsafe { arraydiff_kernel_rect_fwd_f32(
-          x_dim,
-          self.x.val.get(txn, node).as_view().as_ptr(),
-          self.y.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
-      ) };
+    if self.output.val.overwrite(txn, node) {
+      let active = self.cond.var.get(txn);
+      assert!(active < self.xs.len());
+      _packed_copy_fwd(&self.xs[active], &self.output, txn, node);
     }
   }
 
   fn _backward(&self, txn: TxnId) {
     let node = self._id();
-    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-      let y_dim = self.y.grad.get(txn, node).dim();
-      unsafe { arraydiff_kernel_rect_bwd_f32(
-          y_dim,
-          self.x.val.get(txn, node).as_view().as_ptr(),
-          self.y.grad.get(txn, node).as_view().as_ptr(),
-          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
-      ) };
+    let active = self.cond.var.get(txn);
+    assert!(active < self.xs.len());
+    for (idx, x) in self.xs.iter().enumerate() {
+      if idx == active {
+        _packed_copy_bwd(x, &self.output, txn, node);
+      } else {
+        let batch_sz = x.val.get(txn, node)._batch_size();
+        if x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+          // Do nothing.
+        }
+      }
     }
   }
+}
 
-  /*fn _r_forward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.y.r_val.overwrite(txn, node) {
-      let x_dim = self.x.r_val.get(txn, node).dim();
-      unsafe { arraydiff_kernel_rect_bwd_f32(
-          x_dim,
-          self.x.val.get(txn, node).as_view().as_ptr(),
-          self.x.r_val.get(txn, node).as_view().as_ptr(),
-          self.y.r_val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
-      ) };
-    }
-  }
+#[derive(Clone, Copy)]
+pub struct ExpMapKernel;
 
-  fn _r_backward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.x.r_grad.accumulate(txn, node, |r_grad| r_grad.as_view_mut().set_constant(0.0)) {
-      let y_dim = self.y.r_grad.get(txn, node).dim();
-      unsafe { arraydiff_kernel_rect_bwd_f32(
-          y_dim,
-          self.x.val.get(txn, node).as_view().as_ptr(),
-          self.y.r_grad.get(txn, node).as_view().as_ptr(),
-          self.x.r_grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
-      ) };
-    }
-  }
+#[derive(Clone, Copy)]
+pub struct LogMapKernel;
 
-  fn _backward2(&self, txn: TxnId) {
-    let node = self._id();
-    if self.x.grad2.accumulate(txn, node, |grad2| grad2.as_view_mut().set_constant(0.0)) {
-      let y_dim = self.y.grad2.get(txn, node).dim();
-      unsafe { arraydiff_kernel_rect_bwd_f32(
-          y_dim,
-          self.x.val.get(txn, node).as_view().as_ptr(),
-          self.y.grad2.get(txn, node).as_view().as_ptr(),
-          self.x.grad2.get_mut(txn, node).as_view_mut().as_mut_ptr(),
-      ) };
-    }
-  }*/
+#[derive(Clone, Copy)]
+pub struct SqrtMapKernel;
+
+#[derive(Clone, Copy)]
+pub struct SoftplusMapKernel;
+
+#[derive(Clone, Copy)]
+pub struct RectMapKernel;
+
+#[derive(Clone, Copy)]
+pub struct LeakRectMapKernel<T>{c: T}
+
+#[derive(Clone, Copy)]
+pub struct EluMapKernel<T>{c: T}
+
+#[derive(Clone, Copy)]
+pub struct GeluMapKernel;
+
+#[derive(Clone, Copy)]
+pub struct SwishMapKernel;
+
+#[derive(Clone, Copy)]
+pub struct LogisticMapKernel;
+
+#[derive(Clone, Copy)]
+pub struct TanhMapKernel;
+
+pub trait SpecialMapKernel {
+  unsafe fn _fwd_f32(&self, dim: usize, x: *const f32, y: *mut f32);
+  unsafe fn _bwd_f32(&self, dim: usize, x: *const f32, dy: *const f32, dx: *mut f32);
+  /// Accumulates `f''(x) x_tng dy` into `dx`, i.e. the backward of the
+  /// tangent `f'(x) x_tng` with respect to `x`.
+  unsafe fn _tangent_bwd_f32(&self, dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32);
+  /// Accumulates `f'(x)^2 dy2 + f''(x) dy` into `dx2`, the Becker-LeCun
+  /// backward of the Hessian diagonal `dy2`.
+  unsafe fn _bwd2_f32(&self, dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32);
 }
 
-impl<S> AOp for MapOp<Array1d<f32, S>, LogisticMapKernel> where S: DerefMut<Target=[f32]> {
-  fn _forward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.y.val.overwrite(txn, node) {
-      let x_dim = self.x.val.get(txn, node).dim();
-      unsafe { arraydiff_kernel_logistic_fwd_f32(
-          x_dim,
-          self.x.val.get(txn, node).as_view().as_ptr(),
-          self.y.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
-      ) };
-    }
+impl SpecialMapKernel for ExpMapKernel {
+  unsafe fn _fwd_f32(&self, dim: usize, x: *const f32, y: *mut f32) {
+    arraydiff_kernel_exp_fwd_f32(dim, x, y);
   }
 
-  fn _backward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-      let y_dim = self.y.grad.get(txn, node).dim();
-      unsafe { arraydiff_kernel_logistic_bwd_f32(
-          y_dim,
-          self.x.val.get(txn, node).as_view().as_ptr(),
-          self.y.grad.get(txn, node).as_view().as_ptr(),
-          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
-      ) };
-    }
+  unsafe fn _bwd_f32(&self, dim: usize, x: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_exp_bwd_f32(dim, x, dy, dx);
   }
-}
 
-impl<S> AOp for MapOp<Array1d<f32, S>, TanhMapKernel> where S: DerefMut<Target=[f32]> {
-  fn _forward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.y.val.overwrite(txn, node) {
-      let x_dim = self.x.val.get(txn, node).dim();
-      unsafe { arraydiff_kernel_tanh_fwd_f32(
-          x_dim,
-          self.x.val.get(txn, node).as_view().as_ptr(),
-          self.y.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
-      ) };
-    }
+  unsafe fn _tangent_bwd_f32(&self, dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_exp_tangent_bwd_f32(dim, x, x_tng, dy, dx);
   }
 
-  fn _backward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-      let y_dim = self.y.grad.get(txn, node).dim();
-      unsafe { arraydiff_kernel_tanh_bwd_f32(
-          y_dim,
-          self.x.val.get(txn, node).as_view().as_ptr(),
-          self.y.grad.get(txn, node).as_view().as_ptr(),
-          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
-      ) };
-    }
+  unsafe fn _bwd2_f32(&self, dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32) {
+    arraydiff_kernel_exp_bwd2_f32(dim, x, dy, dy2, dx2);
   }
 }
 
-pub struct TransformOp<A, B, Transform> {
-  node_id:  NodeId,
-  stack:    OperatorStack,
-  x_:   Rc<AVar<AData<A>>>,
-  x:    AData<A>,
-  y:    AData<B>,
-  kernel:   Transform,
-  tng_: RefCell<Option<Rc<AVar<AData<B>>>>>,
-}
+impl SpecialMapKernel for LogMapKernel {
+  unsafe fn _fwd_f32(&self, dim: usize, x: *const f32, y: *mut f32) {
+    arraydiff_kernel_log_fwd_f32(dim, x, y);
+  }
 
-pub struct CastTransform;
-pub struct FlattenTransform;
-pub struct ReshapeTransform<Idx> {
-  pub dim:  Idx,
-}
+  unsafe fn _bwd_f32(&self, dim: usize, x: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_log_bwd_f32(dim, x, dy, dx);
+  }
 
-pub struct ZeroPadTransform {
-  pub axis: usize,
-  pub dim:  usize,
-}
+  unsafe fn _tangent_bwd_f32(&self, dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_log_tangent_bwd_f32(dim, x, x_tng, dy, dx);
+  }
 
-pub trait CastExt<A, B> {
-  fn cast(&self) -> Rc<TransformOp<A, B, CastTransform>>;
+  unsafe fn _bwd2_f32(&self, dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32) {
+    arraydiff_kernel_log_bwd2_f32(dim, x, dy, dy2, dx2);
+  }
 }
 
-pub trait FlattenExt<A, B> {
-  fn flatten(&self) -> Rc<TransformOp<A, B, FlattenTransform>>;
-}
+impl SpecialMapKernel for SqrtMapKernel {
+  unsafe fn _fwd_f32(&self, dim: usize, x: *const f32, y: *mut f32) {
+    arraydiff_kernel_sqrt_fwd_f32(dim, x, y);
+  }
 
-pub trait ReshapeExt<Idx, A, B> {
-  fn reshape(&self, dim: Idx) -> Rc<TransformOp<A, B, ReshapeTransform<Idx>>>;
+  unsafe fn _bwd_f32(&self, dim: usize, x: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_sqrt_bwd_f32(dim, x, dy, dx);
+  }
 
-  fn reify(&self, dim: Idx) -> Rc<TransformOp<A, B, ReshapeTransform<Idx>>> {
-    self.reshape(dim)
+  unsafe fn _tangent_bwd_f32(&self, dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_sqrt_tangent_bwd_f32(dim, x, x_tng, dy, dx);
   }
-}
 
-pub trait ZeroPadExt<A, B> {
-  fn zero_pad(&self, axis: usize, dim: usize) -> Rc<TransformOp<A, B, ZeroPadTransform>>;
+  unsafe fn _bwd2_f32(&self, dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32) {
+    arraydiff_kernel_sqrt_bwd2_f32(dim, x, dy, dy2, dx2);
+  }
 }
 
-impl<A, B, Transform> TransformOp<A, B, Transform> {
-  pub fn new<F>(x_: Rc<AVar<AData<A>>>, transform: Transform, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<Self> where F: 'static + Fn(TxnId, NodeId) -> B {
-    let node = NodeId::new();
-    let x = x_.data();
-    Rc::new(TransformOp{
-      node_id:  node,
-      stack:    OperatorStack::new(node, 1),
-      x_:   x_,
-      x:    x,
-      y:    AData::new(/*clk_horizon,*/ alloc),
-      kernel:   transform,
-      tng_: RefCell::new(None),
-    })
+impl SpecialMapKernel for SoftplusMapKernel {
+  unsafe fn _fwd_f32(&self, dim: usize, x: *const f32, y: *mut f32) {
+    arraydiff_kernel_softplus_fwd_f32(dim, x, y);
   }
-}
 
-impl<A, B, Transform> AVar<AData<B>> for TransformOp<A, B, Transform> where TransformOp<A, B, Transform>: AOp {
-  default fn _owned_data(&self) -> &AData<B> {
-    &self.y
+  unsafe fn _bwd_f32(&self, dim: usize, x: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_softplus_bwd_f32(dim, x, dy, dx);
   }
-}
 
-impl<S> FlattenExt<Array3d<f32, S>, Array1d<f32, S>> for Rc<AVar<AData<Array3d<f32, S>>>> where S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
-  fn flatten(&self) -> Rc<TransformOp<Array3d<f32, S>, Array1d<f32, S>, FlattenTransform>> {
-    //let clk_horizon = self.data().horizon();
-    TransformOp::new(self.clone(), FlattenTransform, /*clk_horizon,*/ {
-      let x = self.clone().data();
-      Rc::new(move |txn, node| {
-        let dim = x.val.get(txn, node).dim();
-        let buf = <S as ArrayStorage<usize>>::alloc(dim.flat_len());
-        Array1d::from_storage(dim.flat_len(), buf)
-      })
-    })
+  unsafe fn _tangent_bwd_f32(&self, dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_softplus_tangent_bwd_f32(dim, x, x_tng, dy, dx);
+  }
+
+  unsafe fn _bwd2_f32(&self, dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32) {
+    arraydiff_kernel_softplus_bwd2_f32(dim, x, dy, dy2, dx2);
   }
 }
 
-impl<S> AOp for TransformOp<Array3d<f32, S>, Array1d<f32, S>, FlattenTransform> where S: DerefMut<Target=[f32]> {
-  fn _id(&self) -> NodeId {
-    self.node_id
+impl SpecialMapKernel for RectMapKernel {
+  unsafe fn _fwd_f32(&self, dim: usize, x: *const f32, y: *mut f32) {
+    arraydiff_kernel_rect_fwd_f32(dim, x, y);
   }
 
-  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
-    if 1 == self.stack.push(epoch) {
-      self.x_._push(epoch, apply);
-      apply(self);
-    }
+  unsafe fn _bwd_f32(&self, dim: usize, x: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_rect_bwd_f32(dim, x, dy, dx);
   }
 
-  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
-    if self.stack.degree(epoch) == self.stack.pop(epoch) {
-      apply(self);
-      self.x_._pop(epoch, apply);
-    }
+  unsafe fn _tangent_bwd_f32(&self, _dim: usize, _x: *const f32, _x_tng: *const f32, _dy: *const f32, _dx: *mut f32) {
+    // The second derivative vanishes almost everywhere.
   }
 
-  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
-    self.y.rollover_all(txn, vars);
+  unsafe fn _bwd2_f32(&self, dim: usize, x: *const f32, _dy: *const f32, dy2: *const f32, dx2: *mut f32) {
+    // The curvature term `dy f''(x)` vanishes almost everywhere.
+    arraydiff_kernel_rect_bwd2_f32(dim, x, dy2, dx2);
   }
+}
 
-  fn _forward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.y.val.overwrite(txn, node) {
-      self.y.val.get_excl(txn, node).as_view_mut().copy(self.x.val.get(txn, node).as_view().flatten());
-    }
+impl SpecialMapKernel for LeakRectMapKernel<f32> {
+  unsafe fn _fwd_f32(&self, dim: usize, x: *const f32, y: *mut f32) {
+    arraydiff_kernel_leak_rect_fwd_f32(dim, self.c, x, y);
   }
 
-  fn _backward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-      self.x.grad.get_mut(txn, node).as_view_mut().flatten_mut().add(1.0, self.y.grad.get(txn, node).as_view());
-    }
+  unsafe fn _bwd_f32(&self, dim: usize, x: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_leak_rect_bwd_f32(dim, self.c, x, dy, dx);
   }
 
-  /*fn _r_forward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.y.r_val.overwrite(txn, node) {
-      self.y.r_val.get_excl(txn, node).as_view_mut().copy(self.x.r_val.get(txn, node).as_view().flatten());
-    }
+  unsafe fn _tangent_bwd_f32(&self, _dim: usize, _x: *const f32, _x_tng: *const f32, _dy: *const f32, _dx: *mut f32) {
+    // The second derivative vanishes almost everywhere.
   }
 
-  fn _r_backward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.x.r_grad.accumulate(txn, node, |r_grad| r_grad.as_view_mut().set_constant(0.0)) {
-      self.x.r_grad.get_mut(txn, node).as_view_mut().flatten_mut().add(1.0, self.y.r_grad.get(txn, node).as_view());
-    }
-  }*/
+  unsafe fn _bwd2_f32(&self, dim: usize, x: *const f32, _dy: *const f32, dy2: *const f32, dx2: *mut f32) {
+    // The curvature term `dy f''(x)` vanishes almost everywhere.
+    arraydiff_kernel_leak_rect_bwd2_f32(dim, self.c, x, dy2, dx2);
+  }
 }
 
-pub struct JoinOp<A, JoinF> {
-  node_id:  NodeId,
-  stack:    OperatorStack,
-  xs_:  Vec<Rc<AVar<AData<A>>>>,
-  xs:   Vec<AData<A>>,
-  y:    AData<A>,
-  kernel:   JoinF,
-}
+impl SpecialMapKernel for EluMapKernel<f32> {
+  unsafe fn _fwd_f32(&self, dim: usize, x: *const f32, y: *mut f32) {
+    arraydiff_kernel_elu_fwd_f32(dim, self.c, x, y);
+  }
 
-impl<A, JoinF> JoinOp<A, JoinF> {
-  pub fn new<F>(xs_: Vec<Rc<AVar<AData<A>>>>, kernel: JoinF, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<JoinOp<A, JoinF>> where F: 'static + Fn(TxnId, NodeId) -> A {
-    let node = NodeId::new();
-    let in_degree = xs_.len();
-    let mut xs = Vec::with_capacity(in_degree);
-    for x_ in xs_.iter() {
-      let x = x_.data();
-      xs.push(x);
-    }
-    assert_eq!(in_degree, xs_.len());
-    assert_eq!(in_degree, xs.len());
-    Rc::new(JoinOp{
-      node_id:  node,
-      stack:    OperatorStack::new(node, in_degree),
-      xs_:      xs_,
-      xs:       xs,
-      y:        AData::new(/*clk_horizon,*/ alloc),
-      kernel:   kernel,
-    })
+  unsafe fn _bwd_f32(&self, dim: usize, x: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_elu_bwd_f32(dim, self.c, x, dy, dx);
   }
-}
 
-impl<A, JoinF> AVar<AData<A>> for JoinOp<A, JoinF> where JoinOp<A, JoinF>: AOp {
-  default fn _owned_data(&self) -> &AData<A> {
-    &self.y
+  unsafe fn _tangent_bwd_f32(&self, dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_elu_tangent_bwd_f32(dim, self.c, x, x_tng, dy, dx);
+  }
+
+  unsafe fn _bwd2_f32(&self, dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32) {
+    arraydiff_kernel_elu_bwd2_f32(dim, self.c, x, dy, dy2, dx2);
   }
 }
 
-pub struct AxisJoinKernel;
-pub struct SumJoinKernel;
+impl SpecialMapKernel for GeluMapKernel {
+  unsafe fn _fwd_f32(&self, dim: usize, x: *const f32, y: *mut f32) {
+    arraydiff_kernel_gelu_fwd_f32(dim, x, y);
+  }
 
-pub trait AxisJoinExt<Op, A> where Op: AVar<AData<A>> {
-  fn axis_join(xs: Vec<Rc<Op>>) -> Rc<JoinOp<A, AxisJoinKernel>>;
-}
+  unsafe fn _bwd_f32(&self, dim: usize, x: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_gelu_bwd_f32(dim, x, dy, dx);
+  }
 
-pub fn axis_join<Op, A>(xs: Vec<Rc<Op>>) -> Rc<JoinOp<A, AxisJoinKernel>> where Rc<Op>: AxisJoinExt<Op, A>, Op: AVar<AData<A>> {
-  <Rc<Op> as AxisJoinExt<Op, A>>::axis_join(xs)
-}
+  unsafe fn _tangent_bwd_f32(&self, dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_gelu_tangent_bwd_f32(dim, x, x_tng, dy, dx);
+  }
 
-pub trait AddExt<A> {
-  fn add<RhsOp>(&self, x_: Rc<RhsOp>) -> Rc<JoinOp<A, SumJoinKernel>> where RhsOp: 'static + AVar<AData<A>>;
+  unsafe fn _bwd2_f32(&self, dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32) {
+    arraydiff_kernel_gelu_bwd2_f32(dim, x, dy, dy2, dx2);
+  }
 }
 
-impl<Op, A> AddExt<A> for Rc<Op> where Rc<JoinOp<A, SumJoinKernel>>: SumExt<A>, Op: 'static + AVar<AData<A>> {
-  default fn add<RhsOp>(&self, x_: Rc<RhsOp>) -> Rc<JoinOp<A, SumJoinKernel>> where RhsOp: 'static + AVar<AData<A>> {
-    //<Rc<JoinOp<A, SumJoinKernel>> as SumExt<A>>::sum(vec![ArrayOp::from(self.clone()), ArrayOp::from(x_)])
-    <Rc<JoinOp<A, SumJoinKernel>> as SumExt<A>>::sum(vec![self.clone(), x_])
+impl SpecialMapKernel for SwishMapKernel {
+  unsafe fn _fwd_f32(&self, dim: usize, x: *const f32, y: *mut f32) {
+    arraydiff_kernel_swish_fwd_f32(dim, x, y);
   }
-}
 
-pub trait SumExt<A> {
-  fn sum(xs_: Vec<Rc<AVar<AData<A>>>>) -> Rc<JoinOp<A, SumJoinKernel>>;
-}
+  unsafe fn _bwd_f32(&self, dim: usize, x: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_swish_bwd_f32(dim, x, dy, dx);
+  }
 
-pub fn sum<A>(xs_: Vec<Rc<AVar<AData<A>>>>) -> Rc<JoinOp<A, SumJoinKernel>> where Rc<JoinOp<A, SumJoinKernel>>: SumExt<A> {
-  <Rc<JoinOp<A, SumJoinKernel>> as SumExt<A>>::sum(xs_)
+  unsafe fn _tangent_bwd_f32(&self, dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_swish_tangent_bwd_f32(dim, x, x_tng, dy, dx);
+  }
+
+  unsafe fn _bwd2_f32(&self, dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32) {
+    arraydiff_kernel_swish_bwd2_f32(dim, x, dy, dy2, dx2);
+  }
 }
 
-impl<Op, S> AxisJoinExt<Op, Array1d<f32, S>> for Rc<Op> where Op: AVar<AData<Array1d<f32, S>>>, S: DerefMut<Target=[f32]> {
-  fn axis_join(xs_: Vec<Rc<Op>>) -> Rc<JoinOp<Array1d<f32, S>, AxisJoinKernel>> {
-    unimplemented!();
+impl SpecialMapKernel for LogisticMapKernel {
+  unsafe fn _fwd_f32(&self, dim: usize, x: *const f32, y: *mut f32) {
+    arraydiff_kernel_logistic_fwd_f32(dim, x, y);
+  }
+
+  unsafe fn _bwd_f32(&self, dim: usize, x: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_logistic_bwd_f32(dim, x, dy, dx);
+  }
+
+  unsafe fn _tangent_bwd_f32(&self, dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_logistic_tangent_bwd_f32(dim, x, x_tng, dy, dx);
+  }
+
+  unsafe fn _bwd2_f32(&self, dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32) {
+    arraydiff_kernel_logistic_bwd2_f32(dim, x, dy, dy2, dx2);
   }
 }
 
-/*impl<Op, A> SumExt<A> for Rc<Op> where Op: AVar<AData<A>> {
-  default fn sum(xs_: Vec<Rc<Op>>) -> Rc<JoinOp<A, SumJoinKernel>> {
-    unimplemented!();
+impl SpecialMapKernel for TanhMapKernel {
+  unsafe fn _fwd_f32(&self, dim: usize, x: *const f32, y: *mut f32) {
+    arraydiff_kernel_tanh_fwd_f32(dim, x, y);
   }
 
-  default fn add(&self, x_: Rc<Op>) -> Rc<JoinOp<A, SumJoinKernel>> {
-    Self::sum(vec![self.clone(), x_])
+  unsafe fn _bwd_f32(&self, dim: usize, x: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_tanh_bwd_f32(dim, x, dy, dx);
   }
-}*/
 
-/*impl<Op, S> SumExt<Array1d<f32, S>> for Rc<Op> where Op: AVar<AData<Array1d<f32, S>>>, S: DerefMut<Target=[f32]> {
-  fn sum(xs_: Vec<Rc<Op>>) -> Rc<JoinOp<Array1d<f32, S>, SumJoinKernel>> where S: DerefMut<Target=[f32]> {
-    unimplemented!();
+  unsafe fn _tangent_bwd_f32(&self, dim: usize, x: *const f32, x_tng: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_tanh_tangent_bwd_f32(dim, x, x_tng, dy, dx);
   }
 
-  fn add(&self, x_: Rc<Op>) -> Rc<JoinOp<Array1d<f32, S>, SumJoinKernel>> {
-    Self::sum(vec![self.clone(), x_])
+  unsafe fn _bwd2_f32(&self, dim: usize, x: *const f32, dy: *const f32, dy2: *const f32, dx2: *mut f32) {
+    arraydiff_kernel_tanh_bwd2_f32(dim, x, dy, dy2, dx2);
   }
-}*/
+}
 
-impl<S> AOp for JoinOp<Array1d<f32, S>, SumJoinKernel> where S: DerefMut<Target=[f32]> {
-  fn _id(&self) -> NodeId {
-    self.node_id
+/// Elementwise maps. Only `rect` has device kernels so far; on device
+/// arrays the other maps build ops which are not vars, so using them fails
+/// to compile.
+pub trait SpecialMapExt</*T,*/ A> {
+  fn rect(&self) -> Rc<MapOp<A, RectMapKernel>>;
+  fn exp(&self) -> Rc<MapOp<A, ExpMapKernel>>;
+  fn log(&self) -> Rc<MapOp<A, LogMapKernel>>;
+  fn sqrt(&self) -> Rc<MapOp<A, SqrtMapKernel>>;
+  fn softplus(&self) -> Rc<MapOp<A, SoftplusMapKernel>>;
+  fn leaky_rect(&self, c: f32) -> Rc<MapOp<A, LeakRectMapKernel<f32>>>;
+  fn elu(&self, alpha: f32) -> Rc<MapOp<A, EluMapKernel<f32>>>;
+  fn gelu(&self) -> Rc<MapOp<A, GeluMapKernel>>;
+  fn swish(&self) -> Rc<MapOp<A, SwishMapKernel>>;
+  fn logistic(&self) -> Rc<MapOp<A, LogisticMapKernel>>;
+  fn tanh(&self) -> Rc<MapOp<A, TanhMapKernel>>;
+}
+
+fn _array1d_map_alloc<S>(x: AData<Array1d<f32, S>>) -> Rc<impl Fn(TxnId, NodeId) -> Array1d<f32, S>> where S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
+  Rc::new(move |txn, node| {
+    let dim = x.val.get(txn, node).dim();
+    let buf = <S as ArrayStorage<usize>>::alloc(dim);
+    Array1d::from_storage(dim, buf)
+  })
+}
+
+impl<Op, S> SpecialMapExt</*f32,*/ Array1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
+  fn rect(&self) -> Rc<MapOp<Array1d<f32, S>, RectMapKernel>> {
+    MapOp::new(RectMapKernel, self.clone(), /*clk_horizon,*/ _array1d_map_alloc(self.clone().data()))
   }
 
-  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
-    if 1 == self.stack.push(epoch) {
-      for x_ in self.xs_.iter() {
-        x_._push(epoch, apply);
-      }
-      apply(self);
-    }
+  fn exp(&self) -> Rc<MapOp<Array1d<f32, S>, ExpMapKernel>> {
+    MapOp::new(ExpMapKernel, self.clone(), /*clk_horizon,*/ _array1d_map_alloc(self.clone().data()))
   }
 
-  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
-    if self.stack.degree(epoch) == self.stack.pop(epoch) {
-      apply(self);
-      for x_ in self.xs_.iter().rev() {
-        x_._pop(epoch, apply);
-      }
-    }
+  fn log(&self) -> Rc<MapOp<Array1d<f32, S>, LogMapKernel>> {
+    MapOp::new(LogMapKernel, self.clone(), /*clk_horizon,*/ _array1d_map_alloc(self.clone().data()))
   }
 
-  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
-    self.y.rollover_all(txn, vars);
+  fn sqrt(&self) -> Rc<MapOp<Array1d<f32, S>, SqrtMapKernel>> {
+    MapOp::new(SqrtMapKernel, self.clone(), /*clk_horizon,*/ _array1d_map_alloc(self.clone().data()))
   }
 
-  fn _forward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.y.val.overwrite(txn, node) {
-      self.y.val.get_excl(txn, node).as_view_mut().copy(self.xs[0].val.get(txn, node).as_view());
-      for x in self.xs.iter().skip(1) {
-        self.y.val.get_excl(txn, node).as_view_mut().add(1.0, x.val.get(txn, node).as_view());
-      }
-    }
+  fn softplus(&self) -> Rc<MapOp<Array1d<f32, S>, SoftplusMapKernel>> {
+    MapOp::new(SoftplusMapKernel, self.clone(), /*clk_horizon,*/ _array1d_map_alloc(self.clone().data()))
   }
 
-  fn _backward(&self, txn: TxnId) {
-    let node = self._id();
-    for x in self.xs.iter() {
-      if x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-        x.grad.get_mut(txn, node).as_view_mut().add(1.0, self.y.grad.get(txn, node).as_view());
-      }
-    }
+  fn leaky_rect(&self, c: f32) -> Rc<MapOp<Array1d<f32, S>, LeakRectMapKernel<f32>>> {
+    MapOp::new(LeakRectMapKernel{c: c}, self.clone(), /*clk_horizon,*/ _array1d_map_alloc(self.clone().data()))
   }
 
-  /*fn _r_forward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.y.r_val.overwrite(txn, node) {
-      self.y.r_val.get_excl(txn, node).as_view_mut().copy(self.xs[0].r_val.get(txn, node).as_view());
-      for x in self.xs.iter().skip(1) {
-        self.y.r_val.get_excl(txn, node).as_view_mut().add(1.0, x.r_val.get(txn, node).as_view());
-      }
-    }
+  fn elu(&self, alpha: f32) -> Rc<MapOp<Array1d<f32, S>, EluMapKernel<f32>>> {
+    MapOp::new(EluMapKernel{c: alpha}, self.clone(), /*clk_horizon,*/ _array1d_map_alloc(self.clone().data()))
   }
 
-  fn _r_backward(&self, txn: TxnId) {
-    let node = self._id();
-    for x in self.xs.iter() {
-      if x.r_grad.accumulate(txn, node, |r_grad| r_grad.as_view_mut().set_constant(0.0)) {
-        x.r_grad.get_mut(txn, node).as_view_mut().add(1.0, self.y.r_grad.get(txn, node).as_view());
-      }
-    }
-  }*/
-}
+  fn gelu(&self) -> Rc<MapOp<Array1d<f32, S>, GeluMapKernel>> {
+    MapOp::new(GeluMapKernel, self.clone(), /*clk_horizon,*/ _array1d_map_alloc(self.clone().data()))
+  }
 
-pub struct SplitOp<A, SplitF> {
-  node_id:  NodeId,
-  stack:    OperatorStack,
-  x:    Rc<AVar<AData<A>>>,
-  y:    AData<A>,
-  kernel:   SplitF,
-}
+  fn swish(&self) -> Rc<MapOp<Array1d<f32, S>, SwishMapKernel>> {
+    MapOp::new(SwishMapKernel, self.clone(), /*clk_horizon,*/ _array1d_map_alloc(self.clone().data()))
+  }
 
-pub struct AxisSplitKernel {
-  op_idx:   usize,
-  axis:     usize,
-  offset:   usize,
-  length:   usize,
+  fn logistic(&self) -> Rc<MapOp<Array1d<f32, S>, LogisticMapKernel>> {
+    MapOp::new(LogisticMapKernel, self.clone(), /*clk_horizon,*/ _array1d_map_alloc(self.clone().data()))
+  }
+
+  fn tanh(&self) -> Rc<MapOp<Array1d<f32, S>, TanhMapKernel>> {
+    MapOp::new(TanhMapKernel, self.clone(), /*clk_horizon,*/ _array1d_map_alloc(self.clone().data()))
+  }
 }
 
-pub trait AxisSplitExt<A> {
-  fn axis_split(&self, axis: usize, parts: Vec<usize>) -> Vec<Rc<SplitOp<A, AxisSplitKernel>>>;
+fn _batch_array1d_map_alloc<S>(x: AData<BatchArray1d<f32, S>>) -> Rc<impl Fn(TxnId, NodeId) -> BatchArray1d<f32, S>> where S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  Rc::new(move |txn, node| {
+    let dim = x.val.get(txn, node).dim();
+    let batch_sz = x.val.get(txn, node).batch_size();
+    let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+    BatchArray1d::from_storage(dim, batch_sz, buf)
+  })
 }
 
-pub trait DummyExt<A> {
+impl<Op, S> SpecialMapExt</*f32,*/ BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn rect(&self) -> Rc<MapOp<BatchArray1d<f32, S>, RectMapKernel>> {
+    MapOp::new(RectMapKernel, self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
+
+  fn exp(&self) -> Rc<MapOp<BatchArray1d<f32, S>, ExpMapKernel>> {
+    MapOp::new(ExpMapKernel, self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
+
+  fn log(&self) -> Rc<MapOp<BatchArray1d<f32, S>, LogMapKernel>> {
+    MapOp::new(LogMapKernel, self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
+
+  fn sqrt(&self) -> Rc<MapOp<BatchArray1d<f32, S>, SqrtMapKernel>> {
+    MapOp::new(SqrtMapKernel, self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
+
+  fn softplus(&self) -> Rc<MapOp<BatchArray1d<f32, S>, SoftplusMapKernel>> {
+    MapOp::new(SoftplusMapKernel, self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
+
+  fn leaky_rect(&self, c: f32) -> Rc<MapOp<BatchArray1d<f32, S>, LeakRectMapKernel<f32>>> {
+    MapOp::new(LeakRectMapKernel{c: c}, self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
+
+  fn elu(&self, alpha: f32) -> Rc<MapOp<BatchArray1d<f32, S>, EluMapKernel<f32>>> {
+    MapOp::new(EluMapKernel{c: alpha}, self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
+
+  fn gelu(&self) -> Rc<MapOp<BatchArray1d<f32, S>, GeluMapKernel>> {
+    MapOp::new(GeluMapKernel, self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
+
+  fn swish(&self) -> Rc<MapOp<BatchArray1d<f32, S>, SwishMapKernel>> {
+    MapOp::new(SwishMapKernel, self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
+
+  fn logistic(&self) -> Rc<MapOp<BatchArray1d<f32, S>, LogisticMapKernel>> {
+    MapOp::new(LogisticMapKernel, self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
+
+  fn tanh(&self) -> Rc<MapOp<BatchArray1d<f32, S>, TanhMapKernel>> {
+    MapOp::new(TanhMapKernel, self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
 }
 
-impl<S> DummyExt<Array1d<f32, S>> for Rc<AVar<AData<Array1d<f32, S>>>> where S: DerefMut<Target=[f32]> {
+fn _batch_array3d_map_alloc<S>(x: AData<BatchArray3d<f32, S>>) -> Rc<impl Fn(TxnId, NodeId) -> BatchArray3d<f32, S>> where S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  Rc::new(move |txn, node| {
+    let dim = x.val.get(txn, node).dim();
+    let batch_sz = x.val.get(txn, node).batch_size();
+    let buf = <S as BatchArrayStorage<usize>>::alloc(dim.flat_len(), batch_sz);
+    BatchArray3d::from_storage(dim, batch_sz, buf)
+  })
 }
 
-pub struct SymmUnitClipKernel;
+impl<Op, S> SpecialMapExt</*f32,*/ BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn rect(&self) -> Rc<MapOp<BatchArray3d<f32, S>, RectMapKernel>> {
+    MapOp::new(RectMapKernel, self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
 
-pub trait SymmClipExt<A, Clip> {
-  fn symm_unit_clip(&self, c_: Rc<AVar<AData<Clip>>>) -> Rc<ClipOp<A, Clip, SymmUnitClipKernel>>;
+  fn exp(&self) -> Rc<MapOp<BatchArray3d<f32, S>, ExpMapKernel>> {
+    MapOp::new(ExpMapKernel, self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
+
+  fn log(&self) -> Rc<MapOp<BatchArray3d<f32, S>, LogMapKernel>> {
+    MapOp::new(LogMapKernel, self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
+
+  fn sqrt(&self) -> Rc<MapOp<BatchArray3d<f32, S>, SqrtMapKernel>> {
+    MapOp::new(SqrtMapKernel, self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
+
+  fn softplus(&self) -> Rc<MapOp<BatchArray3d<f32, S>, SoftplusMapKernel>> {
+    MapOp::new(SoftplusMapKernel, self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
+
+  fn leaky_rect(&self, c: f32) -> Rc<MapOp<BatchArray3d<f32, S>, LeakRectMapKernel<f32>>> {
+    MapOp::new(LeakRectMapKernel{c: c}, self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
+
+  fn elu(&self, alpha: f32) -> Rc<MapOp<BatchArray3d<f32, S>, EluMapKernel<f32>>> {
+    MapOp::new(EluMapKernel{c: alpha}, self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
+
+  fn gelu(&self) -> Rc<MapOp<BatchArray3d<f32, S>, GeluMapKernel>> {
+    MapOp::new(GeluMapKernel, self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
+
+  fn swish(&self) -> Rc<MapOp<BatchArray3d<f32, S>, SwishMapKernel>> {
+    MapOp::new(SwishMapKernel, self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
+
+  fn logistic(&self) -> Rc<MapOp<BatchArray3d<f32, S>, LogisticMapKernel>> {
+    MapOp::new(LogisticMapKernel, self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
+
+  fn tanh(&self) -> Rc<MapOp<BatchArray3d<f32, S>, TanhMapKernel>> {
+    MapOp::new(TanhMapKernel, self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
 }
 
-pub struct ClipOp<A, Clip, Kernel> {
+pub struct MapOp<A, MapF> {
   node_id:  NodeId,
   stack:    OperatorStack,
-  c_:   Rc<AVar<AData<Clip>>>,
   x_:   Rc<AVar<AData<A>>>,
-  c:    AData<Clip>,
   x:    AData<A>,
   y:    AData<A>,
-  kernel:   Kernel,
+  kernel:   MapF,
+  tng_: RefCell<Option<Rc<AVar<AData<A>>>>>,
 }
 
-impl<A, Clip, Kernel> ClipOp<A, Clip, Kernel> {
-  pub fn new(x_: Rc<AVar<AData<A>>>, c_: Rc<AVar<AData<Clip>>>, kernel: Kernel, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<ClipOp<A, Clip, Kernel>> {
+impl<A, MapF> MapOp<A, MapF> {
+  pub fn new<F>(kernel: MapF, x_: Rc<AVar<AData<A>>>, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<Self> where F: 'static + Fn(TxnId, NodeId) -> A {
     let node = NodeId::new();
     let x = x_.data();
-    let c = c_.data();
-    Rc::new(ClipOp{
+    Rc::new(MapOp{
       node_id:  node,
-      stack:    OperatorStack::new(node, 2),
-      c_:       c_,
-      x_:       x_,
-      c:        c,
-      x:        x,
-      y:        AData::new(/*clk_horizon,*/ alloc),
+      stack:    OperatorStack::new(node, 1),
+      x_:   x_,
+      x:    x,
+      y:    AData::new(/*clk_horizon,*/ alloc),
       kernel:   kernel,
+      tng_: RefCell::new(None),
     })
   }
 }
 
-impl<A, Clip, Kernel> AVar<AData<A>> for ClipOp<A, Clip, Kernel> where ClipOp<A, Clip, Kernel>: AOp {
+impl<A, MapF> AVar<AData<A>> for MapOp<A, MapF> where MapOp<A, MapF>: AOp {
   default fn _owned_data(&self) -> &AData<A> {
     &self.y
   }
 }
 
-pub trait MultExt<A, B, V, W> {
-  fn mult(&self, x: Rc<AVar<AData<V>>>) -> Rc<LinearOp<A, B, V, W>>;
-  fn mult_add(&self, x: Rc<AVar<AData<V>>>, b: Rc<AVar<AData<B>>>) -> Rc<LinearOp<A, B, V, W>>;
-}
-
-pub trait TransposeMultExt<A, B, V, W> {
-  // FIXME: want a generic transpose type.
-  fn mult(&self, /*transpose: _,*/ x: Rc<AVar<AData<V>>>) -> Rc<LinearOp<A, B, V, W>>;
-  fn mult_add(&self, /*transpose: _,*/ x: Rc<AVar<AData<V>>>, b: Rc<AVar<AData<B>>>) -> Rc<LinearOp<A, B, V, W>>;
-}
-
-pub struct LinearOp<A, B, V, W> {
-  node_id:  NodeId,
-  stack:    OperatorStack,
-  a_:   Rc<AVar<AData<A>>>,
-  b_:   Option<Rc<AVar<AData<B>>>>,
-  x_:   Rc<AVar<AData<V>>>,
-  a:    AData<A>,
-  b:    Option<AData<B>>,
-  x:    AData<V>,
-  y:    AData<W>,
-  tmp:  AData<W>,
-  tng:  RefCell<Option<Rc<AVar<AData<W>>>>>,
-}
-
-impl<A, B, V, W> LinearOp<A, B, V, W> {
-  pub fn new<F>(a_: Rc<AVar<AData<A>>>, x_: Rc<AVar<AData<V>>>, b_: Option<Rc<AVar<AData<B>>>>, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<LinearOp<A, B, V, W>> where F: 'static + Fn(TxnId, NodeId) -> W {
-    let node = NodeId::new();
-    let in_degree = match b_ {
-      None    => 2,
-      Some(_) => 3,
-    };
-    let a = a_.data();
-    let b = b_.as_ref().map(|b_| b_.data());
-    let x = x_.data();
-    Rc::new(LinearOp{
-      node_id:  node,
-      stack:    OperatorStack::new(node, in_degree),
-      a_:   a_,
-      b_:   b_,
-      x_:   x_,
-      a:    a,
-      b:    b,
-      x:    x,
-      y:    AData::new(/*clk_horizon,*/ alloc.clone()),
-      tmp:  AData::new(/*1,*/ alloc),
-      tng:  RefCell::new(None),
-    })
-  }
-}
-
-impl<A, B, V, W> AVar<AData<W>> for LinearOp<A, B, V, W> where LinearOp<A, B, V, W>: AOp {
-  default fn _owned_data(&self) -> &AData<W> {
+impl<A, MapF> AVar<AData<A>> for MapOp<A, MapF> where A: 'static + PackedArray, MapF: 'static + SpecialMapKernel + Clone, MapOp<A, MapF>: AOp {
+  fn _owned_data(&self) -> &AData<A> {
     &self.y
   }
 
-  // FIXME(20170530): requires extra trait bounds on inputs.
-  /*default fn _make_tangent(&self) -> Rc<AVar<AData<W>>> {
-    let tng_a_ = self.a_.tangent();
-    let tng_b_ = self.b_.as_ref().map(|b_| b_.tangent());
-    let tng_x_ = self.x_.tangent();
-    let tng_y_ = match tng_b_ {
-      None          => self.a_.mult(tng_x_).add(tng_a_.mult(self.x_.clone())),
-      Some(tng_b_)  => self.a_.mult(tng_x_).add(tng_a_.mult_add(self.x_.clone(), tng_b_)),
-    };
-    tng_y_
-  }*/
-
-  default fn tangent(&self) -> Rc<AVar<AData<W>>> {
-    if self.tng.borrow().is_none() {
-      *self.tng.borrow_mut() = Some(self._make_tangent());
-    }
-    self.tng.borrow().as_ref().unwrap().clone()
-  }
-}
-
-impl<Op, S> MultExt<Array1d<f32, S>, f32, Array1d<f32, S>, f32> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, S>>>, S: DerefMut<Target=[f32]> {
-  fn mult(&self, x_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<LinearOp<Array1d<f32, S>, f32, Array1d<f32, S>, f32>> {
-    //let clk_horizon = x_.data().horizon();
-    LinearOp::new(self.clone(), x_, None, /*clk_horizon,*/ Rc::new(|_, _| 0.0_f32))
+  fn _make_tangent(&self) -> Rc<AVar<AData<A>>> {
+    let x_tng_ = self.x_.tangent();
+    MapTangentOp::new(self.kernel.clone(), self.x_.clone(), x_tng_, /*clk_horizon,*/ self.y.alloc.clone())
   }
 
-  fn mult_add(&self, x_: Rc<AVar<AData<Array1d<f32, S>>>>, b_: Rc<AVar<AData<f32>>>) -> Rc<LinearOp<Array1d<f32, S>, f32, Array1d<f32, S>, f32>> {
-    //let clk_horizon = x_.data().horizon();
-    LinearOp::new(self.clone(), x_, Some(b_), /*clk_horizon,*/ Rc::new(|_, _| 0.0_f32))
+  fn _adjoint(&self, adj_: Rc<AVar<AData<A>>>, adjs: &mut AdjointMap) {
+    // The adjoint `f'(x) adj` is the tangent map applied to the adjoint.
+    let x_adj_: Rc<AVar<AData<A>>> = MapTangentOp::new(self.kernel.clone(), self.x_.clone(), adj_, /*clk_horizon,*/ self.x.alloc.clone());
+    adjs.accumulate(self.x_.clone(), x_adj_);
   }
-}
 
-/*impl<S> AutodiffObjective for LinearOp<Array1d<f32, S>, Array1d<f32, S>, f32, f32> where S: DerefMut<Target=[f32]> {
-  fn _set_source(&self, txn: TxnId) {
-    let node = self._id();
-    if self.y.grad.accumulate(txn, node, |g| *g = 1.0) {
-    } else {
-      assert_eq!(1.0, *self.y.grad.get_mut(txn, node));
+  fn tangent(&self) -> Rc<AVar<AData<A>>> {
+    if self.tng_.borrow().is_none() {
+      *self.tng_.borrow_mut() = Some(self._make_tangent());
     }
+    self.tng_.borrow().as_ref().unwrap().clone()
   }
-}*/
+}
 
-impl<S> AOp for LinearOp<Array1d<f32, S>, f32, Array1d<f32, S>, f32> where S: DerefMut<Target=[f32]> {
+impl<S, MapF> AOp for MapOp<Array1d<f32, S>, MapF> where S: DerefMut<Target=[f32]>, MapF: SpecialMapKernel {
   fn _id(&self) -> NodeId {
     self.node_id
   }
 
   fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if 1 == self.stack.push(epoch) {
-      self.a_._push(epoch, apply);
       self.x_._push(epoch, apply);
-      if let Some(ref b_) = self.b_ {
-        b_._push(epoch, apply);
-      }
       apply(self);
     }
   }
@@ -2072,11 +2468,7 @@ impl<S> AOp for LinearOp<Array1d<f32, S>, f32, Array1d<f32, S>, f32> where S: De
   fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if self.stack.degree(epoch) == self.stack.pop(epoch) {
       apply(self);
-      if let Some(ref b_) = self.b_ {
-        b_._pop(epoch, apply);
-      }
       self.x_._pop(epoch, apply);
-      self.a_._pop(epoch, apply);
     }
   }
 
@@ -2087,77 +2479,71 @@ impl<S> AOp for LinearOp<Array1d<f32, S>, f32, Array1d<f32, S>, f32> where S: De
   fn _forward(&self, txn: TxnId) {
     let node = self._id();
     if self.y.val.overwrite(txn, node) {
-      *self.y.val.get_excl(txn, node) = self.a.val.get(txn, node).as_view().inner_prod(1.0, self.x.val.get(txn, node).as_view());
-      if let Some(ref b) = self.b {
-        *self.y.val.get_excl(txn, node) += *b.val.get(txn, node);
-      }
+      let x_dim = self.x.val.get(txn, node).dim();
+      unsafe { self.kernel._fwd_f32(
+          x_dim,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.y.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
     }
   }
 
   fn _backward(&self, txn: TxnId) {
     let node = self._id();
-    if self.a.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-      //println!("DEBUG: LinearOp: backward: a.grad: before: {:?}", &self.a.grad.get_mut(txn, node).as_slice()[ .. 5]);
-      self.a.grad.get_mut(txn, node).as_view_mut().add(*self.y.grad.get(txn, node), self.x.val.get(txn, node).as_view());
-      //println!("DEBUG: LinearOp: backward: a.grad: after:  {:?}", &self.a.grad.get_mut(txn, node).as_slice()[ .. 5]);
-    }
     if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-      //println!("DEBUG: LinearOp: backward: x.grad: before: {:?}", &self.x.grad.get_mut(txn, node).as_slice()[ .. 5]);
-      self.x.grad.get_mut(txn, node).as_view_mut().add(*self.y.grad.get(txn, node), self.a.val.get(txn, node).as_view());
-      //println!("DEBUG: LinearOp: backward: x.grad: after:  {:?}", &self.x.grad.get_mut(txn, node).as_slice()[ .. 5]);
-    }
-    if let Some(ref b) = self.b {
-      if b.grad.accumulate(txn, node, |g| *g = 0.0) {
-        *b.grad.get_mut(txn, node) += *self.y.grad.get(txn, node);
-      }
+      let y_dim = self.y.grad.get(txn, node).dim();
+      unsafe { self.kernel._bwd_f32(
+          y_dim,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.y.grad.get(txn, node).as_view().as_ptr(),
+          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
     }
   }
-}
 
-impl<S> MultExt<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>> for Rc<AVar<AData<Array2d<f32, S>>>> where S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
-  fn mult(&self, x_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>>> {
-    //let clk_horizon = x_.data().horizon();
-    LinearOp::new(self.clone(), x_.clone(), None, /*clk_horizon,*/ {
-      let x = x_.clone().data();
-      Rc::new(move |txn, node| {
-        let dim = x.val.get(txn, node).dim();
-        let buf = <S as ArrayStorage<usize>>::alloc(dim);
-        Array1d::from_storage(dim, buf)
-      })
-    })
+  fn _has_r_forward(&self) -> bool {
+    true
   }
 
-  fn mult_add(&self, x_: Rc<AVar<AData<Array1d<f32, S>>>>, b_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>>> {
-    //let clk_horizon = x_.data().horizon();
-    LinearOp::new(self.clone(), x_.clone(), Some(b_), /*clk_horizon,*/ {
-      let x = x_.clone().data();
-      Rc::new(move |txn, node| {
-        let dim = x.val.get(txn, node).dim();
-        let buf = <S as ArrayStorage<usize>>::alloc(dim);
-        Array1d::from_storage(dim, buf)
-      })
-    })
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      // The backward kernel accumulates `f'(x) dy`, so running it on `r_x`
+      // into a zeroed `r_y` gives the directional derivative.
+      let x_dim = self.x.val.get(txn, node).dim();
+      self.y.r_val.get_excl(txn, node).as_view_mut().set_constant(0.0);
+      unsafe { self.kernel._bwd_f32(
+          x_dim,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.x.r_val.get(txn, node).as_view().as_ptr(),
+          self.y.r_val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
   }
-}
 
-/*impl<S> AVar<AData<Array1d<f32, S>>> for LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>> where S: DerefMut<Target=[f32]> {
-  fn _owned_data(&self) -> &AData<A> {
-    &self.data
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    if self.x.grad2.accumulate(txn, node, |grad2| grad2.as_view_mut().set_constant(0.0)) {
+      let y_dim = self.y.grad2.get(txn, node).dim();
+      unsafe { self.kernel._bwd2_f32(
+          y_dim,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.y.grad.get(txn, node).as_view().as_ptr(),
+          self.y.grad2.get(txn, node).as_view().as_ptr(),
+          self.x.grad2.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
   }
-}*/
+}
 
-impl<S> AOp for LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>> where S: DerefMut<Target=[f32]> {
+impl<S, MapF> AOp for MapOp<BatchArray1d<f32, S>, MapF> where S: DerefMut<Target=[f32]>, MapF: SpecialMapKernel {
   fn _id(&self) -> NodeId {
     self.node_id
   }
 
   fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if 1 == self.stack.push(epoch) {
-      self.a_._push(epoch, apply);
       self.x_._push(epoch, apply);
-      if let Some(ref b_) = self.b_ {
-        b_._push(epoch, apply);
-      }
       apply(self);
     }
   }
@@ -2165,11 +2551,7 @@ impl<S> AOp for LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Arra
   fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if self.stack.degree(epoch) == self.stack.pop(epoch) {
       apply(self);
-      if let Some(ref b_) = self.b_ {
-        b_._pop(epoch, apply);
-      }
       self.x_._pop(epoch, apply);
-      self.a_._pop(epoch, apply);
     }
   }
 
@@ -2180,152 +2562,78 @@ impl<S> AOp for LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Arra
   fn _forward(&self, txn: TxnId) {
     let node = self._id();
     if self.y.val.overwrite(txn, node) {
-      self.y.val.get_mut(txn, node).as_view_mut().matrix_vector_prod(
-          1.0,
-          self.a.val.get(txn, node).as_view(), Transpose::N,
-          self.x.val.get(txn, node).as_view(),
-          0.0,
-      );
-      if let Some(ref b) = self.b {
-        self.y.val.get_mut(txn, node).as_view_mut().add(1.0, b.val.get(txn, node).as_view());
-      }
+      let x_dim = self.x.val.get(txn, node).dim();
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      self.y.val.get_excl(txn, node).set_batch_size(batch_sz);
+      unsafe { self.kernel._fwd_f32(
+          x_dim.flat_len() * batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.y.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
     }
   }
 
   fn _backward(&self, txn: TxnId) {
     let node = self._id();
-    if self.a.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-      let x_dim = self.x.val.get(txn, node).dim();
-      let y_dim = self.y.val.get(txn, node).dim();
-      self.a.grad.get_mut(txn, node).as_view_mut().matrix_prod(
-          1.0,
-          self.y.grad.get(txn, node).as_view().reshape((y_dim, 1)), Transpose::N,
-          self.x.val.get(txn, node).as_view().reshape((x_dim, 1)), Transpose::T,
-          1.0,
-      );
-    }
-    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-      self.x.grad.get_mut(txn, node).as_view_mut().matrix_vector_prod(
-          1.0,
-          self.a.val.get(txn, node).as_view(), Transpose::T,
-          self.y.grad.get(txn, node).as_view(),
-          1.0,
-      );
-    }
-    if let Some(ref b) = self.b {
-      if b.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-        b.grad.get_mut(txn, node).as_view_mut().copy(self.y.grad.get(txn, node).as_view());
-      }
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad.set_batch_size(batch_sz); grad.as_view_mut().set_constant(0.0); }) {
+      assert_eq!(batch_sz, self.y.grad.get(txn, node).batch_size());
+      unsafe { self.kernel._bwd_f32(
+          x_dim.flat_len() * batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.y.grad.get(txn, node).as_view().as_ptr(),
+          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
     }
   }
 
-  /*fn _r_forward(&self, txn: TxnId) {
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
     let node = self._id();
     if self.y.r_val.overwrite(txn, node) {
-      self.y.r_val.get_mut(txn, node).as_view_mut().matrix_vector_prod(
-          1.0,
-          self.a.r_val.get(txn, node).as_view(), Transpose::N,
-          self.x.val.get(txn, node).as_view(),
-          0.0,
-      );
-      self.y.r_val.get_mut(txn, node).as_view_mut().matrix_vector_prod(
-          1.0,
-          self.a.val.get(txn, node).as_view(), Transpose::N,
-          self.x.r_val.get(txn, node).as_view(),
-          1.0,
-      );
-      if let Some(ref b) = self.b {
-        self.y.r_val.get_mut(txn, node).as_view_mut().add(1.0, b.r_val.get(txn, node).as_view());
-      }
+      let x_dim = self.x.val.get(txn, node).dim();
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      assert_eq!(batch_sz, self.x.r_val.get(txn, node).batch_size());
+      self.y.r_val.get_excl(txn, node).set_batch_size(batch_sz);
+      self.y.r_val.get_excl(txn, node).as_view_mut().set_constant(0.0);
+      unsafe { self.kernel._bwd_f32(
+          x_dim.flat_len() * batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.x.r_val.get(txn, node).as_view().as_ptr(),
+          self.y.r_val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
     }
   }
 
-  fn _r_backward(&self, txn: TxnId) {
+  fn _backward2(&self, txn: TxnId) {
     let node = self._id();
-    if self.a.r_grad.accumulate(txn, node, |r_grad| r_grad.as_view_mut().set_constant(0.0)) {
-      let x_dim = self.x.val.get(txn, node).dim();
-      let y_dim = self.y.grad.get(txn, node).dim();
-      self.a.r_grad.get_mut(txn, node).as_view_mut().matrix_prod(
-          1.0,
-          self.y.r_grad.get(txn, node).as_view().reshape((y_dim, 1)), Transpose::N,
-          self.x.val.get(txn, node).as_view().reshape((x_dim, 1)), Transpose::T,
-          1.0,
-      );
-      self.a.r_grad.get_mut(txn, node).as_view_mut().matrix_prod(
-          1.0,
-          self.y.grad.get(txn, node).as_view().reshape((y_dim, 1)), Transpose::N,
-          self.x.r_val.get(txn, node).as_view().reshape((x_dim, 1)), Transpose::T,
-          1.0,
-      );
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad2.accumulate(txn, node, |grad2| { grad2.set_batch_size(batch_sz); grad2.as_view_mut().set_constant(0.0); }) {
+      assert_eq!(batch_sz, self.y.grad2.get(txn, node).batch_size());
+      unsafe { self.kernel._bwd2_f32(
+          x_dim.flat_len() * batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.y.grad.get(txn, node).as_view().as_ptr(),
+          self.y.grad2.get(txn, node).as_view().as_ptr(),
+          self.x.grad2.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
     }
-    if self.x.r_grad.accumulate(txn, node, |r_grad| r_grad.as_view_mut().set_constant(0.0)) {
-      self.x.r_grad.get_mut(txn, node).as_view_mut().matrix_vector_prod(
-          1.0,
-          self.a.r_val.get(txn, node).as_view(), Transpose::T,
-          self.y.grad.get(txn, node).as_view(),
-          1.0,
-      );
-      self.x.r_grad.get_mut(txn, node).as_view_mut().matrix_vector_prod(
-          1.0,
-          self.a.val.get(txn, node).as_view(), Transpose::T,
-          self.y.r_grad.get(txn, node).as_view(),
-          1.0,
-      );
-    }
-    if let Some(ref b) = self.b {
-      if b.r_grad.accumulate(txn, node, |r_grad| r_grad.as_view_mut().set_constant(0.0)) {
-        b.r_grad.get_mut(txn, node).as_view_mut().add(1.0, self.y.r_grad.get(txn, node).as_view());
-      }
-    }
-  }*/
-}
-
-impl<S> MultExt<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>> for Rc<AVar<AData<Array2d<f32, S>>>> where S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
-  fn mult(&self, x_: Rc<AVar<AData<BatchArray1d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>>> {
-    //let clk_horizon = x_.data().horizon();
-    LinearOp::new(self.clone(), x_.clone(), None, /*clk_horizon,*/ {
-      let x = x_.clone().data();
-      Rc::new(move |txn, node| {
-        let dim = x.val.get(txn, node).dim();
-        let batch_sz = x.val.get(txn, node).batch_size();
-        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
-        BatchArray1d::from_storage(dim, batch_sz, buf)
-      })
-    })
-  }
-
-  fn mult_add(&self, x_: Rc<AVar<AData<BatchArray1d<f32, S>>>>, b_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>>> {
-    //let clk_horizon = x_.data().horizon();
-    LinearOp::new(self.clone(), x_.clone(), Some(b_), /*clk_horizon,*/ {
-      let x = x_.clone().data();
-      Rc::new(move |txn, node| {
-        let dim = x.val.get(txn, node).dim();
-        let batch_sz = x.val.get(txn, node).batch_size();
-        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
-        BatchArray1d::from_storage(dim, batch_sz, buf)
-      })
-    })
   }
 }
 
-/*impl<S> AVar<AData<BatchArray1d<f32, S>>> for LinearOp<Array2d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>, Array1d<f32, S>> where S: DerefMut<Target=[f32]> {
-  fn _owned_data(&self) -> &AData<A> {
-    &self.data
-  }
-}*/
-
-impl<S> AOp for LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>> where S: DerefMut<Target=[f32]> {
+impl<S, MapF> AOp for MapOp<BatchArray3d<f32, S>, MapF> where S: DerefMut<Target=[f32]>, MapF: SpecialMapKernel {
   fn _id(&self) -> NodeId {
     self.node_id
   }
 
   fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if 1 == self.stack.push(epoch) {
-      self.a_._push(epoch, apply);
       self.x_._push(epoch, apply);
-      if let Some(ref b_) = self.b_ {
-        b_._push(epoch, apply);
-      }
       apply(self);
     }
   }
@@ -2333,11 +2641,7 @@ impl<S> AOp for LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>,
   fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if self.stack.degree(epoch) == self.stack.pop(epoch) {
       apply(self);
-      if let Some(ref b_) = self.b_ {
-        b_._pop(epoch, apply);
-      }
       self.x_._pop(epoch, apply);
-      self.a_._pop(epoch, apply);
     }
   }
 
@@ -2348,194 +2652,129 @@ impl<S> AOp for LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>,
   fn _forward(&self, txn: TxnId) {
     let node = self._id();
     if self.y.val.overwrite(txn, node) {
+      let x_dim = self.x.val.get(txn, node).dim();
       let batch_sz = self.x.val.get(txn, node).batch_size();
-      self.y.val.get_mut(txn, node).set_batch_size(batch_sz);
-      self.y.val.get_mut(txn, node).as_view_mut().matrix_prod(
-          1.0,
-          self.a.val.get(txn, node).as_view(), Transpose::N,
-          self.x.val.get(txn, node).as_view(), Transpose::N,
-          0.0,
-      );
-      if let Some(ref b) = self.b {
-        unimplemented!();
-      }
+      self.y.val.get_excl(txn, node).set_batch_size(batch_sz);
+      unsafe { self.kernel._fwd_f32(
+          x_dim.flat_len() * batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.y.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
     }
   }
 
   fn _backward(&self, txn: TxnId) {
     let node = self._id();
-    if self.a.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-      self.a.grad.get_mut(txn, node).as_view_mut().matrix_prod(
-          1.0,
-          self.y.grad.get(txn, node).as_view(), Transpose::N,
-          self.x.val.get(txn, node).as_view(), Transpose::T,
-          1.0,
-      );
-    }
-    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-      let batch_sz = self.y.grad.get(txn, node).batch_size();
-      self.x.grad.get_mut(txn, node).set_batch_size(batch_sz);
-      self.x.grad.get_mut(txn, node).as_view_mut().matrix_prod(
-          1.0,
-          self.a.val.get(txn, node).as_view(), Transpose::T,
-          self.y.grad.get(txn, node).as_view(), Transpose::N,
-          1.0,
-      );
-    }
-    if let Some(ref b) = self.b {
-      unimplemented!();
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad.set_batch_size(batch_sz); grad.as_view_mut().set_constant(0.0); }) {
+      assert_eq!(batch_sz, self.y.grad.get(txn, node).batch_size());
+      unsafe { self.kernel._bwd_f32(
+          x_dim.flat_len() * batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.y.grad.get(txn, node).as_view().as_ptr(),
+          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
     }
   }
 
-  /*fn _backward2(&self, txn: TxnId) {
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
     let node = self._id();
-    if self.a.grad2.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-      let tmp_txn = TxnId::new();
-      assert!(self.tmp.val.overwrite(tmp_txn, node));
-      self.tmp.val.get_excl(tmp_txn, node).as_view_mut().flatten_mut().copy(self.a.grad.get(txn, node).as_view().flatten());
-      self.tmp.val.get_excl(tmp_txn, node).as_view_mut().flatten_mut().square();
-      self.tmp.val.get_excl(tmp_txn, node).as_view_mut().flatten_mut().elem_mult(self.x.grad2.get(txn, node).as_view().flatten());
-      self.a.grad2.get_mut(txn, node).as_view_mut().flatten_mut().add(1.0, self.tmp.val.get(tmp_txn, node).as_view().flatten());
-    }
-    if self.x.grad2.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
-      let tmp_txn = TxnId::new();
-      assert!(self.tmp.val.overwrite(tmp_txn, node));
-      self.tmp.val.get_excl(tmp_txn, node).as_view_mut().flatten_mut().copy(self.a.val.get(txn, node).as_view().flatten());
-      self.tmp.val.get_excl(tmp_txn, node).as_view_mut().flatten_mut().square();
-      let batch_sz = self.y.grad2.get(txn, node).batch_size();
-      self.x.grad2.get_mut(txn, node).set_batch_size(batch_sz);
-      self.x.grad2.get_mut(txn, node).as_view_mut().matrix_prod(
-          1.0,
-          self.tmp.val.get(tmp_txn, node).as_view(), Transpose::T,
-          self.y.grad2.get(txn, node).as_view(), Transpose::N,
-          1.0,
-      );
-    }
-    if let Some(ref b) = self.b {
-      unimplemented!();
+    if self.y.r_val.overwrite(txn, node) {
+      let x_dim = self.x.val.get(txn, node).dim();
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      assert_eq!(batch_sz, self.x.r_val.get(txn, node).batch_size());
+      self.y.r_val.get_excl(txn, node).set_batch_size(batch_sz);
+      self.y.r_val.get_excl(txn, node).as_view_mut().set_constant(0.0);
+      unsafe { self.kernel._bwd_f32(
+          x_dim.flat_len() * batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.x.r_val.get(txn, node).as_view().as_ptr(),
+          self.y.r_val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
     }
-  }*/
-}
-
-pub struct BroadcastAddOp<A, V> {
-  node_id:  NodeId,
-  stack:    OperatorStack,
-  a_:   Rc<AVar<AData<A>>>,
-  x_:   Rc<AVar<AData<V>>>,
-  a:    AData<A>,
-  x:    AData<V>,
-  y:    AData<V>,
-  //kernel:   K,
-  axes: Vec<usize>,
-}
-
-impl<A, V> BroadcastAddOp<A, V> {
-  pub fn new(axes: Vec<usize>, a_: Rc<AVar<AData<A>>>, x_: Rc<AVar<AData<V>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> V>) -> Rc<Self> {
-    let node = NodeId::new();
-    let a = a_.data();
-    let x = x_.data();
-    Rc::new(BroadcastAddOp{
-      node_id:  node,
-      stack:    OperatorStack::new(node, 2),
-      a_:   a_,
-      x_:   x_,
-      a:    a,
-      x:    x,
-      y:    AData::new(/*clk_horizon,*/ alloc.clone()),
-      axes: axes,
-    })
   }
-}
-
-pub trait BroadcastAddExt<A, V> {
-  fn broadcast_add(&self, axes: Vec<usize>, x_: Rc<AVar<AData<V>>>) -> Rc<BroadcastAddOp<A, V>>;
-}
 
-impl<A, V> AVar<AData<V>> for BroadcastAddOp<A, V> where BroadcastAddOp<A, V>: AOp {
-  default fn _owned_data(&self) -> &AData<V> {
-    &self.y
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad2.accumulate(txn, node, |grad2| { grad2.set_batch_size(batch_sz); grad2.as_view_mut().set_constant(0.0); }) {
+      assert_eq!(batch_sz, self.y.grad2.get(txn, node).batch_size());
+      unsafe { self.kernel._bwd2_f32(
+          x_dim.flat_len() * batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.y.grad.get(txn, node).as_view().as_ptr(),
+          self.y.grad2.get(txn, node).as_view().as_ptr(),
+          self.x.grad2.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
   }
 }
 
-pub struct ElemLinearOp<A, V, K> {
+/// The tangent of a `MapOp`: `y_tng = f'(x) x_tng`. Its backward also flows
+/// into the primal input `x` through the second derivative of `f`.
+pub struct MapTangentOp<A, MapF> {
   node_id:  NodeId,
   stack:    OperatorStack,
-  a_:   Rc<AVar<AData<A>>>,
-  b_:   Option<Rc<AVar<AData<A>>>>,
-  x_:   Rc<AVar<AData<V>>>,
-  a:    AData<A>,
-  b:    Option<AData<A>>,
-  x:    AData<V>,
-  y:    AData<V>,
-  //tmp:  AData<V>,
-  kernel:   K,
-  tng_: RefCell<Option<Rc<AVar<AData<V>>>>>,
+  x_:       Rc<AVar<AData<A>>>,
+  x_tng_:   Rc<AVar<AData<A>>>,
+  x:        AData<A>,
+  x_tng:    AData<A>,
+  y_tng:    AData<A>,
+  kernel:   MapF,
 }
 
-impl<A, V, Kernel> ElemLinearOp<A, V, Kernel> {
-  pub fn new(a_: Rc<AVar<AData<A>>>, x_: Rc<AVar<AData<V>>>, b_: Option<Rc<AVar<AData<A>>>>, kernel: Kernel, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> V>) -> Rc<Self> {
+impl<A, MapF> MapTangentOp<A, MapF> {
+  pub fn new(kernel: MapF, x_: Rc<AVar<AData<A>>>, x_tng_: Rc<AVar<AData<A>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<Self> {
     let node = NodeId::new();
-    let in_degree = match b_ {
-      None    => 2,
-      Some(_) => 3,
-    };
-    let a = a_.data();
     let x = x_.data();
-    let b = b_.as_ref().map(|b_| b_.data());
-    Rc::new(ElemLinearOp{
+    let x_tng = x_tng_.data();
+    Rc::new(MapTangentOp{
       node_id:  node,
-      stack:    OperatorStack::new(node, in_degree),
-      a_:   a_,
-      b_:   b_,
-      x_:   x_,
-      a:    a,
-      b:    b,
-      x:    x,
-      y:    AData::new(/*clk_horizon,*/ alloc.clone()),
-      //tmp:  AData::new(/*clk_horizon,*/ alloc.clone()),
+      stack:    OperatorStack::new(node, 2),
+      x_:       x_,
+      x_tng_:   x_tng_,
+      x:        x,
+      x_tng:    x_tng,
+      y_tng:    AData::new(/*clk_horizon,*/ alloc),
       kernel:   kernel,
-      tng_: RefCell::new(None),
     })
   }
 }
 
-pub struct BroadcastMultAddKernel;
-pub struct ElemNormalizeKernel{pub epsilon: f64}
-
-pub trait ElemMultExt<A, V> {
-  fn elem_mult(&self, x_: Rc<AVar<AData<V>>>) -> Rc<ElemLinearOp<A, V, BroadcastMultAddKernel>>;
-  fn elem_mult_add(&self, x_: Rc<AVar<AData<V>>>, b_: Rc<AVar<AData<A>>>) -> Rc<ElemLinearOp<A, V, BroadcastMultAddKernel>>;
+impl<A, MapF> AVar<AData<A>> for MapTangentOp<A, MapF> where MapTangentOp<A, MapF>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y_tng
+  }
 }
 
-/*pub trait ElemNormalizeExt<A, V> {
-  fn elem_normalize(&self, mean_: Rc<AVar<AData<A>>>, var_: Rc<AVar<AData<A>>>) -> Rc<ElemLinearOp<A, V, ElemNormalizeKernel>>;
-}*/
-
-impl<A, V, Kernel> AVar<AData<V>> for ElemLinearOp<A, V, Kernel> where ElemLinearOp<A, V, Kernel>: AOp {
-  default fn _owned_data(&self) -> &AData<V> {
-    &self.y
+impl<A, MapF> AVar<AData<A>> for MapTangentOp<A, MapF> where A: 'static + PackedArray, MapF: 'static + SpecialMapKernel + Clone, MapTangentOp<A, MapF>: AOp {
+  fn _owned_data(&self) -> &AData<A> {
+    &self.y_tng
   }
 
-  default fn tangent(&self) -> Rc<AVar<AData<V>>> {
-    if self.tng_.borrow().is_none() {
-      *self.tng_.borrow_mut() = Some(self._make_tangent());
-    }
-    self.tng_.borrow().as_ref().unwrap().clone()
+  fn _adjoint(&self, adj_: Rc<AVar<AData<A>>>, adjs: &mut AdjointMap) {
+    let x_tng_adj_: Rc<AVar<AData<A>>> = MapTangentOp::new(self.kernel.clone(), self.x_.clone(), adj_.clone(), /*clk_horizon,*/ self.x_tng.alloc.clone());
+    adjs.accumulate(self.x_tng_.clone(), x_tng_adj_);
+    let x_adj_: Rc<AVar<AData<A>>> = MapCurvatureOp::new(self.kernel.clone(), self.x_.clone(), self.x_tng_.clone(), adj_, /*clk_horizon,*/ self.x.alloc.clone());
+    adjs.accumulate(self.x_.clone(), x_adj_);
   }
 }
 
-/*impl<S> AOp for ElemLinearOp<f32, BatchArray3d<f32, S>, BroadcastMultAddKernel> where S: DerefMut<Target=[f32]> {
+impl<A, MapF> AOp for MapTangentOp<A, MapF> where A: PackedArray, MapF: SpecialMapKernel {
   fn _id(&self) -> NodeId {
     self.node_id
   }
 
   fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if 1 == self.stack.push(epoch) {
-      self.a_._push(epoch, apply);
       self.x_._push(epoch, apply);
-      if let Some(ref b_) = self.b_ {
-        b_._push(epoch, apply);
-      }
+      self.x_tng_._push(epoch, apply);
       apply(self);
     }
   }
@@ -2543,47 +2782,108 @@ impl<A, V, Kernel> AVar<AData<V>> for ElemLinearOp<A, V, Kernel> where ElemLinea
   fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if self.stack.degree(epoch) == self.stack.pop(epoch) {
       apply(self);
-      if let Some(ref b_) = self.b_ {
-        b_._pop(epoch, apply);
-      }
+      self.x_tng_._pop(epoch, apply);
       self.x_._pop(epoch, apply);
-      self.a_._pop(epoch, apply);
     }
   }
 
   fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
-    self.y.rollover_all(txn, vars);
+    self.y_tng.rollover_all(txn, vars);
   }
 
   fn _forward(&self, txn: TxnId) {
-    // TODO
     let node = self._id();
-    if self.y.val.overwrite(txn, node) {
-      let batch_sz = self.x.val.get(txn, node).batch_size();
-      if let Some(ref b) = self.b {
-      }
+    if self.y_tng.val.overwrite(txn, node) {
+      let x_dim = self.x.val.get(txn, node)._packed_dim();
+      let batch_sz = self.x.val.get(txn, node)._batch_size();
+      assert_eq!(batch_sz, self.x_tng.val.get(txn, node)._batch_size());
+      self.y_tng.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      self.y_tng.val.get_excl(txn, node)._set_zero();
+      unsafe { self.kernel._bwd_f32(
+          x_dim.iter().product(),
+          self.x.val.get(txn, node)._as_ptr(),
+          self.x_tng.val.get(txn, node)._as_ptr(),
+          self.y_tng.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
     }
-    unimplemented!();
   }
 
   fn _backward(&self, txn: TxnId) {
-    // TODO
-    unimplemented!();
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.x_tng.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { self.kernel._bwd_f32(
+          x_dim.iter().product(),
+          self.x.val.get(txn, node)._as_ptr(),
+          self.y_tng.grad.get(txn, node)._as_ptr(),
+          self.x_tng.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { self.kernel._tangent_bwd_f32(
+          x_dim.iter().product(),
+          self.x.val.get(txn, node)._as_ptr(),
+          self.x_tng.val.get(txn, node)._as_ptr(),
+          self.y_tng.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
   }
-}*/
+}
 
-/*impl<S> AOp for ElemLinearOp<Array1d<f32, S>, BatchArray3d<f32, S>, ElemNormalizeKernel> where S: DerefMut<Target=[f32]> {
-  fn _id(&self) -> NodeId {
-    self.node_id
-  }
+/// The second-order term `y = f''(x) u v` of a map, e.g. the adjoint of a
+/// `MapTangentOp` with respect to its primal input.
+pub struct MapCurvatureOp<A, MapF> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  x_:       Rc<AVar<AData<A>>>,
+  u_:       Rc<AVar<AData<A>>>,
+  v_:       Rc<AVar<AData<A>>>,
+  x:        AData<A>,
+  u:        AData<A>,
+  v:        AData<A>,
+  y:        AData<A>,
+  kernel:   MapF,
+}
+
+impl<A, MapF> MapCurvatureOp<A, MapF> {
+  pub fn new(kernel: MapF, x_: Rc<AVar<AData<A>>>, u_: Rc<AVar<AData<A>>>, v_: Rc<AVar<AData<A>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<Self> {
+    let node = NodeId::new();
+    let x = x_.data();
+    let u = u_.data();
+    let v = v_.data();
+    Rc::new(MapCurvatureOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 3),
+      x_:       x_,
+      u_:       u_,
+      v_:       v_,
+      x:        x,
+      u:        u,
+      v:        v,
+      y:        AData::new(/*clk_horizon,*/ alloc),
+      kernel:   kernel,
+    })
+  }
+}
+
+impl<A, MapF> AVar<AData<A>> for MapCurvatureOp<A, MapF> where MapCurvatureOp<A, MapF>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+}
+
+impl<A, MapF> AOp for MapCurvatureOp<A, MapF> where A: PackedArray, MapF: SpecialMapKernel {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
 
   fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if 1 == self.stack.push(epoch) {
-      self.a_._push(epoch, apply);
       self.x_._push(epoch, apply);
-      if let Some(ref b_) = self.b_ {
-        b_._push(epoch, apply);
-      }
+      self.u_._push(epoch, apply);
+      self.v_._push(epoch, apply);
       apply(self);
     }
   }
@@ -2591,11 +2891,9 @@ impl<A, V, Kernel> AVar<AData<V>> for ElemLinearOp<A, V, Kernel> where ElemLinea
   fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if self.stack.degree(epoch) == self.stack.pop(epoch) {
       apply(self);
-      if let Some(ref b_) = self.b_ {
-        b_._pop(epoch, apply);
-      }
+      self.v_._pop(epoch, apply);
+      self.u_._pop(epoch, apply);
       self.x_._pop(epoch, apply);
-      self.a_._pop(epoch, apply);
     }
   }
 
@@ -2604,540 +2902,5286 @@ impl<A, V, Kernel> AVar<AData<V>> for ElemLinearOp<A, V, Kernel> where ElemLinea
   }
 
   fn _forward(&self, txn: TxnId) {
-    // TODO
     let node = self._id();
     if self.y.val.overwrite(txn, node) {
-      let batch_sz = self.x.val.get(txn, node).batch_size();
-      if let Some(ref b) = self.b {
-      }
+      let x_dim = self.x.val.get(txn, node)._packed_dim();
+      let batch_sz = self.x.val.get(txn, node)._batch_size();
+      assert_eq!(batch_sz, self.u.val.get(txn, node)._batch_size());
+      assert_eq!(batch_sz, self.v.val.get(txn, node)._batch_size());
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      self.y.val.get_excl(txn, node)._set_zero();
+      unsafe { self.kernel._tangent_bwd_f32(
+          x_dim.iter().product(),
+          self.x.val.get(txn, node)._as_ptr(),
+          self.u.val.get(txn, node)._as_ptr(),
+          self.v.val.get(txn, node)._as_ptr(),
+          self.y.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
     }
-    unimplemented!();
   }
 
   fn _backward(&self, txn: TxnId) {
-    // TODO
-    unimplemented!();
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.u.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { self.kernel._tangent_bwd_f32(
+          x_dim.iter().product(),
+          self.x.val.get(txn, node)._as_ptr(),
+          self.v.val.get(txn, node)._as_ptr(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          self.u.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+    if self.v.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { self.kernel._tangent_bwd_f32(
+          x_dim.iter().product(),
+          self.x.val.get(txn, node)._as_ptr(),
+          self.u.val.get(txn, node)._as_ptr(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          self.v.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      // FIXME: the map kernels do not have third derivatives yet.
+      unimplemented!();
+    }
   }
-}*/
-
-pub struct ElemNormalizeOp<Idx, A, V> where Idx: ArrayIndex {
-  node_id:  NodeId,
-  stack:    OperatorStack,
-  axes:     Idx::Axes,
-  epsilon:  f64,
-  x_:       Rc<AVar<AData<V>>>,
-  mean_:    Rc<AVar<AData<A>>>,
-  var_:     Rc<AVar<AData<A>>>,
-  x:        AData<V>,
-  mean:     AData<A>,
-  var:      AData<A>,
-  y:        AData<V>,
 }
 
-impl<Idx, A, V> ElemNormalizeOp<Idx, A, V> where Idx: ArrayIndex {
-  pub fn new(axes: Idx::Axes, epsilon: f64, x_: Rc<AVar<AData<V>>>, mean_: Rc<AVar<AData<A>>>, var_: Rc<AVar<AData<A>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> V>) -> Rc<Self> {
-    let node = NodeId::new();
-    let x = x_.data();
-    let mean = mean_.data();
-    let var = var_.data();
-    Rc::new(ElemNormalizeOp{
-      node_id:  node,
-      stack:    OperatorStack::new(node, 3),
-      axes:     axes,
-      epsilon:  epsilon,
-      x_:       x_,
-      mean_:    mean_,
-      var_:     var_,
-      x:        x,
-      mean:     mean,
-      var:      var,
-      y:        AData::new(/*clk_horizon,*/ alloc.clone()),
-    })
-  }
+/// Raw access to CPU arrays as packed column-major 4d arrays, for ops whose
+/// kernels do not depend on the array rank.
+pub trait PackedArray {
+  fn _packed_dim(&self) -> [usize; 4];
+  fn _batch_size(&self) -> usize;
+  fn _set_batch_size(&mut self, batch_sz: usize);
+  fn _as_ptr(&self) -> *const f32;
+  fn _as_mut_ptr(&mut self) -> *mut f32;
+  fn _set_zero(&mut self);
 }
 
-pub trait ElemNormalizeExt<Idx, A, V> where Idx: ArrayIndex {
-  fn elem_normalize(&self, axes: Idx::Axes, epsilon: f64, mean_: Rc<AVar<AData<A>>>, var_: Rc<AVar<AData<A>>>) -> Rc<ElemNormalizeOp<Idx, A, V>>;
-}
+impl PackedArray for f32 {
+  fn _packed_dim(&self) -> [usize; 4] {
+    [1, 1, 1, 1]
+  }
 
-impl<Idx, A, V> AVar<AData<V>> for ElemNormalizeOp<Idx, A, V> where ElemNormalizeOp<Idx, A, V>: AOp, Idx: ArrayIndex {
-  default fn _owned_data(&self) -> &AData<V> {
-    &self.y
+  fn _batch_size(&self) -> usize {
+    1
   }
-}
 
-#[derive(Clone, Copy)]
-pub struct ConvShape<Idx> where Idx: ArrayIndex {
-  pub axes:     Idx::Axes,
-  pub kernel:   Idx,
-  pub stride:   Idx,
-  //pub zero_pad: Idx,
-  pub zero_pad: bool,
-  pub filters:  Option<usize>,
-}
+  fn _set_batch_size(&mut self, batch_sz: usize) {
+    assert_eq!(1, batch_sz);
+  }
 
-impl ConvShape<(usize, usize)> {
-  pub fn conv2d_pad_dim(&self, in_dim: (usize, usize, usize)) -> (usize, usize) {
-    match self.zero_pad {
-      false => (0, 0),
-      true  => {
-        match self.axes {
-          // FIXME(20170309): this calculation ignores input and stride dimensions.
-          Axes((0, 1)) => (self.kernel.0 / 2, self.kernel.1 / 2),
-          _ => unimplemented!(),
-        }
-      }
-    }
+  fn _as_ptr(&self) -> *const f32 {
+    self as *const f32
   }
 
-  pub fn conv2d_kernel_dim(&self, in_dim: (usize, usize, usize)) -> (usize, usize, usize, usize) {
-    match self.axes {
-      Axes((0, 1)) => (self.kernel.0, self.kernel.1, in_dim.2, self.filters.unwrap_or(in_dim.2)),
-      _ => unimplemented!(),
-    }
+  fn _as_mut_ptr(&mut self) -> *mut f32 {
+    self as *mut f32
   }
 
-  pub fn conv2d_output_dim(&self, in_dim: (usize, usize, usize)) -> (usize, usize, usize) {
-    match self.axes {
-      Axes((0, 1)) => {
-        let (in_w, in_h, in_chan) = in_dim;
-        let (kernel_w, kernel_h) = self.kernel;
-        let (stride_w, stride_h) = self.stride;
-        let (pad_w, pad_h) = self.conv2d_pad_dim(in_dim);
-        let out_w = max(0, (in_w + 2 * pad_w - kernel_w + stride_w) as isize) as usize / stride_w;
-        let out_h = max(0, (in_h + 2 * pad_h - kernel_h + stride_h) as isize) as usize / stride_h;
-        assert!(out_w > 0);
-        assert!(out_h > 0);
-        (out_w, out_h, self.filters.unwrap_or(in_dim.2))
-      }
-      _ => unimplemented!(),
-    }
+  fn _set_zero(&mut self) {
+    *self = 0.0;
   }
 }
 
-pub trait ConvExt<Idx, A, B, V, Backend> where Idx: ArrayIndex {
-  fn conv(&self, shape: ConvShape<Idx>, x_: Rc<AVar<AData<V>>>) -> Rc<ConvOp<Idx, A, B, V, Backend>>;
-  fn conv_add(&self, shape: ConvShape<Idx>, x_: Rc<AVar<AData<V>>>, b_: Rc<AVar<AData<B>>>) -> Rc<ConvOp<Idx, A, B, V, Backend>>;
-}
+impl PackedArray for Batch<f32> {
+  fn _packed_dim(&self) -> [usize; 4] {
+    [1, 1, 1, self.batch_size()]
+  }
 
-pub struct ConvOp<Idx, A, B, V, Backend> where Idx: ArrayIndex {
-  node_id:  NodeId,
-  stack:    OperatorStack,
-  shape:    ConvShape<Idx>,
-  a_:   Rc<AVar<AData<A>>>,
-  b_:   Option<Rc<AVar<AData<B>>>>,
-  x_:   Rc<AVar<AData<V>>>,
-  a:    AData<A>,
-  b:    Option<AData<B>>,
-  x:    AData<V>,
-  y:    AData<V>,
-  backend:  Backend,
-  tng_: RefCell<Option<Rc<AVar<AData<V>>>>>,
-}
+  fn _batch_size(&self) -> usize {
+    self.batch_size()
+  }
 
-impl<Idx, A, B, V, Backend> ConvOp<Idx, A, B, V, Backend> where Idx: ArrayIndex {
-  pub fn new(shape: ConvShape<Idx>, a_: Rc<AVar<AData<A>>>, x_: Rc<AVar<AData<V>>>, b_: Option<Rc<AVar<AData<B>>>>, backend: Backend, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> V>) -> Rc<Self> {
-    let node = NodeId::new();
-    let in_degree = match b_ {
-      None    => 2,
-      Some(_) => 3,
-    };
-    let a = a_.data();
-    let x = x_.data();
-    let b = b_.as_ref().map(|b_| b_.data());
-    Rc::new(ConvOp{
-      node_id:  node,
-      stack:    OperatorStack::new(node, in_degree),
-      shape:    shape,
-      a_:   a_,
-      x_:   x_,
-      b_:   b_,
-      a:    a,
-      x:    x,
-      b:    b,
-      y:    AData::new(/*clk_horizon,*/ alloc.clone()),
-      //tmp:  AData::new(1, alloc),
-      backend:  backend,
-      tng_: RefCell::new(None),
-    })
+  fn _set_batch_size(&mut self, batch_sz: usize) {
+    self.set_batch_size(batch_sz, 0.0);
   }
-}
 
-impl<Idx, A, B, V, Backend> AVar<AData<V>> for ConvOp<Idx, A, B, V, Backend>
-where Idx: ArrayIndex,
-      Self: AOp,
-{
-  default fn _owned_data(&self) -> &AData<V> {
-    &self.y
+  fn _as_ptr(&self) -> *const f32 {
+    let batch_sz = self.batch_size();
+    self.reshape(batch_sz).as_ptr()
   }
 
-  default fn tangent(&self) -> Rc<AVar<AData<V>>> {
-    if self.tng_.borrow().is_none() {
-      *self.tng_.borrow_mut() = Some(self._make_tangent());
-    }
-    self.tng_.borrow().as_ref().unwrap().clone()
+  fn _as_mut_ptr(&mut self) -> *mut f32 {
+    let batch_sz = self.batch_size();
+    self.reshape_mut(batch_sz).as_mut_ptr()
+  }
+
+  fn _set_zero(&mut self) {
+    let batch_sz = self.batch_size();
+    self.reshape_mut(batch_sz).set_constant(0.0);
   }
 }
 
-impl<S> AOp for ConvOp<(usize, usize), Array4d<f32, S>, Array1d<f32, S>, Array3d<f32, S>, ()> where S: DerefMut<Target=[f32]> {
-  fn _id(&self) -> NodeId {
-    self.node_id
+impl<S> PackedArray for Array1d<f32, S> where S: DerefMut<Target=[f32]> {
+  fn _packed_dim(&self) -> [usize; 4] {
+    [self.dim(), 1, 1, 1]
   }
 
-  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
-    if 1 == self.stack.push(epoch) {
-      self.a_._push(epoch, apply);
-      self.x_._push(epoch, apply);
-      if let Some(ref b_) = self.b_ {
-        b_._push(epoch, apply);
-      }
-      apply(self);
-    }
+  fn _batch_size(&self) -> usize {
+    1
   }
 
-  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
-    if self.stack.degree(epoch) == self.stack.pop(epoch) {
-      apply(self);
-      if let Some(ref b_) = self.b_ {
-        b_._pop(epoch, apply);
-      }
-      self.x_._pop(epoch, apply);
-      self.a_._pop(epoch, apply);
-    }
+  fn _set_batch_size(&mut self, batch_sz: usize) {
+    assert_eq!(1, batch_sz);
   }
 
-  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
-    self.y.rollover_all(txn, vars);
+  fn _as_ptr(&self) -> *const f32 {
+    self.as_view().as_ptr()
   }
 
-  fn _forward(&self, txn: TxnId) {
-    unimplemented!();
+  fn _as_mut_ptr(&mut self) -> *mut f32 {
+    self.as_view_mut().as_mut_ptr()
   }
 
-  fn _backward(&self, txn: TxnId) {
-    unimplemented!();
+  fn _set_zero(&mut self) {
+    self.as_view_mut().set_constant(0.0);
   }
 }
 
-#[derive(Clone, Copy)]
-pub struct PoolShape<Idx> where Idx: ArrayIndex {
-  pub axes:     Idx::Axes,
-  pub kernel:   Idx,
-  pub stride:   Idx,
-  pub zero_pad: bool,
-}
+impl<S> PackedArray for Array2d<f32, S> where S: DerefMut<Target=[f32]> {
+  fn _packed_dim(&self) -> [usize; 4] {
+    let dim = self.dim();
+    [dim.0, dim.1, 1, 1]
+  }
 
-impl PoolShape<(usize, usize)> {
-  pub fn pool2d_pad_dim(&self, in_dim: (usize, usize, usize)) -> (usize, usize) {
-    match self.zero_pad {
-      false => (0, 0),
-      true  => {
-        match self.axes {
-          // FIXME(20170309): this calculation ignores input and stride dimensions.
-          Axes((0, 1)) => (self.kernel.0 / 2, self.kernel.1 / 2),
-          _ => unimplemented!(),
-        }
-      }
-    }
+  fn _batch_size(&self) -> usize {
+    1
   }
 
-  pub fn pool2d_output_dim(&self, in_dim: (usize, usize, usize)) -> (usize, usize, usize) {
-    match self.axes {
-      Axes((0, 1)) => {
-        let (in_w, in_h, _) = in_dim;
-        let (kernel_w, kernel_h) = self.kernel;
-        let (stride_w, stride_h) = self.stride;
-        let (pad_w, pad_h) = self.pool2d_pad_dim(in_dim);
-        let out_w = max(0, (in_w + 2 * pad_w - kernel_w + stride_w) as isize) as usize / stride_w;
-        let out_h = max(0, (in_h + 2 * pad_h - kernel_h + stride_h) as isize) as usize / stride_h;
-        assert!(out_w > 0);
-        assert!(out_h > 0);
-        (out_w, out_h, in_dim.2)
-      }
-      _ => unimplemented!(),
-    }
+  fn _set_batch_size(&mut self, batch_sz: usize) {
+    assert_eq!(1, batch_sz);
   }
-}
 
-pub trait PoolKernel {}
-
-pub struct AvgPool;
-pub struct MaxPool;
+  fn _as_ptr(&self) -> *const f32 {
+    self.as_view().as_ptr()
+  }
 
-impl PoolKernel for AvgPool {}
-impl PoolKernel for MaxPool {}
+  fn _as_mut_ptr(&mut self) -> *mut f32 {
+    self.as_view_mut().as_mut_ptr()
+  }
 
-pub trait PoolExt<Idx, V, Backend>: AVar<AData<V>> where Idx: ArrayIndex {
-  fn avg_pool(shape: PoolShape<Idx>, x_: Rc<Self>) -> Rc<PoolOp<Idx, V, AvgPool, Backend>>;
-  fn max_pool(shape: PoolShape<Idx>, x_: Rc<Self>) -> Rc<PoolOp<Idx, V, MaxPool, Backend>>;
+  fn _set_zero(&mut self) {
+    self.as_view_mut().set_constant(0.0);
+  }
 }
 
-pub fn avg_pool<Op, Idx, V, Backend>(shape: PoolShape<Idx>, x_: Rc<Op>) -> Rc<PoolOp<Idx, V, AvgPool, Backend>> where Op: AVar<AData<V>> + PoolExt<Idx, V, Backend>, Idx: ArrayIndex {
-  PoolExt::avg_pool(shape, x_)
-}
+impl<S> PackedArray for BatchArray1d<f32, S> where S: DerefMut<Target=[f32]> {
+  fn _packed_dim(&self) -> [usize; 4] {
+    [self.dim(), self.batch_size(), 1, 1]
+  }
 
-pub fn max_pool<Op, Idx, V, Backend>(shape: PoolShape<Idx>, x_: Rc<Op>) -> Rc<PoolOp<Idx, V, MaxPool, Backend>> where Op: AVar<AData<V>> + PoolExt<Idx, V, Backend>, Idx: ArrayIndex {
-  PoolExt::max_pool(shape, x_)
-}
+  fn _batch_size(&self) -> usize {
+    self.batch_size()
+  }
 
-pub struct PoolOp<Idx, V, Kernel, Backend> where Idx: ArrayIndex {
-  node_id:  NodeId,
-  stack:    OperatorStack,
-  shape:    PoolShape<Idx>,
-  x_:   Rc<AVar<AData<V>>>,
-  x:    AData<V>,
-  y:    AData<V>,
-  kernel:   Kernel,
-  backend:  Backend,
-}
+  fn _set_batch_size(&mut self, batch_sz: usize) {
+    self.set_batch_size(batch_sz);
+  }
 
-impl<Idx, V, Kernel, Backend> PoolOp<Idx, V, Kernel, Backend> where Idx: ArrayIndex {
-  pub fn new(shape: PoolShape<Idx>, x_: Rc<AVar<AData<V>>>, kernel: Kernel, backend: Backend, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> V>) -> Rc<Self> {
-    let node = NodeId::new();
-    let x = x_.data();
-    Rc::new(PoolOp{
-      node_id:  node,
-      stack:    OperatorStack::new(node, 2),
-      shape:    shape,
-      x_:   x_,
-      x:    x,
-      y:    AData::new(/*clk_horizon,*/ alloc.clone()),
-      //tmp:  AData::new(1, alloc),
-      kernel:   kernel,
-      backend:  backend,
-    })
+  fn _as_ptr(&self) -> *const f32 {
+    self.as_view().as_ptr()
   }
-}
 
-impl<Idx, V, Kernel, Backend> AVar<AData<V>> for PoolOp<Idx, V, Kernel, Backend> where PoolOp<Idx, V, Kernel, Backend>: AOp, Idx: ArrayIndex {
-  fn _owned_data(&self) -> &AData<V> {
-    &self.y
+  fn _as_mut_ptr(&mut self) -> *mut f32 {
+    self.as_view_mut().as_mut_ptr()
   }
-}
 
-pub struct GenPoolOp<Idx, V, Kernel, Backend> where Idx: ArrayIndex {
-  node_id:  NodeId,
-  stack:    OperatorStack,
-  shape:    PoolShape<Idx>,
-  x_:   Rc<AVar<AData<V>>>,
-  sel_: Rc<AVar<AData<V>>>,
-  x:    AData<V>,
-  sel:  AData<V>,
-  y:    AData<V>,
-  kernel:   Kernel,
-  backend:  Backend,
+  fn _set_zero(&mut self) {
+    self.as_view_mut().set_constant(0.0);
+  }
 }
 
-#[derive(Clone, Copy)]
-pub enum BatchStatsAverage {
-  Geometric(f64),
-  Arithmetic,
-  ArithmeticCountCutoff(usize),
-  ArithmeticRateCutoff(f64),
-}
+impl<S> PackedArray for BatchArray3d<f32, S> where S: DerefMut<Target=[f32]> {
+  fn _packed_dim(&self) -> [usize; 4] {
+    let dim = self.dim();
+    [dim.0, dim.1, dim.2, self.batch_size()]
+  }
 
-impl BatchStatsAverage {
-  pub fn rate(self, update_ct: usize) -> f64 {
-    let n = (update_ct + 1) as f64;
-    match self {
-      BatchStatsAverage::Geometric(rate) => rate,
-      BatchStatsAverage::Arithmetic => 1.0 / n,
-      BatchStatsAverage::ArithmeticCountCutoff(max_ct) => {
-        if update_ct >= max_ct { 0.0 }
-        else { 1.0 / n }
-      }
-      BatchStatsAverage::ArithmeticRateCutoff(max_rate) => {
-        let rate = 1.0 / n;
-        if rate >= max_rate { 0.0 }
-        else { rate }
-      }
-    }
+  fn _batch_size(&self) -> usize {
+    self.batch_size()
   }
-}
 
-#[derive(Clone, Copy)]
-pub enum BatchStatsUnbias {
-  Normalize,
-}
+  fn _set_batch_size(&mut self, batch_sz: usize) {
+    self.set_batch_size(batch_sz);
+  }
 
-#[derive(Clone, Copy)]
-pub struct BatchStatsConfig {
-  pub average:  BatchStatsAverage,
-  //pub unbias:   BatchStatsUnbias,
+  fn _as_ptr(&self) -> *const f32 {
+    self.as_view().as_ptr()
+  }
+
+  fn _as_mut_ptr(&mut self) -> *mut f32 {
+    self.as_view_mut().as_mut_ptr()
+  }
+
+  fn _set_zero(&mut self) {
+    self.as_view_mut().set_constant(0.0);
+  }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub enum BatchStatsMode {
-  PassThrough,
-  UseFixedRunningStats,
+pub struct SubBinaryKernel;
+pub struct MulBinaryKernel;
+pub struct DivBinaryKernel;
+pub struct MaxBinaryKernel;
+pub struct MinBinaryKernel;
+pub struct PowBinaryKernel;
+
+pub trait ElemBinaryKernel {
+  unsafe fn _fwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], y: *mut f32);
+  unsafe fn _bwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], dy: *const f32, dx1: *mut f32, dx2: *mut f32);
+  unsafe fn _rfwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], r_x1: *const f32, r_x2: *const f32, r_y: *mut f32);
 }
 
-pub struct BatchStatsState<Idx> where Idx: ArrayIndex {
-  pub reduce_axes:  Idx::Axes,
-  pub cfg:          BatchStatsConfig,
-  pub curr_txn:     Option<TxnId>,
-  pub inner_mode:   BatchStatsMode,
-  pub mode:         Rc<CopyConstant<bool>>,
-  pub batch_ct:     usize,
-  pub update_ct:    usize,
+impl ElemBinaryKernel for SubBinaryKernel {
+  unsafe fn _fwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], y: *mut f32) {
+    arraydiff_kernel_bcast_sub_fwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), y);
+  }
+
+  unsafe fn _bwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], dy: *const f32, dx1: *mut f32, dx2: *mut f32) {
+    arraydiff_kernel_bcast_sub_bwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), dy, dx1, dx2);
+  }
+
+  unsafe fn _rfwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], r_x1: *const f32, r_x2: *const f32, r_y: *mut f32) {
+    arraydiff_kernel_bcast_sub_rfwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), r_x1, r_x2, r_y);
+  }
 }
 
-impl<Idx> BatchStatsState<Idx> where Idx: ArrayIndex {
-  pub fn new(reduce_axes: Idx::Axes, cfg: BatchStatsConfig, mode: Rc<CopyConstant<bool>>) -> Self {
-    BatchStatsState{
-      reduce_axes:  reduce_axes,
-      cfg:          cfg,
-      curr_txn:     None,
-      inner_mode:   BatchStatsMode::PassThrough,
-      mode:         mode,
-      batch_ct:     0,
-      update_ct:    0,
-    }
+impl ElemBinaryKernel for MulBinaryKernel {
+  unsafe fn _fwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], y: *mut f32) {
+    arraydiff_kernel_bcast_mul_fwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), y);
   }
 
-  /*pub fn get_mode(&mut self, txn: TxnId) -> BatchStatsMode {
-    match self.curr_txn {
-      None => {
-        self.curr_txn = Some(txn);
-      }
-      Some(prev_txn) => {
-        if prev_txn != txn {
-          self.curr_txn = Some(txn);
-        }
-      }
-    }
-    self.inner_mode
+  unsafe fn _bwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], dy: *const f32, dx1: *mut f32, dx2: *mut f32) {
+    arraydiff_kernel_bcast_mul_bwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), dy, dx1, dx2);
   }
 
-  pub fn set_mode(&mut self, txn: TxnId, mode: BatchStatsMode) {
-    match self.curr_txn {
-      None => {
-        self.curr_txn = Some(txn);
-        self.inner_mode = mode;
-      }
-      Some(prev_txn) => {
-        if prev_txn != txn {
-          self.curr_txn = Some(txn);
-          self.inner_mode = mode;
-        } else {
-          assert_eq!(self.inner_mode, mode);
-        }
-      }
-    }
-  }*/
+  unsafe fn _rfwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], r_x1: *const f32, r_x2: *const f32, r_y: *mut f32) {
+    arraydiff_kernel_bcast_mul_rfwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), r_x1, r_x2, r_y);
+  }
 }
 
-pub struct BatchStatsControl {
-  mode: Rc<CopyConstant<bool>>,
-  ops:  Vec<Rc<BatchStatsOpExt>>,
-  batch_ops:    Vec<Rc<AOp>>,
-  batch_vars:   VarSet,
-  acc_ops:      Vec<Rc<AOp>>,
-  acc_vars:     VarSet,
-  fixed_ops:    Vec<Rc<AOp>>,
-  fixed_vars:   VarSet,
+impl ElemBinaryKernel for DivBinaryKernel {
+  unsafe fn _fwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], y: *mut f32) {
+    arraydiff_kernel_bcast_div_fwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), y);
+  }
+
+  unsafe fn _bwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], dy: *const f32, dx1: *mut f32, dx2: *mut f32) {
+    arraydiff_kernel_bcast_div_bwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), dy, dx1, dx2);
+  }
+
+  unsafe fn _rfwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], r_x1: *const f32, r_x2: *const f32, r_y: *mut f32) {
+    arraydiff_kernel_bcast_div_rfwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), r_x1, r_x2, r_y);
+  }
 }
 
-impl BatchStatsControl {
-  pub fn new() -> Self {
-    BatchStatsControl{
-      mode: Rc::new(CopyConstant{var: TxnCopyVar::new()}),
-      ops:  vec![],
-      batch_ops:    vec![],
-      batch_vars:   var_set(),
-      acc_ops:      vec![],
-      acc_vars:     var_set(),
-      fixed_ops:    vec![],
-      fixed_vars:   var_set(),
-    }
+impl ElemBinaryKernel for MaxBinaryKernel {
+  unsafe fn _fwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], y: *mut f32) {
+    arraydiff_kernel_bcast_max_fwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), y);
   }
 
-  pub fn mode(&self) -> Rc<CopyConstant<bool>> {
-    self.mode.clone()
+  unsafe fn _bwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], dy: *const f32, dx1: *mut f32, dx2: *mut f32) {
+    arraydiff_kernel_bcast_max_bwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), dy, dx1, dx2);
   }
 
-  pub fn set_mode(&self, txn: TxnId, mode_value: bool) {
-    self.mode.var.set(txn, mode_value);
+  unsafe fn _rfwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], r_x1: *const f32, r_x2: *const f32, r_y: *mut f32) {
+    arraydiff_kernel_bcast_max_rfwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), r_x1, r_x2, r_y);
   }
+}
 
-  pub fn get_mode(&self, txn: TxnId) -> bool {
-    self.mode.var.persist(txn);
-    self.mode.var.get(txn)
+impl ElemBinaryKernel for MinBinaryKernel {
+  unsafe fn _fwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], y: *mut f32) {
+    arraydiff_kernel_bcast_min_fwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), y);
   }
 
-  /*pub fn accumulator_vars(&self) -> VarSet {
-    self.acc_vars.clone()
-  }*/
+  unsafe fn _bwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], dy: *const f32, dx1: *mut f32, dx2: *mut f32) {
+    arraydiff_kernel_bcast_min_bwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), dy, dx1, dx2);
+  }
 
-  pub fn dim(&mut self, txn: TxnId) -> usize {
-    let mut stats_dim = 0;
-    self.batch_vars.unmask_all();
-    for op in self.batch_ops.iter() {
-      stats_dim += op.incremental_val_size(txn, &mut self.batch_vars);
-    }
-    self.batch_vars.unmask_all();
-    stats_dim
+  unsafe fn _rfwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], r_x1: *const f32, r_x2: *const f32, r_y: *mut f32) {
+    arraydiff_kernel_bcast_min_rfwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), r_x1, r_x2, r_y);
   }
+}
 
-  /*pub fn fixed_vars(&self) -> VarSet {
-    self.fixed_vars.clone()
-  }*/
+impl ElemBinaryKernel for PowBinaryKernel {
+  unsafe fn _fwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], y: *mut f32) {
+    arraydiff_kernel_bcast_pow_fwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), y);
+  }
 
-  pub fn configure(&self, f: &Fn(&mut BatchStatsConfig)) {
-    for op in self.ops.iter() {
-      op._configure(f);
-    }
+  unsafe fn _bwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], dy: *const f32, dx1: *mut f32, dx2: *mut f32) {
+    arraydiff_kernel_bcast_pow_bwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), dy, dx1, dx2);
   }
 
-  pub fn reset_accumulators(&self, txn: TxnId) {
-    for op in self.ops.iter() {
-      op._reset_accumulators(txn);
-    }
+  unsafe fn _rfwd_f32(&self, x1_dim: &[usize; 4], x1: *const f32, x2_dim: &[usize; 4], x2: *const f32, y_dim: &[usize; 4], r_x1: *const f32, r_x2: *const f32, r_y: *mut f32) {
+    arraydiff_kernel_bcast_pow_rfwd_f32(x1_dim.as_ptr(), x1, x2_dim.as_ptr(), x2, y_dim.as_ptr(), r_x1, r_x2, r_y);
   }
+}
 
-  pub fn accumulate(&self, txn: TxnId) {
-    for op in self.ops.iter() {
-      op._accumulate(txn);
-    }
+/// Broadcasting rules for elementwise binary ops. Arrays are viewed as packed
+/// 4d arrays; axes of size 1 are broadcast. An `Array1d` operand aligns with
+/// the feature axis of the other operand: axis 0 of a `BatchArray1d`, or the
+/// channel axis of a `BatchArray3d`.
+pub trait ElemBinaryArray: PackedArray {
+  fn _feature_axis() -> usize;
+
+  fn _bcast_dim(&self, feature_axis: usize) -> [usize; 4] {
+    assert_eq!(Self::_feature_axis(), feature_axis);
+    self._packed_dim()
   }
+}
 
-  pub fn update_stats(&self, prev_txn: TxnId, next_txn: TxnId) {
-    for op in self.ops.iter() {
-      op._update_stats(prev_txn, next_txn);
-    }
+impl<S> ElemBinaryArray for Array1d<f32, S> where S: DerefMut<Target=[f32]> {
+  fn _feature_axis() -> usize {
+    0
   }
 
-  pub fn store_accumulators(&mut self, txn: TxnId, mut offset: usize, writer: &mut Any) -> usize {
-    self.acc_vars.unmask_all();
-    for op in self.acc_ops.iter() {
-      offset = op._store_val(txn, &mut self.acc_vars, offset, writer);
-    }
-    self.acc_vars.unmask_all();
-    offset
+  fn _bcast_dim(&self, feature_axis: usize) -> [usize; 4] {
+    let mut dim = [1, 1, 1, 1];
+    dim[feature_axis] = self.dim();
+    dim
   }
+}
 
-  pub fn load_fixed_stats(&mut self, txn: TxnId, mut offset: usize, reader: &mut Any) -> usize {
-    self.fixed_vars.unmask_all();
-    for op in self.fixed_ops.iter() {
-      offset = op._load_val(txn, &mut self.fixed_vars, offset, reader);
-    }
-    self.fixed_vars.unmask_all();
-    offset
+impl<S> ElemBinaryArray for BatchArray1d<f32, S> where S: DerefMut<Target=[f32]> {
+  fn _feature_axis() -> usize {
+    0
   }
 }
 
-pub trait BatchStatsOpExt {
-  fn _configure(&self, f: &Fn(&mut BatchStatsConfig));
-  fn _reset_accumulators(&self, txn: TxnId);
-  fn _accumulate(&self, txn: TxnId);
-  fn _update_stats(&self, prev_txn: TxnId, next_txn: TxnId);
+impl<S> ElemBinaryArray for BatchArray3d<f32, S> where S: DerefMut<Target=[f32]> {
+  fn _feature_axis() -> usize {
+    2
+  }
 }
 
-#[derive(Clone)]
-pub struct BatchStatsOutput<M> {
-  pub mean_batch:   Rc<AVar<AData<M>>>,
-  pub var_batch:    Rc<AVar<AData<M>>>,
-  pub mean_fixed:   Rc<AVar<AData<M>>>,
+fn _bcast_dim_max(lhs: usize, rhs: usize) -> usize {
+  assert!(lhs == rhs || lhs == 1 || rhs == 1);
+  max(lhs, rhs)
+}
+
+pub trait ElemBinaryExt<A1, A2, B> {
+  fn elem_binary<K>(&self, kernel: K, x2_: Rc<AVar<AData<A2>>>) -> Rc<ElemBinaryOp<A1, A2, B, K>> where K: 'static + ElemBinaryKernel;
+
+  fn sub(&self, x2_: Rc<AVar<AData<A2>>>) -> Rc<ElemBinaryOp<A1, A2, B, SubBinaryKernel>> {
+    self.elem_binary(SubBinaryKernel, x2_)
+  }
+
+  fn elem_mul(&self, x2_: Rc<AVar<AData<A2>>>) -> Rc<ElemBinaryOp<A1, A2, B, MulBinaryKernel>> {
+    self.elem_binary(MulBinaryKernel, x2_)
+  }
+
+  fn elem_div(&self, x2_: Rc<AVar<AData<A2>>>) -> Rc<ElemBinaryOp<A1, A2, B, DivBinaryKernel>> {
+    self.elem_binary(DivBinaryKernel, x2_)
+  }
+
+  fn elem_max(&self, x2_: Rc<AVar<AData<A2>>>) -> Rc<ElemBinaryOp<A1, A2, B, MaxBinaryKernel>> {
+    self.elem_binary(MaxBinaryKernel, x2_)
+  }
+
+  fn elem_min(&self, x2_: Rc<AVar<AData<A2>>>) -> Rc<ElemBinaryOp<A1, A2, B, MinBinaryKernel>> {
+    self.elem_binary(MinBinaryKernel, x2_)
+  }
+
+  fn pow(&self, x2_: Rc<AVar<AData<A2>>>) -> Rc<ElemBinaryOp<A1, A2, B, PowBinaryKernel>> {
+    self.elem_binary(PowBinaryKernel, x2_)
+  }
+}
+
+impl<Op, S> ElemBinaryExt<Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
+  fn elem_binary<K>(&self, kernel: K, x2_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<ElemBinaryOp<Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, K>> where K: 'static + ElemBinaryKernel {
+    //let clk_horizon = self.data().horizon();
+    ElemBinaryOp::new(kernel, self.clone(), x2_.clone(), /*clk_horizon,*/ {
+      let x1 = self.clone().data();
+      let x2 = x2_.data();
+      Rc::new(move |txn, node| {
+        let dim = _bcast_dim_max(x1.val.get(txn, node).dim(), x2.val.get(txn, node).dim());
+        let buf = <S as ArrayStorage<usize>>::alloc(dim);
+        Array1d::from_storage(dim, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ElemBinaryExt<BatchArray1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn elem_binary<K>(&self, kernel: K, x2_: Rc<AVar<AData<BatchArray1d<f32, S>>>>) -> Rc<ElemBinaryOp<BatchArray1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>, K>> where K: 'static + ElemBinaryKernel {
+    //let clk_horizon = self.data().horizon();
+    ElemBinaryOp::new(kernel, self.clone(), x2_.clone(), /*clk_horizon,*/ {
+      let x1 = self.clone().data();
+      let x2 = x2_.data();
+      Rc::new(move |txn, node| {
+        let dim = _bcast_dim_max(x1.val.get(txn, node).dim(), x2.val.get(txn, node).dim());
+        let batch_sz = _bcast_dim_max(x1.val.get(txn, node).batch_size(), x2.val.get(txn, node).batch_size());
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ElemBinaryExt<BatchArray1d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn elem_binary<K>(&self, kernel: K, x2_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<ElemBinaryOp<BatchArray1d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, K>> where K: 'static + ElemBinaryKernel {
+    //let clk_horizon = self.data().horizon();
+    ElemBinaryOp::new(kernel, self.clone(), x2_.clone(), /*clk_horizon,*/ {
+      let x1 = self.clone().data();
+      let x2 = x2_.data();
+      Rc::new(move |txn, node| {
+        let dim = _bcast_dim_max(x1.val.get(txn, node).dim(), x2.val.get(txn, node).dim());
+        let batch_sz = x1.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ElemBinaryExt<Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn elem_binary<K>(&self, kernel: K, x2_: Rc<AVar<AData<BatchArray1d<f32, S>>>>) -> Rc<ElemBinaryOp<Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>, K>> where K: 'static + ElemBinaryKernel {
+    //let clk_horizon = self.data().horizon();
+    ElemBinaryOp::new(kernel, self.clone(), x2_.clone(), /*clk_horizon,*/ {
+      let x1 = self.clone().data();
+      let x2 = x2_.data();
+      Rc::new(move |txn, node| {
+        let dim = _bcast_dim_max(x1.val.get(txn, node).dim(), x2.val.get(txn, node).dim());
+        let batch_sz = x2.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ElemBinaryExt<BatchArray3d<f32, S>, BatchArray3d<f32, S>, BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn elem_binary<K>(&self, kernel: K, x2_: Rc<AVar<AData<BatchArray3d<f32, S>>>>) -> Rc<ElemBinaryOp<BatchArray3d<f32, S>, BatchArray3d<f32, S>, BatchArray3d<f32, S>, K>> where K: 'static + ElemBinaryKernel {
+    //let clk_horizon = self.data().horizon();
+    ElemBinaryOp::new(kernel, self.clone(), x2_.clone(), /*clk_horizon,*/ {
+      let x1 = self.clone().data();
+      let x2 = x2_.data();
+      Rc::new(move |txn, node| {
+        let x1_dim = x1.val.get(txn, node).dim();
+        let x2_dim = x2.val.get(txn, node).dim();
+        let dim = (
+            _bcast_dim_max(x1_dim.0, x2_dim.0),
+            _bcast_dim_max(x1_dim.1, x2_dim.1),
+            _bcast_dim_max(x1_dim.2, x2_dim.2),
+        );
+        let batch_sz = _bcast_dim_max(x1.val.get(txn, node).batch_size(), x2.val.get(txn, node).batch_size());
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim.flat_len(), batch_sz);
+        BatchArray3d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ElemBinaryExt<BatchArray3d<f32, S>, Array1d<f32, S>, BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn elem_binary<K>(&self, kernel: K, x2_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<ElemBinaryOp<BatchArray3d<f32, S>, Array1d<f32, S>, BatchArray3d<f32, S>, K>> where K: 'static + ElemBinaryKernel {
+    //let clk_horizon = self.data().horizon();
+    ElemBinaryOp::new(kernel, self.clone(), x2_.clone(), /*clk_horizon,*/ {
+      let x1 = self.clone().data();
+      let x2 = x2_.data();
+      Rc::new(move |txn, node| {
+        let x1_dim = x1.val.get(txn, node).dim();
+        let dim = (x1_dim.0, x1_dim.1, _bcast_dim_max(x1_dim.2, x2.val.get(txn, node).dim()));
+        let batch_sz = x1.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim.flat_len(), batch_sz);
+        BatchArray3d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ElemBinaryExt<Array1d<f32, S>, BatchArray3d<f32, S>, BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn elem_binary<K>(&self, kernel: K, x2_: Rc<AVar<AData<BatchArray3d<f32, S>>>>) -> Rc<ElemBinaryOp<Array1d<f32, S>, BatchArray3d<f32, S>, BatchArray3d<f32, S>, K>> where K: 'static + ElemBinaryKernel {
+    //let clk_horizon = self.data().horizon();
+    ElemBinaryOp::new(kernel, self.clone(), x2_.clone(), /*clk_horizon,*/ {
+      let x1 = self.clone().data();
+      let x2 = x2_.data();
+      Rc::new(move |txn, node| {
+        let x2_dim = x2.val.get(txn, node).dim();
+        let dim = (x2_dim.0, x2_dim.1, _bcast_dim_max(x1.val.get(txn, node).dim(), x2_dim.2));
+        let batch_sz = x2.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim.flat_len(), batch_sz);
+        BatchArray3d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+pub struct ElemBinaryOp<A1, A2, B, Kernel> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  x1_:  Rc<AVar<AData<A1>>>,
+  x2_:  Rc<AVar<AData<A2>>>,
+  x1:   AData<A1>,
+  x2:   AData<A2>,
+  y:    AData<B>,
+  kernel:   Kernel,
+}
+
+impl<A1, A2, B, Kernel> ElemBinaryOp<A1, A2, B, Kernel> {
+  pub fn new<F>(kernel: Kernel, x1_: Rc<AVar<AData<A1>>>, x2_: Rc<AVar<AData<A2>>>, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<Self> where F: 'static + Fn(TxnId, NodeId) -> B {
+    let node = NodeId::new();
+    let x1 = x1_.data();
+    let x2 = x2_.data();
+    Rc::new(ElemBinaryOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 2),
+      x1_:  x1_,
+      x2_:  x2_,
+      x1:   x1,
+      x2:   x2,
+      y:    AData::new(/*clk_horizon,*/ alloc),
+      kernel:   kernel,
+    })
+  }
+}
+
+impl<A1, A2, B, Kernel> AVar<AData<B>> for ElemBinaryOp<A1, A2, B, Kernel> where ElemBinaryOp<A1, A2, B, Kernel>: AOp {
+  default fn _owned_data(&self) -> &AData<B> {
+    &self.y
+  }
+}
+
+impl<A1, A2, B, Kernel> AOp for ElemBinaryOp<A1, A2, B, Kernel> where A1: ElemBinaryArray, A2: ElemBinaryArray, B: ElemBinaryArray, Kernel: ElemBinaryKernel {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x1_._push(epoch, apply);
+      self.x2_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x2_._pop(epoch, apply);
+      self.x1_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let axis = max(A1::_feature_axis(), A2::_feature_axis());
+      let batch_sz = max(self.x1.val.get(txn, node)._batch_size(), self.x2.val.get(txn, node)._batch_size());
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      let x1_dim = self.x1.val.get(txn, node)._bcast_dim(axis);
+      let x2_dim = self.x2.val.get(txn, node)._bcast_dim(axis);
+      let y_dim = self.y.val.get_excl(txn, node)._bcast_dim(axis);
+      for k in 0 .. 4 {
+        assert_eq!(y_dim[k], _bcast_dim_max(x1_dim[k], x2_dim[k]));
+      }
+      unsafe { self.kernel._fwd_f32(
+          &x1_dim, self.x1.val.get(txn, node)._as_ptr(),
+          &x2_dim, self.x2.val.get(txn, node)._as_ptr(),
+          &y_dim, self.y.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let axis = max(A1::_feature_axis(), A2::_feature_axis());
+    let x1_dim = self.x1.val.get(txn, node)._bcast_dim(axis);
+    let x2_dim = self.x2.val.get(txn, node)._bcast_dim(axis);
+    let y_dim = self.y.grad.get(txn, node)._bcast_dim(axis);
+    // The kernels reduce the gradient over broadcast axes by accumulating
+    // into the same input element.
+    let x1_batch_sz = self.x1.val.get(txn, node)._batch_size();
+    if self.x1.grad.accumulate(txn, node, |grad| { grad._set_batch_size(x1_batch_sz); grad._set_zero(); }) {
+      unsafe { self.kernel._bwd_f32(
+          &x1_dim, self.x1.val.get(txn, node)._as_ptr(),
+          &x2_dim, self.x2.val.get(txn, node)._as_ptr(),
+          &y_dim, self.y.grad.get(txn, node)._as_ptr(),
+          self.x1.grad.get_mut(txn, node)._as_mut_ptr(),
+          null_mut(),
+      ) };
+    }
+    let x2_batch_sz = self.x2.val.get(txn, node)._batch_size();
+    if self.x2.grad.accumulate(txn, node, |grad| { grad._set_batch_size(x2_batch_sz); grad._set_zero(); }) {
+      unsafe { self.kernel._bwd_f32(
+          &x1_dim, self.x1.val.get(txn, node)._as_ptr(),
+          &x2_dim, self.x2.val.get(txn, node)._as_ptr(),
+          &y_dim, self.y.grad.get(txn, node)._as_ptr(),
+          null_mut(),
+          self.x2.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      let axis = max(A1::_feature_axis(), A2::_feature_axis());
+      let batch_sz = self.y.val.get(txn, node)._batch_size();
+      self.y.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+      let x1_dim = self.x1.val.get(txn, node)._bcast_dim(axis);
+      let x2_dim = self.x2.val.get(txn, node)._bcast_dim(axis);
+      let y_dim = self.y.val.get(txn, node)._bcast_dim(axis);
+      unsafe { self.kernel._rfwd_f32(
+          &x1_dim, self.x1.val.get(txn, node)._as_ptr(),
+          &x2_dim, self.x2.val.get(txn, node)._as_ptr(),
+          &y_dim,
+          self.x1.r_val.get(txn, node)._as_ptr(),
+          self.x2.r_val.get(txn, node)._as_ptr(),
+          self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+}
+
+pub struct SumReduceKernel;
+pub struct MeanReduceKernel;
+pub struct MaxReduceKernel;
+pub struct MinReduceKernel;
+pub struct L2NormReduceKernel;
+pub struct LogSumExpReduceKernel;
+
+pub trait ReduceKernel {
+  unsafe fn _fwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *mut f32);
+  unsafe fn _bwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, dy: *const f32, dx: *mut f32);
+  unsafe fn _rfwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, r_x: *const f32, r_y: *mut f32);
+}
+
+impl ReduceKernel for SumReduceKernel {
+  unsafe fn _fwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *mut f32) {
+    arraydiff_kernel_reduce_sum_fwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y);
+  }
+
+  unsafe fn _bwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_reduce_sum_bwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y, dy, dx);
+  }
+
+  unsafe fn _rfwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, r_x: *const f32, r_y: *mut f32) {
+    arraydiff_kernel_reduce_sum_rfwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y, r_x, r_y);
+  }
+}
+
+impl ReduceKernel for MeanReduceKernel {
+  unsafe fn _fwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *mut f32) {
+    arraydiff_kernel_reduce_mean_fwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y);
+  }
+
+  unsafe fn _bwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_reduce_mean_bwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y, dy, dx);
+  }
+
+  unsafe fn _rfwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, r_x: *const f32, r_y: *mut f32) {
+    arraydiff_kernel_reduce_mean_rfwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y, r_x, r_y);
+  }
+}
+
+impl ReduceKernel for MaxReduceKernel {
+  unsafe fn _fwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *mut f32) {
+    arraydiff_kernel_reduce_max_fwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y);
+  }
+
+  unsafe fn _bwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_reduce_max_bwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y, dy, dx);
+  }
+
+  unsafe fn _rfwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, r_x: *const f32, r_y: *mut f32) {
+    arraydiff_kernel_reduce_max_rfwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y, r_x, r_y);
+  }
+}
+
+impl ReduceKernel for MinReduceKernel {
+  unsafe fn _fwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *mut f32) {
+    arraydiff_kernel_reduce_min_fwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y);
+  }
+
+  unsafe fn _bwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_reduce_min_bwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y, dy, dx);
+  }
+
+  unsafe fn _rfwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, r_x: *const f32, r_y: *mut f32) {
+    arraydiff_kernel_reduce_min_rfwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y, r_x, r_y);
+  }
+}
+
+impl ReduceKernel for L2NormReduceKernel {
+  unsafe fn _fwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *mut f32) {
+    arraydiff_kernel_reduce_l2_norm_fwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y);
+  }
+
+  unsafe fn _bwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_reduce_l2_norm_bwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y, dy, dx);
+  }
+
+  unsafe fn _rfwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, r_x: *const f32, r_y: *mut f32) {
+    arraydiff_kernel_reduce_l2_norm_rfwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y, r_x, r_y);
+  }
+}
+
+impl ReduceKernel for LogSumExpReduceKernel {
+  unsafe fn _fwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *mut f32) {
+    arraydiff_kernel_reduce_logsumexp_fwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y);
+  }
+
+  unsafe fn _bwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, dy: *const f32, dx: *mut f32) {
+    arraydiff_kernel_reduce_logsumexp_bwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y, dy, dx);
+  }
+
+  unsafe fn _rfwd_f32(&self, x_dim: &[usize; 4], x: *const f32, y_dim: &[usize; 4], y: *const f32, r_x: *const f32, r_y: *mut f32) {
+    arraydiff_kernel_reduce_logsumexp_rfwd_f32(x_dim.as_ptr(), x, y_dim.as_ptr(), y, r_x, r_y);
+  }
+}
+
+/// Reductions over a set of axes. Axes index the dimensions of the input
+/// array, excluding the batch axis, and may be given in any order. The output
+/// type determines how many axes must be reduced; an axis set which does not
+/// fit the output type is rejected when the op is built.
+pub trait ReduceExt<A, B> {
+  fn reduce<K>(&self, kernel: K, axes: Vec<usize>) -> Rc<ReduceOp<A, B, K>> where K: 'static + ReduceKernel;
+
+  fn reduce_sum(&self, axes: Vec<usize>) -> Rc<ReduceOp<A, B, SumReduceKernel>> {
+    self.reduce(SumReduceKernel, axes)
+  }
+
+  fn reduce_mean(&self, axes: Vec<usize>) -> Rc<ReduceOp<A, B, MeanReduceKernel>> {
+    self.reduce(MeanReduceKernel, axes)
+  }
+
+  fn reduce_max(&self, axes: Vec<usize>) -> Rc<ReduceOp<A, B, MaxReduceKernel>> {
+    self.reduce(MaxReduceKernel, axes)
+  }
+
+  fn reduce_min(&self, axes: Vec<usize>) -> Rc<ReduceOp<A, B, MinReduceKernel>> {
+    self.reduce(MinReduceKernel, axes)
+  }
+
+  fn l2_norm(&self, axes: Vec<usize>) -> Rc<ReduceOp<A, B, L2NormReduceKernel>> {
+    self.reduce(L2NormReduceKernel, axes)
+  }
+
+  fn logsumexp(&self, axes: Vec<usize>) -> Rc<ReduceOp<A, B, LogSumExpReduceKernel>> {
+    self.reduce(LogSumExpReduceKernel, axes)
+  }
+}
+
+/// Sorts and checks a reduction axis set against the number of non-batch
+/// axes of the input and, if the output type fixes it, the number of axes
+/// which must be reduced.
+fn _reduce_axes(axes: Vec<usize>, ndim: usize, num_reduced: Option<usize>) -> Vec<usize> {
+  let mut sorted_axes = axes.clone();
+  sorted_axes.sort();
+  sorted_axes.dedup();
+  if sorted_axes.len() != axes.len() {
+    panic!("reduce: repeated axis in {:?}", axes);
+  }
+  if let Some(&axis) = sorted_axes.last() {
+    if axis >= ndim {
+      panic!("reduce: axis {} is out of range for an input with {} axes", axis, ndim);
+    }
+  }
+  if let Some(num_reduced) = num_reduced {
+    if sorted_axes.len() != num_reduced {
+      panic!("reduce: the output type needs {} of the {} input axes reduced, but got axes {:?}", num_reduced, ndim, axes);
+    }
+  }
+  sorted_axes
+}
+
+impl<Op, S> ReduceExt<Array1d<f32, S>, f32> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> {
+  fn reduce<K>(&self, kernel: K, axes: Vec<usize>) -> Rc<ReduceOp<Array1d<f32, S>, f32, K>> where K: 'static + ReduceKernel {
+    let axes = _reduce_axes(axes, 1, Some(1));
+    //let clk_horizon = self.data().horizon();
+    ReduceOp::new(kernel, axes, self.clone(), /*clk_horizon,*/ Rc::new(|_, _| 0.0))
+  }
+}
+
+impl<Op, S> ReduceExt<Array2d<f32, S>, f32> for Rc<Op> where Op: 'static + AVar<AData<Array2d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> {
+  fn reduce<K>(&self, kernel: K, axes: Vec<usize>) -> Rc<ReduceOp<Array2d<f32, S>, f32, K>> where K: 'static + ReduceKernel {
+    let axes = _reduce_axes(axes, 2, Some(2));
+    //let clk_horizon = self.data().horizon();
+    ReduceOp::new(kernel, axes, self.clone(), /*clk_horizon,*/ Rc::new(|_, _| 0.0))
+  }
+}
+
+impl<Op, S> ReduceExt<Array2d<f32, S>, Array1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Array2d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
+  fn reduce<K>(&self, kernel: K, axes: Vec<usize>) -> Rc<ReduceOp<Array2d<f32, S>, Array1d<f32, S>, K>> where K: 'static + ReduceKernel {
+    let axes = _reduce_axes(axes, 2, Some(1));
+    let axis = axes[0];
+    //let clk_horizon = self.data().horizon();
+    ReduceOp::new(kernel, axes, self.clone(), /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let x_dim = x.val.get(txn, node).dim();
+        let dim = match axis {
+          0 => x_dim.1,
+          1 => x_dim.0,
+          _ => unreachable!(),
+        };
+        let buf = <S as ArrayStorage<usize>>::alloc(dim);
+        Array1d::from_storage(dim, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ReduceExt<BatchArray1d<f32, S>, Batch<f32>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> {
+  fn reduce<K>(&self, kernel: K, axes: Vec<usize>) -> Rc<ReduceOp<BatchArray1d<f32, S>, Batch<f32>, K>> where K: 'static + ReduceKernel {
+    let axes = _reduce_axes(axes, 1, Some(1));
+    //let clk_horizon = self.data().horizon();
+    ReduceOp::new(kernel, axes, self.clone(), /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let mut y = Batch::new();
+        y.set_batch_size(batch_sz, 0.0);
+        y
+      })
+    })
+  }
+}
+
+impl<Op, S> ReduceExt<BatchArray3d<f32, S>, Batch<f32>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> {
+  fn reduce<K>(&self, kernel: K, axes: Vec<usize>) -> Rc<ReduceOp<BatchArray3d<f32, S>, Batch<f32>, K>> where K: 'static + ReduceKernel {
+    let axes = _reduce_axes(axes, 3, Some(3));
+    //let clk_horizon = self.data().horizon();
+    ReduceOp::new(kernel, axes, self.clone(), /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let mut y = Batch::new();
+        y.set_batch_size(batch_sz, 0.0);
+        y
+      })
+    })
+  }
+}
+
+impl<Op, S> ReduceExt<BatchArray3d<f32, S>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn reduce<K>(&self, kernel: K, axes: Vec<usize>) -> Rc<ReduceOp<BatchArray3d<f32, S>, BatchArray1d<f32, S>, K>> where K: 'static + ReduceKernel {
+    let axes = _reduce_axes(axes, 3, Some(2));
+    let keep_axis = (0 .. 3).find(|axis| !axes.contains(axis)).unwrap();
+    //let clk_horizon = self.data().horizon();
+    ReduceOp::new(kernel, axes, self.clone(), /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let x_dim = x.val.get(txn, node).dim();
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let dim = match keep_axis {
+          0 => x_dim.0,
+          1 => x_dim.1,
+          2 => x_dim.2,
+          _ => unreachable!(),
+        };
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ReduceExt<BatchArray3d<f32, S>, BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn reduce<K>(&self, kernel: K, axes: Vec<usize>) -> Rc<ReduceOp<BatchArray3d<f32, S>, BatchArray3d<f32, S>, K>> where K: 'static + ReduceKernel {
+    let axes = _reduce_axes(axes, 3, None);
+    //let clk_horizon = self.data().horizon();
+    ReduceOp::new(kernel, axes.clone(), self.clone(), /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let x_dim = x.val.get(txn, node).dim();
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let mut dim = [x_dim.0, x_dim.1, x_dim.2];
+        for &axis in axes.iter() {
+          dim[axis] = 1;
+        }
+        let dim = (dim[0], dim[1], dim[2]);
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim.flat_len(), batch_sz);
+        BatchArray3d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+pub struct ReduceOp<A, B, Kernel> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  axes:     Vec<usize>,
+  x_:   Rc<AVar<AData<A>>>,
+  x:    AData<A>,
+  y:    AData<B>,
+  kernel:   Kernel,
+}
+
+impl<A, B, Kernel> ReduceOp<A, B, Kernel> {
+  pub fn new<F>(kernel: Kernel, axes: Vec<usize>, x_: Rc<AVar<AData<A>>>, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<Self> where F: 'static + Fn(TxnId, NodeId) -> B {
+    let node = NodeId::new();
+    let x = x_.data();
+    Rc::new(ReduceOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 1),
+      axes:     axes,
+      x_:   x_,
+      x:    x,
+      y:    AData::new(/*clk_horizon,*/ alloc),
+      kernel:   kernel,
+    })
+  }
+
+  fn _reduce_dim(&self, x_dim: &[usize; 4]) -> [usize; 4] {
+    let mut y_dim = *x_dim;
+    for &axis in self.axes.iter() {
+      y_dim[axis] = 1;
+    }
+    y_dim
+  }
+}
+
+impl<A, B, Kernel> AVar<AData<B>> for ReduceOp<A, B, Kernel> where ReduceOp<A, B, Kernel>: AOp {
+  default fn _owned_data(&self) -> &AData<B> {
+    &self.y
+  }
+}
+
+impl<A, B, Kernel> AOp for ReduceOp<A, B, Kernel> where A: PackedArray, B: PackedArray, Kernel: ReduceKernel {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let x_dim = self.x.val.get(txn, node)._packed_dim();
+      let y_dim = self._reduce_dim(&x_dim);
+      let batch_sz = self.x.val.get(txn, node)._batch_size();
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      assert_eq!(y_dim.iter().product::<usize>(), self.y.val.get_excl(txn, node)._packed_dim().iter().product::<usize>());
+      unsafe { self.kernel._fwd_f32(
+          &x_dim, self.x.val.get(txn, node)._as_ptr(),
+          &y_dim, self.y.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let y_dim = self._reduce_dim(&x_dim);
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { self.kernel._bwd_f32(
+          &x_dim, self.x.val.get(txn, node)._as_ptr(),
+          &y_dim, self.y.val.get(txn, node)._as_ptr(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      let x_dim = self.x.val.get(txn, node)._packed_dim();
+      let y_dim = self._reduce_dim(&x_dim);
+      let batch_sz = self.x.val.get(txn, node)._batch_size();
+      self.y.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+      unsafe { self.kernel._rfwd_f32(
+          &x_dim, self.x.val.get(txn, node)._as_ptr(),
+          &y_dim, self.y.val.get(txn, node)._as_ptr(),
+          self.x.r_val.get(txn, node)._as_ptr(),
+          self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+}
+
+pub struct TransformOp<A, B, Transform> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  x_:   Rc<AVar<AData<A>>>,
+  x:    AData<A>,
+  y:    AData<B>,
+  kernel:   Transform,
+  tng_: RefCell<Option<Rc<AVar<AData<B>>>>>,
+}
+
+pub struct CastTransform;
+pub struct FlattenTransform;
+pub struct ReshapeTransform<Idx> {
+  pub dim:  Idx,
+}
+
+pub struct ZeroPadTransform {
+  pub axis: usize,
+  pub dim:  usize,
+}
+
+pub trait CastExt<A, B> {
+  fn cast(&self) -> Rc<TransformOp<A, B, CastTransform>>;
+}
+
+pub trait FlattenExt<A, B> {
+  fn flatten(&self) -> Rc<TransformOp<A, B, FlattenTransform>>;
+}
+
+pub trait ReshapeExt<Idx, A, B> {
+  fn reshape(&self, dim: Idx) -> Rc<TransformOp<A, B, ReshapeTransform<Idx>>>;
+
+  fn reify(&self, dim: Idx) -> Rc<TransformOp<A, B, ReshapeTransform<Idx>>> {
+    self.reshape(dim)
+  }
+}
+
+pub trait ZeroPadExt<A, B> {
+  fn zero_pad(&self, axis: usize, dim: usize) -> Rc<TransformOp<A, B, ZeroPadTransform>>;
+}
+
+impl<A, B, Transform> TransformOp<A, B, Transform> {
+  pub fn new<F>(x_: Rc<AVar<AData<A>>>, transform: Transform, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<Self> where F: 'static + Fn(TxnId, NodeId) -> B {
+    let node = NodeId::new();
+    let x = x_.data();
+    Rc::new(TransformOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 1),
+      x_:   x_,
+      x:    x,
+      y:    AData::new(/*clk_horizon,*/ alloc),
+      kernel:   transform,
+      tng_: RefCell::new(None),
+    })
+  }
+}
+
+impl<A, B, Transform> AVar<AData<B>> for TransformOp<A, B, Transform> where TransformOp<A, B, Transform>: AOp {
+  default fn _owned_data(&self) -> &AData<B> {
+    &self.y
+  }
+}
+
+impl<S> FlattenExt<Array3d<f32, S>, Array1d<f32, S>> for Rc<AVar<AData<Array3d<f32, S>>>> where S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
+  fn flatten(&self) -> Rc<TransformOp<Array3d<f32, S>, Array1d<f32, S>, FlattenTransform>> {
+    //let clk_horizon = self.data().horizon();
+    TransformOp::new(self.clone(), FlattenTransform, /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let dim = x.val.get(txn, node).dim();
+        let buf = <S as ArrayStorage<usize>>::alloc(dim.flat_len());
+        Array1d::from_storage(dim.flat_len(), buf)
+      })
+    })
+  }
+}
+
+impl<S> AOp for TransformOp<Array3d<f32, S>, Array1d<f32, S>, FlattenTransform> where S: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      self.y.val.get_excl(txn, node).as_view_mut().copy(self.x.val.get(txn, node).as_view().flatten());
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      self.x.grad.get_mut(txn, node).as_view_mut().flatten_mut().add(1.0, self.y.grad.get(txn, node).as_view());
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      self.y.r_val.get_excl(txn, node).as_view_mut().copy(self.x.r_val.get(txn, node).as_view().flatten());
+    }
+  }
+
+  /*fn _r_backward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.x.r_grad.accumulate(txn, node, |r_grad| r_grad.as_view_mut().set_constant(0.0)) {
+      self.x.r_grad.get_mut(txn, node).as_view_mut().flatten_mut().add(1.0, self.y.r_grad.get(txn, node).as_view());
+    }
+  }*/
+}
+
+impl<Op, S> FlattenExt<BatchArray3d<f32, S>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn flatten(&self) -> Rc<TransformOp<BatchArray3d<f32, S>, BatchArray1d<f32, S>, FlattenTransform>> {
+    //let clk_horizon = self.data().horizon();
+    TransformOp::new(self.clone(), FlattenTransform, /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let x_dim = x.val.get(txn, node).dim();
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(x_dim.flat_len(), batch_sz);
+        BatchArray1d::from_storage(x_dim.flat_len(), batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ReshapeExt<usize, BatchArray3d<f32, S>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn reshape(&self, dim: usize) -> Rc<TransformOp<BatchArray3d<f32, S>, BatchArray1d<f32, S>, ReshapeTransform<usize>>> {
+    //let clk_horizon = self.data().horizon();
+    TransformOp::new(self.clone(), ReshapeTransform{dim: dim}, /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let x_dim = x.val.get(txn, node).dim();
+        assert_eq!(x_dim.flat_len(), dim);
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ReshapeExt<(usize, usize, usize), BatchArray1d<f32, S>, BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn reshape(&self, dim: (usize, usize, usize)) -> Rc<TransformOp<BatchArray1d<f32, S>, BatchArray3d<f32, S>, ReshapeTransform<(usize, usize, usize)>>> {
+    //let clk_horizon = self.data().horizon();
+    TransformOp::new(self.clone(), ReshapeTransform{dim: dim}, /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let x_dim = x.val.get(txn, node).dim();
+        assert_eq!(x_dim, dim.flat_len());
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim.flat_len(), batch_sz);
+        BatchArray3d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ReshapeExt<(usize, usize, usize), BatchArray3d<f32, S>, BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn reshape(&self, dim: (usize, usize, usize)) -> Rc<TransformOp<BatchArray3d<f32, S>, BatchArray3d<f32, S>, ReshapeTransform<(usize, usize, usize)>>> {
+    //let clk_horizon = self.data().horizon();
+    TransformOp::new(self.clone(), ReshapeTransform{dim: dim}, /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let x_dim = x.val.get(txn, node).dim();
+        assert_eq!(x_dim.flat_len(), dim.flat_len());
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim.flat_len(), batch_sz);
+        BatchArray3d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ReshapeExt<(), BatchArray1d<f32, S>, Batch<f32>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> {
+  fn reshape(&self, dim: ()) -> Rc<TransformOp<BatchArray1d<f32, S>, Batch<f32>, ReshapeTransform<()>>> {
+    //let clk_horizon = self.data().horizon();
+    TransformOp::new(self.clone(), ReshapeTransform{dim: dim}, /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let x_dim = x.val.get(txn, node).dim();
+        assert_eq!(1, x_dim);
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let mut y = Batch::new();
+        y.set_batch_size(batch_sz, 0.0);
+        y
+      })
+    })
+  }
+}
+
+impl<Op, S> ReshapeExt<usize, Batch<f32>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Batch<f32>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn reshape(&self, dim: usize) -> Rc<TransformOp<Batch<f32>, BatchArray1d<f32, S>, ReshapeTransform<usize>>> {
+    assert_eq!(1, dim);
+    //let clk_horizon = self.data().horizon();
+    TransformOp::new(self.clone(), ReshapeTransform{dim: dim}, /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<A, B> AOp for TransformOp<A, B, FlattenTransform> where A: PackedArray, B: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      _packed_copy_fwd(&self.x, &self.y, txn, node);
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    _packed_copy_bwd(&self.x, &self.y, txn, node);
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      _packed_copy_r_fwd(&self.x, &self.y, txn, node);
+    }
+  }
+
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    _packed_copy_bwd2(&self.x, &self.y, txn, node);
+  }
+}
+
+impl<A, B, Idx> AOp for TransformOp<A, B, ReshapeTransform<Idx>> where A: PackedArray, B: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      _packed_copy_fwd(&self.x, &self.y, txn, node);
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    _packed_copy_bwd(&self.x, &self.y, txn, node);
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      _packed_copy_r_fwd(&self.x, &self.y, txn, node);
+    }
+  }
+
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    _packed_copy_bwd2(&self.x, &self.y, txn, node);
+  }
+}
+
+/// Adds the `len` entries of `x` to each of the `batch_sz` rows of `y`.
+unsafe fn _batch_broadcast_add(len: usize, batch_sz: usize, x: *const f32, y: *mut f32) {
+  for idx in 0 .. batch_sz {
+    arraydiff_kernel_add_f32(len, x, y.offset((idx * len) as isize));
+  }
+}
+
+/// Adds the sum of the `batch_sz` rows of `x` to the `len` entries of `y`.
+unsafe fn _batch_sum_add(len: usize, batch_sz: usize, x: *const f32, y: *mut f32) {
+  for idx in 0 .. batch_sz {
+    arraydiff_kernel_add_f32(len, x.offset((idx * len) as isize), y);
+  }
+}
+
+fn _packed_copy_fwd<A, B>(x: &AData<A>, y: &AData<B>, txn: TxnId, node: NodeId) where A: PackedArray, B: PackedArray {
+  let batch_sz = x.val.get(txn, node)._batch_size();
+  y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+  let len = x.val.get(txn, node)._packed_dim().iter().product();
+  assert_eq!(len, y.val.get_excl(txn, node)._packed_dim().iter().product::<usize>());
+  unsafe { arraydiff_kernel_copy_f32(
+      len,
+      x.val.get(txn, node)._as_ptr(),
+      y.val.get_excl(txn, node)._as_mut_ptr(),
+  ) };
+}
+
+fn _packed_copy_r_fwd<A, B>(x: &AData<A>, y: &AData<B>, txn: TxnId, node: NodeId) where A: PackedArray, B: PackedArray {
+  let batch_sz = x.r_val.get(txn, node)._batch_size();
+  y.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+  let len = x.r_val.get(txn, node)._packed_dim().iter().product();
+  assert_eq!(len, y.r_val.get_excl(txn, node)._packed_dim().iter().product::<usize>());
+  unsafe { arraydiff_kernel_copy_f32(
+      len,
+      x.r_val.get(txn, node)._as_ptr(),
+      y.r_val.get_excl(txn, node)._as_mut_ptr(),
+  ) };
+}
+
+fn _packed_copy_bwd<A, B>(x: &AData<A>, y: &AData<B>, txn: TxnId, node: NodeId) where A: PackedArray, B: PackedArray {
+  let batch_sz = x.val.get(txn, node)._batch_size();
+  let len = x.val.get(txn, node)._packed_dim().iter().product();
+  if x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+    unsafe { arraydiff_kernel_add_f32(
+        len,
+        y.grad.get(txn, node)._as_ptr(),
+        x.grad.get_mut(txn, node)._as_mut_ptr(),
+    ) };
+  }
+}
+
+fn _packed_copy_bwd2<A, B>(x: &AData<A>, y: &AData<B>, txn: TxnId, node: NodeId) where A: PackedArray, B: PackedArray {
+  let batch_sz = x.val.get(txn, node)._batch_size();
+  let len = x.val.get(txn, node)._packed_dim().iter().product();
+  if x.grad2.accumulate(txn, node, |grad2| { grad2._set_batch_size(batch_sz); grad2._set_zero(); }) {
+    unsafe { arraydiff_kernel_add_f32(
+        len,
+        y.grad2.get(txn, node)._as_ptr(),
+        x.grad2.get_mut(txn, node)._as_mut_ptr(),
+    ) };
+  }
+}
+
+impl<Op, S> ZeroPadExt<BatchArray1d<f32, S>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn zero_pad(&self, axis: usize, dim: usize) -> Rc<TransformOp<BatchArray1d<f32, S>, BatchArray1d<f32, S>, ZeroPadTransform>> {
+    assert_eq!(0, axis);
+    //let clk_horizon = self.data().horizon();
+    TransformOp::new(self.clone(), ZeroPadTransform{axis: axis, dim: dim}, /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let x_dim = x.val.get(txn, node).dim();
+        assert!(dim >= x_dim);
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<Op, S> ZeroPadExt<BatchArray3d<f32, S>, BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn zero_pad(&self, axis: usize, dim: usize) -> Rc<TransformOp<BatchArray3d<f32, S>, BatchArray3d<f32, S>, ZeroPadTransform>> {
+    assert!(axis < 3);
+    //let clk_horizon = self.data().horizon();
+    TransformOp::new(self.clone(), ZeroPadTransform{axis: axis, dim: dim}, /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let x_dim = x.val.get(txn, node).dim();
+        let y_dim = match axis {
+          0 => { assert!(dim >= x_dim.0); (dim, x_dim.1, x_dim.2) }
+          1 => { assert!(dim >= x_dim.1); (x_dim.0, dim, x_dim.2) }
+          2 => { assert!(dim >= x_dim.2); (x_dim.0, x_dim.1, dim) }
+          _ => unreachable!(),
+        };
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(y_dim.flat_len(), batch_sz);
+        BatchArray3d::from_storage(y_dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<A> AOp for TransformOp<A, A, ZeroPadTransform> where A: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let batch_sz = self.x.val.get(txn, node)._batch_size();
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      self.y.val.get_excl(txn, node)._set_zero();
+      let (inner_dim, x_axis_dim, outer_dim) = _axis_slice_dims(&self.x.val.get(txn, node)._packed_dim(), self.kernel.axis);
+      let y_axis_dim = self.kernel.dim;
+      unsafe { arraydiff_kernel_axis_slice_copy_f32(
+          inner_dim, outer_dim, x_axis_dim,
+          x_axis_dim, 0, self.x.val.get(txn, node)._as_ptr(),
+          y_axis_dim, 0, self.y.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    let (inner_dim, x_axis_dim, outer_dim) = _axis_slice_dims(&self.x.val.get(txn, node)._packed_dim(), self.kernel.axis);
+    let y_axis_dim = self.kernel.dim;
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { arraydiff_kernel_axis_slice_add_f32(
+          inner_dim, outer_dim, x_axis_dim,
+          y_axis_dim, 0, self.y.grad.get(txn, node)._as_ptr(),
+          x_axis_dim, 0, self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      let batch_sz = self.x.r_val.get(txn, node)._batch_size();
+      self.y.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+      self.y.r_val.get_excl(txn, node)._set_zero();
+      let (inner_dim, x_axis_dim, outer_dim) = _axis_slice_dims(&self.x.r_val.get(txn, node)._packed_dim(), self.kernel.axis);
+      let y_axis_dim = self.kernel.dim;
+      unsafe { arraydiff_kernel_axis_slice_copy_f32(
+          inner_dim, outer_dim, x_axis_dim,
+          x_axis_dim, 0, self.x.r_val.get(txn, node)._as_ptr(),
+          y_axis_dim, 0, self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+}
+
+impl<Op, S, T> CastExt<BatchArray1d<u8, S>, BatchArray1d<f32, T>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<u8, S>>>, S: 'static + DerefMut<Target=[u8]>, T: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn cast(&self) -> Rc<TransformOp<BatchArray1d<u8, S>, BatchArray1d<f32, T>, CastTransform>> {
+    //let clk_horizon = self.data().horizon();
+    TransformOp::new(self.clone(), CastTransform, /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let dim = x.val.get(txn, node).dim();
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <T as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<S, T> AOp for TransformOp<BatchArray1d<u8, S>, BatchArray1d<f32, T>, CastTransform> where S: DerefMut<Target=[u8]>, T: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let x_dim = self.x.val.get(txn, node).dim();
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      self.y.val.get_excl(txn, node).set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_cast_u8_to_f32(
+          x_dim * batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.y.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, _txn: TxnId) {
+    // The input is integer valued and has no gradient.
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    // Likewise, the input does not vary.
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      self.y.r_val.get_excl(txn, node).set_batch_size(batch_sz);
+      self.y.r_val.get_excl(txn, node).as_view_mut().set_constant(0.0);
+    }
+  }
+}
+
+impl<Op, S, T> CastExt<BatchArray3d<u8, S>, BatchArray3d<f32, T>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<u8, S>>>, S: 'static + DerefMut<Target=[u8]>, T: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn cast(&self) -> Rc<TransformOp<BatchArray3d<u8, S>, BatchArray3d<f32, T>, CastTransform>> {
+    //let clk_horizon = self.data().horizon();
+    TransformOp::new(self.clone(), CastTransform, /*clk_horizon,*/ {
+      let x = self.clone().data();
+      Rc::new(move |txn, node| {
+        let dim = x.val.get(txn, node).dim();
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <T as BatchArrayStorage<usize>>::alloc(dim.flat_len(), batch_sz);
+        BatchArray3d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<S, T> AOp for TransformOp<BatchArray3d<u8, S>, BatchArray3d<f32, T>, CastTransform> where S: DerefMut<Target=[u8]>, T: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let x_dim = self.x.val.get(txn, node).dim();
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      self.y.val.get_excl(txn, node).set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_cast_u8_to_f32(
+          x_dim.flat_len() * batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.y.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, _txn: TxnId) {
+    // The input is integer valued and has no gradient.
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    // Likewise, the input does not vary.
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      self.y.r_val.get_excl(txn, node).set_batch_size(batch_sz);
+      self.y.r_val.get_excl(txn, node).as_view_mut().set_constant(0.0);
+    }
+  }
+}
+
+pub struct JoinOp<A, JoinF> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  xs_:  Vec<Rc<AVar<AData<A>>>>,
+  xs:   Vec<AData<A>>,
+  y:    AData<A>,
+  kernel:   JoinF,
+  tng_: RefCell<Option<Rc<AVar<AData<A>>>>>,
+}
+
+impl<A, JoinF> JoinOp<A, JoinF> {
+  pub fn new<F>(xs_: Vec<Rc<AVar<AData<A>>>>, kernel: JoinF, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<JoinOp<A, JoinF>> where F: 'static + Fn(TxnId, NodeId) -> A {
+    let node = NodeId::new();
+    let in_degree = xs_.len();
+    let mut xs = Vec::with_capacity(in_degree);
+    for x_ in xs_.iter() {
+      let x = x_.data();
+      xs.push(x);
+    }
+    assert_eq!(in_degree, xs_.len());
+    assert_eq!(in_degree, xs.len());
+    Rc::new(JoinOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, in_degree),
+      xs_:      xs_,
+      xs:       xs,
+      y:        AData::new(/*clk_horizon,*/ alloc),
+      kernel:   kernel,
+      tng_:     RefCell::new(None),
+    })
+  }
+}
+
+/// Reuses the allocator of `x` for another op with the same output shape,
+/// e.g. a tangent op.
+fn _shared_alloc<A>(x: &AData<A>) -> Rc<impl Fn(TxnId, NodeId) -> A> where A: 'static {
+  let alloc = x.alloc.clone();
+  Rc::new(move |txn, node| (alloc)(txn, node))
+}
+
+impl<A, JoinF> AVar<AData<A>> for JoinOp<A, JoinF> where JoinOp<A, JoinF>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+
+  default fn tangent(&self) -> Rc<AVar<AData<A>>> {
+    if self.tng_.borrow().is_none() {
+      *self.tng_.borrow_mut() = Some(self._make_tangent());
+    }
+    self.tng_.borrow().as_ref().unwrap().clone()
+  }
+}
+
+impl<A> AVar<AData<A>> for JoinOp<A, SumJoinKernel> where A: 'static + PackedArray, JoinOp<A, SumJoinKernel>: AOp {
+  fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+
+  fn _make_tangent(&self) -> Rc<AVar<AData<A>>> {
+    let tng_xs_ = self.xs_.iter().map(|x_| x_.tangent()).collect();
+    JoinOp::new(tng_xs_, SumJoinKernel, /*clk_horizon,*/ _shared_alloc(&self.y))
+  }
+
+  fn _adjoint(&self, adj_: Rc<AVar<AData<A>>>, adjs: &mut AdjointMap) {
+    for x_ in self.xs_.iter() {
+      adjs.accumulate(x_.clone(), adj_.clone());
+    }
+  }
+}
+
+impl<A> AVar<AData<A>> for JoinOp<A, AxisJoinKernel> where A: 'static + PackedArray, JoinOp<A, AxisJoinKernel>: AOp {
+  fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+
+  fn _make_tangent(&self) -> Rc<AVar<AData<A>>> {
+    let tng_xs_ = self.xs_.iter().map(|x_| x_.tangent()).collect();
+    JoinOp::new(tng_xs_, AxisJoinKernel{axis: self.kernel.axis}, /*clk_horizon,*/ _shared_alloc(&self.y))
+  }
+}
+
+pub struct AxisJoinKernel {
+  axis: usize,
+}
+pub struct SumJoinKernel;
+
+pub trait AxisJoinExt<A> {
+  fn axis_join(axis: usize, xs_: Vec<Rc<AVar<AData<A>>>>) -> Rc<JoinOp<A, AxisJoinKernel>>;
+}
+
+/// Concatenates `xs` along axis 0. This is the original entry point, kept
+/// for existing callers; use `axis_join_at` to join along another axis.
+pub fn axis_join<Op, A>(xs: Vec<Rc<Op>>) -> Rc<JoinOp<A, AxisJoinKernel>> where Rc<JoinOp<A, AxisJoinKernel>>: AxisJoinExt<A>, Op: 'static + AVar<AData<A>> {
+  let xs_: Vec<Rc<AVar<AData<A>>>> = xs.into_iter().map(|x| { let x_: Rc<AVar<AData<A>>> = x; x_ }).collect();
+  <Rc<JoinOp<A, AxisJoinKernel>> as AxisJoinExt<A>>::axis_join(0, xs_)
+}
+
+pub fn axis_join_at<A>(axis: usize, xs_: Vec<Rc<AVar<AData<A>>>>) -> Rc<JoinOp<A, AxisJoinKernel>> where Rc<JoinOp<A, AxisJoinKernel>>: AxisJoinExt<A> {
+  <Rc<JoinOp<A, AxisJoinKernel>> as AxisJoinExt<A>>::axis_join(axis, xs_)
+}
+
+pub trait AddExt<A> {
+  fn add<RhsOp>(&self, x_: Rc<RhsOp>) -> Rc<JoinOp<A, SumJoinKernel>> where RhsOp: 'static + AVar<AData<A>>;
+}
+
+impl<Op, A> AddExt<A> for Rc<Op> where Rc<JoinOp<A, SumJoinKernel>>: SumExt<A>, Op: 'static + AVar<AData<A>> {
+  default fn add<RhsOp>(&self, x_: Rc<RhsOp>) -> Rc<JoinOp<A, SumJoinKernel>> where RhsOp: 'static + AVar<AData<A>> {
+    //<Rc<JoinOp<A, SumJoinKernel>> as SumExt<A>>::sum(vec![ArrayOp::from(self.clone()), ArrayOp::from(x_)])
+    <Rc<JoinOp<A, SumJoinKernel>> as SumExt<A>>::sum(vec![self.clone(), x_])
+  }
+}
+
+pub trait SumExt<A> {
+  fn sum(xs_: Vec<Rc<AVar<AData<A>>>>) -> Rc<JoinOp<A, SumJoinKernel>>;
+}
+
+pub fn sum<A>(xs_: Vec<Rc<AVar<AData<A>>>>) -> Rc<JoinOp<A, SumJoinKernel>> where Rc<JoinOp<A, SumJoinKernel>>: SumExt<A> {
+  <Rc<JoinOp<A, SumJoinKernel>> as SumExt<A>>::sum(xs_)
+}
+
+fn _axis_slice_dims(dim: &[usize; 4], axis: usize) -> (usize, usize, usize) {
+  assert!(axis < 4);
+  let inner_dim = dim[ .. axis].iter().product();
+  let outer_dim = dim[axis + 1 .. ].iter().product();
+  (inner_dim, dim[axis], outer_dim)
+}
+
+impl<S> AxisJoinExt<Array1d<f32, S>> for Rc<JoinOp<Array1d<f32, S>, AxisJoinKernel>> where S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
+  fn axis_join(axis: usize, xs_: Vec<Rc<AVar<AData<Array1d<f32, S>>>>>) -> Rc<JoinOp<Array1d<f32, S>, AxisJoinKernel>> {
+    assert_eq!(0, axis);
+    let mut xs = Vec::with_capacity(xs_.len());
+    for x_ in xs_.iter() {
+      xs.push(x_.data());
+    }
+    //let clk_horizon = xs[0].horizon();
+    JoinOp::new(xs_, AxisJoinKernel{axis: axis}, /*clk_horizon,*/ {
+      Rc::new(move |txn, node| {
+        let mut dim = 0;
+        for x in xs.iter() {
+          dim += x.val.get(txn, node).dim();
+        }
+        let buf = <S as ArrayStorage<usize>>::alloc(dim);
+        Array1d::from_storage(dim, buf)
+      })
+    })
+  }
+}
+
+impl<S> AxisJoinExt<BatchArray1d<f32, S>> for Rc<JoinOp<BatchArray1d<f32, S>, AxisJoinKernel>> where S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn axis_join(axis: usize, xs_: Vec<Rc<AVar<AData<BatchArray1d<f32, S>>>>>) -> Rc<JoinOp<BatchArray1d<f32, S>, AxisJoinKernel>> {
+    assert_eq!(0, axis);
+    let mut xs = Vec::with_capacity(xs_.len());
+    for x_ in xs_.iter() {
+      xs.push(x_.data());
+    }
+    //let clk_horizon = xs[0].horizon();
+    JoinOp::new(xs_, AxisJoinKernel{axis: axis}, /*clk_horizon,*/ {
+      Rc::new(move |txn, node| {
+        let mut dim = 0;
+        let mut batch_sz0 = None;
+        for x in xs.iter() {
+          dim += x.val.get(txn, node).dim();
+          let batch_sz = x.val.get(txn, node).batch_size();
+          match batch_sz0 {
+            None      => batch_sz0 = Some(batch_sz),
+            Some(bsz) => assert_eq!(bsz, batch_sz),
+          }
+        }
+        let batch_sz = batch_sz0.unwrap();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<S> AxisJoinExt<BatchArray3d<f32, S>> for Rc<JoinOp<BatchArray3d<f32, S>, AxisJoinKernel>> where S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn axis_join(axis: usize, xs_: Vec<Rc<AVar<AData<BatchArray3d<f32, S>>>>>) -> Rc<JoinOp<BatchArray3d<f32, S>, AxisJoinKernel>> {
+    assert!(axis < 3);
+    let mut xs = Vec::with_capacity(xs_.len());
+    for x_ in xs_.iter() {
+      xs.push(x_.data());
+    }
+    //let clk_horizon = xs[0].horizon();
+    JoinOp::new(xs_, AxisJoinKernel{axis: axis}, /*clk_horizon,*/ {
+      Rc::new(move |txn, node| {
+        let mut dim0: Option<(usize, usize, usize)> = None;
+        let mut batch_sz0 = None;
+        for x in xs.iter() {
+          let x_dim = x.val.get(txn, node).dim();
+          dim0 = match dim0 {
+            None      => Some(x_dim),
+            Some(dim) => match axis {
+              0 => { assert_eq!((dim.1, dim.2), (x_dim.1, x_dim.2)); Some((dim.0 + x_dim.0, dim.1, dim.2)) }
+              1 => { assert_eq!((dim.0, dim.2), (x_dim.0, x_dim.2)); Some((dim.0, dim.1 + x_dim.1, dim.2)) }
+              2 => { assert_eq!((dim.0, dim.1), (x_dim.0, x_dim.1)); Some((dim.0, dim.1, dim.2 + x_dim.2)) }
+              _ => unreachable!(),
+            },
+          };
+          let batch_sz = x.val.get(txn, node).batch_size();
+          match batch_sz0 {
+            None      => batch_sz0 = Some(batch_sz),
+            Some(bsz) => assert_eq!(bsz, batch_sz),
+          }
+        }
+        let dim = dim0.unwrap();
+        let batch_sz = batch_sz0.unwrap();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim.flat_len(), batch_sz);
+        BatchArray3d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<A> AOp for JoinOp<A, AxisJoinKernel> where A: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      for x_ in self.xs_.iter() {
+        x_._push(epoch, apply);
+      }
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      for x_ in self.xs_.iter().rev() {
+        x_._pop(epoch, apply);
+      }
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let axis = self.kernel.axis;
+      let batch_sz = self.xs[0].val.get(txn, node)._batch_size();
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      let (_, y_axis_dim, _) = _axis_slice_dims(&self.y.val.get_excl(txn, node)._packed_dim(), axis);
+      let mut offset = 0;
+      for x in self.xs.iter() {
+        let (inner_dim, x_axis_dim, outer_dim) = _axis_slice_dims(&x.val.get(txn, node)._packed_dim(), axis);
+        unsafe { arraydiff_kernel_axis_slice_copy_f32(
+            inner_dim, outer_dim, x_axis_dim,
+            x_axis_dim, 0, x.val.get(txn, node)._as_ptr(),
+            y_axis_dim, offset, self.y.val.get_excl(txn, node)._as_mut_ptr(),
+        ) };
+        offset += x_axis_dim;
+      }
+      assert_eq!(offset, y_axis_dim);
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let axis = self.kernel.axis;
+    let (_, y_axis_dim, _) = _axis_slice_dims(&self.y.grad.get(txn, node)._packed_dim(), axis);
+    let mut offset = 0;
+    for x in self.xs.iter() {
+      let batch_sz = x.val.get(txn, node)._batch_size();
+      let (inner_dim, x_axis_dim, outer_dim) = _axis_slice_dims(&x.val.get(txn, node)._packed_dim(), axis);
+      if x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+        unsafe { arraydiff_kernel_axis_slice_add_f32(
+            inner_dim, outer_dim, x_axis_dim,
+            y_axis_dim, offset, self.y.grad.get(txn, node)._as_ptr(),
+            x_axis_dim, 0, x.grad.get_mut(txn, node)._as_mut_ptr(),
+        ) };
+      }
+      offset += x_axis_dim;
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      let axis = self.kernel.axis;
+      let batch_sz = self.xs[0].r_val.get(txn, node)._batch_size();
+      self.y.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+      let (_, y_axis_dim, _) = _axis_slice_dims(&self.y.r_val.get_excl(txn, node)._packed_dim(), axis);
+      let mut offset = 0;
+      for x in self.xs.iter() {
+        let (inner_dim, x_axis_dim, outer_dim) = _axis_slice_dims(&x.r_val.get(txn, node)._packed_dim(), axis);
+        unsafe { arraydiff_kernel_axis_slice_copy_f32(
+            inner_dim, outer_dim, x_axis_dim,
+            x_axis_dim, 0, x.r_val.get(txn, node)._as_ptr(),
+            y_axis_dim, offset, self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+        ) };
+        offset += x_axis_dim;
+      }
+      assert_eq!(offset, y_axis_dim);
+    }
+  }
+
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    let axis = self.kernel.axis;
+    let (_, y_axis_dim, _) = _axis_slice_dims(&self.y.grad2.get(txn, node)._packed_dim(), axis);
+    let mut offset = 0;
+    for x in self.xs.iter() {
+      let batch_sz = x.val.get(txn, node)._batch_size();
+      let (inner_dim, x_axis_dim, outer_dim) = _axis_slice_dims(&x.val.get(txn, node)._packed_dim(), axis);
+      if x.grad2.accumulate(txn, node, |grad2| { grad2._set_batch_size(batch_sz); grad2._set_zero(); }) {
+        unsafe { arraydiff_kernel_axis_slice_add_f32(
+            inner_dim, outer_dim, x_axis_dim,
+            y_axis_dim, offset, self.y.grad2.get(txn, node)._as_ptr(),
+            x_axis_dim, 0, x.grad2.get_mut(txn, node)._as_mut_ptr(),
+        ) };
+      }
+      offset += x_axis_dim;
+    }
+  }
+}
+
+/*impl<Op, A> SumExt<A> for Rc<Op> where Op: AVar<AData<A>> {
+  default fn sum(xs_: Vec<Rc<Op>>) -> Rc<JoinOp<A, SumJoinKernel>> {
+    unimplemented!();
+  }
+
+  default fn add(&self, x_: Rc<Op>) -> Rc<JoinOp<A, SumJoinKernel>> {
+    Self::sum(vec![self.clone(), x_])
+  }
+}*/
+
+/*impl<Op, S> SumExt<Array1d<f32, S>> for Rc<Op> where Op: AVar<AData<Array1d<f32, S>>>, S: DerefMut<Target=[f32]> {
+  fn sum(xs_: Vec<Rc<Op>>) -> Rc<JoinOp<Array1d<f32, S>, SumJoinKernel>> where S: DerefMut<Target=[f32]> {
+    unimplemented!();
+  }
+
+  fn add(&self, x_: Rc<Op>) -> Rc<JoinOp<Array1d<f32, S>, SumJoinKernel>> {
+    Self::sum(vec![self.clone(), x_])
+  }
+}*/
+
+impl<A> SumExt<A> for Rc<JoinOp<A, SumJoinKernel>> where A: 'static + PackedArray {
+  fn sum(xs_: Vec<Rc<AVar<AData<A>>>>) -> Rc<JoinOp<A, SumJoinKernel>> {
+    assert!(xs_.len() >= 1);
+    //let clk_horizon = xs_[0].data().horizon();
+    let alloc = _shared_alloc(&xs_[0].data());
+    JoinOp::new(xs_, SumJoinKernel, /*clk_horizon,*/ alloc)
+  }
+}
+
+impl<A> AOp for JoinOp<A, SumJoinKernel> where A: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      for x_ in self.xs_.iter() {
+        x_._push(epoch, apply);
+      }
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      for x_ in self.xs_.iter().rev() {
+        x_._pop(epoch, apply);
+      }
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      _packed_copy_fwd(&self.xs[0], &self.y, txn, node);
+      let len = self.y.val.get_excl(txn, node)._packed_dim().iter().product();
+      for x in self.xs.iter().skip(1) {
+        assert_eq!(len, x.val.get(txn, node)._packed_dim().iter().product::<usize>());
+        unsafe { arraydiff_kernel_add_f32(
+            len,
+            x.val.get(txn, node)._as_ptr(),
+            self.y.val.get_excl(txn, node)._as_mut_ptr(),
+        ) };
+      }
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    for x in self.xs.iter() {
+      _packed_copy_bwd(x, &self.y, txn, node);
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      _packed_copy_r_fwd(&self.xs[0], &self.y, txn, node);
+      let len = self.y.r_val.get_excl(txn, node)._packed_dim().iter().product();
+      for x in self.xs.iter().skip(1) {
+        assert_eq!(len, x.r_val.get(txn, node)._packed_dim().iter().product::<usize>());
+        unsafe { arraydiff_kernel_add_f32(
+            len,
+            x.r_val.get(txn, node)._as_ptr(),
+            self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+        ) };
+      }
+    }
+  }
+
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    for x in self.xs.iter() {
+      _packed_copy_bwd2(x, &self.y, txn, node);
+    }
+  }
+
+  /*fn _r_backward(&self, txn: TxnId) {
+    let node = self._id();
+    for x in self.xs.iter() {
+      if x.r_grad.accumulate(txn, node, |r_grad| r_grad.as_view_mut().set_constant(0.0)) {
+        x.r_grad.get_mut(txn, node).as_view_mut().add(1.0, self.y.r_grad.get(txn, node).as_view());
+      }
+    }
+  }*/
+}
+
+pub struct SplitOp<A, SplitF> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  x_:   Rc<AVar<AData<A>>>,
+  x:    AData<A>,
+  y:    AData<A>,
+  kernel:   SplitF,
+}
+
+impl<A, SplitF> SplitOp<A, SplitF> {
+  pub fn new<F>(x_: Rc<AVar<AData<A>>>, kernel: SplitF, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<SplitOp<A, SplitF>> where F: 'static + Fn(TxnId, NodeId) -> A {
+    let node = NodeId::new();
+    let x = x_.data();
+    Rc::new(SplitOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 1),
+      x_:   x_,
+      x:    x,
+      y:    AData::new(/*clk_horizon,*/ alloc),
+      kernel:   kernel,
+    })
+  }
+}
+
+impl<A, SplitF> AVar<AData<A>> for SplitOp<A, SplitF> where SplitOp<A, SplitF>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+}
+
+pub struct AxisSplitKernel {
+  op_idx:   usize,
+  axis:     usize,
+  offset:   usize,
+  length:   usize,
+}
+
+pub trait AxisSplitExt<A> {
+  fn axis_split(&self, axis: usize, parts: Vec<usize>) -> Vec<Rc<SplitOp<A, AxisSplitKernel>>>;
+}
+
+impl<Op, S> AxisSplitExt<Array1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
+  fn axis_split(&self, axis: usize, parts: Vec<usize>) -> Vec<Rc<SplitOp<Array1d<f32, S>, AxisSplitKernel>>> {
+    assert_eq!(0, axis);
+    let mut ops = Vec::with_capacity(parts.len());
+    let mut offset = 0;
+    for (op_idx, &length) in parts.iter().enumerate() {
+      //let clk_horizon = self.data().horizon();
+      ops.push(SplitOp::new(self.clone(), AxisSplitKernel{op_idx: op_idx, axis: axis, offset: offset, length: length}, /*clk_horizon,*/ {
+        Rc::new(move |_, _| {
+          let buf = <S as ArrayStorage<usize>>::alloc(length);
+          Array1d::from_storage(length, buf)
+        })
+      }));
+      offset += length;
+    }
+    ops
+  }
+}
+
+impl<Op, S> AxisSplitExt<BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn axis_split(&self, axis: usize, parts: Vec<usize>) -> Vec<Rc<SplitOp<BatchArray1d<f32, S>, AxisSplitKernel>>> {
+    assert_eq!(0, axis);
+    let mut ops = Vec::with_capacity(parts.len());
+    let mut offset = 0;
+    for (op_idx, &length) in parts.iter().enumerate() {
+      //let clk_horizon = self.data().horizon();
+      ops.push(SplitOp::new(self.clone(), AxisSplitKernel{op_idx: op_idx, axis: axis, offset: offset, length: length}, /*clk_horizon,*/ {
+        let x = self.clone().data();
+        Rc::new(move |txn, node| {
+          let batch_sz = x.val.get(txn, node).batch_size();
+          let buf = <S as BatchArrayStorage<usize>>::alloc(length, batch_sz);
+          BatchArray1d::from_storage(length, batch_sz, buf)
+        })
+      }));
+      offset += length;
+    }
+    ops
+  }
+}
+
+impl<Op, S> AxisSplitExt<BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn axis_split(&self, axis: usize, parts: Vec<usize>) -> Vec<Rc<SplitOp<BatchArray3d<f32, S>, AxisSplitKernel>>> {
+    assert!(axis < 3);
+    let mut ops = Vec::with_capacity(parts.len());
+    let mut offset = 0;
+    for (op_idx, &length) in parts.iter().enumerate() {
+      //let clk_horizon = self.data().horizon();
+      ops.push(SplitOp::new(self.clone(), AxisSplitKernel{op_idx: op_idx, axis: axis, offset: offset, length: length}, /*clk_horizon,*/ {
+        let x = self.clone().data();
+        Rc::new(move |txn, node| {
+          let x_dim = x.val.get(txn, node).dim();
+          let dim = match axis {
+            0 => (length, x_dim.1, x_dim.2),
+            1 => (x_dim.0, length, x_dim.2),
+            2 => (x_dim.0, x_dim.1, length),
+            _ => unreachable!(),
+          };
+          let batch_sz = x.val.get(txn, node).batch_size();
+          let buf = <S as BatchArrayStorage<usize>>::alloc(dim.flat_len(), batch_sz);
+          BatchArray3d::from_storage(dim, batch_sz, buf)
+        })
+      }));
+      offset += length;
+    }
+    ops
+  }
+}
+
+impl<A> AOp for SplitOp<A, AxisSplitKernel> where A: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let batch_sz = self.x.val.get(txn, node)._batch_size();
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      let (inner_dim, x_axis_dim, outer_dim) = _axis_slice_dims(&self.x.val.get(txn, node)._packed_dim(), self.kernel.axis);
+      assert!(self.kernel.offset + self.kernel.length <= x_axis_dim);
+      unsafe { arraydiff_kernel_axis_slice_copy_f32(
+          inner_dim, outer_dim, self.kernel.length,
+          x_axis_dim, self.kernel.offset, self.x.val.get(txn, node)._as_ptr(),
+          self.kernel.length, 0, self.y.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    let (inner_dim, x_axis_dim, outer_dim) = _axis_slice_dims(&self.x.val.get(txn, node)._packed_dim(), self.kernel.axis);
+    // Each part of the split accumulates its own slice of the input gradient.
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { arraydiff_kernel_axis_slice_add_f32(
+          inner_dim, outer_dim, self.kernel.length,
+          self.kernel.length, 0, self.y.grad.get(txn, node)._as_ptr(),
+          x_axis_dim, self.kernel.offset, self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+}
+
+pub trait DummyExt<A> {
+}
+
+impl<S> DummyExt<Array1d<f32, S>> for Rc<AVar<AData<Array1d<f32, S>>>> where S: DerefMut<Target=[f32]> {
+}
+
+pub struct SymmUnitClipKernel;
+
+pub trait SymmClipExt<A, Clip> {
+  fn symm_unit_clip(&self, c_: Rc<AVar<AData<Clip>>>) -> Rc<ClipOp<A, Clip, SymmUnitClipKernel>>;
+}
+
+pub struct ClipOp<A, Clip, Kernel> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  c_:   Rc<AVar<AData<Clip>>>,
+  x_:   Rc<AVar<AData<A>>>,
+  c:    AData<Clip>,
+  x:    AData<A>,
+  y:    AData<A>,
+  kernel:   Kernel,
+}
+
+impl<A, Clip, Kernel> ClipOp<A, Clip, Kernel> {
+  pub fn new(x_: Rc<AVar<AData<A>>>, c_: Rc<AVar<AData<Clip>>>, kernel: Kernel, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<ClipOp<A, Clip, Kernel>> {
+    let node = NodeId::new();
+    let x = x_.data();
+    let c = c_.data();
+    Rc::new(ClipOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 2),
+      c_:       c_,
+      x_:       x_,
+      c:        c,
+      x:        x,
+      y:        AData::new(/*clk_horizon,*/ alloc),
+      kernel:   kernel,
+    })
+  }
+}
+
+impl<A, Clip, Kernel> AVar<AData<A>> for ClipOp<A, Clip, Kernel> where ClipOp<A, Clip, Kernel>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+}
+
+impl<Op, S> SymmClipExt<BatchArray1d<f32, S>, f32> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn symm_unit_clip(&self, c_: Rc<AVar<AData<f32>>>) -> Rc<ClipOp<BatchArray1d<f32, S>, f32, SymmUnitClipKernel>> {
+    //let clk_horizon = self.data().horizon();
+    ClipOp::new(self.clone(), c_, SymmUnitClipKernel, /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
+}
+
+impl<Op, S, T> SymmClipExt<BatchArray1d<f32, S>, Array1d<f32, T>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize>, T: 'static + DerefMut<Target=[f32]> {
+  fn symm_unit_clip(&self, c_: Rc<AVar<AData<Array1d<f32, T>>>>) -> Rc<ClipOp<BatchArray1d<f32, S>, Array1d<f32, T>, SymmUnitClipKernel>> {
+    //let clk_horizon = self.data().horizon();
+    ClipOp::new(self.clone(), c_, SymmUnitClipKernel, /*clk_horizon,*/ _batch_array1d_map_alloc(self.clone().data()))
+  }
+}
+
+impl<Op, S> SymmClipExt<BatchArray3d<f32, S>, f32> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn symm_unit_clip(&self, c_: Rc<AVar<AData<f32>>>) -> Rc<ClipOp<BatchArray3d<f32, S>, f32, SymmUnitClipKernel>> {
+    //let clk_horizon = self.data().horizon();
+    ClipOp::new(self.clone(), c_, SymmUnitClipKernel, /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
+}
+
+impl<Op, S, T> SymmClipExt<BatchArray3d<f32, S>, Array1d<f32, T>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize>, T: 'static + DerefMut<Target=[f32]> {
+  fn symm_unit_clip(&self, c_: Rc<AVar<AData<Array1d<f32, T>>>>) -> Rc<ClipOp<BatchArray3d<f32, S>, Array1d<f32, T>, SymmUnitClipKernel>> {
+    //let clk_horizon = self.data().horizon();
+    ClipOp::new(self.clone(), c_, SymmUnitClipKernel, /*clk_horizon,*/ _batch_array3d_map_alloc(self.clone().data()))
+  }
+}
+
+/// The input viewed as `[inner, chan, outer]` for a per-channel parameter,
+/// where a scalar parameter is a single channel and a vector parameter runs
+/// along the feature axis.
+fn _chan_dims<A>(x_dim: &[usize; 4], param_len: usize) -> (usize, usize, usize) where A: ElemBinaryArray {
+  if param_len == 1 {
+    (x_dim.iter().product(), 1, 1)
+  } else {
+    let (inner_dim, chan_dim, outer_dim) = _axis_slice_dims(x_dim, A::_feature_axis());
+    assert_eq!(param_len, chan_dim);
+    (inner_dim, chan_dim, outer_dim)
+  }
+}
+
+impl<A, Clip, Kernel> ClipOp<A, Clip, Kernel> where A: ElemBinaryArray, Clip: PackedArray {
+  fn _clip_dims(&self, txn: TxnId, node: NodeId) -> (usize, usize, usize) {
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let c_len = self.c.val.get(txn, node)._packed_dim().iter().product();
+    _chan_dims::<A>(&x_dim, c_len)
+  }
+}
+
+impl<A, Clip> AOp for ClipOp<A, Clip, SymmUnitClipKernel> where A: ElemBinaryArray, Clip: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      self.c_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.c_._pop(epoch, apply);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    let (inner_dim, chan_dim, outer_dim) = self._clip_dims(txn, node);
+    if self.y.val.overwrite(txn, node) {
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_symm_unit_clip_fwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          self.c.val.get(txn, node)._as_ptr(),
+          self.x.val.get(txn, node)._as_ptr(),
+          self.y.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    let (inner_dim, chan_dim, outer_dim) = self._clip_dims(txn, node);
+    if self.c.grad.accumulate(txn, node, |grad| grad._set_zero()) {
+      unsafe { arraydiff_kernel_symm_unit_clip_param_bwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          self.c.val.get(txn, node)._as_ptr(),
+          self.x.val.get(txn, node)._as_ptr(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          self.c.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { arraydiff_kernel_symm_unit_clip_input_bwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          self.c.val.get(txn, node)._as_ptr(),
+          self.x.val.get(txn, node)._as_ptr(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    let (inner_dim, chan_dim, outer_dim) = self._clip_dims(txn, node);
+    if self.y.r_val.overwrite(txn, node) {
+      self.y.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_symm_unit_clip_rfwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          self.c.val.get(txn, node)._as_ptr(),
+          self.x.val.get(txn, node)._as_ptr(),
+          self.c.r_val.get(txn, node)._as_ptr(),
+          self.x.r_val.get(txn, node)._as_ptr(),
+          self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+}
+
+pub trait DropoutExt<A> {
+  fn dropout(&self, rate: f32, mode: Rc<CopyConstant<bool>>, seed_rng: Rc<RefCell<ChaChaRng>>) -> Rc<DropoutOp<A>>;
+}
+
+/// Inverted dropout. The mode constant follows `BatchStatsControl::mode`:
+/// `false` samples a fresh mask (training), `true` passes the input through
+/// unchanged (evaluation), so the two can share one switch.
+///
+/// The mask is a txn variable, so backward always uses the mask sampled by
+/// the forward pass of the same txn. With `GlobalConfig.deterministic` set,
+/// the mask of each training step is drawn from a generator keyed by the
+/// op's seed and the step count alone, so it does not depend on the sizes of
+/// earlier batches.
+pub struct DropoutOp<A> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  rate:     f32,
+  mode:     Rc<CopyConstant<bool>>,
+  seed:     RefCell<Vec<u32>>,
+  rng:      RefCell<ChaChaRng>,
+  step:     Cell<u64>,
+  mask:     TxnVar<Vec<f32>>,
+  x_:   Rc<AVar<AData<A>>>,
+  x:    AData<A>,
+  y:    AData<A>,
+}
+
+fn _dropout_seed(seed_rng: &mut ChaChaRng) -> Vec<u32> {
+  (0 .. 8).map(|_| seed_rng.next_u32()).collect()
+}
+
+impl<A> DropoutOp<A> {
+  pub fn new(rate: f32, mode: Rc<CopyConstant<bool>>, seed_rng: Rc<RefCell<ChaChaRng>>, x_: Rc<AVar<AData<A>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<DropoutOp<A>> {
+    assert!(rate >= 0.0 && rate < 1.0);
+    let node = NodeId::new();
+    let x = x_.data();
+    let seed = _dropout_seed(&mut *seed_rng.borrow_mut());
+    let rng = ChaChaRng::from_seed(&seed);
+    let mask = TxnVar::new(Symbol::new(), Val, x.clock.clone(), Rc::new(|_, _| vec![]));
+    Rc::new(DropoutOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 1),
+      rate:     rate,
+      mode:     mode,
+      seed:     RefCell::new(seed),
+      rng:      RefCell::new(rng),
+      step:     Cell::new(0),
+      mask:     mask,
+      x_:       x_,
+      x:        x,
+      y:        AData::new(/*clk_horizon,*/ alloc),
+    })
+  }
+
+  fn _sample_mask(&self, len: usize, mask: &mut Vec<f32>) {
+    let scale = 1.0 / (1.0 - self.rate);
+    let step = self.step.get();
+    self.step.set(step + 1);
+    let mut keyed_rng = if GLOBAL_CONFIG.with(|cfg| cfg.deterministic) {
+      let mut key = self.seed.borrow().clone();
+      key.push(step as u32);
+      key.push((step >> 32) as u32);
+      Some(ChaChaRng::from_seed(&key))
+    } else {
+      None
+    };
+    let mut stream_rng = self.rng.borrow_mut();
+    let rng: &mut ChaChaRng = match keyed_rng {
+      Some(ref mut rng) => rng,
+      None => &mut *stream_rng,
+    };
+    mask.clear();
+    for _ in 0 .. len {
+      mask.push(if rng.next_f32() < self.rate { 0.0 } else { scale });
+    }
+  }
+}
+
+impl<A> AVar<AData<A>> for DropoutOp<A> where DropoutOp<A>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+}
+
+impl<Op, S> DropoutExt<BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn dropout(&self, rate: f32, mode: Rc<CopyConstant<bool>>, seed_rng: Rc<RefCell<ChaChaRng>>) -> Rc<DropoutOp<BatchArray1d<f32, S>>> {
+    //let clk_horizon = self.data().horizon();
+    DropoutOp::new(rate, mode, seed_rng, self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.data()))
+  }
+}
+
+impl<Op, S> DropoutExt<BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn dropout(&self, rate: f32, mode: Rc<CopyConstant<bool>>, seed_rng: Rc<RefCell<ChaChaRng>>) -> Rc<DropoutOp<BatchArray3d<f32, S>>> {
+    //let clk_horizon = self.data().horizon();
+    DropoutOp::new(rate, mode, seed_rng, self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.data()))
+  }
+}
+
+impl<A> AOp for DropoutOp<A> where A: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    // The mask goes with the output value it produced.
+    if vars.contains(&self.y.val.var()) {
+      self.mask.rollover(txn, &mut var_set().add(self.mask.var()));
+    }
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _init(&self, _txn: TxnId, seed_rng: Rc<RefCell<ChaChaRng>>) {
+    // Reseeding on init makes the sequence of masks a function of the
+    // graph's seed alone.
+    let seed = _dropout_seed(&mut *seed_rng.borrow_mut());
+    *self.rng.borrow_mut() = ChaChaRng::from_seed(&seed);
+    *self.seed.borrow_mut() = seed;
+    self.step.set(0);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    self.mode.var.persist(txn);
+    if self.y.val.overwrite(txn, node) {
+      match self.mode.var.get(txn) {
+        false => {
+          let batch_sz = self.x.val.get(txn, node)._batch_size();
+          let len = self.x.val.get(txn, node)._packed_dim().iter().product();
+          if self.mask.overwrite(txn, node) {
+            self._sample_mask(len, &mut *self.mask.get_excl(txn, node));
+          }
+          self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+          unsafe { arraydiff_kernel_bcast_mult_add_fwd_f32(
+              1, len, 1,
+              self.x.val.get(txn, node)._as_ptr(),
+              self.mask.get(txn, node).as_ptr(),
+              null(),
+              self.y.val.get_excl(txn, node)._as_mut_ptr(),
+          ) };
+        }
+        true => {
+          _packed_copy_fwd(&self.x, &self.y, txn, node);
+        }
+      }
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    match self.mode.var.get(txn) {
+      false => {
+        let batch_sz = self.x.val.get(txn, node)._batch_size();
+        let len = self.x.val.get(txn, node)._packed_dim().iter().product();
+        if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+          // Reuse the mask sampled during the forward pass of this txn.
+          let mask = self.mask.get(txn, node);
+          assert_eq!(len, mask.len());
+          unsafe { arraydiff_kernel_bcast_mult_add_input_bwd_f32(
+              1, len, 1,
+              mask.as_ptr(),
+              self.y.grad.get(txn, node)._as_ptr(),
+              self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+          ) };
+        }
+      }
+      true => {
+        _packed_copy_bwd(&self.x, &self.y, txn, node);
+      }
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      match self.mode.var.get(txn) {
+        false => {
+          let batch_sz = self.x.val.get(txn, node)._batch_size();
+          let len = self.x.val.get(txn, node)._packed_dim().iter().product();
+          let mask = self.mask.get(txn, node);
+          assert_eq!(len, mask.len());
+          self.y.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+          unsafe { arraydiff_kernel_bcast_mult_add_fwd_f32(
+              1, len, 1,
+              self.x.r_val.get(txn, node)._as_ptr(),
+              mask.as_ptr(),
+              null(),
+              self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+          ) };
+        }
+        true => {
+          _packed_copy_r_fwd(&self.x, &self.y, txn, node);
+        }
+      }
+    }
+  }
+}
+
+pub trait MultExt<A, B, V, W> {
+  fn mult(&self, x: Rc<AVar<AData<V>>>) -> Rc<LinearOp<A, B, V, W>>;
+  fn mult_add(&self, x: Rc<AVar<AData<V>>>, b: Rc<AVar<AData<B>>>) -> Rc<LinearOp<A, B, V, W>>;
+}
+
+/// Products with the transpose of the left operand, `y = aᵀ x (+ b)`.
+pub trait TransposeMultExt<A, B, V, W> {
+  fn t_mult(&self, x: Rc<AVar<AData<V>>>) -> Rc<LinearOp<A, B, V, W>>;
+  fn t_mult_add(&self, x: Rc<AVar<AData<V>>>, b: Rc<AVar<AData<B>>>) -> Rc<LinearOp<A, B, V, W>>;
+}
+
+/// Products with the transpose of the right operand, `y = a xᵀ`.
+pub trait MultTransposeExt<A, B, V, W> {
+  fn mult_t(&self, x: Rc<AVar<AData<V>>>) -> Rc<LinearOp<A, B, V, W>>;
+}
+
+pub struct LinearOp<A, B, V, W> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  a_:   Rc<AVar<AData<A>>>,
+  b_:   Option<Rc<AVar<AData<B>>>>,
+  x_:   Rc<AVar<AData<V>>>,
+  a:    AData<A>,
+  b:    Option<AData<B>>,
+  x:    AData<V>,
+  y:    AData<W>,
+  tmp:  AData<W>,
+  tng:  RefCell<Option<Rc<AVar<AData<W>>>>>,
+  a_trans:  Transpose,
+  x_trans:  Transpose,
+}
+
+fn _flip_transpose(trans: Transpose) -> Transpose {
+  match trans {
+    Transpose::N => Transpose::T,
+    Transpose::T => Transpose::N,
+  }
+}
+
+impl<A, B, V, W> LinearOp<A, B, V, W> {
+  pub fn new<F>(a_: Rc<AVar<AData<A>>>, x_: Rc<AVar<AData<V>>>, b_: Option<Rc<AVar<AData<B>>>>, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<LinearOp<A, B, V, W>> where F: 'static + Fn(TxnId, NodeId) -> W {
+    LinearOp::new_transpose(Transpose::N, Transpose::N, a_, x_, b_, /*clk_horizon,*/ alloc)
+  }
+
+  /// Computes `y = op(a) op(x) (+ b)`, where each `op` is the identity or the
+  /// transpose according to `a_trans` and `x_trans`.
+  pub fn new_transpose<F>(a_trans: Transpose, x_trans: Transpose, a_: Rc<AVar<AData<A>>>, x_: Rc<AVar<AData<V>>>, b_: Option<Rc<AVar<AData<B>>>>, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<LinearOp<A, B, V, W>> where F: 'static + Fn(TxnId, NodeId) -> W {
+    let node = NodeId::new();
+    let in_degree = match b_ {
+      None    => 2,
+      Some(_) => 3,
+    };
+    let a = a_.data();
+    let b = b_.as_ref().map(|b_| b_.data());
+    let x = x_.data();
+    Rc::new(LinearOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, in_degree),
+      a_:   a_,
+      b_:   b_,
+      x_:   x_,
+      a:    a,
+      b:    b,
+      x:    x,
+      y:    AData::new(/*clk_horizon,*/ alloc.clone()),
+      tmp:  AData::new(/*1,*/ alloc),
+      tng:  RefCell::new(None),
+      a_trans:  a_trans,
+      x_trans:  x_trans,
+    })
+  }
+}
+
+impl<A, B, V, W> AVar<AData<W>> for LinearOp<A, B, V, W> where LinearOp<A, B, V, W>: AOp {
+  default fn _owned_data(&self) -> &AData<W> {
+    &self.y
+  }
+
+  default fn tangent(&self) -> Rc<AVar<AData<W>>> {
+    if self.tng.borrow().is_none() {
+      *self.tng.borrow_mut() = Some(self._make_tangent());
+    }
+    self.tng.borrow().as_ref().unwrap().clone()
+  }
+}
+
+impl<A, B, V, W> AVar<AData<W>> for LinearOp<A, B, V, W>
+where A: 'static, B: 'static, V: 'static, W: 'static + PackedArray,
+      LinearOp<A, B, V, W>: AOp,
+      JoinOp<W, SumJoinKernel>: AOp,
+{
+  fn _owned_data(&self) -> &AData<W> {
+    &self.y
+  }
+
+  fn _make_tangent(&self) -> Rc<AVar<AData<W>>> {
+    // The product rule: `op(a) op(x') + op(a') op(x) (+ b')`.
+    let tng_a_ = self.a_.tangent();
+    let tng_b_ = self.b_.as_ref().map(|b_| b_.tangent());
+    let tng_x_ = self.x_.tangent();
+    let alloc = _shared_alloc(&self.y);
+    let lhs_: Rc<AVar<AData<W>>> = LinearOp::new_transpose(self.a_trans, self.x_trans, self.a_.clone(), tng_x_, None, /*clk_horizon,*/ alloc.clone());
+    let rhs_: Rc<AVar<AData<W>>> = LinearOp::new_transpose(self.a_trans, self.x_trans, tng_a_, self.x_.clone(), tng_b_, /*clk_horizon,*/ alloc.clone());
+    JoinOp::new(vec![lhs_, rhs_], SumJoinKernel, /*clk_horizon,*/ alloc)
+  }
+
+  fn _adjoint(&self, adj_: Rc<AVar<AData<W>>>, adjs: &mut AdjointMap) {
+    self._linear_adjoint(adj_, adjs);
+  }
+}
+
+impl<Op, S> MultExt<Array1d<f32, S>, f32, Array1d<f32, S>, f32> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, S>>>, S: DerefMut<Target=[f32]> {
+  fn mult(&self, x_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<LinearOp<Array1d<f32, S>, f32, Array1d<f32, S>, f32>> {
+    //let clk_horizon = x_.data().horizon();
+    LinearOp::new(self.clone(), x_, None, /*clk_horizon,*/ Rc::new(|_, _| 0.0_f32))
+  }
+
+  fn mult_add(&self, x_: Rc<AVar<AData<Array1d<f32, S>>>>, b_: Rc<AVar<AData<f32>>>) -> Rc<LinearOp<Array1d<f32, S>, f32, Array1d<f32, S>, f32>> {
+    //let clk_horizon = x_.data().horizon();
+    LinearOp::new(self.clone(), x_, Some(b_), /*clk_horizon,*/ Rc::new(|_, _| 0.0_f32))
+  }
+}
+
+/*impl<S> AutodiffObjective for LinearOp<Array1d<f32, S>, Array1d<f32, S>, f32, f32> where S: DerefMut<Target=[f32]> {
+  fn _set_source(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.grad.accumulate(txn, node, |g| *g = 1.0) {
+    } else {
+      assert_eq!(1.0, *self.y.grad.get_mut(txn, node));
+    }
+  }
+}*/
+
+impl<S> AOp for LinearOp<Array1d<f32, S>, f32, Array1d<f32, S>, f32> where S: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.a_._push(epoch, apply);
+      self.x_._push(epoch, apply);
+      if let Some(ref b_) = self.b_ {
+        b_._push(epoch, apply);
+      }
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      if let Some(ref b_) = self.b_ {
+        b_._pop(epoch, apply);
+      }
+      self.x_._pop(epoch, apply);
+      self.a_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      *self.y.val.get_excl(txn, node) = self.a.val.get(txn, node).as_view().inner_prod(1.0, self.x.val.get(txn, node).as_view());
+      if let Some(ref b) = self.b {
+        *self.y.val.get_excl(txn, node) += *b.val.get(txn, node);
+      }
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.a.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      //println!("DEBUG: LinearOp: backward: a.grad: before: {:?}", &self.a.grad.get_mut(txn, node).as_slice()[ .. 5]);
+      self.a.grad.get_mut(txn, node).as_view_mut().add(*self.y.grad.get(txn, node), self.x.val.get(txn, node).as_view());
+      //println!("DEBUG: LinearOp: backward: a.grad: after:  {:?}", &self.a.grad.get_mut(txn, node).as_slice()[ .. 5]);
+    }
+    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      //println!("DEBUG: LinearOp: backward: x.grad: before: {:?}", &self.x.grad.get_mut(txn, node).as_slice()[ .. 5]);
+      self.x.grad.get_mut(txn, node).as_view_mut().add(*self.y.grad.get(txn, node), self.a.val.get(txn, node).as_view());
+      //println!("DEBUG: LinearOp: backward: x.grad: after:  {:?}", &self.x.grad.get_mut(txn, node).as_slice()[ .. 5]);
+    }
+    if let Some(ref b) = self.b {
+      if b.grad.accumulate(txn, node, |g| *g = 0.0) {
+        *b.grad.get_mut(txn, node) += *self.y.grad.get(txn, node);
+      }
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      *self.y.r_val.get_excl(txn, node) =
+          self.a.r_val.get(txn, node).as_view().inner_prod(1.0, self.x.val.get(txn, node).as_view())
+          + self.a.val.get(txn, node).as_view().inner_prod(1.0, self.x.r_val.get(txn, node).as_view());
+      if let Some(ref b) = self.b {
+        *self.y.r_val.get_excl(txn, node) += *b.r_val.get(txn, node);
+      }
+    }
+  }
+
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    let dim = self.x.val.get(txn, node).dim();
+    if self.a.grad2.accumulate(txn, node, |grad2| grad2.as_view_mut().set_constant(0.0)) {
+      let mut x_sq = Array1d::zeros(dim);
+      unsafe { arraydiff_kernel_square_f32(
+          dim,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          x_sq.as_view_mut().as_mut_ptr(),
+      ) };
+      self.a.grad2.get_mut(txn, node).as_view_mut().add(*self.y.grad2.get(txn, node), x_sq.as_view());
+    }
+    if self.x.grad2.accumulate(txn, node, |grad2| grad2.as_view_mut().set_constant(0.0)) {
+      let mut a_sq = Array1d::zeros(dim);
+      unsafe { arraydiff_kernel_square_f32(
+          dim,
+          self.a.val.get(txn, node).as_view().as_ptr(),
+          a_sq.as_view_mut().as_mut_ptr(),
+      ) };
+      self.x.grad2.get_mut(txn, node).as_view_mut().add(*self.y.grad2.get(txn, node), a_sq.as_view());
+    }
+    if let Some(ref b) = self.b {
+      if b.grad2.accumulate(txn, node, |g| *g = 0.0) {
+        *b.grad2.get_mut(txn, node) += *self.y.grad2.get(txn, node);
+      }
+    }
+  }
+}
+
+impl<S> MultExt<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>> for Rc<AVar<AData<Array2d<f32, S>>>> where S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
+  fn mult(&self, x_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>>> {
+    //let clk_horizon = x_.data().horizon();
+    LinearOp::new(self.clone(), x_.clone(), None, /*clk_horizon,*/ {
+      let a = self.clone().data();
+      Rc::new(move |txn, node| {
+        let dim = a.val.get(txn, node).dim().0;
+        let buf = <S as ArrayStorage<usize>>::alloc(dim);
+        Array1d::from_storage(dim, buf)
+      })
+    })
+  }
+
+  fn mult_add(&self, x_: Rc<AVar<AData<Array1d<f32, S>>>>, b_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>>> {
+    //let clk_horizon = x_.data().horizon();
+    LinearOp::new(self.clone(), x_.clone(), Some(b_), /*clk_horizon,*/ {
+      let a = self.clone().data();
+      Rc::new(move |txn, node| {
+        let dim = a.val.get(txn, node).dim().0;
+        let buf = <S as ArrayStorage<usize>>::alloc(dim);
+        Array1d::from_storage(dim, buf)
+      })
+    })
+  }
+}
+
+/*impl<S> AVar<AData<Array1d<f32, S>>> for LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>> where S: DerefMut<Target=[f32]> {
+  fn _owned_data(&self) -> &AData<A> {
+    &self.data
+  }
+}*/
+
+impl<S> AOp for LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>> where S: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.a_._push(epoch, apply);
+      self.x_._push(epoch, apply);
+      if let Some(ref b_) = self.b_ {
+        b_._push(epoch, apply);
+      }
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      if let Some(ref b_) = self.b_ {
+        b_._pop(epoch, apply);
+      }
+      self.x_._pop(epoch, apply);
+      self.a_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      self.y.val.get_mut(txn, node).as_view_mut().matrix_vector_prod(
+          1.0,
+          self.a.val.get(txn, node).as_view(), self.a_trans,
+          self.x.val.get(txn, node).as_view(),
+          0.0,
+      );
+      if let Some(ref b) = self.b {
+        self.y.val.get_mut(txn, node).as_view_mut().add(1.0, b.val.get(txn, node).as_view());
+      }
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.a.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      let x_dim = self.x.val.get(txn, node).dim();
+      let y_dim = self.y.val.get(txn, node).dim();
+      match self.a_trans {
+        Transpose::N => {
+          self.a.grad.get_mut(txn, node).as_view_mut().matrix_prod(
+              1.0,
+              self.y.grad.get(txn, node).as_view().reshape((y_dim, 1)), Transpose::N,
+              self.x.val.get(txn, node).as_view().reshape((x_dim, 1)), Transpose::T,
+              1.0,
+          );
+        }
+        Transpose::T => {
+          self.a.grad.get_mut(txn, node).as_view_mut().matrix_prod(
+              1.0,
+              self.x.val.get(txn, node).as_view().reshape((x_dim, 1)), Transpose::N,
+              self.y.grad.get(txn, node).as_view().reshape((y_dim, 1)), Transpose::T,
+              1.0,
+          );
+        }
+      }
+    }
+    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      self.x.grad.get_mut(txn, node).as_view_mut().matrix_vector_prod(
+          1.0,
+          self.a.val.get(txn, node).as_view(), _flip_transpose(self.a_trans),
+          self.y.grad.get(txn, node).as_view(),
+          1.0,
+      );
+    }
+    if let Some(ref b) = self.b {
+      if b.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+        b.grad.get_mut(txn, node).as_view_mut().copy(self.y.grad.get(txn, node).as_view());
+      }
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      self.y.r_val.get_mut(txn, node).as_view_mut().matrix_vector_prod(
+          1.0,
+          self.a.r_val.get(txn, node).as_view(), self.a_trans,
+          self.x.val.get(txn, node).as_view(),
+          0.0,
+      );
+      self.y.r_val.get_mut(txn, node).as_view_mut().matrix_vector_prod(
+          1.0,
+          self.a.val.get(txn, node).as_view(), self.a_trans,
+          self.x.r_val.get(txn, node).as_view(),
+          1.0,
+      );
+      if let Some(ref b) = self.b {
+        self.y.r_val.get_mut(txn, node).as_view_mut().add(1.0, b.r_val.get(txn, node).as_view());
+      }
+    }
+  }
+
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    if self.a.grad2.accumulate(txn, node, |grad2| grad2.as_view_mut().set_constant(0.0)) {
+      let x_dim = self.x.val.get(txn, node).dim();
+      let y_dim = self.y.val.get(txn, node).dim();
+      let mut x_sq = Array1d::zeros(x_dim);
+      unsafe { arraydiff_kernel_square_f32(
+          x_dim,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          x_sq.as_view_mut().as_mut_ptr(),
+      ) };
+      match self.a_trans {
+        Transpose::N => {
+          self.a.grad2.get_mut(txn, node).as_view_mut().matrix_prod(
+              1.0,
+              self.y.grad2.get(txn, node).as_view().reshape((y_dim, 1)), Transpose::N,
+              x_sq.as_view().reshape((x_dim, 1)), Transpose::T,
+              1.0,
+          );
+        }
+        Transpose::T => {
+          self.a.grad2.get_mut(txn, node).as_view_mut().matrix_prod(
+              1.0,
+              x_sq.as_view().reshape((x_dim, 1)), Transpose::N,
+              self.y.grad2.get(txn, node).as_view().reshape((y_dim, 1)), Transpose::T,
+              1.0,
+          );
+        }
+      }
+    }
+    if self.x.grad2.accumulate(txn, node, |grad2| grad2.as_view_mut().set_constant(0.0)) {
+      let a_dim = self.a.val.get(txn, node).dim();
+      let mut a_sq = Array2d::zeros(a_dim);
+      unsafe { arraydiff_kernel_square_f32(
+          a_dim.flat_len(),
+          self.a.val.get(txn, node).as_view().as_ptr(),
+          a_sq.as_view_mut().as_mut_ptr(),
+      ) };
+      self.x.grad2.get_mut(txn, node).as_view_mut().matrix_vector_prod(
+          1.0,
+          a_sq.as_view(), _flip_transpose(self.a_trans),
+          self.y.grad2.get(txn, node).as_view(),
+          1.0,
+      );
+    }
+    if let Some(ref b) = self.b {
+      if b.grad2.accumulate(txn, node, |grad2| grad2.as_view_mut().set_constant(0.0)) {
+        b.grad2.get_mut(txn, node).as_view_mut().add(1.0, self.y.grad2.get(txn, node).as_view());
+      }
+    }
+  }
+
+  /*fn _r_backward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.a.r_grad.accumulate(txn, node, |r_grad| r_grad.as_view_mut().set_constant(0.0)) {
+      let x_dim = self.x.val.get(txn, node).dim();
+      let y_dim = self.y.grad.get(txn, node).dim();
+      self.a.r_grad.get_mut(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.y.r_grad.get(txn, node).as_view().reshape((y_dim, 1)), Transpose::N,
+          self.x.val.get(txn, node).as_view().reshape((x_dim, 1)), Transpose::T,
+          1.0,
+      );
+      self.a.r_grad.get_mut(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.y.grad.get(txn, node).as_view().reshape((y_dim, 1)), Transpose::N,
+          self.x.r_val.get(txn, node).as_view().reshape((x_dim, 1)), Transpose::T,
+          1.0,
+      );
+    }
+    if self.x.r_grad.accumulate(txn, node, |r_grad| r_grad.as_view_mut().set_constant(0.0)) {
+      self.x.r_grad.get_mut(txn, node).as_view_mut().matrix_vector_prod(
+          1.0,
+          self.a.r_val.get(txn, node).as_view(), Transpose::T,
+          self.y.grad.get(txn, node).as_view(),
+          1.0,
+      );
+      self.x.r_grad.get_mut(txn, node).as_view_mut().matrix_vector_prod(
+          1.0,
+          self.a.val.get(txn, node).as_view(), Transpose::T,
+          self.y.r_grad.get(txn, node).as_view(),
+          1.0,
+      );
+    }
+    if let Some(ref b) = self.b {
+      if b.r_grad.accumulate(txn, node, |r_grad| r_grad.as_view_mut().set_constant(0.0)) {
+        b.r_grad.get_mut(txn, node).as_view_mut().add(1.0, self.y.r_grad.get(txn, node).as_view());
+      }
+    }
+  }*/
+}
+
+impl<S> MultExt<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>> for Rc<AVar<AData<Array2d<f32, S>>>> where S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn mult(&self, x_: Rc<AVar<AData<BatchArray1d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>>> {
+    //let clk_horizon = x_.data().horizon();
+    LinearOp::new(self.clone(), x_.clone(), None, /*clk_horizon,*/ {
+      let a = self.clone().data();
+      let x = x_.clone().data();
+      Rc::new(move |txn, node| {
+        let dim = a.val.get(txn, node).dim().0;
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+
+  fn mult_add(&self, x_: Rc<AVar<AData<BatchArray1d<f32, S>>>>, b_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>>> {
+    //let clk_horizon = x_.data().horizon();
+    LinearOp::new(self.clone(), x_.clone(), Some(b_), /*clk_horizon,*/ {
+      let a = self.clone().data();
+      let x = x_.clone().data();
+      Rc::new(move |txn, node| {
+        let dim = a.val.get(txn, node).dim().0;
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+/*impl<S> AVar<AData<BatchArray1d<f32, S>>> for LinearOp<Array2d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>, Array1d<f32, S>> where S: DerefMut<Target=[f32]> {
+  fn _owned_data(&self) -> &AData<A> {
+    &self.data
+  }
+}*/
+
+impl<S> AOp for LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>> where S: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.a_._push(epoch, apply);
+      self.x_._push(epoch, apply);
+      if let Some(ref b_) = self.b_ {
+        b_._push(epoch, apply);
+      }
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      if let Some(ref b_) = self.b_ {
+        b_._pop(epoch, apply);
+      }
+      self.x_._pop(epoch, apply);
+      self.a_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      self.y.val.get_mut(txn, node).set_batch_size(batch_sz);
+      self.y.val.get_mut(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.a.val.get(txn, node).as_view(), self.a_trans,
+          self.x.val.get(txn, node).as_view(), Transpose::N,
+          0.0,
+      );
+      if let Some(ref b) = self.b {
+        let y_dim = self.y.val.get(txn, node).dim();
+        unsafe { _batch_broadcast_add(
+            y_dim,
+            batch_sz,
+            b.val.get(txn, node).as_view().as_ptr(),
+            self.y.val.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+        ) };
+      }
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.a.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      match self.a_trans {
+        Transpose::N => {
+          self.a.grad.get_mut(txn, node).as_view_mut().matrix_prod(
+              1.0,
+              self.y.grad.get(txn, node).as_view(), Transpose::N,
+              self.x.val.get(txn, node).as_view(), Transpose::T,
+              1.0,
+          );
+        }
+        Transpose::T => {
+          self.a.grad.get_mut(txn, node).as_view_mut().matrix_prod(
+              1.0,
+              self.x.val.get(txn, node).as_view(), Transpose::N,
+              self.y.grad.get(txn, node).as_view(), Transpose::T,
+              1.0,
+          );
+        }
+      }
+    }
+    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      let batch_sz = self.y.grad.get(txn, node).batch_size();
+      self.x.grad.get_mut(txn, node).set_batch_size(batch_sz);
+      self.x.grad.get_mut(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.a.val.get(txn, node).as_view(), _flip_transpose(self.a_trans),
+          self.y.grad.get(txn, node).as_view(), Transpose::N,
+          1.0,
+      );
+    }
+    if let Some(ref b) = self.b {
+      if b.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+        let y_dim = self.y.grad.get(txn, node).dim();
+        let batch_sz = self.y.grad.get(txn, node).batch_size();
+        unsafe { _batch_sum_add(
+            y_dim,
+            batch_sz,
+            self.y.grad.get(txn, node).as_view().as_ptr(),
+            b.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+        ) };
+      }
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      assert_eq!(batch_sz, self.x.r_val.get(txn, node).batch_size());
+      self.y.r_val.get_mut(txn, node).set_batch_size(batch_sz);
+      self.y.r_val.get_mut(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.a.r_val.get(txn, node).as_view(), self.a_trans,
+          self.x.val.get(txn, node).as_view(), Transpose::N,
+          0.0,
+      );
+      self.y.r_val.get_mut(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.a.val.get(txn, node).as_view(), self.a_trans,
+          self.x.r_val.get(txn, node).as_view(), Transpose::N,
+          1.0,
+      );
+      if let Some(ref b) = self.b {
+        let y_dim = self.y.r_val.get(txn, node).dim();
+        unsafe { _batch_broadcast_add(
+            y_dim,
+            batch_sz,
+            b.r_val.get(txn, node).as_view().as_ptr(),
+            self.y.r_val.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+        ) };
+      }
+    }
+  }
+
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    if self.a.grad2.accumulate(txn, node, |grad2| grad2.as_view_mut().set_constant(0.0)) {
+      let x_dim = self.x.val.get(txn, node).dim();
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      let mut x_sq = Array2d::zeros((x_dim, batch_sz));
+      unsafe { arraydiff_kernel_square_f32(
+          x_dim * batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          x_sq.as_view_mut().as_mut_ptr(),
+      ) };
+      match self.a_trans {
+        Transpose::N => {
+          self.a.grad2.get_mut(txn, node).as_view_mut().matrix_prod(
+              1.0,
+              self.y.grad2.get(txn, node).as_view(), Transpose::N,
+              x_sq.as_view(), Transpose::T,
+              1.0,
+          );
+        }
+        Transpose::T => {
+          self.a.grad2.get_mut(txn, node).as_view_mut().matrix_prod(
+              1.0,
+              x_sq.as_view(), Transpose::N,
+              self.y.grad2.get(txn, node).as_view(), Transpose::T,
+              1.0,
+          );
+        }
+      }
+    }
+    if self.x.grad2.accumulate(txn, node, |grad2| grad2.as_view_mut().set_constant(0.0)) {
+      let a_dim = self.a.val.get(txn, node).dim();
+      let mut a_sq = Array2d::zeros(a_dim);
+      unsafe { arraydiff_kernel_square_f32(
+          a_dim.flat_len(),
+          self.a.val.get(txn, node).as_view().as_ptr(),
+          a_sq.as_view_mut().as_mut_ptr(),
+      ) };
+      let batch_sz = self.y.grad2.get(txn, node).batch_size();
+      self.x.grad2.get_mut(txn, node).set_batch_size(batch_sz);
+      self.x.grad2.get_mut(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          a_sq.as_view(), _flip_transpose(self.a_trans),
+          self.y.grad2.get(txn, node).as_view(), Transpose::N,
+          1.0,
+      );
+    }
+    if let Some(ref b) = self.b {
+      if b.grad2.accumulate(txn, node, |grad2| grad2.as_view_mut().set_constant(0.0)) {
+        let y_dim = self.y.grad2.get(txn, node).dim();
+        let batch_sz = self.y.grad2.get(txn, node).batch_size();
+        unsafe { _batch_sum_add(
+            y_dim,
+            batch_sz,
+            self.y.grad2.get(txn, node).as_view().as_ptr(),
+            b.grad2.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+        ) };
+      }
+    }
+  }
+}
+
+impl<S> TransposeMultExt<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>> for Rc<AVar<AData<Array2d<f32, S>>>> where S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
+  fn t_mult(&self, x_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>>> {
+    //let clk_horizon = x_.data().horizon();
+    LinearOp::new_transpose(Transpose::T, Transpose::N, self.clone(), x_.clone(), None, /*clk_horizon,*/ {
+      let a = self.clone().data();
+      Rc::new(move |txn, node| {
+        let dim = a.val.get(txn, node).dim().1;
+        let buf = <S as ArrayStorage<usize>>::alloc(dim);
+        Array1d::from_storage(dim, buf)
+      })
+    })
+  }
+
+  fn t_mult_add(&self, x_: Rc<AVar<AData<Array1d<f32, S>>>>, b_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>>> {
+    //let clk_horizon = x_.data().horizon();
+    LinearOp::new_transpose(Transpose::T, Transpose::N, self.clone(), x_.clone(), Some(b_), /*clk_horizon,*/ {
+      let a = self.clone().data();
+      Rc::new(move |txn, node| {
+        let dim = a.val.get(txn, node).dim().1;
+        let buf = <S as ArrayStorage<usize>>::alloc(dim);
+        Array1d::from_storage(dim, buf)
+      })
+    })
+  }
+}
+
+impl<S> TransposeMultExt<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>> for Rc<AVar<AData<Array2d<f32, S>>>> where S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn t_mult(&self, x_: Rc<AVar<AData<BatchArray1d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>>> {
+    //let clk_horizon = x_.data().horizon();
+    LinearOp::new_transpose(Transpose::T, Transpose::N, self.clone(), x_.clone(), None, /*clk_horizon,*/ {
+      let a = self.clone().data();
+      let x = x_.clone().data();
+      Rc::new(move |txn, node| {
+        let dim = a.val.get(txn, node).dim().1;
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+
+  fn t_mult_add(&self, x_: Rc<AVar<AData<BatchArray1d<f32, S>>>>, b_: Rc<AVar<AData<Array1d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>>> {
+    //let clk_horizon = x_.data().horizon();
+    LinearOp::new_transpose(Transpose::T, Transpose::N, self.clone(), x_.clone(), Some(b_), /*clk_horizon,*/ {
+      let a = self.clone().data();
+      let x = x_.clone().data();
+      Rc::new(move |txn, node| {
+        let dim = a.val.get(txn, node).dim().1;
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim, batch_sz);
+        BatchArray1d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<S> MultTransposeExt<Array2d<f32, S>, Array1d<f32, S>, Array2d<f32, S>, Array2d<f32, S>> for Rc<AVar<AData<Array2d<f32, S>>>> where S: 'static + DerefMut<Target=[f32]> + ArrayStorage<(usize, usize)> {
+  fn mult_t(&self, x_: Rc<AVar<AData<Array2d<f32, S>>>>) -> Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array2d<f32, S>, Array2d<f32, S>>> {
+    //let clk_horizon = x_.data().horizon();
+    LinearOp::new_transpose(Transpose::N, Transpose::T, self.clone(), x_.clone(), None, /*clk_horizon,*/ {
+      let a = self.clone().data();
+      let x = x_.clone().data();
+      Rc::new(move |txn, node| {
+        let a_dim = a.val.get(txn, node).dim();
+        let x_dim = x.val.get(txn, node).dim();
+        let dim = (a_dim.0, x_dim.0);
+        let buf = <S as ArrayStorage<(usize, usize)>>::alloc(dim);
+        Array2d::from_storage(dim, buf)
+      })
+    })
+  }
+}
+
+impl<S> AOp for LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array2d<f32, S>, Array2d<f32, S>> where S: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.a_._push(epoch, apply);
+      self.x_._push(epoch, apply);
+      if let Some(ref b_) = self.b_ {
+        b_._push(epoch, apply);
+      }
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      if let Some(ref b_) = self.b_ {
+        b_._pop(epoch, apply);
+      }
+      self.x_._pop(epoch, apply);
+      self.a_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      self.y.val.get_mut(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.a.val.get(txn, node).as_view(), self.a_trans,
+          self.x.val.get(txn, node).as_view(), self.x_trans,
+          0.0,
+      );
+      // `mult_t` never attaches a bias to a matrix product.
+      assert!(self.b.is_none());
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    // With `y = op(a) op(x)`, the gradient of `op(a)` is `dy op(x)ᵀ` and the
+    // gradient of `op(x)` is `op(a)ᵀ dy`; a transposed operand receives the
+    // transpose of its gradient.
+    if self.a.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      match self.a_trans {
+        Transpose::N => {
+          self.a.grad.get_mut(txn, node).as_view_mut().matrix_prod(
+              1.0,
+              self.y.grad.get(txn, node).as_view(), Transpose::N,
+              self.x.val.get(txn, node).as_view(), _flip_transpose(self.x_trans),
+              1.0,
+          );
+        }
+        Transpose::T => {
+          self.a.grad.get_mut(txn, node).as_view_mut().matrix_prod(
+              1.0,
+              self.x.val.get(txn, node).as_view(), self.x_trans,
+              self.y.grad.get(txn, node).as_view(), Transpose::T,
+              1.0,
+          );
+        }
+      }
+    }
+    if self.x.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      match self.x_trans {
+        Transpose::N => {
+          self.x.grad.get_mut(txn, node).as_view_mut().matrix_prod(
+              1.0,
+              self.a.val.get(txn, node).as_view(), _flip_transpose(self.a_trans),
+              self.y.grad.get(txn, node).as_view(), Transpose::N,
+              1.0,
+          );
+        }
+        Transpose::T => {
+          self.x.grad.get_mut(txn, node).as_view_mut().matrix_prod(
+              1.0,
+              self.y.grad.get(txn, node).as_view(), Transpose::T,
+              self.a.val.get(txn, node).as_view(), self.a_trans,
+              1.0,
+          );
+        }
+      }
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      self.y.r_val.get_mut(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.a.r_val.get(txn, node).as_view(), self.a_trans,
+          self.x.val.get(txn, node).as_view(), self.x_trans,
+          0.0,
+      );
+      self.y.r_val.get_mut(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.a.val.get(txn, node).as_view(), self.a_trans,
+          self.x.r_val.get(txn, node).as_view(), self.x_trans,
+          1.0,
+      );
+    }
+  }
+}
+
+trait LinearAdjoint<W> {
+  fn _linear_adjoint(&self, adj_: Rc<AVar<AData<W>>>, adjs: &mut AdjointMap);
+}
+
+impl<A, B, V, W> LinearAdjoint<W> for LinearOp<A, B, V, W> {
+  default fn _linear_adjoint(&self, _adj_: Rc<AVar<AData<W>>>, _adjs: &mut AdjointMap) {
+    unimplemented!();
+  }
+}
+
+impl<S> LinearAdjoint<Array1d<f32, S>> for LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>> where S: 'static + DerefMut<Target=[f32]> {
+  fn _linear_adjoint(&self, adj_: Rc<AVar<AData<Array1d<f32, S>>>>, adjs: &mut AdjointMap) {
+    let a_adj_: Rc<AVar<AData<Array2d<f32, S>>>> = match self.a_trans {
+      Transpose::N => OuterProdOp::new(adj_.clone(), self.x_.clone(), _shared_alloc(&self.a)),
+      Transpose::T => OuterProdOp::new(self.x_.clone(), adj_.clone(), _shared_alloc(&self.a)),
+    };
+    adjs.accumulate(self.a_.clone(), a_adj_);
+    let x_adj_: Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>>> = LinearOp::new_transpose(_flip_transpose(self.a_trans), Transpose::N, self.a_.clone(), adj_.clone(), None, /*clk_horizon,*/ _shared_alloc(&self.x));
+    adjs.accumulate(self.x_.clone(), x_adj_);
+    if let Some(ref b_) = self.b_ {
+      adjs.accumulate(b_.clone(), adj_);
+    }
+  }
+}
+
+impl<S> LinearAdjoint<BatchArray1d<f32, S>> for LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>> where S: 'static + DerefMut<Target=[f32]> {
+  fn _linear_adjoint(&self, adj_: Rc<AVar<AData<BatchArray1d<f32, S>>>>, adjs: &mut AdjointMap) {
+    let a_adj_: Rc<AVar<AData<Array2d<f32, S>>>> = match self.a_trans {
+      Transpose::N => OuterProdOp::new(adj_.clone(), self.x_.clone(), _shared_alloc(&self.a)),
+      Transpose::T => OuterProdOp::new(self.x_.clone(), adj_.clone(), _shared_alloc(&self.a)),
+    };
+    adjs.accumulate(self.a_.clone(), a_adj_);
+    let x_adj_: Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>>> = LinearOp::new_transpose(_flip_transpose(self.a_trans), Transpose::N, self.a_.clone(), adj_.clone(), None, /*clk_horizon,*/ _shared_alloc(&self.x));
+    adjs.accumulate(self.x_.clone(), x_adj_);
+    if let (&Some(ref b_), &Some(ref b)) = (&self.b_, &self.b) {
+      // The bias is broadcast over the batch, so its adjoint is the batch sum.
+      let b_adj_: Rc<BatchJoinOp<BatchArray1d<f32, S>, Array1d<f32, S>, SumJoinKernel>> = BatchJoinOp::_new(adj_, SumJoinKernel, /*clk_horizon,*/ _shared_alloc(b));
+      adjs.accumulate(b_.clone(), b_adj_);
+    }
+  }
+}
+
+impl<S> LinearAdjoint<f32> for LinearOp<Array1d<f32, S>, f32, Array1d<f32, S>, f32> where S: 'static + DerefMut<Target=[f32]> {
+  fn _linear_adjoint(&self, adj_: Rc<AVar<AData<f32>>>, adjs: &mut AdjointMap) {
+    // The adjoint of an inner product scales the other operand.
+    let a_adj_: Rc<ElemLinearOp<f32, Array1d<f32, S>, BroadcastMultAddKernel>> = ElemLinearOp::new(adj_.clone(), self.x_.clone(), None, BroadcastMultAddKernel, /*clk_horizon,*/ _shared_alloc(&self.a));
+    adjs.accumulate(self.a_.clone(), a_adj_);
+    let x_adj_: Rc<ElemLinearOp<f32, Array1d<f32, S>, BroadcastMultAddKernel>> = ElemLinearOp::new(adj_.clone(), self.a_.clone(), None, BroadcastMultAddKernel, /*clk_horizon,*/ _shared_alloc(&self.x));
+    adjs.accumulate(self.x_.clone(), x_adj_);
+    if let Some(ref b_) = self.b_ {
+      adjs.accumulate(b_.clone(), adj_);
+    }
+  }
+}
+
+/// The sum of outer products `y = sum_i u_i v_i^T` over the batch, e.g. the
+/// adjoint of the matrix of a `LinearOp`.
+pub struct OuterProdOp<V, A> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  u_:   Rc<AVar<AData<V>>>,
+  v_:   Rc<AVar<AData<V>>>,
+  u:    AData<V>,
+  v:    AData<V>,
+  y:    AData<A>,
+}
+
+impl<V, A> OuterProdOp<V, A> {
+  pub fn new<F>(u_: Rc<AVar<AData<V>>>, v_: Rc<AVar<AData<V>>>, /*clk_horizon: usize,*/ alloc: Rc<F>) -> Rc<Self> where F: 'static + Fn(TxnId, NodeId) -> A {
+    let node = NodeId::new();
+    let u = u_.data();
+    let v = v_.data();
+    Rc::new(OuterProdOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 2),
+      u_:   u_,
+      v_:   v_,
+      u:    u,
+      v:    v,
+      y:    AData::new(/*clk_horizon,*/ alloc),
+    })
+  }
+}
+
+impl<V, A> AVar<AData<A>> for OuterProdOp<V, A> where OuterProdOp<V, A>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+}
+
+impl<S> AVar<AData<Array2d<f32, S>>> for OuterProdOp<Array1d<f32, S>, Array2d<f32, S>> where S: 'static + DerefMut<Target=[f32]> {
+  fn _owned_data(&self) -> &AData<Array2d<f32, S>> {
+    &self.y
+  }
+
+  fn _adjoint(&self, adj_: Rc<AVar<AData<Array2d<f32, S>>>>, adjs: &mut AdjointMap) {
+    // `u_adj = adj v` and `v_adj = adj^T u`.
+    let u_adj_: Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>>> = LinearOp::new_transpose(Transpose::N, Transpose::N, adj_.clone(), self.v_.clone(), None, /*clk_horizon,*/ _shared_alloc(&self.u));
+    adjs.accumulate(self.u_.clone(), u_adj_);
+    let v_adj_: Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, Array1d<f32, S>, Array1d<f32, S>>> = LinearOp::new_transpose(Transpose::T, Transpose::N, adj_, self.u_.clone(), None, /*clk_horizon,*/ _shared_alloc(&self.v));
+    adjs.accumulate(self.v_.clone(), v_adj_);
+  }
+}
+
+impl<S> AVar<AData<Array2d<f32, S>>> for OuterProdOp<BatchArray1d<f32, S>, Array2d<f32, S>> where S: 'static + DerefMut<Target=[f32]> {
+  fn _owned_data(&self) -> &AData<Array2d<f32, S>> {
+    &self.y
+  }
+
+  fn _adjoint(&self, adj_: Rc<AVar<AData<Array2d<f32, S>>>>, adjs: &mut AdjointMap) {
+    let u_adj_: Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>>> = LinearOp::new_transpose(Transpose::N, Transpose::N, adj_.clone(), self.v_.clone(), None, /*clk_horizon,*/ _shared_alloc(&self.u));
+    adjs.accumulate(self.u_.clone(), u_adj_);
+    let v_adj_: Rc<LinearOp<Array2d<f32, S>, Array1d<f32, S>, BatchArray1d<f32, S>, BatchArray1d<f32, S>>> = LinearOp::new_transpose(Transpose::T, Transpose::N, adj_, self.u_.clone(), None, /*clk_horizon,*/ _shared_alloc(&self.v));
+    adjs.accumulate(self.v_.clone(), v_adj_);
+  }
+}
+
+impl<S> AOp for OuterProdOp<Array1d<f32, S>, Array2d<f32, S>> where S: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.u_._push(epoch, apply);
+      self.v_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.v_._pop(epoch, apply);
+      self.u_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let u_dim = self.u.val.get(txn, node).dim();
+      let v_dim = self.v.val.get(txn, node).dim();
+      self.y.val.get_excl(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.u.val.get(txn, node).as_view().reshape((u_dim, 1)), Transpose::N,
+          self.v.val.get(txn, node).as_view().reshape((v_dim, 1)), Transpose::T,
+          0.0,
+      );
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.u.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      self.u.grad.get_mut(txn, node).as_view_mut().matrix_vector_prod(
+          1.0,
+          self.y.grad.get(txn, node).as_view(), Transpose::N,
+          self.v.val.get(txn, node).as_view(),
+          1.0,
+      );
+    }
+    if self.v.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      self.v.grad.get_mut(txn, node).as_view_mut().matrix_vector_prod(
+          1.0,
+          self.y.grad.get(txn, node).as_view(), Transpose::T,
+          self.u.val.get(txn, node).as_view(),
+          1.0,
+      );
+    }
+  }
+}
+
+impl<S> AOp for OuterProdOp<BatchArray1d<f32, S>, Array2d<f32, S>> where S: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.u_._push(epoch, apply);
+      self.v_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.v_._pop(epoch, apply);
+      self.u_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      assert_eq!(self.u.val.get(txn, node).batch_size(), self.v.val.get(txn, node).batch_size());
+      self.y.val.get_excl(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.u.val.get(txn, node).as_view(), Transpose::N,
+          self.v.val.get(txn, node).as_view(), Transpose::T,
+          0.0,
+      );
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.u.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      let batch_sz = self.v.val.get(txn, node).batch_size();
+      self.u.grad.get_mut(txn, node).set_batch_size(batch_sz);
+      self.u.grad.get_mut(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.y.grad.get(txn, node).as_view(), Transpose::N,
+          self.v.val.get(txn, node).as_view(), Transpose::N,
+          1.0,
+      );
+    }
+    if self.v.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+      let batch_sz = self.u.val.get(txn, node).batch_size();
+      self.v.grad.get_mut(txn, node).set_batch_size(batch_sz);
+      self.v.grad.get_mut(txn, node).as_view_mut().matrix_prod(
+          1.0,
+          self.y.grad.get(txn, node).as_view(), Transpose::T,
+          self.u.val.get(txn, node).as_view(), Transpose::N,
+          1.0,
+      );
+    }
+  }
+}
+
+fn _transpose_dims(dim: (usize, usize), trans: Transpose) -> (usize, usize) {
+  match trans {
+    Transpose::N => dim,
+    Transpose::T => (dim.1, dim.0),
+  }
+}
+
+/// Computes `out_i = alpha op(p_i) op(q_i) + beta out_i` for each of the
+/// `num_mats` packed matrices.
+fn _batch_gemm(alpha: f32, p: *const f32, p_dim: (usize, usize), p_trans: Transpose, q: *const f32, q_dim: (usize, usize), q_trans: Transpose, num_mats: usize, beta: f32, out: *mut f32) {
+  let (m, k) = _transpose_dims(p_dim, p_trans);
+  let (q_k, n) = _transpose_dims(q_dim, q_trans);
+  assert_eq!(k, q_k);
+  unsafe { arraydiff_kernel_batch_gemm_f32(
+      m, n, k,
+      num_mats,
+      alpha,
+      p,
+      match p_trans {
+        Transpose::N => 0,
+        Transpose::T => 1,
+      },
+      q,
+      match q_trans {
+        Transpose::N => 0,
+        Transpose::T => 1,
+      },
+      beta,
+      out,
+  ) };
+}
+
+pub trait BatchMultExt<A> {
+  fn batch_mult(&self, a_trans: Transpose, x_: Rc<AVar<AData<A>>>, x_trans: Transpose) -> Rc<BatchMultOp<A>>;
+  fn batch_mult_scale(&self, alpha: f32, a_trans: Transpose, x_: Rc<AVar<AData<A>>>, x_trans: Transpose) -> Rc<BatchMultOp<A>>;
+}
+
+/// Batched matrix product `y = alpha op(a) op(x)`.
+///
+/// A `BatchArray3d` of dim `(rows, cols, num_mats)` is treated as `num_mats`
+/// column-major `rows x cols` matrices per example (e.g. one per attention
+/// head); use `num_mats == 1` for a single matrix per example.
+pub struct BatchMultOp<A> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  alpha:    f32,
+  a_trans:  Transpose,
+  x_trans:  Transpose,
+  a_:   Rc<AVar<AData<A>>>,
+  x_:   Rc<AVar<AData<A>>>,
+  a:    AData<A>,
+  x:    AData<A>,
+  y:    AData<A>,
+}
+
+impl<A> BatchMultOp<A> {
+  pub fn new(alpha: f32, a_trans: Transpose, a_: Rc<AVar<AData<A>>>, x_trans: Transpose, x_: Rc<AVar<AData<A>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<Self> {
+    let node = NodeId::new();
+    let a = a_.data();
+    let x = x_.data();
+    Rc::new(BatchMultOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 2),
+      alpha:    alpha,
+      a_trans:  a_trans,
+      x_trans:  x_trans,
+      a_:   a_,
+      x_:   x_,
+      a:    a,
+      x:    x,
+      y:    AData::new(/*clk_horizon,*/ alloc),
+    })
+  }
+}
+
+impl<A> AVar<AData<A>> for BatchMultOp<A> where BatchMultOp<A>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+}
+
+impl<Op, S> BatchMultExt<BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn batch_mult(&self, a_trans: Transpose, x_: Rc<AVar<AData<BatchArray3d<f32, S>>>>, x_trans: Transpose) -> Rc<BatchMultOp<BatchArray3d<f32, S>>> {
+    self.batch_mult_scale(1.0, a_trans, x_, x_trans)
+  }
+
+  fn batch_mult_scale(&self, alpha: f32, a_trans: Transpose, x_: Rc<AVar<AData<BatchArray3d<f32, S>>>>, x_trans: Transpose) -> Rc<BatchMultOp<BatchArray3d<f32, S>>> {
+    //let clk_horizon = x_.data().horizon();
+    BatchMultOp::new(alpha, a_trans, self.clone(), x_trans, x_.clone(), /*clk_horizon,*/ {
+      let a = self.clone().data();
+      let x = x_.clone().data();
+      Rc::new(move |txn, node| {
+        let a_dim = a.val.get(txn, node).dim();
+        let x_dim = x.val.get(txn, node).dim();
+        assert_eq!(a_dim.2, x_dim.2);
+        let (m, _) = _transpose_dims((a_dim.0, a_dim.1), a_trans);
+        let (_, n) = _transpose_dims((x_dim.0, x_dim.1), x_trans);
+        let dim = (m, n, a_dim.2);
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let buf = <S as BatchArrayStorage<usize>>::alloc(dim.flat_len(), batch_sz);
+        BatchArray3d::from_storage(dim, batch_sz, buf)
+      })
+    })
+  }
+}
+
+impl<A> AOp for BatchMultOp<A> where A: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.a_._push(epoch, apply);
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+      self.a_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    let a_dim = self.a.val.get(txn, node)._packed_dim();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    assert_eq!(batch_sz, self.a.val.get(txn, node)._batch_size());
+    assert_eq!(a_dim[2], x_dim[2]);
+    let num_mats = a_dim[2] * a_dim[3];
+    if self.y.val.overwrite(txn, node) {
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      _batch_gemm(
+          self.alpha,
+          self.a.val.get(txn, node)._as_ptr(), (a_dim[0], a_dim[1]), self.a_trans,
+          self.x.val.get(txn, node)._as_ptr(), (x_dim[0], x_dim[1]), self.x_trans,
+          num_mats,
+          0.0,
+          self.y.val.get_excl(txn, node)._as_mut_ptr(),
+      );
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let a_dim = self.a.val.get(txn, node)._packed_dim();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let y_dim = self.y.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    let num_mats = a_dim[2] * a_dim[3];
+    // Same rule as the matrix-matrix `LinearOp`, per matrix.
+    if self.a.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      match self.a_trans {
+        Transpose::N => _batch_gemm(
+            self.alpha,
+            self.y.grad.get(txn, node)._as_ptr(), (y_dim[0], y_dim[1]), Transpose::N,
+            self.x.val.get(txn, node)._as_ptr(), (x_dim[0], x_dim[1]), _flip_transpose(self.x_trans),
+            num_mats,
+            1.0,
+            self.a.grad.get_mut(txn, node)._as_mut_ptr(),
+        ),
+        Transpose::T => _batch_gemm(
+            self.alpha,
+            self.x.val.get(txn, node)._as_ptr(), (x_dim[0], x_dim[1]), self.x_trans,
+            self.y.grad.get(txn, node)._as_ptr(), (y_dim[0], y_dim[1]), Transpose::T,
+            num_mats,
+            1.0,
+            self.a.grad.get_mut(txn, node)._as_mut_ptr(),
+        ),
+      }
+    }
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      match self.x_trans {
+        Transpose::N => _batch_gemm(
+            self.alpha,
+            self.a.val.get(txn, node)._as_ptr(), (a_dim[0], a_dim[1]), _flip_transpose(self.a_trans),
+            self.y.grad.get(txn, node)._as_ptr(), (y_dim[0], y_dim[1]), Transpose::N,
+            num_mats,
+            1.0,
+            self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+        ),
+        Transpose::T => _batch_gemm(
+            self.alpha,
+            self.y.grad.get(txn, node)._as_ptr(), (y_dim[0], y_dim[1]), Transpose::T,
+            self.a.val.get(txn, node)._as_ptr(), (a_dim[0], a_dim[1]), self.a_trans,
+            num_mats,
+            1.0,
+            self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+        ),
+      }
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    let a_dim = self.a.val.get(txn, node)._packed_dim();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    let num_mats = a_dim[2] * a_dim[3];
+    if self.y.r_val.overwrite(txn, node) {
+      // `r_y = alpha (op(r_a) op(x) + op(a) op(r_x))`.
+      self.y.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+      _batch_gemm(
+          self.alpha,
+          self.a.r_val.get(txn, node)._as_ptr(), (a_dim[0], a_dim[1]), self.a_trans,
+          self.x.val.get(txn, node)._as_ptr(), (x_dim[0], x_dim[1]), self.x_trans,
+          num_mats,
+          0.0,
+          self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+      );
+      _batch_gemm(
+          self.alpha,
+          self.a.val.get(txn, node)._as_ptr(), (a_dim[0], a_dim[1]), self.a_trans,
+          self.x.r_val.get(txn, node)._as_ptr(), (x_dim[0], x_dim[1]), self.x_trans,
+          num_mats,
+          1.0,
+          self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+      );
+    }
+  }
+}
+
+/// Scaled dot-product attention over `num_heads` heads. Tokens are columns:
+/// `q` has dim `(head_dim, num_queries, num_heads)`, `k` has dim
+/// `(head_dim, num_keys, num_heads)`, and `v` has dim
+/// `(value_dim, num_keys, num_heads)`. Computes
+/// `v softmax(kᵀ q / sqrt(head_dim))`, where the softmax runs over the keys
+/// of each query.
+pub fn scaled_dot_product_attention<Q, K, V, S>(head_dim: usize, q_: Rc<Q>, k_: Rc<K>, v_: Rc<V>) -> Rc<BatchMultOp<BatchArray3d<f32, S>>> where Q: 'static + AVar<AData<BatchArray3d<f32, S>>>, K: 'static + AVar<AData<BatchArray3d<f32, S>>>, V: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  let alpha = 1.0 / (head_dim as f32).sqrt();
+  let score_ = k_.batch_mult_scale(alpha, Transpose::T, q_, Transpose::N);
+  let prob_ = score_.softmax();
+  v_.batch_mult(Transpose::N, prob_, Transpose::N)
+}
+
+pub struct BroadcastAddOp<A, V> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  a_:   Rc<AVar<AData<A>>>,
+  x_:   Rc<AVar<AData<V>>>,
+  a:    AData<A>,
+  x:    AData<V>,
+  y:    AData<V>,
+  //kernel:   K,
+  axes: Vec<usize>,
+}
+
+impl<A, V> BroadcastAddOp<A, V> {
+  pub fn new(axes: Vec<usize>, a_: Rc<AVar<AData<A>>>, x_: Rc<AVar<AData<V>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> V>) -> Rc<Self> {
+    let node = NodeId::new();
+    let a = a_.data();
+    let x = x_.data();
+    Rc::new(BroadcastAddOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 2),
+      a_:   a_,
+      x_:   x_,
+      a:    a,
+      x:    x,
+      y:    AData::new(/*clk_horizon,*/ alloc.clone()),
+      axes: axes,
+    })
+  }
+}
+
+/// Adds `self` to `x_`, broadcast over the `axes` of `x_`. The kernels of
+/// both backends only handle one layout per pair of array types: no axes for
+/// batches of vectors, and the spatial axes `[0, 1]` for batches of 3d arrays.
+pub trait BroadcastAddExt<A, V> {
+  fn broadcast_add(&self, axes: Vec<usize>, x_: Rc<AVar<AData<V>>>) -> Rc<BroadcastAddOp<A, V>>;
+}
+
+/// Panics unless `axes` are the broadcast axes the kernels support.
+pub fn _check_bcast_axes(axes: &[usize], supported: &[usize]) {
+  if axes != supported {
+    panic!("broadcast_add: axes {:?} are not supported, only {:?}", axes, supported);
+  }
+}
+
+impl<A, V> AVar<AData<V>> for BroadcastAddOp<A, V> where BroadcastAddOp<A, V>: AOp {
+  default fn _owned_data(&self) -> &AData<V> {
+    &self.y
+  }
+}
+
+impl<Op, S, T> BroadcastAddExt<Array1d<f32, T>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, T>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize>, T: 'static + DerefMut<Target=[f32]> {
+  fn broadcast_add(&self, axes: Vec<usize>, x_: Rc<AVar<AData<BatchArray1d<f32, S>>>>) -> Rc<BroadcastAddOp<Array1d<f32, T>, BatchArray1d<f32, S>>> {
+    _check_bcast_axes(&axes, &[]);
+    //let clk_horizon = x_.data().horizon();
+    BroadcastAddOp::new(axes, self.clone(), x_.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(x_.data()))
+  }
+}
+
+impl<Op, S, T> BroadcastAddExt<Array1d<f32, T>, BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, T>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize>, T: 'static + DerefMut<Target=[f32]> {
+  fn broadcast_add(&self, axes: Vec<usize>, x_: Rc<AVar<AData<BatchArray3d<f32, S>>>>) -> Rc<BroadcastAddOp<Array1d<f32, T>, BatchArray3d<f32, S>>> {
+    _check_bcast_axes(&axes, &[0, 1]);
+    //let clk_horizon = x_.data().horizon();
+    BroadcastAddOp::new(axes, self.clone(), x_.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(x_.data()))
+  }
+}
+
+impl<A, V> AOp for BroadcastAddOp<A, V> where A: PackedArray, V: ElemBinaryArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      self.a_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.a_._pop(epoch, apply);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let a_len = self.a.val.get(txn, node)._packed_dim().iter().product();
+    let (inner_dim, chan_dim, outer_dim) = _chan_dims::<V>(&x_dim, a_len);
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.y.val.overwrite(txn, node) {
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_bcast_add_fwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          self.a.val.get(txn, node)._as_ptr(),
+          self.x.val.get(txn, node)._as_ptr(),
+          self.y.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let a_len = self.a.val.get(txn, node)._packed_dim().iter().product();
+    let (inner_dim, chan_dim, outer_dim) = _chan_dims::<V>(&x_dim, a_len);
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.a.grad.accumulate(txn, node, |grad| grad._set_zero()) {
+      unsafe { arraydiff_kernel_bcast_mult_add_param_bwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          null(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          null_mut(),
+          self.a.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { arraydiff_kernel_bcast_mult_add_input_bwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          null(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let a_len = self.a.val.get(txn, node)._packed_dim().iter().product();
+    let (inner_dim, chan_dim, outer_dim) = _chan_dims::<V>(&x_dim, a_len);
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.y.r_val.overwrite(txn, node) {
+      self.y.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_bcast_add_fwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          self.a.r_val.get(txn, node)._as_ptr(),
+          self.x.r_val.get(txn, node)._as_ptr(),
+          self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+}
+
+pub struct ElemLinearOp<A, V, K> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  a_:   Rc<AVar<AData<A>>>,
+  b_:   Option<Rc<AVar<AData<A>>>>,
+  x_:   Rc<AVar<AData<V>>>,
+  a:    AData<A>,
+  b:    Option<AData<A>>,
+  x:    AData<V>,
+  y:    AData<V>,
+  //tmp:  AData<V>,
+  kernel:   K,
+  tng_: RefCell<Option<Rc<AVar<AData<V>>>>>,
+}
+
+impl<A, V, Kernel> ElemLinearOp<A, V, Kernel> {
+  pub fn new(a_: Rc<AVar<AData<A>>>, x_: Rc<AVar<AData<V>>>, b_: Option<Rc<AVar<AData<A>>>>, kernel: Kernel, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> V>) -> Rc<Self> {
+    let node = NodeId::new();
+    let in_degree = match b_ {
+      None    => 2,
+      Some(_) => 3,
+    };
+    let a = a_.data();
+    let x = x_.data();
+    let b = b_.as_ref().map(|b_| b_.data());
+    Rc::new(ElemLinearOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, in_degree),
+      a_:   a_,
+      b_:   b_,
+      x_:   x_,
+      a:    a,
+      b:    b,
+      x:    x,
+      y:    AData::new(/*clk_horizon,*/ alloc.clone()),
+      //tmp:  AData::new(/*clk_horizon,*/ alloc.clone()),
+      kernel:   kernel,
+      tng_: RefCell::new(None),
+    })
+  }
+}
+
+pub struct BroadcastMultAddKernel;
+pub struct ElemNormalizeKernel{pub epsilon: f64}
+
+pub trait ElemMultExt<A, V> {
+  fn elem_mult(&self, x_: Rc<AVar<AData<V>>>) -> Rc<ElemLinearOp<A, V, BroadcastMultAddKernel>>;
+  fn elem_mult_add(&self, x_: Rc<AVar<AData<V>>>, b_: Rc<AVar<AData<A>>>) -> Rc<ElemLinearOp<A, V, BroadcastMultAddKernel>>;
+}
+
+/*pub trait ElemNormalizeExt<A, V> {
+  fn elem_normalize(&self, mean_: Rc<AVar<AData<A>>>, var_: Rc<AVar<AData<A>>>) -> Rc<ElemLinearOp<A, V, ElemNormalizeKernel>>;
+}*/
+
+impl<A, V, Kernel> AVar<AData<V>> for ElemLinearOp<A, V, Kernel> where ElemLinearOp<A, V, Kernel>: AOp {
+  default fn _owned_data(&self) -> &AData<V> {
+    &self.y
+  }
+
+  default fn tangent(&self) -> Rc<AVar<AData<V>>> {
+    if self.tng_.borrow().is_none() {
+      *self.tng_.borrow_mut() = Some(self._make_tangent());
+    }
+    self.tng_.borrow().as_ref().unwrap().clone()
+  }
+}
+
+impl<A, V> AVar<AData<V>> for ElemLinearOp<A, V, BroadcastMultAddKernel>
+where A: 'static, V: 'static + PackedArray,
+      ElemLinearOp<A, V, BroadcastMultAddKernel>: AOp,
+      JoinOp<V, SumJoinKernel>: AOp,
+{
+  fn _owned_data(&self) -> &AData<V> {
+    &self.y
+  }
+
+  fn _make_tangent(&self) -> Rc<AVar<AData<V>>> {
+    // The product rule: `a x' + a' x (+ b')`.
+    let tng_a_ = self.a_.tangent();
+    let tng_b_ = self.b_.as_ref().map(|b_| b_.tangent());
+    let tng_x_ = self.x_.tangent();
+    let lhs_: Rc<AVar<AData<V>>> = ElemLinearOp::new(self.a_.clone(), tng_x_, None, BroadcastMultAddKernel, /*clk_horizon,*/ self.y.alloc.clone());
+    let rhs_: Rc<AVar<AData<V>>> = ElemLinearOp::new(tng_a_, self.x_.clone(), tng_b_, BroadcastMultAddKernel, /*clk_horizon,*/ self.y.alloc.clone());
+    JoinOp::new(vec![lhs_, rhs_], SumJoinKernel, /*clk_horizon,*/ _shared_alloc(&self.y))
+  }
+}
+
+impl<Op, S> ElemMultExt<f32, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<f32>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn elem_mult(&self, x_: Rc<AVar<AData<BatchArray1d<f32, S>>>>) -> Rc<ElemLinearOp<f32, BatchArray1d<f32, S>, BroadcastMultAddKernel>> {
+    //let clk_horizon = x_.data().horizon();
+    ElemLinearOp::new(self.clone(), x_.clone(), None, BroadcastMultAddKernel, /*clk_horizon,*/ _batch_array1d_map_alloc(x_.data()))
+  }
+
+  fn elem_mult_add(&self, x_: Rc<AVar<AData<BatchArray1d<f32, S>>>>, b_: Rc<AVar<AData<f32>>>) -> Rc<ElemLinearOp<f32, BatchArray1d<f32, S>, BroadcastMultAddKernel>> {
+    //let clk_horizon = x_.data().horizon();
+    ElemLinearOp::new(self.clone(), x_.clone(), Some(b_), BroadcastMultAddKernel, /*clk_horizon,*/ _batch_array1d_map_alloc(x_.data()))
+  }
+}
+
+impl<Op, S> ElemMultExt<f32, BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<f32>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn elem_mult(&self, x_: Rc<AVar<AData<BatchArray3d<f32, S>>>>) -> Rc<ElemLinearOp<f32, BatchArray3d<f32, S>, BroadcastMultAddKernel>> {
+    //let clk_horizon = x_.data().horizon();
+    ElemLinearOp::new(self.clone(), x_.clone(), None, BroadcastMultAddKernel, /*clk_horizon,*/ _batch_array3d_map_alloc(x_.data()))
+  }
+
+  fn elem_mult_add(&self, x_: Rc<AVar<AData<BatchArray3d<f32, S>>>>, b_: Rc<AVar<AData<f32>>>) -> Rc<ElemLinearOp<f32, BatchArray3d<f32, S>, BroadcastMultAddKernel>> {
+    //let clk_horizon = x_.data().horizon();
+    ElemLinearOp::new(self.clone(), x_.clone(), Some(b_), BroadcastMultAddKernel, /*clk_horizon,*/ _batch_array3d_map_alloc(x_.data()))
+  }
+}
+
+impl<Op, S, T> ElemMultExt<Array1d<f32, T>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, T>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize>, T: 'static + DerefMut<Target=[f32]> {
+  fn elem_mult(&self, x_: Rc<AVar<AData<BatchArray1d<f32, S>>>>) -> Rc<ElemLinearOp<Array1d<f32, T>, BatchArray1d<f32, S>, BroadcastMultAddKernel>> {
+    //let clk_horizon = x_.data().horizon();
+    ElemLinearOp::new(self.clone(), x_.clone(), None, BroadcastMultAddKernel, /*clk_horizon,*/ _batch_array1d_map_alloc(x_.data()))
+  }
+
+  fn elem_mult_add(&self, x_: Rc<AVar<AData<BatchArray1d<f32, S>>>>, b_: Rc<AVar<AData<Array1d<f32, T>>>>) -> Rc<ElemLinearOp<Array1d<f32, T>, BatchArray1d<f32, S>, BroadcastMultAddKernel>> {
+    //let clk_horizon = x_.data().horizon();
+    ElemLinearOp::new(self.clone(), x_.clone(), Some(b_), BroadcastMultAddKernel, /*clk_horizon,*/ _batch_array1d_map_alloc(x_.data()))
+  }
+}
+
+impl<Op, S, T> ElemMultExt<Array1d<f32, T>, BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Array1d<f32, T>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize>, T: 'static + DerefMut<Target=[f32]> {
+  fn elem_mult(&self, x_: Rc<AVar<AData<BatchArray3d<f32, S>>>>) -> Rc<ElemLinearOp<Array1d<f32, T>, BatchArray3d<f32, S>, BroadcastMultAddKernel>> {
+    //let clk_horizon = x_.data().horizon();
+    ElemLinearOp::new(self.clone(), x_.clone(), None, BroadcastMultAddKernel, /*clk_horizon,*/ _batch_array3d_map_alloc(x_.data()))
+  }
+
+  fn elem_mult_add(&self, x_: Rc<AVar<AData<BatchArray3d<f32, S>>>>, b_: Rc<AVar<AData<Array1d<f32, T>>>>) -> Rc<ElemLinearOp<Array1d<f32, T>, BatchArray3d<f32, S>, BroadcastMultAddKernel>> {
+    //let clk_horizon = x_.data().horizon();
+    ElemLinearOp::new(self.clone(), x_.clone(), Some(b_), BroadcastMultAddKernel, /*clk_horizon,*/ _batch_array3d_map_alloc(x_.data()))
+  }
+}
+
+impl<A, V> AOp for ElemLinearOp<A, V, BroadcastMultAddKernel> where A: PackedArray, V: ElemBinaryArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      self.a_._push(epoch, apply);
+      if let Some(ref b_) = self.b_ {
+        b_._push(epoch, apply);
+      }
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      if let Some(ref b_) = self.b_ {
+        b_._pop(epoch, apply);
+      }
+      self.a_._pop(epoch, apply);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let a_len = self.a.val.get(txn, node)._packed_dim().iter().product();
+    let (inner_dim, chan_dim, outer_dim) = _chan_dims::<V>(&x_dim, a_len);
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.y.val.overwrite(txn, node) {
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      let b_val = self.b.as_ref().map(|b| b.val.get(txn, node));
+      unsafe { arraydiff_kernel_bcast_mult_add_fwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.a.val.get(txn, node)._as_ptr(),
+          b_val.as_ref().map_or(null(), |b| b._as_ptr()),
+          self.y.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let a_len = self.a.val.get(txn, node)._packed_dim().iter().product();
+    let (inner_dim, chan_dim, outer_dim) = _chan_dims::<V>(&x_dim, a_len);
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.a.grad.accumulate(txn, node, |grad| grad._set_zero()) {
+      unsafe { arraydiff_kernel_bcast_mult_add_param_bwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          self.a.grad.get_mut(txn, node)._as_mut_ptr(),
+          null_mut(),
+      ) };
+    }
+    if let Some(ref b) = self.b {
+      if b.grad.accumulate(txn, node, |grad| grad._set_zero()) {
+        unsafe { arraydiff_kernel_bcast_mult_add_param_bwd_f32(
+            inner_dim, chan_dim, outer_dim,
+            null(),
+            self.y.grad.get(txn, node)._as_ptr(),
+            null_mut(),
+            b.grad.get_mut(txn, node)._as_mut_ptr(),
+        ) };
+      }
+    }
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { arraydiff_kernel_bcast_mult_add_input_bwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          self.a.val.get(txn, node)._as_ptr(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let a_len = self.a.val.get(txn, node)._packed_dim().iter().product();
+    let (inner_dim, chan_dim, outer_dim) = _chan_dims::<V>(&x_dim, a_len);
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.y.r_val.overwrite(txn, node) {
+      // `r_y = a r_x + r_b`, then `r_y += r_a x`.
+      self.y.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+      let b_r_val = self.b.as_ref().map(|b| b.r_val.get(txn, node));
+      unsafe { arraydiff_kernel_bcast_mult_add_fwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          self.x.r_val.get(txn, node)._as_ptr(),
+          self.a.val.get(txn, node)._as_ptr(),
+          b_r_val.as_ref().map_or(null(), |b| b._as_ptr()),
+          self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+      unsafe { arraydiff_kernel_bcast_mult_add_input_bwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          self.a.r_val.get(txn, node)._as_ptr(),
+          self.x.val.get(txn, node)._as_ptr(),
+          self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let x_len = x_dim.iter().product();
+    let a_len = self.a.val.get(txn, node)._packed_dim().iter().product();
+    let (inner_dim, chan_dim, outer_dim) = _chan_dims::<V>(&x_dim, a_len);
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.a.grad2.accumulate(txn, node, |grad2| grad2._set_zero()) {
+      let mut x_sq = vec![0.0; x_len];
+      unsafe { arraydiff_kernel_square_f32(
+          x_len,
+          self.x.val.get(txn, node)._as_ptr(),
+          x_sq.as_mut_ptr(),
+      ) };
+      unsafe { arraydiff_kernel_bcast_mult_add_param_bwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          x_sq.as_ptr(),
+          self.y.grad2.get(txn, node)._as_ptr(),
+          self.a.grad2.get_mut(txn, node)._as_mut_ptr(),
+          null_mut(),
+      ) };
+    }
+    if let Some(ref b) = self.b {
+      if b.grad2.accumulate(txn, node, |grad2| grad2._set_zero()) {
+        unsafe { arraydiff_kernel_bcast_mult_add_param_bwd_f32(
+            inner_dim, chan_dim, outer_dim,
+            null(),
+            self.y.grad2.get(txn, node)._as_ptr(),
+            null_mut(),
+            b.grad2.get_mut(txn, node)._as_mut_ptr(),
+        ) };
+      }
+    }
+    if self.x.grad2.accumulate(txn, node, |grad2| { grad2._set_batch_size(batch_sz); grad2._set_zero(); }) {
+      let mut a_sq = vec![0.0; a_len];
+      unsafe { arraydiff_kernel_square_f32(
+          a_len,
+          self.a.val.get(txn, node)._as_ptr(),
+          a_sq.as_mut_ptr(),
+      ) };
+      unsafe { arraydiff_kernel_bcast_mult_add_input_bwd_f32(
+          inner_dim, chan_dim, outer_dim,
+          a_sq.as_ptr(),
+          self.y.grad2.get(txn, node)._as_ptr(),
+          self.x.grad2.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+}
+
+/*impl<S> AOp for ElemLinearOp<Array1d<f32, S>, BatchArray3d<f32, S>, ElemNormalizeKernel> where S: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.a_._push(epoch, apply);
+      self.x_._push(epoch, apply);
+      if let Some(ref b_) = self.b_ {
+        b_._push(epoch, apply);
+      }
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      if let Some(ref b_) = self.b_ {
+        b_._pop(epoch, apply);
+      }
+      self.x_._pop(epoch, apply);
+      self.a_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    // TODO
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      if let Some(ref b) = self.b {
+      }
+    }
+    unimplemented!();
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    // TODO
+    unimplemented!();
+  }
+}*/
+
+pub struct ElemNormalizeOp<Idx, A, V> where Idx: ArrayIndex {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  axes:     Idx::Axes,
+  epsilon:  f64,
+  x_:       Rc<AVar<AData<V>>>,
+  mean_:    Rc<AVar<AData<A>>>,
+  var_:     Rc<AVar<AData<A>>>,
+  x:        AData<V>,
+  mean:     AData<A>,
+  var:      AData<A>,
+  y:        AData<V>,
+}
+
+impl<Idx, A, V> ElemNormalizeOp<Idx, A, V> where Idx: ArrayIndex {
+  pub fn new(axes: Idx::Axes, epsilon: f64, x_: Rc<AVar<AData<V>>>, mean_: Rc<AVar<AData<A>>>, var_: Rc<AVar<AData<A>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> V>) -> Rc<Self> {
+    let node = NodeId::new();
+    let x = x_.data();
+    let mean = mean_.data();
+    let var = var_.data();
+    Rc::new(ElemNormalizeOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 3),
+      axes:     axes,
+      epsilon:  epsilon,
+      x_:       x_,
+      mean_:    mean_,
+      var_:     var_,
+      x:        x,
+      mean:     mean,
+      var:      var,
+      y:        AData::new(/*clk_horizon,*/ alloc.clone()),
+    })
+  }
+}
+
+pub trait ElemNormalizeExt<Idx, A, V> where Idx: ArrayIndex {
+  fn elem_normalize(&self, axes: Idx::Axes, epsilon: f64, mean_: Rc<AVar<AData<A>>>, var_: Rc<AVar<AData<A>>>) -> Rc<ElemNormalizeOp<Idx, A, V>>;
+}
+
+impl<Idx, A, V> AVar<AData<V>> for ElemNormalizeOp<Idx, A, V> where ElemNormalizeOp<Idx, A, V>: AOp, Idx: ArrayIndex {
+  default fn _owned_data(&self) -> &AData<V> {
+    &self.y
+  }
+}
+
+/// Normalizes each example over `num_groups` contiguous groups of channels,
+/// followed by a per-channel scale and shift. Unlike `BatchStatsOp`, the
+/// statistics are per example, so no running averages are kept and the
+/// output does not depend on the batch size.
+pub struct GroupNormOp<P, A> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  num_groups:   usize,
+  epsilon:  f64,
+  x_:       Rc<AVar<AData<A>>>,
+  scale_:   Rc<AVar<AData<P>>>,
+  shift_:   Rc<AVar<AData<P>>>,
+  x:        AData<A>,
+  scale:    AData<P>,
+  shift:    AData<P>,
+  y:        AData<A>,
+}
+
+/// Layer normalization is group normalization with a single group.
+pub type LayerNormOp<P, A> = GroupNormOp<P, A>;
+
+impl<P, A> GroupNormOp<P, A> {
+  pub fn new(num_groups: usize, epsilon: f64, x_: Rc<AVar<AData<A>>>, scale_: Rc<AVar<AData<P>>>, shift_: Rc<AVar<AData<P>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<Self> {
+    assert!(num_groups >= 1);
+    let node = NodeId::new();
+    let x = x_.data();
+    let scale = scale_.data();
+    let shift = shift_.data();
+    Rc::new(GroupNormOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 3),
+      num_groups:   num_groups,
+      epsilon:  epsilon,
+      x_:       x_,
+      scale_:   scale_,
+      shift_:   shift_,
+      x:        x,
+      scale:    scale,
+      shift:    shift,
+      y:        AData::new(/*clk_horizon,*/ alloc),
+    })
+  }
+}
+
+impl<P, A> AVar<AData<A>> for GroupNormOp<P, A> where GroupNormOp<P, A>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+}
+
+pub trait LayerNormExt<P, A> {
+  fn layer_norm(&self, epsilon: f64, scale_: Rc<AVar<AData<P>>>, shift_: Rc<AVar<AData<P>>>) -> Rc<LayerNormOp<P, A>>;
+}
+
+pub trait GroupNormExt<P, A> {
+  fn group_norm(&self, num_groups: usize, epsilon: f64, scale_: Rc<AVar<AData<P>>>, shift_: Rc<AVar<AData<P>>>) -> Rc<GroupNormOp<P, A>>;
+}
+
+impl<Op, S, T> LayerNormExt<Array1d<f32, T>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize>, T: 'static + DerefMut<Target=[f32]> {
+  fn layer_norm(&self, epsilon: f64, scale_: Rc<AVar<AData<Array1d<f32, T>>>>, shift_: Rc<AVar<AData<Array1d<f32, T>>>>) -> Rc<LayerNormOp<Array1d<f32, T>, BatchArray1d<f32, S>>> {
+    //let clk_horizon = self.data().horizon();
+    GroupNormOp::new(1, epsilon, self.clone(), scale_, shift_, /*clk_horizon,*/ _batch_array1d_map_alloc(self.data()))
+  }
+}
+
+impl<Op, S, T> LayerNormExt<Array1d<f32, T>, BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize>, T: 'static + DerefMut<Target=[f32]> {
+  fn layer_norm(&self, epsilon: f64, scale_: Rc<AVar<AData<Array1d<f32, T>>>>, shift_: Rc<AVar<AData<Array1d<f32, T>>>>) -> Rc<LayerNormOp<Array1d<f32, T>, BatchArray3d<f32, S>>> {
+    //let clk_horizon = self.data().horizon();
+    GroupNormOp::new(1, epsilon, self.clone(), scale_, shift_, /*clk_horizon,*/ _batch_array3d_map_alloc(self.data()))
+  }
+}
+
+impl<Op, S, T> GroupNormExt<Array1d<f32, T>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize>, T: 'static + DerefMut<Target=[f32]> {
+  fn group_norm(&self, num_groups: usize, epsilon: f64, scale_: Rc<AVar<AData<Array1d<f32, T>>>>, shift_: Rc<AVar<AData<Array1d<f32, T>>>>) -> Rc<GroupNormOp<Array1d<f32, T>, BatchArray1d<f32, S>>> {
+    //let clk_horizon = self.data().horizon();
+    GroupNormOp::new(num_groups, epsilon, self.clone(), scale_, shift_, /*clk_horizon,*/ _batch_array1d_map_alloc(self.data()))
+  }
+}
+
+impl<Op, S, T> GroupNormExt<Array1d<f32, T>, BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize>, T: 'static + DerefMut<Target=[f32]> {
+  fn group_norm(&self, num_groups: usize, epsilon: f64, scale_: Rc<AVar<AData<Array1d<f32, T>>>>, shift_: Rc<AVar<AData<Array1d<f32, T>>>>) -> Rc<GroupNormOp<Array1d<f32, T>, BatchArray3d<f32, S>>> {
+    //let clk_horizon = self.data().horizon();
+    GroupNormOp::new(num_groups, epsilon, self.clone(), scale_, shift_, /*clk_horizon,*/ _batch_array3d_map_alloc(self.data()))
+  }
+}
+
+impl<P, A> GroupNormOp<P, A> where P: PackedArray, A: ElemBinaryArray {
+  fn _group_dims(&self, txn: TxnId, node: NodeId) -> (usize, usize, usize) {
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let (inner_dim, chan_dim, outer_dim) = _axis_slice_dims(&x_dim, A::_feature_axis());
+    assert_eq!(chan_dim, self.scale.val.get(txn, node)._packed_dim().iter().product::<usize>());
+    assert_eq!(chan_dim, self.shift.val.get(txn, node)._packed_dim().iter().product::<usize>());
+    assert_eq!(0, chan_dim % self.num_groups);
+    (inner_dim, chan_dim, outer_dim)
+  }
+}
+
+impl<P, A> AOp for GroupNormOp<P, A> where P: PackedArray, A: ElemBinaryArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      self.scale_._push(epoch, apply);
+      self.shift_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.shift_._pop(epoch, apply);
+      self.scale_._pop(epoch, apply);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    let (inner_dim, chan_dim, outer_dim) = self._group_dims(txn, node);
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.y.val.overwrite(txn, node) {
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_group_norm_fwd_f32(
+          inner_dim, chan_dim, self.num_groups, outer_dim,
+          self.epsilon,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.scale.val.get(txn, node)._as_ptr(),
+          self.shift.val.get(txn, node)._as_ptr(),
+          self.y.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let (inner_dim, chan_dim, outer_dim) = self._group_dims(txn, node);
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.scale.grad.accumulate(txn, node, |grad| grad._set_zero()) {
+      unsafe { arraydiff_kernel_group_norm_param_bwd_f32(
+          inner_dim, chan_dim, self.num_groups, outer_dim,
+          self.epsilon,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          self.scale.grad.get_mut(txn, node)._as_mut_ptr(),
+          null_mut(),
+      ) };
+    }
+    if self.shift.grad.accumulate(txn, node, |grad| grad._set_zero()) {
+      unsafe { arraydiff_kernel_group_norm_param_bwd_f32(
+          inner_dim, chan_dim, self.num_groups, outer_dim,
+          self.epsilon,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          null_mut(),
+          self.shift.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { arraydiff_kernel_group_norm_input_bwd_f32(
+          inner_dim, chan_dim, self.num_groups, outer_dim,
+          self.epsilon,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.scale.val.get(txn, node)._as_ptr(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    let (inner_dim, chan_dim, outer_dim) = self._group_dims(txn, node);
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.y.r_val.overwrite(txn, node) {
+      self.y.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_group_norm_rfwd_f32(
+          inner_dim, chan_dim, self.num_groups, outer_dim,
+          self.epsilon,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.scale.val.get(txn, node)._as_ptr(),
+          self.x.r_val.get(txn, node)._as_ptr(),
+          self.scale.r_val.get(txn, node)._as_ptr(),
+          self.shift.r_val.get(txn, node)._as_ptr(),
+          self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+}
+
+#[derive(Clone, Copy)]
+pub struct ConvShape<Idx> where Idx: ArrayIndex {
+  pub axes:     Idx::Axes,
+  pub kernel:   Idx,
+  pub stride:   Idx,
+  //pub zero_pad: Idx,
+  pub zero_pad: bool,
+  pub filters:  Option<usize>,
+}
+
+impl ConvShape<(usize, usize)> {
+  pub fn conv2d_pad_dim(&self, in_dim: (usize, usize, usize)) -> (usize, usize) {
+    match self.zero_pad {
+      false => (0, 0),
+      true  => {
+        match self.axes {
+          // FIXME(20170309): this calculation ignores input and stride dimensions.
+          Axes((0, 1)) => (self.kernel.0 / 2, self.kernel.1 / 2),
+          _ => unimplemented!(),
+        }
+      }
+    }
+  }
+
+  pub fn conv2d_kernel_dim(&self, in_dim: (usize, usize, usize)) -> (usize, usize, usize, usize) {
+    match self.axes {
+      Axes((0, 1)) => (self.kernel.0, self.kernel.1, in_dim.2, self.filters.unwrap_or(in_dim.2)),
+      _ => unimplemented!(),
+    }
+  }
+
+  pub fn conv2d_output_dim(&self, in_dim: (usize, usize, usize)) -> (usize, usize, usize) {
+    match self.axes {
+      Axes((0, 1)) => {
+        let (in_w, in_h, in_chan) = in_dim;
+        let (kernel_w, kernel_h) = self.kernel;
+        let (stride_w, stride_h) = self.stride;
+        let (pad_w, pad_h) = self.conv2d_pad_dim(in_dim);
+        let out_w = max(0, (in_w + 2 * pad_w - kernel_w + stride_w) as isize) as usize / stride_w;
+        let out_h = max(0, (in_h + 2 * pad_h - kernel_h + stride_h) as isize) as usize / stride_h;
+        assert!(out_w > 0);
+        assert!(out_h > 0);
+        (out_w, out_h, self.filters.unwrap_or(in_dim.2))
+      }
+      _ => unimplemented!(),
+    }
+  }
+}
+
+pub trait ConvExt<Idx, A, B, V, Backend> where Idx: ArrayIndex {
+  fn conv(&self, shape: ConvShape<Idx>, x_: Rc<AVar<AData<V>>>) -> Rc<ConvOp<Idx, A, B, V, Backend>>;
+  fn conv_add(&self, shape: ConvShape<Idx>, x_: Rc<AVar<AData<V>>>, b_: Rc<AVar<AData<B>>>) -> Rc<ConvOp<Idx, A, B, V, Backend>>;
+}
+
+pub struct ConvOp<Idx, A, B, V, Backend> where Idx: ArrayIndex {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  shape:    ConvShape<Idx>,
+  a_:   Rc<AVar<AData<A>>>,
+  b_:   Option<Rc<AVar<AData<B>>>>,
+  x_:   Rc<AVar<AData<V>>>,
+  a:    AData<A>,
+  b:    Option<AData<B>>,
+  x:    AData<V>,
+  y:    AData<V>,
+  backend:  Backend,
+  tng_: RefCell<Option<Rc<AVar<AData<V>>>>>,
+}
+
+impl<Idx, A, B, V, Backend> ConvOp<Idx, A, B, V, Backend> where Idx: ArrayIndex {
+  pub fn new(shape: ConvShape<Idx>, a_: Rc<AVar<AData<A>>>, x_: Rc<AVar<AData<V>>>, b_: Option<Rc<AVar<AData<B>>>>, backend: Backend, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> V>) -> Rc<Self> {
+    let node = NodeId::new();
+    let in_degree = match b_ {
+      None    => 2,
+      Some(_) => 3,
+    };
+    let a = a_.data();
+    let x = x_.data();
+    let b = b_.as_ref().map(|b_| b_.data());
+    Rc::new(ConvOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, in_degree),
+      shape:    shape,
+      a_:   a_,
+      x_:   x_,
+      b_:   b_,
+      a:    a,
+      x:    x,
+      b:    b,
+      y:    AData::new(/*clk_horizon,*/ alloc.clone()),
+      //tmp:  AData::new(1, alloc),
+      backend:  backend,
+      tng_: RefCell::new(None),
+    })
+  }
+}
+
+impl<Idx, A, B, V, Backend> AVar<AData<V>> for ConvOp<Idx, A, B, V, Backend>
+where Idx: ArrayIndex,
+      Self: AOp,
+{
+  default fn _owned_data(&self) -> &AData<V> {
+    &self.y
+  }
+
+  default fn tangent(&self) -> Rc<AVar<AData<V>>> {
+    if self.tng_.borrow().is_none() {
+      *self.tng_.borrow_mut() = Some(self._make_tangent());
+    }
+    self.tng_.borrow().as_ref().unwrap().clone()
+  }
+}
+
+// The CPU convolution has no forward yet, so it has no tangent either; only
+// the GPU `ConvOp` supports `tangent`.
+impl<S> AOp for ConvOp<(usize, usize), Array4d<f32, S>, Array1d<f32, S>, Array3d<f32, S>, ()> where S: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.a_._push(epoch, apply);
+      self.x_._push(epoch, apply);
+      if let Some(ref b_) = self.b_ {
+        b_._push(epoch, apply);
+      }
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      if let Some(ref b_) = self.b_ {
+        b_._pop(epoch, apply);
+      }
+      self.x_._pop(epoch, apply);
+      self.a_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    unimplemented!();
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    unimplemented!();
+  }
+
+  fn _backward2(&self, txn: TxnId) {
+    // FIXME: the CPU convolution is not implemented yet, so neither is its
+    // curvature; only the GPU path supports `conv`.
+    unimplemented!();
+  }
+}
+
+#[derive(Clone, Copy)]
+pub struct PoolShape<Idx> where Idx: ArrayIndex {
+  pub axes:     Idx::Axes,
+  pub kernel:   Idx,
+  pub stride:   Idx,
+  pub zero_pad: bool,
+}
+
+impl PoolShape<(usize, usize)> {
+  pub fn pool2d_pad_dim(&self, in_dim: (usize, usize, usize)) -> (usize, usize) {
+    match self.zero_pad {
+      false => (0, 0),
+      true  => {
+        match self.axes {
+          // FIXME(20170309): this calculation ignores input and stride dimensions.
+          Axes((0, 1)) => (self.kernel.0 / 2, self.kernel.1 / 2),
+          _ => unimplemented!(),
+        }
+      }
+    }
+  }
+
+  pub fn pool2d_output_dim(&self, in_dim: (usize, usize, usize)) -> (usize, usize, usize) {
+    match self.axes {
+      Axes((0, 1)) => {
+        let (in_w, in_h, _) = in_dim;
+        let (kernel_w, kernel_h) = self.kernel;
+        let (stride_w, stride_h) = self.stride;
+        let (pad_w, pad_h) = self.pool2d_pad_dim(in_dim);
+        let out_w = max(0, (in_w + 2 * pad_w - kernel_w + stride_w) as isize) as usize / stride_w;
+        let out_h = max(0, (in_h + 2 * pad_h - kernel_h + stride_h) as isize) as usize / stride_h;
+        assert!(out_w > 0);
+        assert!(out_h > 0);
+        (out_w, out_h, in_dim.2)
+      }
+      _ => unimplemented!(),
+    }
+  }
+}
+
+pub trait PoolKernel {}
+
+pub struct AvgPool;
+pub struct MaxPool;
+
+impl PoolKernel for AvgPool {}
+impl PoolKernel for MaxPool {}
+
+pub trait PoolExt<Idx, V, Backend>: AVar<AData<V>> where Idx: ArrayIndex {
+  fn avg_pool(shape: PoolShape<Idx>, x_: Rc<Self>) -> Rc<PoolOp<Idx, V, AvgPool, Backend>>;
+  fn max_pool(shape: PoolShape<Idx>, x_: Rc<Self>) -> Rc<PoolOp<Idx, V, MaxPool, Backend>>;
+}
+
+pub fn avg_pool<Op, Idx, V, Backend>(shape: PoolShape<Idx>, x_: Rc<Op>) -> Rc<PoolOp<Idx, V, AvgPool, Backend>> where Op: AVar<AData<V>> + PoolExt<Idx, V, Backend>, Idx: ArrayIndex {
+  PoolExt::avg_pool(shape, x_)
+}
+
+pub fn max_pool<Op, Idx, V, Backend>(shape: PoolShape<Idx>, x_: Rc<Op>) -> Rc<PoolOp<Idx, V, MaxPool, Backend>> where Op: AVar<AData<V>> + PoolExt<Idx, V, Backend>, Idx: ArrayIndex {
+  PoolExt::max_pool(shape, x_)
+}
+
+pub struct PoolOp<Idx, V, Kernel, Backend> where Idx: ArrayIndex {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  shape:    PoolShape<Idx>,
+  x_:   Rc<AVar<AData<V>>>,
+  x:    AData<V>,
+  y:    AData<V>,
+  kernel:   Kernel,
+  backend:  Backend,
+}
+
+impl<Idx, V, Kernel, Backend> PoolOp<Idx, V, Kernel, Backend> where Idx: ArrayIndex {
+  pub fn new(shape: PoolShape<Idx>, x_: Rc<AVar<AData<V>>>, kernel: Kernel, backend: Backend, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> V>) -> Rc<Self> {
+    let node = NodeId::new();
+    let x = x_.data();
+    Rc::new(PoolOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 2),
+      shape:    shape,
+      x_:   x_,
+      x:    x,
+      y:    AData::new(/*clk_horizon,*/ alloc.clone()),
+      //tmp:  AData::new(1, alloc),
+      kernel:   kernel,
+      backend:  backend,
+    })
+  }
+}
+
+impl<Idx, V, Kernel, Backend> AVar<AData<V>> for PoolOp<Idx, V, Kernel, Backend> where PoolOp<Idx, V, Kernel, Backend>: AOp, Idx: ArrayIndex {
+  fn _owned_data(&self) -> &AData<V> {
+    &self.y
+  }
+}
+
+pub struct GenPoolOp<Idx, V, Kernel, Backend> where Idx: ArrayIndex {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  shape:    PoolShape<Idx>,
+  x_:   Rc<AVar<AData<V>>>,
+  sel_: Rc<AVar<AData<V>>>,
+  x:    AData<V>,
+  sel:  AData<V>,
+  y:    AData<V>,
+  kernel:   Kernel,
+  backend:  Backend,
+}
+
+#[derive(Clone, Copy)]
+pub enum BatchStatsAverage {
+  Geometric(f64),
+  Arithmetic,
+  ArithmeticCountCutoff(usize),
+  ArithmeticRateCutoff(f64),
+}
+
+impl BatchStatsAverage {
+  pub fn rate(self, update_ct: usize) -> f64 {
+    let n = (update_ct + 1) as f64;
+    match self {
+      BatchStatsAverage::Geometric(rate) => rate,
+      BatchStatsAverage::Arithmetic => 1.0 / n,
+      BatchStatsAverage::ArithmeticCountCutoff(max_ct) => {
+        if update_ct >= max_ct { 0.0 }
+        else { 1.0 / n }
+      }
+      BatchStatsAverage::ArithmeticRateCutoff(max_rate) => {
+        let rate = 1.0 / n;
+        if rate >= max_rate { 0.0 }
+        else { rate }
+      }
+    }
+  }
+}
+
+#[derive(Clone, Copy)]
+pub enum BatchStatsUnbias {
+  Normalize,
+}
+
+#[derive(Clone, Copy)]
+pub struct BatchStatsConfig {
+  pub average:  BatchStatsAverage,
+  //pub unbias:   BatchStatsUnbias,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BatchStatsMode {
+  PassThrough,
+  UseFixedRunningStats,
+}
+
+pub struct BatchStatsState<Idx> where Idx: ArrayIndex {
+  pub reduce_axes:  Idx::Axes,
+  pub cfg:          BatchStatsConfig,
+  pub curr_txn:     Option<TxnId>,
+  pub inner_mode:   BatchStatsMode,
+  pub mode:         Rc<CopyConstant<bool>>,
+  pub batch_ct:     usize,
+  pub update_ct:    usize,
+}
+
+impl<Idx> BatchStatsState<Idx> where Idx: ArrayIndex {
+  pub fn new(reduce_axes: Idx::Axes, cfg: BatchStatsConfig, mode: Rc<CopyConstant<bool>>) -> Self {
+    BatchStatsState{
+      reduce_axes:  reduce_axes,
+      cfg:          cfg,
+      curr_txn:     None,
+      inner_mode:   BatchStatsMode::PassThrough,
+      mode:         mode,
+      batch_ct:     0,
+      update_ct:    0,
+    }
+  }
+
+  /*pub fn get_mode(&mut self, txn: TxnId) -> BatchStatsMode {
+    match self.curr_txn {
+      None => {
+        self.curr_txn = Some(txn);
+      }
+      Some(prev_txn) => {
+        if prev_txn != txn {
+          self.curr_txn = Some(txn);
+        }
+      }
+    }
+    self.inner_mode
+  }
+
+  pub fn set_mode(&mut self, txn: TxnId, mode: BatchStatsMode) {
+    match self.curr_txn {
+      None => {
+        self.curr_txn = Some(txn);
+        self.inner_mode = mode;
+      }
+      Some(prev_txn) => {
+        if prev_txn != txn {
+          self.curr_txn = Some(txn);
+          self.inner_mode = mode;
+        } else {
+          assert_eq!(self.inner_mode, mode);
+        }
+      }
+    }
+  }*/
+}
+
+pub struct BatchStatsControl {
+  mode: Rc<CopyConstant<bool>>,
+  ops:  Vec<Rc<BatchStatsOpExt>>,
+  batch_ops:    Vec<Rc<AOp>>,
+  batch_vars:   VarSet,
+  acc_ops:      Vec<Rc<AOp>>,
+  acc_vars:     VarSet,
+  fixed_ops:    Vec<Rc<AOp>>,
+  fixed_vars:   VarSet,
+}
+
+impl BatchStatsControl {
+  pub fn new() -> Self {
+    BatchStatsControl{
+      mode: Rc::new(CopyConstant{var: TxnCopyVar::new()}),
+      ops:  vec![],
+      batch_ops:    vec![],
+      batch_vars:   var_set(),
+      acc_ops:      vec![],
+      acc_vars:     var_set(),
+      fixed_ops:    vec![],
+      fixed_vars:   var_set(),
+    }
+  }
+
+  pub fn mode(&self) -> Rc<CopyConstant<bool>> {
+    self.mode.clone()
+  }
+
+  pub fn set_mode(&self, txn: TxnId, mode_value: bool) {
+    self.mode.var.set(txn, mode_value);
+  }
+
+  pub fn get_mode(&self, txn: TxnId) -> bool {
+    self.mode.var.persist(txn);
+    self.mode.var.get(txn)
+  }
+
+  /*pub fn accumulator_vars(&self) -> VarSet {
+    self.acc_vars.clone()
+  }*/
+
+  pub fn dim(&mut self, txn: TxnId) -> usize {
+    let mut stats_dim = 0;
+    self.batch_vars.unmask_all();
+    for op in self.batch_ops.iter() {
+      stats_dim += op.incremental_val_size(txn, &mut self.batch_vars);
+    }
+    self.batch_vars.unmask_all();
+    stats_dim
+  }
+
+  /*pub fn fixed_vars(&self) -> VarSet {
+    self.fixed_vars.clone()
+  }*/
+
+  pub fn configure(&self, f: &Fn(&mut BatchStatsConfig)) {
+    for op in self.ops.iter() {
+      op._configure(f);
+    }
+  }
+
+  pub fn reset_accumulators(&self, txn: TxnId) {
+    for op in self.ops.iter() {
+      op._reset_accumulators(txn);
+    }
+  }
+
+  pub fn accumulate(&self, txn: TxnId) {
+    for op in self.ops.iter() {
+      op._accumulate(txn);
+    }
+  }
+
+  pub fn update_stats(&self, prev_txn: TxnId, next_txn: TxnId) {
+    for op in self.ops.iter() {
+      op._update_stats(prev_txn, next_txn);
+    }
+  }
+
+  pub fn store_accumulators(&mut self, txn: TxnId, mut offset: usize, writer: &mut Any) -> usize {
+    self.acc_vars.unmask_all();
+    for op in self.acc_ops.iter() {
+      offset = op._store_val(txn, &mut self.acc_vars, offset, writer);
+    }
+    self.acc_vars.unmask_all();
+    offset
+  }
+
+  pub fn load_fixed_stats(&mut self, txn: TxnId, mut offset: usize, reader: &mut Any) -> usize {
+    self.fixed_vars.unmask_all();
+    for op in self.fixed_ops.iter() {
+      offset = op._load_val(txn, &mut self.fixed_vars, offset, reader);
+    }
+    self.fixed_vars.unmask_all();
+    offset
+  }
+}
+
+pub trait BatchStatsOpExt {
+  fn _configure(&self, f: &Fn(&mut BatchStatsConfig));
+  fn _reset_accumulators(&self, txn: TxnId);
+  fn _accumulate(&self, txn: TxnId);
+  fn _update_stats(&self, prev_txn: TxnId, next_txn: TxnId);
+}
+
+#[derive(Clone)]
+pub struct BatchStatsOutput<M> {
+  pub mean_batch:   Rc<AVar<AData<M>>>,
+  pub var_batch:    Rc<AVar<AData<M>>>,
+  pub mean_fixed:   Rc<AVar<AData<M>>>,
   pub var_fixed:    Rc<AVar<AData<M>>>,
   pub mean_branch:  Rc<BranchOp<Rc<CopyConstant<bool>>, Rc<AVar<AData<M>>>, Rc<AVar<AData<M>>>, AData<M>>>,
   //pub mean_branch:  Rc<AVar<AData<M>>>,
@@ -3146,390 +8190,2086 @@ pub struct BatchStatsOutput<M> {
   // TODO: expose accumulators as well for block reductions.
 }
 
-#[derive(Clone)]
-pub struct BatchStatsOutputNew<M> {
-  //pub mean:     Rc<BranchOp<CopyConstant<bool>, Rc<AVar<AData<M>>>, Rc<AVar<AData<M>>>, M>>,
-  //pub var:      Rc<BranchOp<CopyConstant<bool>, Rc<AVar<AData<M>>>, Rc<AVar<AData<M>>>, M>>,
-  pub mean:     Rc<AVar<AData<M>>>,
-  pub var:      Rc<AVar<AData<M>>>,
-  pub batch_mean:   VarSet,
-  pub batch_var:    VarSet,
-  pub running_mean: VarSet,
-  pub running_var:  VarSet,
-}
+#[derive(Clone)]
+pub struct BatchStatsOutputNew<M> {
+  //pub mean:     Rc<BranchOp<CopyConstant<bool>, Rc<AVar<AData<M>>>, Rc<AVar<AData<M>>>, M>>,
+  //pub var:      Rc<BranchOp<CopyConstant<bool>, Rc<AVar<AData<M>>>, Rc<AVar<AData<M>>>, M>>,
+  pub mean:     Rc<AVar<AData<M>>>,
+  pub var:      Rc<AVar<AData<M>>>,
+  pub batch_mean:   VarSet,
+  pub batch_var:    VarSet,
+  pub running_mean: VarSet,
+  pub running_var:  VarSet,
+}
+
+pub trait BatchStatsExt<Idx, A, M> where Idx: ArrayIndex {
+  fn batch_stats(reduce_axes: Idx::Axes, cfg: BatchStatsConfig, ctrl: &mut BatchStatsControl, x_: Self) -> BatchStatsOutput<M> where Self: Sized;
+}
+
+pub fn batch_stats<Op, Idx, A, M>(reduce_axes: Idx::Axes, cfg: BatchStatsConfig, ctrl: &mut BatchStatsControl, x_: Rc<Op>) -> BatchStatsOutput<M> where Rc<Op>: BatchStatsExt<Idx, A, M>, Op: 'static + AVar<AData<A>>, Idx: ArrayIndex {
+  <Rc<Op> as BatchStatsExt<Idx, A, M>>::batch_stats(reduce_axes, cfg, ctrl, x_)
+}
+
+pub struct BatchStatsOp<Idx, A, M> where Idx: ArrayIndex {
+  node_id:      NodeId,
+  stack:        OperatorStack,
+  state:        RefCell<BatchStatsState<Idx>>,
+  x_:           Rc<AVar<AData<A>>>,
+  mean_:        Weak<PassOp<(), M>>,
+  mean_acc_:    Rc<SrcOp<M>>,
+  mean_run_:    Rc<SrcOp<M>>,
+  //mean_branch_: Rc<AVar<AData<M>>>,
+  var_:         Weak<PassOp<(), M>>,
+  var_acc_:     Rc<SrcOp<M>>,
+  var_run_:     Rc<SrcOp<M>>,
+  //var_branch_:  Rc<AVar<AData<M>>>,
+  x:            AData<A>,
+  mean:         AData<M>,
+  mean_acc:     AData<M>,
+  mean_run:     AData<M>,
+  var:          AData<M>,
+  var_acc:      AData<M>,
+  var_run:      AData<M>,
+}
+
+impl<Idx, A, M> BatchStatsOp<Idx, A, M> where BatchStatsOp<Idx, A, M>: AOp + BatchStatsOpExt, SrcOp<M>: AOp, Idx: 'static + ArrayIndex, A: 'static, M: 'static {
+  pub fn new<Op>(reduce_axes: Idx::Axes, cfg: BatchStatsConfig, ctrl: &mut BatchStatsControl, x_: Rc<Op>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> M>) -> BatchStatsOutput<M> where Op: 'static + AVar<AData<A>> {
+    let node = NodeId::new();
+    let x = x_.data();
+    let mean = AData::new(/*clk_horizon,*/ alloc.clone());
+    let var = AData::new(/*clk_horizon,*/ alloc.clone());
+    let mean_ = PassOp::new(None, mean.clone());
+    let var_ = PassOp::new(None, var.clone());
+    // FIXME: some hackiness around clocks.
+    let mean_acc_ = SrcOp::new(/*clk_horizon,*/ /*clk_horizon > 1,*/ alloc.clone()); //src(alloc.clone());
+    let var_acc_ = SrcOp::new(/*clk_horizon,*/ /*clk_horizon > 1,*/ alloc.clone()); //src(alloc.clone());
+    let mean_run_ = SrcOp::new(/*clk_horizon,*/ /*clk_horizon > 1,*/ alloc.clone());
+      //.initialize(init_val(|_, x: &mut DeviceArray1d<f32>| x.as_view_mut().set_constant(0.0, DeviceStream::implicit().conn())));
+    let var_run_ = SrcOp::new(/*clk_horizon,*/ /*clk_horizon > 1,*/ alloc.clone());
+      //.initialize(init_val(|_, x: &mut DeviceArray1d<f32>| x.as_view_mut().set_constant(0.0, DeviceStream::implicit().conn())));
+    let mean_branch_ = BranchOp::new(ctrl.mode.clone(), mean_.clone(), mean_run_.clone(), /*clk_horizon,*/ alloc.clone());
+    let var_branch_ = BranchOp::new(ctrl.mode.clone(), var_.clone(), var_run_.clone(), /*clk_horizon,*/ alloc.clone());
+    ctrl.batch_ops.push(mean_.clone());
+    ctrl.batch_ops.push(var_.clone());
+    ctrl.batch_vars.insert_all(&mean_.vars());
+    ctrl.batch_vars.insert_all(&var_.vars());
+    ctrl.acc_ops.push(mean_acc_.clone());
+    ctrl.acc_ops.push(var_acc_.clone());
+    ctrl.acc_vars.insert_all(&mean_acc_.vars());
+    ctrl.acc_vars.insert_all(&var_acc_.vars());
+    ctrl.fixed_ops.push(mean_run_.clone());
+    ctrl.fixed_ops.push(var_run_.clone());
+    ctrl.fixed_vars.insert_all(&mean_run_.vars());
+    ctrl.fixed_vars.insert_all(&var_run_.vars());
+    let mean_acc = mean_acc_.data();
+    let mean_run = mean_run_.data();
+    let var_acc = var_acc_.data();
+    let var_run = var_run_.data();
+    let op = Rc::new(BatchStatsOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 1),
+      state:    RefCell::new(BatchStatsState::new(reduce_axes, cfg, ctrl.mode.clone())),
+      x_:           x_,
+      mean_:        Rc::downgrade(&mean_),
+      mean_acc_:    mean_acc_,
+      mean_run_:    mean_run_.clone(),
+      //mean_branch_: mean_branch_.clone(),
+      var_:         Rc::downgrade(&var_),
+      var_acc_:     var_acc_,
+      var_run_:     var_run_.clone(),
+      //var_branch_:  var_branch_.clone(),
+      x:            x,
+      mean:         mean,
+      mean_acc:     mean_acc,
+      mean_run:     mean_run,
+      var:          var,
+      var_acc:      var_acc,
+      var_run:      var_run,
+    });
+    *mean_.x_.borrow_mut() = Some(AVar::from(op.clone()));
+    *var_.x_.borrow_mut() = Some(AVar::from(op.clone()));
+    ctrl.ops.push(op);
+    BatchStatsOutput{
+      mean_batch:   mean_,
+      var_batch:    var_,
+      mean_fixed:   mean_run_,
+      var_fixed:    var_run_,
+      mean_branch:  mean_branch_,
+      var_branch:   var_branch_,
+    }
+  }
+}
+
+impl<Idx, A, M> AVar<()> for BatchStatsOp<Idx, A, M> where BatchStatsOp<Idx, A, M>: AOp + BatchStatsOpExt, SrcOp<M>: AOp, Idx: 'static + ArrayIndex, A: 'static, M: 'static {
+  default fn _owned_data(&self) -> &() {
+    unreachable!();
+  }
+
+  default fn data(&self) -> () {
+    ()
+  }
+
+  default fn vars(&self) -> VarSet {
+    var_set()
+  }
+
+  default fn tangent(&self) -> Rc<AVar<()>> {
+    // FIXME
+    unimplemented!();
+    /*if self.tng.borrow().is_none() {
+      *self.tng.borrow_mut() = Some(self._make_tangent());
+    }
+    self.tng.borrow().as_ref().unwrap().clone()*/
+  }
+}
+
+//impl<Idx, A, M> BatchStatsOpExt for BatchStatsOp<Idx, A, M> where Idx: ArrayIndex {
+impl<S> BatchStatsOpExt for BatchStatsOp<(usize, usize), BatchArray3d<f32, S>, Array1d<f32, S>> where S: DerefMut<Target=[f32]> {
+  fn _configure(&self, reconf: &Fn(&mut BatchStatsConfig)) {
+    // FIXME(20170214): only safe to mutate state at the beginning of a txn.
+    let mut state = self.state.borrow_mut();
+    reconf(&mut state.cfg);
+  }
+
+  fn _reset_accumulators(&self, txn: TxnId) {
+    let mut state = self.state.borrow_mut();
+    state.batch_ct = 0;
+  }
+
+  fn _accumulate(&self, txn: TxnId) {
+    let node = self._id();
+    let mut state = self.state.borrow_mut();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    // FIXME: does not account for non-uniform batch sizes.
+    let n = (state.batch_ct + 1) as f32;
+    //self.mean_acc.val.rollover(txn, self.mean_acc.val.var()); // FIXME
+    if self.mean_acc.val.accumulate(txn, node, |val| val.as_view_mut().set_constant(0.0)) {
+      assert!(!self.mean.val.overwrite(txn, node));
+      self.mean_acc.val.get_mut(txn, node).as_view_mut().average(1.0 / n, self.mean.val.get_excl(txn, node).as_view());
+    }
+    //self.var_acc.val.rollover(txn, self.var_acc.val.var()); // FIXME
+    if self.var_acc.val.accumulate(txn, node, |val| val.as_view_mut().set_constant(0.0)) {
+      assert!(!self.var.val.overwrite(txn, node));
+      self.var_acc.val.get_mut(txn, node).as_view_mut().average(1.0 / n, self.var.val.get_excl(txn, node).as_view());
+    }
+    state.batch_ct += 1;
+  }
+
+  fn _update_stats(&self, prev_txn: TxnId, next_txn: TxnId) {
+    let node = self._id();
+    let mut state = self.state.borrow_mut();
+    assert!(state.batch_ct >= 1);
+    // FIXME: rather than directly average with `rate`, should use a
+    // normalized rate for bias correction.
+    let rate = state.cfg.average.rate(state.update_ct) as f32;
+    //self.mean_run.val.rollover(next_txn, self.mean_run.val.var()); // FIXME
+    if self.mean_run.val.accumulate(next_txn, node, |val| val.as_view_mut().set_constant(0.0)) {
+      if rate != 0.0 {
+        self.mean_run.val.get_mut(next_txn, node).as_view_mut().average(rate, self.mean_acc.val.get(prev_txn, node).as_view());
+      }
+      if self.mean_acc.val.overwrite(next_txn, node) {
+        self.mean_acc.val.get_excl(next_txn, node).as_view_mut().set_constant(0.0);
+      }
+    }
+    //self.var_run.val.rollover(next_txn, self.var_run.val.var()); // FIXME
+    if self.var_run.val.accumulate(next_txn, node, |val| val.as_view_mut().set_constant(0.0)) {
+      if rate != 0.0 {
+        self.var_run.val.get_mut(next_txn, node).as_view_mut().average(rate, self.var_acc.val.get(prev_txn, node).as_view());
+      }
+      if self.var_acc.val.overwrite(next_txn, node) {
+        self.var_acc.val.get_excl(next_txn, node).as_view_mut().set_constant(0.0);
+      }
+    }
+    state.batch_ct = 0;
+    state.update_ct += 1;
+  }
+}
+
+impl<S> AOp for BatchStatsOp<(usize, usize), BatchArray3d<f32, S>, Array1d<f32, S>> where S: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.mean.rollover_all(txn, vars);
+    self.var.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    unimplemented!();
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    unimplemented!();
+  }
+}
+
+pub trait IndexExt<IdxOp, A, Idx, Out> {
+  //fn one_hot(x_: Rc<Op>, index_: Rc<IdxOp>) -> Rc<Self>;
+  fn index(&self, index_: Rc<IdxOp>) -> Rc<IndexOp<A, Idx, Out>>;
+}
+
+/*pub fn one_hot<Op, IdxOp, A, Idx, Out>(x_: Rc<Op>, index_: Rc<IdxOp>) -> Rc<IndexOp<A, Idx, Out>> where Op: 'static + AVar<AData<A>>, IdxOp: 'static + AVar<AData<Idx>>, IndexOp<A, Idx, Out>: IndexExt<Op, IdxOp> {
+  <IndexOp<A, Idx, Out> as IndexExt<Op, IdxOp>>::one_hot(x_, index_)
+}*/
+
+pub struct IndexOp<A, Idx, Out> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  x_:       Rc<AVar<AData<A>>>,
+  index_:   Rc<AVar<AData<Idx>>>,
+  x:        AData<A>,
+  index:    AData<Idx>,
+  y:        AData<Out>,
+}
+
+impl<A, Idx, Out> AVar<AData<Out>> for IndexOp<A, Idx, Out> where IndexOp<A, Idx, Out>: AOp {
+  fn _owned_data(&self) -> &AData<Out> {
+    &self.y
+  }
+}
+
+impl<A, Idx, Out> IndexOp<A, Idx, Out> {
+  pub fn new(x_: Rc<AVar<AData<A>>>, index_: Rc<AVar<AData<Idx>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> Out>) -> Rc<IndexOp<A, Idx, Out>> {
+    let node = NodeId::new();
+    let x = x_.data();
+    let index = index_.data();
+    Rc::new(IndexOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 2),
+      x_:       x_,
+      index_:   index_,
+      x:        x,
+      index:    index,
+      y:        AData::new(/*clk_horizon,*/ alloc),
+    })
+  }
+}
+
+impl<Op, IdxOp, S> IndexExt<IdxOp, BatchArray1d<f32, S>, Batch<u32>, Batch<f32>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, IdxOp: 'static + AVar<AData<Batch<u32>>>, S: 'static + DerefMut<Target=[f32]> {
+  fn index(&self, index_: Rc<IdxOp>) -> Rc<IndexOp<BatchArray1d<f32, S>, Batch<u32>, Batch<f32>>> {
+    let x = self.clone().data();
+    //let clk_horizon = x.horizon();
+    IndexOp::new(self.clone(), index_.clone(), /*clk_horizon,*/ {
+      Rc::new(move |txn, node| {
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let mut y = Batch::new();
+        y.set_batch_size(batch_sz, 0.0);
+        y
+      })
+    })
+  }
+}
+
+impl<S> AOp for IndexOp<BatchArray1d<f32, S>, Batch<u32>, Batch<f32>> where S: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      self.index_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.index_._pop(epoch, apply);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.y.val.overwrite(txn, node) {
+      let index = self.index.val.get(txn, node);
+      assert_eq!(batch_sz, index.batch_size());
+      for idx in 0 .. batch_sz {
+        assert!((index[idx] as usize) < x_dim);
+      }
+      self.y.val.get_excl(txn, node).set_batch_size(batch_sz, 0.0);
+      unsafe { arraydiff_kernel_gather_fwd_f32(
+          x_dim,
+          1,
+          batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          index.reshape(batch_sz).as_ptr(),
+          self.y.val.get_excl(txn, node).reshape_mut(batch_sz).as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad.set_batch_size(batch_sz); grad.as_view_mut().set_constant(0.0); }) {
+      unsafe { arraydiff_kernel_gather_bwd_f32(
+          x_dim,
+          1,
+          batch_sz,
+          self.y.grad.get(txn, node).reshape(batch_sz).as_ptr(),
+          self.index.val.get(txn, node).reshape(batch_sz).as_ptr(),
+          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.y.r_val.overwrite(txn, node) {
+      self.y.r_val.get_excl(txn, node).set_batch_size(batch_sz, 0.0);
+      unsafe { arraydiff_kernel_gather_fwd_f32(
+          x_dim,
+          1,
+          batch_sz,
+          self.x.r_val.get(txn, node).as_view().as_ptr(),
+          self.index.val.get(txn, node).reshape(batch_sz).as_ptr(),
+          self.y.r_val.get_excl(txn, node).reshape_mut(batch_sz).as_mut_ptr(),
+      ) };
+    }
+  }
+}
+
+/// Gathers `k` entries per example. `k` is fixed by the indices of the first
+/// txn, and a later txn with a different `k` panics.
+impl<Op, IdxOp, S> IndexExt<IdxOp, BatchArray1d<f32, S>, Batch<Vec<u32>>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, IdxOp: 'static + AVar<AData<Batch<Vec<u32>>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn index(&self, index_: Rc<IdxOp>) -> Rc<IndexOp<BatchArray1d<f32, S>, Batch<Vec<u32>>, BatchArray1d<f32, S>>> {
+    let x = self.clone().data();
+    let index = index_.clone().data();
+    //let clk_horizon = x.horizon();
+    IndexOp::new(self.clone(), index_.clone(), /*clk_horizon,*/ {
+      Rc::new(move |txn, node| {
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let k = _multi_index_len(&*index.val.get(txn, node)).unwrap_or(0);
+        let buf = <S as BatchArrayStorage<usize>>::alloc(k, batch_sz);
+        BatchArray1d::from_storage(k, batch_sz, buf)
+      })
+    })
+  }
+}
+
+/// The number of indices per example, or `None` for an empty batch.
+fn _multi_index_len(index: &Batch<Vec<u32>>) -> Option<usize> {
+  match index.batch_size() {
+    0 => None,
+    _ => Some(index[0].len()),
+  }
+}
+
+/// Packs a batch of per-example index vectors into a `[k, batch]` buffer.
+fn _pack_multi_index(index: &Batch<Vec<u32>>, k: usize, dim: usize) -> Vec<u32> {
+  let batch_sz = index.batch_size();
+  let mut packed = Vec::with_capacity(k * batch_sz);
+  for idx in 0 .. batch_sz {
+    assert_eq!(k, index[idx].len());
+    for &j in index[idx].iter() {
+      assert!((j as usize) < dim);
+      packed.push(j);
+    }
+  }
+  packed
+}
+
+impl<S> AOp for IndexOp<BatchArray1d<f32, S>, Batch<Vec<u32>>, BatchArray1d<f32, S>> where S: DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      self.index_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.index_._pop(epoch, apply);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.y.val.overwrite(txn, node) {
+      // The buffers of this op and of the ops downstream are sized once, by
+      // the indices of the first txn, so `k` cannot change afterwards.
+      let mut y = self.y.val.get_excl(txn, node);
+      let k = _multi_index_len(&*self.index.val.get(txn, node)).unwrap_or(y.dim());
+      assert!(k == y.dim(), "IndexOp: {} indices per example, but the op was sized for {}", k, y.dim());
+      let index = _pack_multi_index(&*self.index.val.get(txn, node), k, x_dim);
+      assert_eq!(k * batch_sz, index.len());
+      y.set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_gather_fwd_f32(
+          x_dim,
+          k,
+          batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          index.as_ptr(),
+          y.as_view_mut().as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad.set_batch_size(batch_sz); grad.as_view_mut().set_constant(0.0); }) {
+      let k = self.y.val.get(txn, node).dim();
+      assert_eq!(k, self.y.grad.get(txn, node).dim());
+      let index = _pack_multi_index(&*self.index.val.get(txn, node), k, x_dim);
+      assert_eq!(k * batch_sz, index.len());
+      unsafe { arraydiff_kernel_gather_bwd_f32(
+          x_dim,
+          k,
+          batch_sz,
+          self.y.grad.get(txn, node).as_view().as_ptr(),
+          index.as_ptr(),
+          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.y.r_val.overwrite(txn, node) {
+      let k = self.y.val.get(txn, node).dim();
+      let mut r_y = self.y.r_val.get_excl(txn, node);
+      assert_eq!(k, r_y.dim());
+      let index = _pack_multi_index(&*self.index.val.get(txn, node), k, x_dim);
+      assert_eq!(k * batch_sz, index.len());
+      r_y.set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_gather_fwd_f32(
+          x_dim,
+          k,
+          batch_sz,
+          self.x.r_val.get(txn, node).as_view().as_ptr(),
+          index.as_ptr(),
+          r_y.as_view_mut().as_mut_ptr(),
+      ) };
+    }
+  }
+}
+
+/// Gradient of an embedding table restricted to the rows touched in the
+/// current txn. `values` holds `row_len` entries per element of `rows`.
+#[derive(Clone, Default, Debug)]
+pub struct SparseRowGrad {
+  pub row_len:  usize,
+  pub num_rows: usize,
+  pub rows:     Vec<u32>,
+  pub values:   Vec<f32>,
+}
+
+impl SparseRowGrad {
+  pub fn new(row_len: usize, num_rows: usize) -> Self {
+    SparseRowGrad{
+      row_len:  row_len,
+      num_rows: num_rows,
+      rows:     vec![],
+      values:   vec![],
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.rows.clear();
+    self.values.clear();
+  }
+
+  pub fn dense_len(&self) -> usize {
+    self.row_len * self.num_rows
+  }
+
+  /// Sorts the rows and sums the values of duplicate rows.
+  pub fn coalesce(&mut self) {
+    let row_len = self.row_len;
+    let mut order: Vec<usize> = (0 .. self.rows.len()).collect();
+    order.sort_by_key(|&k| self.rows[k]);
+    let mut rows: Vec<u32> = Vec::with_capacity(self.rows.len());
+    let mut values: Vec<f32> = Vec::with_capacity(self.values.len());
+    for &k in order.iter() {
+      let src = &self.values[k * row_len .. (k + 1) * row_len];
+      if rows.last() == Some(&self.rows[k]) {
+        let dst_start = values.len() - row_len;
+        for (dst, &v) in values[dst_start .. ].iter_mut().zip(src.iter()) {
+          *dst += v;
+        }
+      } else {
+        rows.push(self.rows[k]);
+        values.extend_from_slice(src);
+      }
+    }
+    self.rows = rows;
+    self.values = values;
+  }
+
+  /// Adds `alpha` times the gradient into a dense, flattened table.
+  pub fn scatter_add(&self, alpha: f32, dst: &mut [f32]) {
+    let row_len = self.row_len;
+    assert_eq!(self.dense_len(), dst.len());
+    for (k, &row) in self.rows.iter().enumerate() {
+      let row = row as usize;
+      let src = &self.values[k * row_len .. (k + 1) * row_len];
+      for (d, &v) in dst[row * row_len .. (row + 1) * row_len].iter_mut().zip(src.iter()) {
+        *d += alpha * v;
+      }
+    }
+  }
+
+  /// Stores the gradient at `offset` of the flattened parameter layout.
+  /// A `Vec<f32>` writer receives the densified table; a
+  /// `Vec<(usize, SparseRowGrad)>` writer receives the sparse rows tagged
+  /// with their offset, which is what sparse optimizers should consume.
+  pub fn store(&self, mut offset: usize, writer: &mut Any) -> usize {
+    let buf_len = self.dense_len();
+    if writer.downcast_mut::<NullIo>().is_some() {
+      offset += buf_len;
+    } else if writer.downcast_mut::<Vec<f32>>().is_some() {
+      let writer = writer.downcast_mut::<Vec<f32>>().unwrap();
+      let dst = &mut writer[offset .. offset + buf_len];
+      for d in dst.iter_mut() {
+        *d = 0.0;
+      }
+      self.scatter_add(1.0, dst);
+      offset += buf_len;
+    } else if writer.downcast_mut::<Vec<(usize, SparseRowGrad)>>().is_some() {
+      let writer = writer.downcast_mut::<Vec<(usize, SparseRowGrad)>>().unwrap();
+      writer.push((offset, self.clone()));
+      offset += buf_len;
+    } else {
+      unimplemented!();
+    }
+    offset
+  }
+}
+
+/// Index sources accepted by `EmbeddingOp`.
+pub trait EmbedIndex {
+  /// Returns the flat row indices and, for ragged indices, the per-example
+  /// offsets into them.
+  fn _pack_embed_index(&self, num_rows: usize) -> (Vec<u32>, Option<Vec<u32>>);
+}
+
+impl EmbedIndex for Batch<u32> {
+  fn _pack_embed_index(&self, num_rows: usize) -> (Vec<u32>, Option<Vec<u32>>) {
+    let batch_sz = self.batch_size();
+    let mut packed = Vec::with_capacity(batch_sz);
+    for idx in 0 .. batch_sz {
+      assert!((self[idx] as usize) < num_rows);
+      packed.push(self[idx]);
+    }
+    (packed, None)
+  }
+}
+
+impl EmbedIndex for Batch<Vec<u32>> {
+  fn _pack_embed_index(&self, num_rows: usize) -> (Vec<u32>, Option<Vec<u32>>) {
+    let batch_sz = self.batch_size();
+    let mut packed = vec![];
+    let mut offsets = Vec::with_capacity(batch_sz + 1);
+    offsets.push(0);
+    for idx in 0 .. batch_sz {
+      for &j in self[idx].iter() {
+        assert!((j as usize) < num_rows);
+        packed.push(j);
+      }
+      offsets.push(packed.len() as u32);
+    }
+    (packed, Some(offsets))
+  }
+}
+
+pub trait EmbeddingExt<IdxOp, W, Idx, Out> {
+  fn embed(&self, index_: Rc<IdxOp>) -> Rc<EmbeddingOp<W, Idx, Out>>;
+  /// Like `embed`, but also accumulates the table gradient into `w.grad`.
+  fn embed_dense(&self, index_: Rc<IdxOp>) -> Rc<EmbeddingOp<W, Idx, Out>>;
+}
+
+thread_local!(static SPARSE_ROW_GRADS: RefCell<FnvHashMap<Var, (bool, Weak<TxnVar<SparseRowGrad>>)>> = RefCell::new(FnvHashMap::default()));
+
+/// Looks up rows of a `(row_len, num_rows)` table. A `Batch<Vec<u32>>` index
+/// sums the rows of each example (an embedding bag).
+///
+/// The table gradient is kept only for the touched rows, in `sparse_grad`,
+/// which all lookups into the same table share; `store_grad` writes the
+/// table's grad from those rows and `w.grad` is never written. This assumes
+/// the lookups are the table's only consumers. A table that also feeds other
+/// ops (e.g. an output layer tied to it) must use `embed_dense`, which in
+/// addition accumulates a dense `w.grad`. All lookups into a table must use
+/// the same mode.
+pub struct EmbeddingOp<W, Idx, Out> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  w_:       Rc<AVar<AData<W>>>,
+  index_:   Rc<AVar<AData<Idx>>>,
+  w:        AData<W>,
+  index:    AData<Idx>,
+  y:        AData<Out>,
+  w_grad:   Rc<TxnVar<SparseRowGrad>>,
+  dense_grad:   bool,
+}
+
+impl<W, Idx, Out> AVar<AData<Out>> for EmbeddingOp<W, Idx, Out> where EmbeddingOp<W, Idx, Out>: AOp {
+  fn _owned_data(&self) -> &AData<Out> {
+    &self.y
+  }
+}
+
+impl<W, Idx, Out> EmbeddingOp<W, Idx, Out> where W: 'static + PackedArray {
+  pub fn new(w_: Rc<AVar<AData<W>>>, index_: Rc<AVar<AData<Idx>>>, dense_grad: bool, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> Out>) -> Rc<EmbeddingOp<W, Idx, Out>> {
+    let node = NodeId::new();
+    let w = w_.data();
+    let index = index_.data();
+    let w_grad = SPARSE_ROW_GRADS.with(|grads| {
+      let mut grads = grads.borrow_mut();
+      if let Some(&(prev_dense_grad, ref prev_w_grad)) = grads.get(&w.grad.var()) {
+        if let Some(w_grad) = prev_w_grad.upgrade() {
+          if prev_dense_grad != dense_grad {
+            panic!("embed: all lookups into a table must use the same gradient mode");
+          }
+          return w_grad;
+        }
+      }
+      let w_grad = Rc::new(TxnVar::new(Symbol::new(), Grad, w.clock.clone(), {
+        let w = w.clone();
+        Rc::new(move |txn, node| {
+          let w_dim = w.val.get(txn, node)._packed_dim();
+          SparseRowGrad::new(w_dim[0], w_dim[1])
+        })
+      }));
+      grads.insert(w.grad.var(), (dense_grad, Rc::downgrade(&w_grad)));
+      w_grad
+    });
+    Rc::new(EmbeddingOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 2),
+      w_:       w_,
+      index_:   index_,
+      w:        w,
+      index:    index,
+      y:        AData::new(/*clk_horizon,*/ alloc),
+      w_grad:   w_grad,
+      dense_grad:   dense_grad,
+    })
+  }
+}
+
+impl<W, Idx, Out> EmbeddingOp<W, Idx, Out> {
+  pub fn sparse_grad(&self) -> &TxnVar<SparseRowGrad> {
+    &self.w_grad
+  }
+}
+
+impl<Op, IdxOp, I, S, T> EmbeddingExt<IdxOp, Array2d<f32, T>, Batch<I>, BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<Array2d<f32, T>>>, IdxOp: 'static + AVar<AData<Batch<I>>>, I: 'static, Batch<I>: EmbedIndex, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize>, T: 'static + DerefMut<Target=[f32]> {
+  fn embed(&self, index_: Rc<IdxOp>) -> Rc<EmbeddingOp<Array2d<f32, T>, Batch<I>, BatchArray1d<f32, S>>> {
+    _embed(self.clone(), index_, false)
+  }
+
+  fn embed_dense(&self, index_: Rc<IdxOp>) -> Rc<EmbeddingOp<Array2d<f32, T>, Batch<I>, BatchArray1d<f32, S>>> {
+    _embed(self.clone(), index_, true)
+  }
+}
+
+fn _embed<Op, IdxOp, I, S, T>(w_: Rc<Op>, index_: Rc<IdxOp>, dense_grad: bool) -> Rc<EmbeddingOp<Array2d<f32, T>, Batch<I>, BatchArray1d<f32, S>>> where Op: 'static + AVar<AData<Array2d<f32, T>>>, IdxOp: 'static + AVar<AData<Batch<I>>>, I: 'static, Batch<I>: EmbedIndex, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize>, T: 'static + DerefMut<Target=[f32]> {
+  let w = w_.data();
+  let index = index_.data();
+  //let clk_horizon = w.horizon();
+  EmbeddingOp::new(w_, index_, dense_grad, /*clk_horizon,*/ {
+    Rc::new(move |txn, node| {
+      let row_len = w.val.get(txn, node).dim().0;
+      let batch_sz = index.val.get(txn, node).batch_size();
+      let buf = <S as BatchArrayStorage<usize>>::alloc(row_len, batch_sz);
+      BatchArray1d::from_storage(row_len, batch_sz, buf)
+    })
+  })
+}
+
+impl<Idx, S, T> AOp for EmbeddingOp<Array2d<f32, T>, Idx, BatchArray1d<f32, S>> where Idx: EmbedIndex, S: DerefMut<Target=[f32]>, T: 'static + DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.w_._push(epoch, apply);
+      self.index_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.index_._pop(epoch, apply);
+      self.w_._pop(epoch, apply);
+    }
+  }
+
+  fn _store_grad(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, writer: &mut Any) -> usize {
+    let node = self._id();
+    // The table's grad is written here, before its source is visited, so the
+    // source skips it.
+    if !self.dense_grad && vars.mask(self.w.grad.var()) {
+      let grad = self.w_grad.get(txn, node);
+      offset = grad.store(offset, writer);
+    }
+    offset
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+    // The sparse grad stands in for the table's grad.
+    if vars.contains(&self.w.grad.var()) {
+      self.w_grad.rollover(txn, &mut VarSet::empty().add(self.w_grad.var()));
+    }
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    let (row_len, num_rows) = self.w.val.get(txn, node).dim();
+    if self.y.val.overwrite(txn, node) {
+      let (index, offsets) = self.index.val.get(txn, node)._pack_embed_index(num_rows);
+      let batch_sz = offsets.as_ref().map_or(index.len(), |offsets| offsets.len() - 1);
+      self.y.val.get_excl(txn, node).set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_embed_lookup_fwd_f32(
+          row_len,
+          num_rows,
+          batch_sz,
+          self.w.val.get(txn, node).as_view().as_ptr(),
+          index.as_ptr(),
+          offsets.as_ref().map_or(null(), |offsets| offsets.as_ptr()),
+          self.y.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let (row_len, num_rows) = self.w.val.get(txn, node).dim();
+    if self.w_grad.accumulate(txn, node, |grad| grad.clear()) {
+      let (index, offsets) = self.index.val.get(txn, node)._pack_embed_index(num_rows);
+      let batch_sz = offsets.as_ref().map_or(index.len(), |offsets| offsets.len() - 1);
+      let mut w_grad = self.w_grad.get_mut(txn, node);
+      assert_eq!(row_len, w_grad.row_len);
+      assert_eq!(num_rows, w_grad.num_rows);
+      let prev_len = w_grad.values.len();
+      w_grad.values.resize(prev_len + index.len() * row_len, 0.0);
+      unsafe { arraydiff_kernel_embed_lookup_bwd_f32(
+          row_len,
+          batch_sz,
+          offsets.as_ref().map_or(null(), |offsets| offsets.as_ptr()),
+          self.y.grad.get(txn, node).as_view().as_ptr(),
+          w_grad.values[prev_len .. ].as_mut_ptr(),
+      ) };
+      if self.dense_grad && self.w.grad.accumulate(txn, node, |grad| grad.as_view_mut().set_constant(0.0)) {
+        // Only this lookup's rows; the sparse grad is shared by all lookups.
+        let mut w_dense_grad = self.w.grad.get_mut(txn, node);
+        let w_dense_grad = w_dense_grad.as_mut_slice();
+        for (k, &row) in index.iter().enumerate() {
+          let row = row as usize;
+          let src = &w_grad.values[prev_len + k * row_len .. prev_len + (k + 1) * row_len];
+          for (d, &v) in w_dense_grad[row * row_len .. (row + 1) * row_len].iter_mut().zip(src.iter()) {
+            *d += v;
+          }
+        }
+      }
+      w_grad.rows.extend_from_slice(&index);
+      w_grad.coalesce();
+    }
+  }
+}
+
+pub struct BatchJoinOp<A, B, Join> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  x_:   Rc<AVar<AData<A>>>,
+  x:    AData<A>,
+  y:    AData<B>,
+  kernel:   Join,
+  tng_: RefCell<Option<Rc<AVar<AData<B>>>>>,
+}
+
+impl<A, B, Join> BatchJoinOp<A, B, Join> {
+  pub fn new<Op>(x_: Rc<Op>, kernel: Join, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> B>) -> Rc<BatchJoinOp<A, B, Join>> where Op: 'static + AVar<AData<A>> {
+    Self::_new(x_, kernel, alloc)
+  }
+
+  fn _new(x_: Rc<AVar<AData<A>>>, kernel: Join, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> B>) -> Rc<BatchJoinOp<A, B, Join>> {
+    let node = NodeId::new();
+    let x = x_.data();
+    Rc::new(BatchJoinOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 1),
+      x_:   x_,
+      x:    x,
+      y:    AData::new(/*clk_horizon,*/ alloc),
+      kernel:   kernel,
+      tng_: RefCell::new(None),
+    })
+  }
+}
+
+pub trait BatchSumExt<Op, A, B> where Op: AVar<AData<A>> {
+  fn batch_sum(x_: Rc<Op>) -> Rc<BatchJoinOp<A, B, SumJoinKernel>>;
+}
+
+pub fn batch_sum<Op, A, B>(x_: Rc<Op>) -> Rc<BatchJoinOp<A, B, SumJoinKernel>> where Rc<Op>: BatchSumExt<Op, A, B>, Op: AVar<AData<A>> {
+  <Rc<Op> as BatchSumExt<Op, A, B>>::batch_sum(x_)
+}
+
+/*impl BatchSumExt<Batch<f32>, f32> for BatchJoinOp<Batch<f32>, f32, SumJoinKernel> {
+  fn batch_sum(x_: Rc<AVar<AData<Batch<f32>>>>) -> Rc<Self> {
+    unimplemented!();
+  }
+}*/
+
+impl<Op> BatchSumExt<Op, Batch<f32>, f32> for Rc<Op> where Op: 'static + AVar<AData<Batch<f32>>> {
+  fn batch_sum(x_: Rc<Op>) -> Rc<BatchJoinOp<Batch<f32>, f32, SumJoinKernel>> {
+    //let clk_horizon = x_.data().horizon();
+    BatchJoinOp::new(x_, SumJoinKernel, /*clk_horizon,*/ Rc::new(|_, _| 0.0_f32))
+  }
+}
+
+impl<Op, S> BatchSumExt<Op, BatchArray1d<f32, S>, Array1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + ArrayStorage<usize> {
+  fn batch_sum(x_: Rc<Op>) -> Rc<BatchJoinOp<BatchArray1d<f32, S>, Array1d<f32, S>, SumJoinKernel>> {
+    //let clk_horizon = x_.data().horizon();
+    let x = x_.data();
+    BatchJoinOp::new(x_, SumJoinKernel, /*clk_horizon,*/ Rc::new(move |txn, node| {
+      let dim = x.val.get(txn, node).dim();
+      let buf = <S as ArrayStorage<usize>>::alloc(dim);
+      Array1d::from_storage(dim, buf)
+    }))
+  }
+}
+
+impl<A, B, Join> AVar<AData<B>> for BatchJoinOp<A, B, Join> where BatchJoinOp<A, B, Join>: AOp {
+  default fn _owned_data(&self) -> &AData<B> {
+    &self.y
+  }
+
+  default fn tangent(&self) -> Rc<AVar<AData<B>>> {
+    if self.tng_.borrow().is_none() {
+      *self.tng_.borrow_mut() = Some(self._make_tangent());
+    }
+    self.tng_.borrow().as_ref().unwrap().clone()
+  }
+}
+
+impl AVar<AData<f32>> for BatchJoinOp<Batch<f32>, f32, SumJoinKernel> {
+  fn _owned_data(&self) -> &AData<f32> {
+    &self.y
+  }
+
+  fn _make_tangent(&self) -> Rc<AVar<AData<f32>>> {
+    BatchJoinOp::_new(self.x_.tangent(), SumJoinKernel, /*clk_horizon,*/ _shared_alloc(&self.y))
+  }
+
+  fn _adjoint(&self, adj_: Rc<AVar<AData<f32>>>, adjs: &mut AdjointMap) {
+    let x_adj_: Rc<BatchBroadcastOp<f32, Batch<f32>>> = BatchBroadcastOp::new(adj_, self.x_.clone(), /*clk_horizon,*/ _shared_alloc(&self.x));
+    adjs.accumulate(self.x_.clone(), x_adj_);
+  }
+}
+
+impl<S> AVar<AData<Array1d<f32, S>>> for BatchJoinOp<BatchArray1d<f32, S>, Array1d<f32, S>, SumJoinKernel> where S: 'static + DerefMut<Target=[f32]> {
+  fn _owned_data(&self) -> &AData<Array1d<f32, S>> {
+    &self.y
+  }
+
+  fn _make_tangent(&self) -> Rc<AVar<AData<Array1d<f32, S>>>> {
+    BatchJoinOp::_new(self.x_.tangent(), SumJoinKernel, /*clk_horizon,*/ _shared_alloc(&self.y))
+  }
+
+  fn _adjoint(&self, adj_: Rc<AVar<AData<Array1d<f32, S>>>>, adjs: &mut AdjointMap) {
+    let x_adj_: Rc<BatchBroadcastOp<Array1d<f32, S>, BatchArray1d<f32, S>>> = BatchBroadcastOp::new(adj_, self.x_.clone(), /*clk_horizon,*/ _shared_alloc(&self.x));
+    adjs.accumulate(self.x_.clone(), x_adj_);
+  }
+}
+
+/*impl AutodiffObjective for BatchJoinOp<Batch<f32>, f32, SumJoinKernel> {
+  fn _set_source(&self, txn: TxnId) {
+    let node = self._id();
+    if !self.y.grad.accumulate(txn, node, |g| *g = 1.0) {
+      assert_eq!(1.0, *self.y.grad.get_mut(txn, node));
+    }
+  }
+}*/
+
+impl AOp for BatchJoinOp<Batch<f32>, f32, SumJoinKernel> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let x_val = self.x.val.get(txn, node);
+      let mut y_val = self.y.val.get_excl(txn, node);
+      *y_val = 0.0;
+      let batch_sz = x_val.batch_size();
+      for i in 0 .. batch_sz {
+        *y_val += x_val[i];
+      }
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| grad.reshape_mut(batch_sz).set_constant(0.0)) {
+      let mut x_grad = self.x.grad.get_mut(txn, node);
+      let y_grad = self.y.grad.get(txn, node);
+      for i in 0 .. batch_sz {
+        x_grad[i] += *y_grad;
+      }
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      let x_r_val = self.x.r_val.get(txn, node);
+      let mut y_r_val = self.y.r_val.get_excl(txn, node);
+      *y_r_val = 0.0;
+      let batch_sz = x_r_val.batch_size();
+      for i in 0 .. batch_sz {
+        *y_r_val += x_r_val[i];
+      }
+    }
+  }
+
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad2.accumulate(txn, node, |grad2| grad2.reshape_mut(batch_sz).set_constant(0.0)) {
+      let mut x_grad2 = self.x.grad2.get_mut(txn, node);
+      let y_grad2 = self.y.grad2.get(txn, node);
+      for i in 0 .. batch_sz {
+        x_grad2[i] += *y_grad2;
+      }
+    }
+  }
+}
+
+impl<S> AOp for BatchJoinOp<BatchArray1d<f32, S>, Array1d<f32, S>, SumJoinKernel> where S: DerefMut<Target=[f32]> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let x_dim = self.x.val.get(txn, node).dim();
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      assert_eq!(x_dim, self.y.val.get_excl(txn, node).dim());
+      self.y.val.get_excl(txn, node).as_view_mut().set_constant(0.0);
+      unsafe { _batch_sum_add(
+          x_dim,
+          batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.y.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad.set_batch_size(batch_sz); grad.as_view_mut().set_constant(0.0); }) {
+      unsafe { _batch_broadcast_add(
+          x_dim,
+          batch_sz,
+          self.y.grad.get(txn, node).as_view().as_ptr(),
+          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      let x_dim = self.x.r_val.get(txn, node).dim();
+      let batch_sz = self.x.r_val.get(txn, node).batch_size();
+      self.y.r_val.get_excl(txn, node).as_view_mut().set_constant(0.0);
+      unsafe { _batch_sum_add(
+          x_dim,
+          batch_sz,
+          self.x.r_val.get(txn, node).as_view().as_ptr(),
+          self.y.r_val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad2.accumulate(txn, node, |grad2| { grad2.set_batch_size(batch_sz); grad2.as_view_mut().set_constant(0.0); }) {
+      unsafe { _batch_broadcast_add(
+          x_dim,
+          batch_sz,
+          self.y.grad2.get(txn, node).as_view().as_ptr(),
+          self.x.grad2.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
+  }
+}
+
+/// Copies `x` into each example of a batch the size of the batch of `like_`,
+/// e.g. the adjoint of a `BatchJoinOp` sum. Only a zero gradient flows back
+/// into `like_`.
+pub struct BatchBroadcastOp<A, B> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  x_:       Rc<AVar<AData<A>>>,
+  like_:    Rc<AVar<AData<B>>>,
+  x:        AData<A>,
+  like:     AData<B>,
+  y:        AData<B>,
+  tng_:     RefCell<Option<Rc<AVar<AData<B>>>>>,
+}
+
+impl<A, B> BatchBroadcastOp<A, B> {
+  pub fn new(x_: Rc<AVar<AData<A>>>, like_: Rc<AVar<AData<B>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> B>) -> Rc<Self> {
+    let node = NodeId::new();
+    let x = x_.data();
+    let like = like_.data();
+    Rc::new(BatchBroadcastOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 2),
+      x_:       x_,
+      like_:    like_,
+      x:        x,
+      like:     like,
+      y:        AData::new(/*clk_horizon,*/ alloc),
+      tng_:     RefCell::new(None),
+    })
+  }
+}
+
+impl<A, B> AVar<AData<B>> for BatchBroadcastOp<A, B> where BatchBroadcastOp<A, B>: AOp {
+  default fn _owned_data(&self) -> &AData<B> {
+    &self.y
+  }
+
+  default fn tangent(&self) -> Rc<AVar<AData<B>>> {
+    if self.tng_.borrow().is_none() {
+      *self.tng_.borrow_mut() = Some(self._make_tangent());
+    }
+    self.tng_.borrow().as_ref().unwrap().clone()
+  }
+}
+
+impl<A, B> AVar<AData<B>> for BatchBroadcastOp<A, B> where A: 'static + PackedArray, B: 'static + PackedArray, BatchJoinOp<B, A, SumJoinKernel>: AOp {
+  fn _owned_data(&self) -> &AData<B> {
+    &self.y
+  }
+
+  fn _make_tangent(&self) -> Rc<AVar<AData<B>>> {
+    BatchBroadcastOp::new(self.x_.tangent(), self.like_.clone(), /*clk_horizon,*/ _shared_alloc(&self.y))
+  }
+
+  fn _adjoint(&self, adj_: Rc<AVar<AData<B>>>, adjs: &mut AdjointMap) {
+    let x_adj_: Rc<BatchJoinOp<B, A, SumJoinKernel>> = BatchJoinOp::_new(adj_, SumJoinKernel, /*clk_horizon,*/ _shared_alloc(&self.x));
+    adjs.accumulate(self.x_.clone(), x_adj_);
+  }
+}
+
+impl<A, B> AOp for BatchBroadcastOp<A, B> where A: PackedArray, B: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      self.like_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.like_._pop(epoch, apply);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let len = self.x.val.get(txn, node)._packed_dim().iter().product();
+      let batch_sz = self.like.val.get(txn, node)._batch_size();
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      assert_eq!(len * batch_sz, self.y.val.get_excl(txn, node)._packed_dim().iter().product::<usize>());
+      self.y.val.get_excl(txn, node)._set_zero();
+      unsafe { _batch_broadcast_add(
+          len,
+          batch_sz,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.y.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let len = self.x.val.get(txn, node)._packed_dim().iter().product();
+    let batch_sz = self.like.val.get(txn, node)._batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| grad._set_zero()) {
+      unsafe { _batch_sum_add(
+          len,
+          batch_sz,
+          self.y.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+    self.like.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); });
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
 
-pub trait BatchStatsExt<Idx, A, M> where Idx: ArrayIndex {
-  fn batch_stats(reduce_axes: Idx::Axes, cfg: BatchStatsConfig, ctrl: &mut BatchStatsControl, x_: Self) -> BatchStatsOutput<M> where Self: Sized;
-}
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.r_val.overwrite(txn, node) {
+      let len = self.x.r_val.get(txn, node)._packed_dim().iter().product();
+      let batch_sz = self.like.val.get(txn, node)._batch_size();
+      self.y.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+      self.y.r_val.get_excl(txn, node)._set_zero();
+      unsafe { _batch_broadcast_add(
+          len,
+          batch_sz,
+          self.x.r_val.get(txn, node)._as_ptr(),
+          self.y.r_val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
 
-pub fn batch_stats<Op, Idx, A, M>(reduce_axes: Idx::Axes, cfg: BatchStatsConfig, ctrl: &mut BatchStatsControl, x_: Rc<Op>) -> BatchStatsOutput<M> where Rc<Op>: BatchStatsExt<Idx, A, M>, Op: 'static + AVar<AData<A>>, Idx: ArrayIndex {
-  <Rc<Op> as BatchStatsExt<Idx, A, M>>::batch_stats(reduce_axes, cfg, ctrl, x_)
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    let len = self.x.val.get(txn, node)._packed_dim().iter().product();
+    let batch_sz = self.like.val.get(txn, node)._batch_size();
+    if self.x.grad2.accumulate(txn, node, |grad2| grad2._set_zero()) {
+      unsafe { _batch_sum_add(
+          len,
+          batch_sz,
+          self.y.grad2.get(txn, node)._as_ptr(),
+          self.x.grad2.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
 }
 
-pub struct BatchStatsOp<Idx, A, M> where Idx: ArrayIndex {
-  node_id:      NodeId,
-  stack:        OperatorStack,
-  state:        RefCell<BatchStatsState<Idx>>,
-  x_:           Rc<AVar<AData<A>>>,
-  mean_:        Weak<PassOp<(), M>>,
-  mean_acc_:    Rc<SrcOp<M>>,
-  mean_run_:    Rc<SrcOp<M>>,
-  //mean_branch_: Rc<AVar<AData<M>>>,
-  var_:         Weak<PassOp<(), M>>,
-  var_acc_:     Rc<SrcOp<M>>,
-  var_run_:     Rc<SrcOp<M>>,
-  //var_branch_:  Rc<AVar<AData<M>>>,
-  x:            AData<A>,
-  mean:         AData<M>,
-  mean_acc:     AData<M>,
-  mean_run:     AData<M>,
-  var:          AData<M>,
-  var_acc:      AData<M>,
-  var_run:      AData<M>,
+pub struct SequentialJoinOp<A, B, JoinF> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  x_:   Rc<AVar<AData<A>>>,
+  x:    AData<A>,
+  y:    AData<B>,
+  //curr_clk: Cell<usize>,
+  clock:    Rc<Clock>,
+  kernel:   JoinF,
+  tng_: RefCell<Option<Rc<AVar<AData<B>>>>>,
 }
 
-impl<Idx, A, M> BatchStatsOp<Idx, A, M> where BatchStatsOp<Idx, A, M>: AOp + BatchStatsOpExt, SrcOp<M>: AOp, Idx: 'static + ArrayIndex, A: 'static, M: 'static {
-  pub fn new<Op>(reduce_axes: Idx::Axes, cfg: BatchStatsConfig, ctrl: &mut BatchStatsControl, x_: Rc<Op>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> M>) -> BatchStatsOutput<M> where Op: 'static + AVar<AData<A>> {
+impl<A, B, JoinF> SequentialJoinOp<A, B, JoinF> {
+  pub fn new<Op>(x_: Rc<Op>, kernel: JoinF, alloc: Rc<Fn(TxnId, NodeId) -> B>) -> Rc<SequentialJoinOp<A, B, JoinF>> where Op: 'static + AVar<AData<A>> {
+    let clock = x_.data().clock.clone();
+    Self::_new(x_, clock, kernel, alloc)
+  }
+
+  fn _new(x_: Rc<AVar<AData<A>>>, clock: Rc<Clock>, kernel: JoinF, alloc: Rc<Fn(TxnId, NodeId) -> B>) -> Rc<SequentialJoinOp<A, B, JoinF>> {
     let node = NodeId::new();
     let x = x_.data();
-    let mean = AData::new(/*clk_horizon,*/ alloc.clone());
-    let var = AData::new(/*clk_horizon,*/ alloc.clone());
-    let mean_ = PassOp::new(None, mean.clone());
-    let var_ = PassOp::new(None, var.clone());
-    // FIXME: some hackiness around clocks.
-    let mean_acc_ = SrcOp::new(/*clk_horizon,*/ /*clk_horizon > 1,*/ alloc.clone()); //src(alloc.clone());
-    let var_acc_ = SrcOp::new(/*clk_horizon,*/ /*clk_horizon > 1,*/ alloc.clone()); //src(alloc.clone());
-    let mean_run_ = SrcOp::new(/*clk_horizon,*/ /*clk_horizon > 1,*/ alloc.clone());
-      //.initialize(init_val(|_, x: &mut DeviceArray1d<f32>| x.as_view_mut().set_constant(0.0, DeviceStream::implicit().conn())));
-    let var_run_ = SrcOp::new(/*clk_horizon,*/ /*clk_horizon > 1,*/ alloc.clone());
-      //.initialize(init_val(|_, x: &mut DeviceArray1d<f32>| x.as_view_mut().set_constant(0.0, DeviceStream::implicit().conn())));
-    let mean_branch_ = BranchOp::new(ctrl.mode.clone(), mean_.clone(), mean_run_.clone(), /*clk_horizon,*/ alloc.clone());
-    let var_branch_ = BranchOp::new(ctrl.mode.clone(), var_.clone(), var_run_.clone(), /*clk_horizon,*/ alloc.clone());
-    ctrl.batch_ops.push(mean_.clone());
-    ctrl.batch_ops.push(var_.clone());
-    ctrl.batch_vars.insert_all(&mean_.vars());
-    ctrl.batch_vars.insert_all(&var_.vars());
-    ctrl.acc_ops.push(mean_acc_.clone());
-    ctrl.acc_ops.push(var_acc_.clone());
-    ctrl.acc_vars.insert_all(&mean_acc_.vars());
-    ctrl.acc_vars.insert_all(&var_acc_.vars());
-    ctrl.fixed_ops.push(mean_run_.clone());
-    ctrl.fixed_ops.push(var_run_.clone());
-    ctrl.fixed_vars.insert_all(&mean_run_.vars());
-    ctrl.fixed_vars.insert_all(&var_run_.vars());
-    let mean_acc = mean_acc_.data();
-    let mean_run = mean_run_.data();
-    let var_acc = var_acc_.data();
-    let var_run = var_run_.data();
-    let op = Rc::new(BatchStatsOp{
+    Rc::new(SequentialJoinOp{
       node_id:  node,
       stack:    OperatorStack::new(node, 1),
-      state:    RefCell::new(BatchStatsState::new(reduce_axes, cfg, ctrl.mode.clone())),
-      x_:           x_,
-      mean_:        Rc::downgrade(&mean_),
-      mean_acc_:    mean_acc_,
-      mean_run_:    mean_run_.clone(),
-      //mean_branch_: mean_branch_.clone(),
-      var_:         Rc::downgrade(&var_),
-      var_acc_:     var_acc_,
-      var_run_:     var_run_.clone(),
-      //var_branch_:  var_branch_.clone(),
-      x:            x,
-      mean:         mean,
-      mean_acc:     mean_acc,
-      mean_run:     mean_run,
-      var:          var,
-      var_acc:      var_acc,
-      var_run:      var_run,
-    });
-    *mean_.x_.borrow_mut() = Some(AVar::from(op.clone()));
-    *var_.x_.borrow_mut() = Some(AVar::from(op.clone()));
-    ctrl.ops.push(op);
-    BatchStatsOutput{
-      mean_batch:   mean_,
-      var_batch:    var_,
-      mean_fixed:   mean_run_,
-      var_fixed:    var_run_,
-      mean_branch:  mean_branch_,
-      var_branch:   var_branch_,
+      x_:   x_,
+      x:    x,
+      y:    AData::new(alloc),
+      clock:    clock,
+      kernel:   kernel,
+      tng_: RefCell::new(None),
+    })
+  }
+}
+
+impl<A, B, JoinF> AVar<AData<B>> for SequentialJoinOp<A, B, JoinF> where SequentialJoinOp<A, B, JoinF>: AOp {
+  default fn _owned_data(&self) -> &AData<B> {
+    &self.y
+  }
+
+  default fn tangent(&self) -> Rc<AVar<AData<B>>> {
+    if self.tng_.borrow().is_none() {
+      *self.tng_.borrow_mut() = Some(self._make_tangent());
     }
+    self.tng_.borrow().as_ref().unwrap().clone()
   }
 }
 
-impl<Idx, A, M> AVar<()> for BatchStatsOp<Idx, A, M> where BatchStatsOp<Idx, A, M>: AOp + BatchStatsOpExt, SrcOp<M>: AOp, Idx: 'static + ArrayIndex, A: 'static, M: 'static {
-  default fn _owned_data(&self) -> &() {
-    unreachable!();
+impl AVar<AData<Batch<f32>>> for SequentialJoinOp<Batch<f32>, Batch<f32>, SumJoinKernel> {
+  fn _owned_data(&self) -> &AData<Batch<f32>> {
+    &self.y
   }
 
-  default fn data(&self) -> () {
-    ()
+  fn _make_tangent(&self) -> Rc<AVar<AData<Batch<f32>>>> {
+    // The tangent steps through the sequence on the clock of the primal op.
+    SequentialJoinOp::_new(self.x_.tangent(), self.clock.clone(), SumJoinKernel, _shared_alloc(&self.y))
   }
+}
 
-  default fn vars(&self) -> VarSet {
-    var_set()
+impl AOp for SequentialJoinOp<Batch<f32>, Batch<f32>, SumJoinKernel> {
+  fn _id(&self) -> NodeId {
+    self.node_id
   }
 
-  default fn tangent(&self) -> Rc<AVar<()>> {
-    // FIXME
-    unimplemented!();
-    /*if self.tng.borrow().is_none() {
-      *self.tng.borrow_mut() = Some(self._make_tangent());
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    let clk = self.clock.time();
+    let x_val = self.x.val.get_clk(clk, txn, node);
+    let batch_sz = x_val.batch_size();
+    if self.y.val.accumulate(txn, node, |val| val.reshape_mut(batch_sz).set_constant(0.0)) {
+      self.y.val.get_mut(txn, node).reshape_mut(batch_sz)
+        .add(1.0, x_val.reshape(batch_sz));
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let clk = self.clock.time();
+    let y_grad = self.y.grad.get_clk(clk, txn, node);
+    let batch_sz = y_grad.batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| grad.reshape_mut(batch_sz).set_constant(0.0)) {
+      self.x.grad.get_mut(txn, node).reshape_mut(batch_sz)
+        .add(1.0, y_grad.reshape(batch_sz));
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    let clk = self.clock.time();
+    let x_r_val = self.x.r_val.get_clk(clk, txn, node);
+    let batch_sz = x_r_val.batch_size();
+    if self.y.r_val.accumulate(txn, node, |r_val| r_val.reshape_mut(batch_sz).set_constant(0.0)) {
+      self.y.r_val.get_mut(txn, node).reshape_mut(batch_sz)
+        .add(1.0, x_r_val.reshape(batch_sz));
+    }
+  }
+
+  /*fn _reset_clock(&self) {
+    self.curr_clk.set(0);
+  }
+
+  fn _set_clock(&self, new_clk: usize) {
+    self.curr_clk.set(new_clk);
+  }*/
+}
+
+pub fn sink<Op, A>(x_: Rc<Op>) -> Rc<ArraySink<Op, A>> where Op: AVar<AData<A>> {
+  let x = x_.data();
+  Rc::new(ArraySink{
+    node:   NodeId::new(),
+    x_:     x_,
+    x:      x,
+    _m:     PhantomData,
+  })
+}
+
+pub struct ArraySink<Op, A> where Op: AVar<AData<A>> {
+  node: NodeId,
+  x_:   Rc<Op>,
+  x:    AData<A>,
+  _m:   PhantomData<A>,
+}
+
+/*(impl<Op, A> Deref for ArraySink<Op, A> where Op: AVar<AData<A>> {
+  type Target = Op;
+
+  fn deref(&self) -> &Op {
+    &*self.x_
+  }
+}*/
+
+//impl<Op> AutodiffSink<Op> for ArraySink<Op, f32> where Op: AVar<AData<f32>> {
+impl<Op> AutodiffSink for ArraySink<Op, f32> where Op: AVar<AData<f32>> {
+  fn _op(&self) -> &AOp {
+    &*self.x_
+  }
+
+  fn _set_source(&self, txn: TxnId) {
+    let node = self.node;
+    if self.x.grad.overwrite(txn, node) {
+      *self.x.grad.get_excl(txn, node) = 1.0;
+    }
+  }
+}
+
+impl<Op, A> ArraySink<Op, A> where Op: AVar<AData<A>>, A: PackedArray {
+  /// Seeds the gradient with the `idx`-th unit vector of the packed output,
+  /// so that a backward pass computes one row of the Jacobian.
+  pub fn _set_one_hot_source(&self, txn: TxnId, idx: usize) {
+    let node = self.node;
+    if self.x.grad.overwrite(txn, node) {
+      let batch_sz = self.x.val.get(txn, node)._batch_size();
+      let mut grad = self.x.grad.get_excl(txn, node);
+      grad._set_batch_size(batch_sz);
+      grad._set_zero();
+      let len: usize = grad._packed_dim().iter().product();
+      assert!(idx < len);
+      unsafe { *grad._as_mut_ptr().offset(idx as isize) = 1.0 };
+    }
+  }
+}
+
+/// The Jacobian of `y_` with respect to the sources whose vars are in `xs`,
+/// as a `(y_len, x_len)` array. The rows follow the packed layout of `y_` and
+/// the columns the order of `store_val` over `xs`.
+///
+/// Every row or column is computed in a fresh txn, and `load` is called with
+/// each txn to load the inputs of the graph (including the sources in `xs`).
+/// Forward mode (one `r_eval` per input) is used when there are at most as
+/// many inputs as outputs and every op in the graph has an R-operator, and
+/// reverse mode (one backward pass per output) otherwise.
+pub fn jacobian<Op, A, F>(y_: Rc<Op>, xs: &VarSet, mut load: F) -> Array2d<f32> where Op: AVar<AData<A>>, A: PackedArray, F: FnMut(TxnId) {
+  let mut val_vars = xs.filter(|v| v.kind == Val);
+  let mut grad_vars = xs.filter(|v| v.kind == Grad);
+  let mut r_val_vars = xs.filter(|v| v.kind == RVal);
+  let sink = sink(y_.clone());
+  let node = sink.node;
+  let (y_len, x_len) = {
+    let txn = txn();
+    load(txn);
+    y_.eval(txn);
+    let y_len: usize = sink.x.val.get(txn, node)._packed_dim().iter().product();
+    let x_len = y_.val_size(txn, &mut val_vars);
+    (y_len, x_len)
+  };
+  let mut jac = Array2d::zeros((y_len, x_len));
+  if x_len <= y_len && y_.supports_r_eval() {
+    let mut probe: Vec<f32> = vec![0.0; x_len];
+    for j in 0 .. x_len {
+      let txn = txn();
+      load(txn);
+      probe[j] = 1.0;
+      let offset = y_.load_r_val(txn, &mut r_val_vars, 0, &mut probe);
+      assert_eq!(offset, x_len);
+      probe[j] = 0.0;
+      y_.r_eval(txn);
+      let y_r_val = sink.x.r_val.get(txn, node);
+      assert_eq!(y_len, y_r_val._packed_dim().iter().product());
+      unsafe { arraydiff_kernel_copy_f32(
+          y_len,
+          y_r_val._as_ptr(),
+          jac.as_mut_slice()[j * y_len .. (j + 1) * y_len].as_mut_ptr(),
+      ) };
+    }
+  } else {
+    let mut row: Vec<f32> = vec![0.0; x_len];
+    for i in 0 .. y_len {
+      let txn = txn();
+      load(txn);
+      y_.eval(txn);
+      sink._set_one_hot_source(txn, i);
+      y_._traverse_bwd(&mut |op| { op._backward(txn); });
+      let offset = y_.store_grad(txn, &mut grad_vars, 0, &mut row);
+      assert_eq!(offset, x_len);
+      let jac = jac.as_mut_slice();
+      for j in 0 .. x_len {
+        jac[i + j * y_len] = row[j];
+      }
     }
-    self.tng.borrow().as_ref().unwrap().clone()*/
   }
+  jac
 }
 
-//impl<Idx, A, M> BatchStatsOpExt for BatchStatsOp<Idx, A, M> where Idx: ArrayIndex {
-impl<S> BatchStatsOpExt for BatchStatsOp<(usize, usize), BatchArray3d<f32, S>, Array1d<f32, S>> where S: DerefMut<Target=[f32]> {
-  fn _configure(&self, reconf: &Fn(&mut BatchStatsConfig)) {
-    // FIXME(20170214): only safe to mutate state at the beginning of a txn.
-    let mut state = self.state.borrow_mut();
-    reconf(&mut state.cfg);
+pub trait GradientExt<A> {
+  fn gradient(x_: Self) -> Rc<GradientSink<A>>;
+}
+
+impl<A> GradientExt<A> for Rc<AVar<AData<A>>> {
+  fn gradient(x_: Rc<AVar<AData<A>>>) -> Rc<GradientSink<A>> {
+    GradientSink::new(x_)
   }
+}
 
-  fn _reset_accumulators(&self, txn: TxnId) {
-    let mut state = self.state.borrow_mut();
-    state.batch_ct = 0;
+impl<A, Op> GradientExt<A> for Rc<Op> where Op: 'static + AVar<AData<A>> {
+  fn gradient(x_: Rc<Op>) -> Rc<GradientSink<A>> {
+    GradientSink::new(x_)
   }
+}
 
-  fn _accumulate(&self, txn: TxnId) {
-    let node = self._id();
-    let mut state = self.state.borrow_mut();
-    let batch_sz = self.x.val.get(txn, node).batch_size();
-    // FIXME: does not account for non-uniform batch sizes.
-    let n = (state.batch_ct + 1) as f32;
-    //self.mean_acc.val.rollover(txn, self.mean_acc.val.var()); // FIXME
-    if self.mean_acc.val.accumulate(txn, node, |val| val.as_view_mut().set_constant(0.0)) {
-      assert!(!self.mean.val.overwrite(txn, node));
-      self.mean_acc.val.get_mut(txn, node).as_view_mut().average(1.0 / n, self.mean.val.get_excl(txn, node).as_view());
-    }
-    //self.var_acc.val.rollover(txn, self.var_acc.val.var()); // FIXME
-    if self.var_acc.val.accumulate(txn, node, |val| val.as_view_mut().set_constant(0.0)) {
-      assert!(!self.var.val.overwrite(txn, node));
-      self.var_acc.val.get_mut(txn, node).as_view_mut().average(1.0 / n, self.var.val.get_excl(txn, node).as_view());
-    }
-    state.batch_ct += 1;
+pub struct GradientSink<A> {
+  node: NodeId,
+  x_:   Rc<AVar<AData<A>>>,
+  x:    AData<A>,
+}
+
+impl<A> GradientSink<A> {
+  pub fn new(x_: Rc<AVar<AData<A>>>) -> Rc<Self> {
+    let node = NodeId::new();
+    let x = x_.data();
+    Rc::new(GradientSink{
+      node: node,
+      x_:   x_,
+      x:    x,
+    })
   }
+}
 
-  fn _update_stats(&self, prev_txn: TxnId, next_txn: TxnId) {
-    let node = self._id();
-    let mut state = self.state.borrow_mut();
-    assert!(state.batch_ct >= 1);
-    // FIXME: rather than directly average with `rate`, should use a
-    // normalized rate for bias correction.
-    let rate = state.cfg.average.rate(state.update_ct) as f32;
-    //self.mean_run.val.rollover(next_txn, self.mean_run.val.var()); // FIXME
-    if self.mean_run.val.accumulate(next_txn, node, |val| val.as_view_mut().set_constant(0.0)) {
-      if rate != 0.0 {
-        self.mean_run.val.get_mut(next_txn, node).as_view_mut().average(rate, self.mean_acc.val.get(prev_txn, node).as_view());
-      }
-      if self.mean_acc.val.overwrite(next_txn, node) {
-        self.mean_acc.val.get_excl(next_txn, node).as_view_mut().set_constant(0.0);
+impl GradientSinkExt for GradientSink<f32> {
+  fn eval_gradient(&self, txn: TxnId) {
+    self.x_._traverse_fwd(&mut |op| { op._forward(txn); });
+    {
+      let node = self.node;
+      if self.x.grad.overwrite(txn, node) {
+        *self.x.grad.get_excl(txn, node) = 1.0;
       }
     }
-    //self.var_run.val.rollover(next_txn, self.var_run.val.var()); // FIXME
-    if self.var_run.val.accumulate(next_txn, node, |val| val.as_view_mut().set_constant(0.0)) {
-      if rate != 0.0 {
-        self.var_run.val.get_mut(next_txn, node).as_view_mut().average(rate, self.var_acc.val.get(prev_txn, node).as_view());
+    self.x_._traverse_bwd(&mut |op| { op._backward(txn); });
+  }
+}
+
+/// The result of `gradcheck` for one source.
+#[derive(Clone, Debug)]
+pub struct GradCheckParam {
+  pub node:         NodeId,
+  /// The offset and length of the source in the flattened params.
+  pub offset:       usize,
+  pub len:          usize,
+  /// The largest relative error over the entries of the source, and the
+  /// entry at which it occurs.
+  pub max_rel_err:  f32,
+  pub argmax:       usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct GradCheck {
+  pub params:   Vec<GradCheckParam>,
+  pub tol:      f32,
+}
+
+impl GradCheck {
+  pub fn max_rel_err(&self) -> f32 {
+    self.params.iter().fold(0.0_f32, |e, p| e.max(p.max_rel_err))
+  }
+
+  pub fn is_ok(&self) -> bool {
+    self.params.iter().all(|p| p.max_rel_err <= self.tol)
+  }
+}
+
+fn _packed_sum<A>(x: &A) -> f64 where A: PackedArray {
+  let len = x._packed_dim().iter().product();
+  let mut buf: Vec<f32> = vec![0.0; len];
+  unsafe { arraydiff_kernel_copy_f32(
+      len,
+      x._as_ptr(),
+      buf.as_mut_ptr(),
+  ) };
+  buf.iter().map(|&v| v as f64).sum()
+}
+
+/// Compares the gradient of the loss of `sink` with respect to the sources
+/// whose vars are in `params` against central differences with step `eps`.
+/// The error of each entry is the relative error `|a - n| / max(|a|, |n|)`
+/// of the analytic gradient `a` and the numerical one `n`, with the
+/// denominator floored at `f32::EPSILON` so that zero gradients agree.
+///
+/// The inputs and params must be loaded in `txn`. Each perturbation is
+/// evaluated in a fresh txn, in which `load` must load every input of the
+/// graph except for the params. Afterwards the params hold their original
+/// values again, loaded in another fresh txn; persist them to keep using
+/// them.
+pub fn gradcheck<A, F>(sink: &GradientSink<A>, params: &VarSet, eps: f32, tol: f32, txn: TxnId, mut load: F) -> GradCheck where A: PackedArray, GradientSink<A>: GradientSinkExt, F: FnMut(TxnId) {
+  let mut val_vars = params.filter(|v| v.kind == Val);
+  let mut grad_vars = params.filter(|v| v.kind == Grad);
+  let mut checks = vec![];
+  {
+    let epoch = Epoch::new(sink.x_._id());
+    let mut offset = 0;
+    val_vars.unmask_all();
+    sink.x_._push(epoch, &mut |_op| {});
+    sink.x_._pop(epoch, &mut |op| {
+      let next_offset = op._store_val(txn, &mut val_vars, offset, &mut NullIo);
+      if next_offset > offset {
+        checks.push(GradCheckParam{
+          node:         op._id(),
+          offset:       offset,
+          len:          next_offset - offset,
+          max_rel_err:  0.0,
+          argmax:       0,
+        });
       }
-      if self.var_acc.val.overwrite(next_txn, node) {
-        self.var_acc.val.get_excl(next_txn, node).as_view_mut().set_constant(0.0);
+      offset = next_offset;
+    });
+    val_vars.unmask_all();
+  }
+  let param_len: usize = checks.iter().map(|p| p.len).sum();
+  let mut theta: Vec<f32> = vec![0.0; param_len];
+  let mut grad: Vec<f32> = vec![0.0; param_len];
+  sink.eval_gradient(txn);
+  assert_eq!(param_len, sink.x_.store_val(txn, &mut val_vars, 0, &mut theta));
+  assert_eq!(param_len, sink.x_.store_grad(txn, &mut grad_vars, 0, &mut grad));
+  {
+    let mut eval_loss = |theta: &mut Vec<f32>| {
+      let txn = TxnId::new();
+      load(txn);
+      sink.x_.load_val(txn, &mut val_vars, 0, theta);
+      sink.x_.eval(txn);
+      let loss = _packed_sum(&*sink.x.val.get(txn, sink.node));
+      loss
+    };
+    let mut perturbed = theta.clone();
+    for p in checks.iter_mut() {
+      for k in 0 .. p.len {
+        let idx = p.offset + k;
+        perturbed[idx] = theta[idx] + eps;
+        let loss_plus = eval_loss(&mut perturbed);
+        perturbed[idx] = theta[idx] - eps;
+        let loss_minus = eval_loss(&mut perturbed);
+        perturbed[idx] = theta[idx];
+        let num_grad = ((loss_plus - loss_minus) / (2.0 * eps as f64)) as f32;
+        let rel_err = (grad[idx] - num_grad).abs() / grad[idx].abs().max(num_grad.abs()).max(f32::EPSILON);
+        if rel_err > p.max_rel_err {
+          p.max_rel_err = rel_err;
+          p.argmax = k;
+        }
       }
     }
-    state.batch_ct = 0;
-    state.update_ct += 1;
+  }
+  sink.x_.load_val(TxnId::new(), &mut val_vars, 0, &mut theta);
+  GradCheck{
+    params: checks,
+    tol:    tol,
   }
 }
 
-impl<S> AOp for BatchStatsOp<(usize, usize), BatchArray3d<f32, S>, Array1d<f32, S>> where S: DerefMut<Target=[f32]> {
-  fn _id(&self) -> NodeId {
-    self.node_id
-  }
+/// Sums the gradients of several micro-batches, each evaluated in its own
+/// txn, before a single optimizer step:
+///
+/// ```text
+/// let mut acc = GradAccumulator::new(loss_.clone(), &params);
+/// for k in 0 .. num_micro_batches {
+///   let txn = acc.begin();
+///   // Load (or persist) the params and the k-th micro-batch in `txn`.
+///   sink.eval_gradient(txn);
+/// }
+/// loss_.store_grad(acc.txn().unwrap(), &mut params, 0, &mut grad);
+/// acc.reset();
+/// ```
+///
+/// The first accumulation into a grad in a new txn zeroes it, unless the grad
+/// was rolled over (see `AOp::persist`). `begin` rolls over the grads in
+/// `params` into every txn after the first one since the last `reset`, so
+/// they keep summing; all other grads are reset as usual. Only the grads of
+/// the sources should be in `params`: rolling over the grad of an
+/// intermediate var would backpropagate its previous micro-batches again.
+///
+/// The result is the sum over the micro-batches; divide by `num_steps` for
+/// the mean.
+pub struct GradAccumulator {
+  op:       Rc<AOp>,
+  grads:    VarSet,
+  txn:      Option<TxnId>,
+  steps:    usize,
+}
 
-  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
-    if 1 == self.stack.push(epoch) {
-      self.x_._push(epoch, apply);
-      apply(self);
+impl GradAccumulator {
+  /// Only the `Grad` vars of `params` are rolled over.
+  pub fn new<Op>(op: Rc<Op>, params: &VarSet) -> Self where Op: 'static + AOp {
+    GradAccumulator{
+      op:       op,
+      grads:    params.filter(|v| v.kind == Grad),
+      txn:      None,
+      steps:    0,
     }
   }
 
-  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
-    if self.stack.degree(epoch) == self.stack.pop(epoch) {
-      apply(self);
-      self.x_._pop(epoch, apply);
+  /// Starts the txn of the next micro-batch.
+  pub fn begin(&mut self) -> TxnId {
+    let txn = TxnId::new();
+    if self.txn.is_some() {
+      self.op.persist(txn, &mut self.grads);
     }
+    self.txn = Some(txn);
+    self.steps += 1;
+    txn
   }
 
-  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
-    self.mean.rollover_all(txn, vars);
-    self.var.rollover_all(txn, vars);
+  /// The txn of the latest micro-batch, in which the accumulated grads can
+  /// be stored.
+  pub fn txn(&self) -> Option<TxnId> {
+    self.txn
   }
 
-  fn _forward(&self, txn: TxnId) {
-    unimplemented!();
+  pub fn num_steps(&self) -> usize {
+    self.steps
   }
 
-  fn _backward(&self, txn: TxnId) {
-    unimplemented!();
+  /// Starts a new accumulation; the next `begin` zeroes the grads.
+  pub fn reset(&mut self) {
+    self.txn = None;
+    self.steps = 0;
   }
 }
 
-pub trait IndexExt<IdxOp, A, Idx, Out> {
-  //fn one_hot(x_: Rc<Op>, index_: Rc<IdxOp>) -> Rc<Self>;
-  fn index(&self, index_: Rc<IdxOp>) -> Rc<IndexOp<A, Idx, Out>>;
+pub trait HessianExt<A> {
+  fn hessian(x_: Self) -> Rc<HessianSink<A>>;
 }
 
-/*pub fn one_hot<Op, IdxOp, A, Idx, Out>(x_: Rc<Op>, index_: Rc<IdxOp>) -> Rc<IndexOp<A, Idx, Out>> where Op: 'static + AVar<AData<A>>, IdxOp: 'static + AVar<AData<Idx>>, IndexOp<A, Idx, Out>: IndexExt<Op, IdxOp> {
-  <IndexOp<A, Idx, Out> as IndexExt<Op, IdxOp>>::one_hot(x_, index_)
-}*/
-
-pub struct IndexOp<A, Idx, Out> {
-  node_id:  NodeId,
-  stack:    OperatorStack,
-  x_:       Rc<AVar<AData<A>>>,
-  index_:   Rc<AVar<AData<Idx>>>,
-  x:        AData<A>,
-  index:    AData<Idx>,
-  y:        AData<Out>,
+impl<A> HessianExt<A> for Rc<AVar<AData<A>>> {
+  fn hessian(x_: Rc<AVar<AData<A>>>) -> Rc<HessianSink<A>> {
+    HessianSink::new(x_)
+  }
 }
 
-impl<A, Idx, Out> AVar<AData<Out>> for IndexOp<A, Idx, Out> where IndexOp<A, Idx, Out>: AOp {
-  fn _owned_data(&self) -> &AData<Out> {
-    &self.y
+impl<A, Op> HessianExt<A> for Rc<Op> where Op: 'static + AVar<AData<A>> {
+  fn hessian(x_: Rc<Op>) -> Rc<HessianSink<A>> {
+    HessianSink::new(x_)
   }
 }
 
-impl<A, Idx, Out> IndexOp<A, Idx, Out> {
-  pub fn new(x_: Rc<AVar<AData<A>>>, index_: Rc<AVar<AData<Idx>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> Out>) -> Rc<IndexOp<A, Idx, Out>> {
+/// Sink for exact Hessian-vector products, built from the tangent graph of
+/// `x` (see `AVar::tangent`). The direction `v` is read from the values of
+/// the tangent sources. A single backward pass runs from the tangent output,
+/// so the primal sources receive `H v` in their `grad` and the tangent
+/// sources receive the gradient of `x`. The primal output itself is not
+/// seeded; run a `GradientSink` for the plain gradient.
+pub struct HessianSink<A> {
+  node: NodeId,
+  x_:       Rc<AVar<AData<A>>>,
+  x_tng_:   Rc<AVar<AData<A>>>,
+  x:        AData<A>,
+  x_tng:    AData<A>,
+}
+
+impl<A> HessianSink<A> {
+  pub fn new(x_: Rc<AVar<AData<A>>>) -> Rc<Self> {
     let node = NodeId::new();
+    let x_tng_ = x_.tangent();
     let x = x_.data();
-    let index = index_.data();
-    Rc::new(IndexOp{
-      node_id:  node,
-      stack:    OperatorStack::new(node, 2),
+    let x_tng = x_tng_.data();
+    Rc::new(HessianSink{
+      node: node,
       x_:       x_,
-      index_:   index_,
+      x_tng_:   x_tng_,
       x:        x,
-      index:    index,
-      y:        AData::new(/*clk_horizon,*/ alloc),
+      x_tng:    x_tng,
     })
   }
 }
 
-pub struct BatchJoinOp<A, B, Join> {
-  node_id:  NodeId,
-  stack:    OperatorStack,
+impl HessianSinkExt for HessianSink<f32> {
+  fn eval_hessian_vector_product(&self, txn: TxnId) {
+    // The primal graph is evaluated first so that the tangent graph, which
+    // reads the primal values, finds them already written.
+    self.x_._traverse_fwd(&mut |op| { op._forward(txn); });
+    self.x_tng_._traverse_fwd(&mut |op| { op._forward(txn); });
+    {
+      let node = self.node;
+      if self.x_tng.grad.overwrite(txn, node) {
+        *self.x_tng.grad.get_excl(txn, node) = 1.0;
+      }
+    }
+    // Differentiating the directional derivative `<grad f, v>` w.r.t. the
+    // primal inputs gives `H v`; w.r.t. the tangent inputs it gives `grad f`.
+    self.x_tng_._traverse_bwd(&mut |op| { op._backward(txn); });
+  }
+}
+
+impl HessianSinkExt for HessianSink<Batch<f32>> {
+  fn eval_hessian_vector_product(&self, txn: TxnId) {
+    self.x_._traverse_fwd(&mut |op| { op._forward(txn); });
+    self.x_tng_._traverse_fwd(&mut |op| { op._forward(txn); });
+    {
+      let node = self.node;
+      if self.x_tng.grad.overwrite(txn, node) {
+        let batch_sz = self.x_tng.val.get(txn, node).batch_size();
+        self.x_tng.grad.get_excl(txn, node).set_batch_size(batch_sz, 0.0);
+        for idx in 0 .. batch_sz {
+          self.x_tng.grad.get_excl(txn, node)[idx] = 1.0;
+        }
+      }
+    }
+    self.x_tng_._traverse_bwd(&mut |op| { op._backward(txn); });
+  }
+}
+
+pub trait GaussNewtonExt<A> {
+  fn gauss_newton(x_: Self) -> Rc<GaussNewtonSink<A>>;
+}
+
+impl<A> GaussNewtonExt<A> for Rc<AVar<AData<A>>> {
+  fn gauss_newton(x_: Rc<AVar<AData<A>>>) -> Rc<GaussNewtonSink<A>> {
+    GaussNewtonSink::new(x_)
+  }
+}
+
+impl<A, Op> GaussNewtonExt<A> for Rc<Op> where Op: 'static + AVar<AData<A>> {
+  fn gauss_newton(x_: Rc<Op>) -> Rc<GaussNewtonSink<A>> {
+    GaussNewtonSink::new(x_)
+  }
+}
+
+/// Sink for curvature-vector products of a loss. Unlike `HessianSink`, it
+/// only needs the R-operator (`_r_forward`) of the ops, not their tangents.
+pub struct GaussNewtonSink<A> {
+  node: NodeId,
   x_:   Rc<AVar<AData<A>>>,
   x:    AData<A>,
-  y:    AData<B>,
-  kernel:   Join,
 }
 
-impl<A, B, Join> BatchJoinOp<A, B, Join> {
-  pub fn new<Op>(x_: Rc<Op>, kernel: Join, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> B>) -> Rc<BatchJoinOp<A, B, Join>> where Op: 'static + AVar<AData<A>> {
+impl<A> GaussNewtonSink<A> {
+  pub fn new(x_: Rc<AVar<AData<A>>>) -> Rc<Self> {
     let node = NodeId::new();
     let x = x_.data();
-    Rc::new(BatchJoinOp{
-      node_id:  node,
-      stack:    OperatorStack::new(node, 1),
+    Rc::new(GaussNewtonSink{
+      node: node,
       x_:   x_,
       x:    x,
-      y:    AData::new(/*clk_horizon,*/ alloc),
-      kernel:   kernel,
     })
   }
 }
 
-pub trait BatchSumExt<Op, A, B> where Op: AVar<AData<A>> {
-  fn batch_sum(x_: Rc<Op>) -> Rc<BatchJoinOp<A, B, SumJoinKernel>>;
+/// Seeds the gradient of a sink output with ones, so that a batch of losses
+/// is differentiated as their sum.
+fn _packed_seed_grad<A>(x: &AData<A>, txn: TxnId, node: NodeId) where A: PackedArray {
+  if x.grad.overwrite(txn, node) {
+    let batch_sz = x.val.get(txn, node)._batch_size();
+    _packed_set_ones(&mut *x.grad.get_excl(txn, node), batch_sz);
+  }
 }
 
-pub fn batch_sum<Op, A, B>(x_: Rc<Op>) -> Rc<BatchJoinOp<A, B, SumJoinKernel>> where Rc<Op>: BatchSumExt<Op, A, B>, Op: AVar<AData<A>> {
-  <Rc<Op> as BatchSumExt<Op, A, B>>::batch_sum(x_)
+fn _packed_set_ones<A>(y: &mut A, batch_sz: usize) where A: PackedArray {
+  y._set_batch_size(batch_sz);
+  let len = y._packed_dim().iter().product();
+  let ones: Vec<f32> = vec![1.0; len];
+  unsafe { arraydiff_kernel_copy_f32(
+      len,
+      ones.as_ptr(),
+      y._as_mut_ptr(),
+  ) };
 }
 
-/*impl BatchSumExt<Batch<f32>, f32> for BatchJoinOp<Batch<f32>, f32, SumJoinKernel> {
-  fn batch_sum(x_: Rc<AVar<AData<Batch<f32>>>>) -> Rc<Self> {
-    unimplemented!();
+impl<A> GaussNewtonSinkExt for GaussNewtonSink<A> where A: PackedArray {
+  fn eval_gauss_newton_vector_product(&self, txn: TxnId) {
+    self.x_.r_eval(txn);
+    _packed_seed_grad(&self.x, txn, self.node);
+    self.x_._traverse_bwd(&mut |op| { op._backward_gauss_newton(txn); });
   }
-}*/
 
-impl<A, B, Join> AVar<AData<B>> for BatchJoinOp<A, B, Join> where BatchJoinOp<A, B, Join>: AOp {
-  default fn _owned_data(&self) -> &AData<B> {
-    &self.y
+  fn eval_empirical_fisher_vector_product(&self, txn: TxnId) {
+    self.x_.r_eval(txn);
+    _packed_seed_grad(&self.x, txn, self.node);
+    self.x_._traverse_bwd(&mut |op| { op._backward_empirical_fisher(txn); });
   }
 }
 
-/*impl AutodiffObjective for BatchJoinOp<Batch<f32>, f32, SumJoinKernel> {
-  fn _set_source(&self, txn: TxnId) {
-    let node = self._id();
-    if !self.y.grad.accumulate(txn, node, |g| *g = 1.0) {
-      assert_eq!(1.0, *self.y.grad.get_mut(txn, node));
+pub trait HessianDiagExt<A> {
+  fn hessian_diagonal(x_: Self) -> Rc<HessianDiagSink<A>>;
+}
+
+impl<A> HessianDiagExt<A> for Rc<AVar<AData<A>>> {
+  fn hessian_diagonal(x_: Rc<AVar<AData<A>>>) -> Rc<HessianDiagSink<A>> {
+    HessianDiagSink::new(x_)
+  }
+}
+
+impl<A, Op> HessianDiagExt<A> for Rc<Op> where Op: 'static + AVar<AData<A>> {
+  fn hessian_diagonal(x_: Rc<Op>) -> Rc<HessianDiagSink<A>> {
+    HessianDiagSink::new(x_)
+  }
+}
+
+/// Sink for the Becker-LeCun diagonal of the Hessian of a loss.
+pub struct HessianDiagSink<A> {
+  node: NodeId,
+  x_:   Rc<AVar<AData<A>>>,
+  x:    AData<A>,
+}
+
+impl<A> HessianDiagSink<A> {
+  pub fn new(x_: Rc<AVar<AData<A>>>) -> Rc<Self> {
+    let node = NodeId::new();
+    let x = x_.data();
+    Rc::new(HessianDiagSink{
+      node: node,
+      x_:   x_,
+      x:    x,
+    })
+  }
+}
+
+impl<A> HessianDiagSinkExt for HessianDiagSink<A> where A: PackedArray {
+  fn eval_hessian_diagonal(&self, txn: TxnId) {
+    self.x_._traverse_fwd(&mut |op| { op._forward(txn); });
+    _packed_seed_grad(&self.x, txn, self.node);
+    {
+      // The loss is its own sum, so its second derivative vanishes.
+      let node = self.node;
+      if self.x.grad2.overwrite(txn, node) {
+        let batch_sz = self.x.val.get(txn, node)._batch_size();
+        self.x.grad2.get_excl(txn, node)._set_batch_size(batch_sz);
+        self.x.grad2.get_excl(txn, node)._set_zero();
+      }
     }
+    self.x_._traverse_bwd(&mut |op| { op._backward(txn); });
+    self.x_._traverse_bwd(&mut |op| { op._backward2(txn); });
+  }
+}
+
+struct HutchinsonSource {
+  load_probe:   Box<Fn(TxnId, &mut VarSet, usize, &mut Any) -> usize>,
+  store_hvp:    Box<Fn(TxnId, &mut VarSet, usize, &mut Any) -> usize>,
+  tng_vars:     VarSet,
+  vars:         VarSet,
+}
+
+/// Hutchinson's stochastic estimate of the Hessian diagonal, the mean of
+/// `v * H v` over random sign vectors `v`. Unlike `HessianDiagSink` it is
+/// unbiased, at the cost of one Hessian-vector product per sample.
+///
+/// The sources are flattened in the order in which they are added, and
+/// `dim` must be their total length.
+pub struct HutchinsonEstimator {
+  srcs:     Vec<HutchinsonSource>,
+  probe:    Vec<f32>,
+  hvp:      Vec<f32>,
+  sum:      Vec<f32>,
+  count:    usize,
+}
+
+impl HutchinsonEstimator {
+  pub fn new(dim: usize) -> Self {
+    HutchinsonEstimator{
+      srcs:     vec![],
+      probe:    vec![0.0; dim],
+      hvp:      vec![0.0; dim],
+      sum:      vec![0.0; dim],
+      count:    0,
+    }
+  }
+
+  /// Adds a source of the graph. Its tangent receives the probe and its
+  /// gradient holds `H v` after each Hessian-vector product.
+  pub fn add_source<Op, A>(&mut self, x_: Rc<Op>) where Op: 'static + AVar<AData<A>>, A: 'static {
+    let tng_ = x_.tangent();
+    let tng_vars = VarSet::empty().add(tng_.data().val.var());
+    let vars = VarSet::empty().add(x_.data().grad.var());
+    self.srcs.push(HutchinsonSource{
+      load_probe:   Box::new(move |txn: TxnId, vars: &mut VarSet, offset: usize, reader: &mut Any| tng_.load_val(txn, vars, offset, reader)),
+      store_hvp:    Box::new(move |txn: TxnId, vars: &mut VarSet, offset: usize, writer: &mut Any| x_.store_grad(txn, vars, offset, writer)),
+      tng_vars:     tng_vars,
+      vars:         vars,
+    });
   }
-}*/
 
-impl AOp for BatchJoinOp<Batch<f32>, f32, SumJoinKernel> {
-  fn _id(&self) -> NodeId {
-    self.node_id
+  pub fn num_samples(&self) -> usize {
+    self.count
   }
 
-  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
-    if 1 == self.stack.push(epoch) {
-      self.x_._push(epoch, apply);
-      apply(self);
+  pub fn reset(&mut self) {
+    for s in self.sum.iter_mut() {
+      *s = 0.0;
     }
+    self.count = 0;
   }
 
-  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
-    if self.stack.degree(epoch) == self.stack.pop(epoch) {
-      apply(self);
-      self.x_._pop(epoch, apply);
+  /// Draws one sample in `txn`. The other inputs of the graph must already
+  /// be loaded in `txn`, and each sample requires a fresh txn.
+  pub fn eval_sample<R>(&mut self, txn: TxnId, sink: &HessianSinkExt, rng: &mut R) where R: Rng {
+    for v in self.probe.iter_mut() {
+      *v = if rng.next_u32() & 1 == 0 { 1.0 } else { -1.0 };
     }
+    let mut offset = 0;
+    for src in self.srcs.iter_mut() {
+      offset = (src.load_probe)(txn, &mut src.tng_vars, offset, &mut self.probe);
+    }
+    assert_eq!(offset, self.probe.len());
+    sink.eval_hessian_vector_product(txn);
+    let mut offset = 0;
+    for src in self.srcs.iter_mut() {
+      offset = (src.store_hvp)(txn, &mut src.vars, offset, &mut self.hvp);
+    }
+    assert_eq!(offset, self.hvp.len());
+    for ((s, &v), &hv) in self.sum.iter_mut().zip(self.probe.iter()).zip(self.hvp.iter()) {
+      *s += v * hv;
+    }
+    self.count += 1;
   }
 
-  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
-    self.y.rollover_all(txn, vars);
-  }
-
-  fn _forward(&self, txn: TxnId) {
-    let node = self._id();
-    if self.y.val.overwrite(txn, node) {
-      let x_val = self.x.val.get(txn, node);
-      let mut y_val = self.y.val.get_excl(txn, node);
-      *y_val = 0.0;
-      let batch_sz = x_val.batch_size();
-      for i in 0 .. batch_sz {
-        *y_val += x_val[i];
-      }
+  /// Writes the current estimate of the diagonal into `diag`.
+  pub fn diagonal(&self, diag: &mut [f32]) {
+    assert!(self.count > 0);
+    assert_eq!(diag.len(), self.sum.len());
+    let scale = 1.0 / self.count as f32;
+    for (d, &s) in diag.iter_mut().zip(self.sum.iter()) {
+      *d = scale * s;
     }
   }
+}
 
-  fn _backward(&self, txn: TxnId) {
-    let node = self._id();
-    let batch_sz = self.x.val.get(txn, node).batch_size();
-    if self.x.grad.accumulate(txn, node, |grad| grad.reshape_mut(batch_sz).set_constant(0.0)) {
-      let mut x_grad = self.x.grad.get_mut(txn, node);
-      let y_grad = self.y.grad.get(txn, node);
-      for i in 0 .. batch_sz {
-        x_grad[i] += *y_grad;
-      }
-    }
+impl<A> AdjointArray for A where A: 'static + PackedArray {
+  fn sum_adjoints(x_: &Rc<AVar<AData<A>>>, adjs_: Vec<Rc<AVar<AData<A>>>>) -> Rc<AVar<AData<A>>> {
+    let x = x_.data();
+    JoinOp::new(adjs_, SumJoinKernel, /*clk_horizon,*/ _shared_alloc(&x))
   }
 }
 
-pub struct SequentialJoinOp<A, B, JoinF> {
+/// An array of ones shaped like `x`, which seeds the adjoint of a loss. It is
+/// a constant, so its backward only initializes the gradient of `x` to zero
+/// for the ops that produced `x`.
+pub struct OnesLikeOp<A> {
   node_id:  NodeId,
   stack:    OperatorStack,
   x_:   Rc<AVar<AData<A>>>,
   x:    AData<A>,
-  y:    AData<B>,
-  //curr_clk: Cell<usize>,
-  clock:    Rc<Clock>,
-  kernel:   JoinF,
+  y:    AData<A>,
 }
 
-impl AOp for SequentialJoinOp<Batch<f32>, Batch<f32>, SumJoinKernel> {
+impl<A> OnesLikeOp<A> where A: 'static {
+  pub fn new(x_: Rc<AVar<AData<A>>>) -> Rc<Self> {
+    let node = NodeId::new();
+    let x = x_.data();
+    let y = AData::new(/*clk_horizon,*/ x.alloc.clone());
+    Rc::new(OnesLikeOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 1),
+      x_:   x_,
+      x:    x,
+      y:    y,
+    })
+  }
+}
+
+impl<A> AVar<AData<A>> for OnesLikeOp<A> where OnesLikeOp<A>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+
+  default fn _adjoint(&self, _adj_: Rc<AVar<AData<A>>>, _adjs: &mut AdjointMap) {
+  }
+}
+
+impl<A> AOp for OnesLikeOp<A> where A: PackedArray {
   fn _id(&self) -> NodeId {
     self.node_id
   }
@@ -3554,242 +10294,443 @@ impl AOp for SequentialJoinOp<Batch<f32>, Batch<f32>, SumJoinKernel> {
 
   fn _forward(&self, txn: TxnId) {
     let node = self._id();
-    let clk = self.clock.time();
-    let x_val = self.x.val.get_clk(clk, txn, node);
-    let batch_sz = x_val.batch_size();
-    if self.y.val.accumulate(txn, node, |val| val.reshape_mut(batch_sz).set_constant(0.0)) {
-      self.y.val.get_mut(txn, node).reshape_mut(batch_sz)
-        .add(1.0, x_val.reshape(batch_sz));
+    if self.y.val.overwrite(txn, node) {
+      let batch_sz = self.x.val.get(txn, node)._batch_size();
+      _packed_set_ones(&mut *self.y.val.get_excl(txn, node), batch_sz);
     }
   }
 
   fn _backward(&self, txn: TxnId) {
     let node = self._id();
-    let clk = self.clock.time();
-    let y_grad = self.y.grad.get_clk(clk, txn, node);
-    let batch_sz = y_grad.batch_size();
-    if self.x.grad.accumulate(txn, node, |grad| grad.reshape_mut(batch_sz).set_constant(0.0)) {
-      self.x.grad.get_mut(txn, node).reshape_mut(batch_sz)
-        .add(1.0, y_grad.reshape(batch_sz));
-    }
-  }
-
-  /*fn _reset_clock(&self) {
-    self.curr_clk.set(0);
-  }
-
-  fn _set_clock(&self, new_clk: usize) {
-    self.curr_clk.set(new_clk);
-  }*/
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); });
+  }
+}
+
+/// Builds the backward pass of `loss_` as a graph of vars, seeded with ones
+/// so that a batch of losses is differentiated as their sum. Unlike the
+/// gradients computed by `_backward`, the adjoints are themselves vars: they
+/// can be evaluated in any txn and differentiated again with the usual sinks,
+/// e.g. for gradient penalties or for the inner loop of meta-learning.
+///
+/// Adjoints are implemented for sources, maps and their tangents, sums, batch
+/// sums, linear maps, outer products, gradient scaling and the softmax and
+/// least squares losses; every var that `loss_` depends on must implement `_adjoint`. The
+/// adjoint ops of the losses can be differentiated with the sinks but have no
+/// adjoints of their own, and a `MapCurvatureOp` has no gradient w.r.t. its
+/// primal input.
+pub fn adjoint_graph<Op, L>(loss_: Rc<Op>) -> AdjointMap where Op: 'static + AVar<AData<L>>, L: 'static + PackedArray {
+  let mut order = vec![];
+  loss_._traverse_bwd(&mut |op| { order.push(op._id()); });
+  let loss_: Rc<AVar<AData<L>>> = loss_;
+  let seed_: Rc<AVar<AData<L>>> = OnesLikeOp::new(loss_.clone());
+  let mut adjs = AdjointMap::new();
+  adjs.accumulate(loss_, seed_);
+  for &node in order.iter() {
+    adjs._propagate(node);
+  }
+  adjs
+}
+
+/// The gradient of `loss_` with respect to `wrt_` as a var (see
+/// `adjoint_graph`).
+///
+/// Only these ops have adjoints: sources and io ops, pass ops, `MapOp` and
+/// `MapTangentOp`, sum joins, batch sums and batch broadcasts, `LinearOp`,
+/// `OuterProdOp`, `GradScaleOp`, and the softmax and least squares losses. Building the
+/// gradient of a loss that depends on any other op panics in that op's
+/// `_adjoint`; it does not return an error.
+pub fn gradient_graph<Op, L, X, A>(loss_: Rc<Op>, wrt_: Rc<X>) -> Rc<AVar<AData<A>>> where Op: 'static + AVar<AData<L>>, L: 'static + PackedArray, X: AVar<AData<A>>, A: 'static {
+  adjoint_graph(loss_).adjoint(&wrt_).expect("the loss does not depend on wrt_")
 }
 
-pub fn sink<Op, A>(x_: Rc<Op>) -> Rc<ArraySink<Op, A>> where Op: AVar<AData<A>> {
-  let x = x_.data();
-  Rc::new(ArraySink{
-    node:   NodeId::new(),
-    x_:     x_,
-    x:      x,
-    _m:     PhantomData,
-  })
+pub trait LstSqLossExt<Op, Target> {
+  fn lst_sq_loss(huber_clip: bool, x_: Rc<Op>, target_: Rc<Target>) -> Rc<Self> where Self: 'static + Sized;
 }
 
-pub struct ArraySink<Op, A> where Op: AVar<AData<A>> {
-  node: NodeId,
-  x_:   Rc<Op>,
-  x:    AData<A>,
-  _m:   PhantomData<A>,
+pub fn lst_sq_loss<Op, Target, A, Loss>(huber_clip: bool, x_: Rc<Op>, t_: Rc<Target>) -> Rc<LstSqLoss<A, Loss>> where Op: 'static + AVar<AData<A>>, Target: 'static + AVar<AData<A>>, A: 'static, Loss: 'static, LstSqLoss<A, Loss>: LstSqLossExt<Op, Target> {
+  <LstSqLoss<A, Loss> as LstSqLossExt<Op, Target>>::lst_sq_loss(huber_clip, x_, t_)
 }
 
-/*(impl<Op, A> Deref for ArraySink<Op, A> where Op: AVar<AData<A>> {
-  type Target = Op;
-
-  fn deref(&self) -> &Op {
-    &*self.x_
-  }
-}*/
+pub struct LstSqLoss<A, Loss> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  x_:       Rc<AVar<AData<A>>>,
+  target_:  Rc<AVar<AData<A>>>,
+  x:        AData<A>,
+  target:   AData<A>,
+  loss:     AData<Loss>,
+  clip:     bool,
+}
 
-//impl<Op> AutodiffSink<Op> for ArraySink<Op, f32> where Op: AVar<AData<f32>> {
-impl<Op> AutodiffSink for ArraySink<Op, f32> where Op: AVar<AData<f32>> {
-  fn _op(&self) -> &AOp {
-    &*self.x_
+impl<A, Loss> LstSqLoss<A, Loss> {
+  pub fn new(clip: bool, x_: Rc<AVar<AData<A>>>, target_: Rc<AVar<AData<A>>>, /*clk_horizon: usize,*/ loss_alloc: Rc<Fn(TxnId, NodeId) -> Loss>) -> Rc<LstSqLoss<A, Loss>> {
+    let node = NodeId::new();
+    let x = x_.data();
+    let target = target_.data();
+    let loss = AData::new(/*clk_horizon,*/ loss_alloc.clone());
+    Rc::new(LstSqLoss{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 2),
+      x_:       x_,
+      target_:  target_,
+      x:        x,
+      target:   target,
+      loss:     loss,
+      clip:     clip,
+    })
   }
+}
 
-  fn _set_source(&self, txn: TxnId) {
-    let node = self.node;
-    if self.x.grad.overwrite(txn, node) {
-      *self.x.grad.get_excl(txn, node) = 1.0;
-    }
+impl<A, Loss> AVar<AData<Loss>> for LstSqLoss<A, Loss> where LstSqLoss<A, Loss>: AOp {
+  default fn _owned_data(&self) -> &AData<Loss> {
+    &self.loss
   }
 }
 
-pub trait GradientExt<A> {
-  fn gradient(x_: Self) -> Rc<GradientSink<A>>;
+impl<Op, Target> LstSqLossExt<Op, Target> for LstSqLoss<Batch<f32>, Batch<f32>> where Op: 'static + AVar<AData<Batch<f32>>>, Target: 'static + AVar<AData<Batch<f32>>> {
+  fn lst_sq_loss(huber_clip: bool, x_: Rc<Op>, target_: Rc<Target>) -> Rc<Self> {
+    let x = x_.data();
+    //let clk_horizon = x.horizon();
+    LstSqLoss::new(huber_clip, x_.clone(), target_.clone(), /*clk_horizon,*/ {
+      Rc::new(move |txn, node| {
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let mut loss = Batch::new();
+        loss.set_batch_size(batch_sz, 0.0);
+        loss
+      })
+    })
+  }
 }
 
-impl<A> GradientExt<A> for Rc<AVar<AData<A>>> {
-  fn gradient(x_: Rc<AVar<AData<A>>>) -> Rc<GradientSink<A>> {
-    GradientSink::new(x_)
+impl<Op, Target, S> LstSqLossExt<Op, Target> for LstSqLoss<BatchArray1d<f32, S>, Batch<f32>> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, Target: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> {
+  fn lst_sq_loss(huber_clip: bool, x_: Rc<Op>, target_: Rc<Target>) -> Rc<Self> {
+    let x = x_.data();
+    //let clk_horizon = x.horizon();
+    LstSqLoss::new(huber_clip, x_.clone(), target_.clone(), /*clk_horizon,*/ {
+      Rc::new(move |txn, node| {
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let mut loss = Batch::new();
+        loss.set_batch_size(batch_sz, 0.0);
+        loss
+      })
+    })
   }
 }
 
-impl<A, Op> GradientExt<A> for Rc<Op> where Op: 'static + AVar<AData<A>> {
-  fn gradient(x_: Rc<Op>) -> Rc<GradientSink<A>> {
-    GradientSink::new(x_)
+/// The number of entries per example of a packed array; zero for an empty
+/// batch.
+fn _example_len(x_dim: &[usize; 4], batch_sz: usize) -> usize {
+  match batch_sz {
+    0 => 0,
+    _ => x_dim.iter().product::<usize>() / batch_sz,
   }
 }
 
-pub struct GradientSink<A> {
-  node: NodeId,
-  x_:   Rc<AVar<AData<A>>>,
-  x:    AData<A>,
-}
+impl<A> AOp for LstSqLoss<A, Batch<f32>> where A: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
 
-impl<A> GradientSink<A> {
-  pub fn new(x_: Rc<AVar<AData<A>>>) -> Rc<Self> {
-    let node = NodeId::new();
-    let x = x_.data();
-    Rc::new(GradientSink{
-      node: node,
-      x_:   x_,
-      x:    x,
-    })
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      self.target_._push(epoch, apply);
+      apply(self);
+    }
   }
-}
 
-impl GradientSinkExt for GradientSink<f32> {
-  fn eval_gradient(&self, txn: TxnId) {
-    self.x_._traverse_fwd(&mut |op| { op._forward(txn); });
-    {
-      let node = self.node;
-      if self.x.grad.overwrite(txn, node) {
-        *self.x.grad.get_excl(txn, node) = 1.0;
-      }
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.target_._pop(epoch, apply);
+      self.x_._pop(epoch, apply);
     }
-    self.x_._traverse_bwd(&mut |op| { op._backward(txn); });
   }
-}
 
-pub trait HessianExt<A> {
-  fn hessian(x_: Self) -> Rc<HessianSink<A>>;
-}
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.loss.rollover_all(txn, vars);
+  }
 
-impl<A> HessianExt<A> for Rc<AVar<AData<A>>> {
-  fn hessian(x_: Rc<AVar<AData<A>>>) -> Rc<HessianSink<A>> {
-    HessianSink::new(x_)
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    assert_eq!(x_dim, self.target.val.get(txn, node)._packed_dim());
+    if self.loss.val.overwrite(txn, node) {
+      self.loss.val.get_excl(txn, node).set_batch_size(batch_sz, 0.0);
+      unsafe { arraydiff_kernel_lst_sq_fwd_f32(
+          _example_len(&x_dim, batch_sz),
+          batch_sz,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.target.val.get(txn, node)._as_ptr(),
+          self.loss.val.get_excl(txn, node)._as_mut_ptr(),
+          match self.clip {
+            false => 0,
+            true  => 1,
+          },
+      ) };
+    }
   }
-}
 
-impl<A, Op> HessianExt<A> for Rc<Op> where Op: 'static + AVar<AData<A>> {
-  fn hessian(x_: Rc<Op>) -> Rc<HessianSink<A>> {
-    HessianSink::new(x_)
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { arraydiff_kernel_lst_sq_bwd_f32(
+          _example_len(&x_dim, batch_sz),
+          batch_sz,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.target.val.get(txn, node)._as_ptr(),
+          self.loss.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+          match self.clip {
+            false => 0,
+            true  => 1,
+          },
+      ) };
+    }
   }
-}
 
-pub struct HessianSink<A> {
-  node: NodeId,
-  x_:       Rc<AVar<AData<A>>>,
-  x_tng_:   Rc<AVar<AData<A>>>,
-  x:        AData<A>,
-  x_tng:    AData<A>,
-}
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
 
-impl<A> HessianSink<A> {
-  pub fn new(x_: Rc<AVar<AData<A>>>) -> Rc<Self> {
-    let node = NodeId::new();
-    let x_tng_ = x_.tangent();
-    let x = x_.data();
-    let x_tng = x_tng_.data();
-    Rc::new(HessianSink{
-      node: node,
-      x_:       x_,
-      x_tng_:   x_tng_,
-      x:        x,
-      x_tng:    x_tng,
-    })
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.loss.r_val.overwrite(txn, node) {
+      self.loss.r_val.get_excl(txn, node).set_batch_size(batch_sz, 0.0);
+      unsafe { arraydiff_kernel_lst_sq_rfwd_f32(
+          _example_len(&x_dim, batch_sz),
+          batch_sz,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.target.val.get(txn, node)._as_ptr(),
+          self.x.r_val.get(txn, node)._as_ptr(),
+          self.target.r_val.get(txn, node)._as_ptr(),
+          self.loss.r_val.get_excl(txn, node)._as_mut_ptr(),
+          match self.clip {
+            false => 0,
+            true  => 1,
+          },
+      ) };
+    }
   }
-}
 
-impl HessianSinkExt for HessianSink<f32> {
-  fn eval_hessian_vector_product(&self, txn: TxnId) {
-    self.x_._traverse_fwd(&mut |op| { op._forward(txn); });
-    self.x_tng_._traverse_fwd(&mut |op| { op._forward(txn); });
-    {
-      let node = self.node;
-      if self.x.grad.overwrite(txn, node) {
-        *self.x.grad.get_excl(txn, node) = 1.0;
-      }
+  fn _backward_gauss_newton(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { arraydiff_kernel_lst_sq_gauss_newton_bwd_f32(
+          _example_len(&x_dim, batch_sz),
+          batch_sz,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.target.val.get(txn, node)._as_ptr(),
+          self.x.r_val.get(txn, node)._as_ptr(),
+          self.loss.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+          match self.clip {
+            false => 0,
+            true  => 1,
+          },
+      ) };
     }
-    {
-      let node = self.node;
-      if self.x_tng.grad.overwrite(txn, node) {
-        *self.x_tng.grad.get_excl(txn, node) = 0.0;
-      }
+  }
+
+  fn _backward_empirical_fisher(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { arraydiff_kernel_lst_sq_empirical_fisher_bwd_f32(
+          _example_len(&x_dim, batch_sz),
+          batch_sz,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.target.val.get(txn, node)._as_ptr(),
+          self.x.r_val.get(txn, node)._as_ptr(),
+          self.loss.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+          match self.clip {
+            false => 0,
+            true  => 1,
+          },
+      ) };
     }
-    self.x_._traverse_bwd(&mut |op| { op._backward(txn); });
-    self.x_tng_._traverse_bwd(&mut |op| { op._backward(txn); });
   }
-}
 
-impl GaussNewtonSinkExt for HessianSink<Batch<f32>> {
-  fn eval_gauss_newton_vector_product(&self, txn: TxnId) {
-    self.x_._traverse_fwd(&mut |op| { op._forward(txn); });
-    self.x_tng_._traverse_fwd(&mut |op| { op._forward(txn); });
-    {
-      let node = self.node;
-      if self.x.grad.overwrite(txn, node) {
-        let batch_sz = self.x.val.get(txn, node).batch_size();
-        assert_eq!(batch_sz, self.x_tng.val.get(txn, node).batch_size());
-        self.x.grad.get_excl(txn, node).set_batch_size(batch_sz, 0.0);
-        for idx in 0 .. batch_sz {
-          self.x.grad.get_excl(txn, node)[idx] = self.x_tng.val.get(txn, node)[idx];
-        }
-      }
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.x.grad2.accumulate(txn, node, |grad2| { grad2._set_batch_size(batch_sz); grad2._set_zero(); }) {
+      unsafe { arraydiff_kernel_lst_sq_bwd2_f32(
+          _example_len(&x_dim, batch_sz),
+          batch_sz,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.target.val.get(txn, node)._as_ptr(),
+          self.loss.grad.get(txn, node)._as_ptr(),
+          self.loss.grad2.get(txn, node)._as_ptr(),
+          self.x.grad2.get_mut(txn, node)._as_mut_ptr(),
+          match self.clip {
+            false => 0,
+            true  => 1,
+          },
+      ) };
     }
-    self.x_._traverse_bwd(&mut |op| { op._backward(txn); });
   }
 }
 
-pub trait LstSqLossExt<Op, Target> {
-  fn lst_sq_loss(huber_clip: bool, x_: Rc<Op>, target_: Rc<Target>) -> Rc<Self> where Self: 'static + Sized;
-}
+impl<A> AVar<AData<Batch<f32>>> for LstSqLoss<A, Batch<f32>> where A: 'static + PackedArray {
+  fn _owned_data(&self) -> &AData<Batch<f32>> {
+    &self.loss
+  }
 
-pub fn lst_sq_loss<Op, Target, A, Loss>(huber_clip: bool, x_: Rc<Op>, t_: Rc<Target>) -> Rc<LstSqLoss<A, Loss>> where Op: 'static + AVar<AData<A>>, Target: 'static + AVar<AData<A>>, A: 'static, Loss: 'static, LstSqLoss<A, Loss>: LstSqLossExt<Op, Target> {
-  <LstSqLoss<A, Loss> as LstSqLossExt<Op, Target>>::lst_sq_loss(huber_clip, x_, t_)
+  fn _adjoint(&self, adj_: Rc<AVar<AData<Batch<f32>>>>, adjs: &mut AdjointMap) {
+    // Like `_backward`, the target is treated as a constant.
+    let x_adj_: Rc<LstSqAdjointLoss<A>> = LstSqAdjointLoss::new(self.clip, self.x_.clone(), self.target_.clone(), adj_, /*clk_horizon,*/ self.x.alloc.clone());
+    adjs.accumulate(self.x_.clone(), x_adj_);
+  }
 }
 
-pub struct LstSqLoss<A, Loss> {
+/// The adjoint of `LstSqLoss` with respect to its input, i.e. the gradient
+/// `y = adj (x - target)` (clipped) weighted by the adjoint of each loss.
+pub struct LstSqAdjointLoss<A> {
   node_id:  NodeId,
   stack:    OperatorStack,
   x_:       Rc<AVar<AData<A>>>,
   target_:  Rc<AVar<AData<A>>>,
+  adj_:     Rc<AVar<AData<Batch<f32>>>>,
   x:        AData<A>,
   target:   AData<A>,
-  loss:     AData<Loss>,
+  adj:      AData<Batch<f32>>,
+  y:        AData<A>,
   clip:     bool,
 }
 
-impl<A, Loss> LstSqLoss<A, Loss> {
-  pub fn new(clip: bool, x_: Rc<AVar<AData<A>>>, target_: Rc<AVar<AData<A>>>, /*clk_horizon: usize,*/ loss_alloc: Rc<Fn(TxnId, NodeId) -> Loss>) -> Rc<LstSqLoss<A, Loss>> {
+impl<A> LstSqAdjointLoss<A> {
+  pub fn new(clip: bool, x_: Rc<AVar<AData<A>>>, target_: Rc<AVar<AData<A>>>, adj_: Rc<AVar<AData<Batch<f32>>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<Self> {
     let node = NodeId::new();
     let x = x_.data();
     let target = target_.data();
-    let loss = AData::new(/*clk_horizon,*/ loss_alloc.clone());
-    Rc::new(LstSqLoss{
+    let adj = adj_.data();
+    Rc::new(LstSqAdjointLoss{
       node_id:  node,
-      stack:    OperatorStack::new(node, 2),
+      stack:    OperatorStack::new(node, 3),
       x_:       x_,
       target_:  target_,
+      adj_:     adj_,
       x:        x,
       target:   target,
-      loss:     loss,
+      adj:      adj,
+      y:        AData::new(/*clk_horizon,*/ alloc),
       clip:     clip,
     })
   }
 }
 
-impl<A, Loss> AVar<AData<Loss>> for LstSqLoss<A, Loss> where LstSqLoss<A, Loss>: AOp {
-  default fn _owned_data(&self) -> &AData<Loss> {
-    &self.loss
+impl<A> AVar<AData<A>> for LstSqAdjointLoss<A> where LstSqAdjointLoss<A>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+}
+
+impl<A> AOp for LstSqAdjointLoss<A> where A: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      self.target_._push(epoch, apply);
+      self.adj_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.adj_._pop(epoch, apply);
+      self.target_._pop(epoch, apply);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.y.val.overwrite(txn, node) {
+      let x_dim = self.x.val.get(txn, node)._packed_dim();
+      let batch_sz = self.x.val.get(txn, node)._batch_size();
+      assert_eq!(batch_sz, self.adj.val.get(txn, node).batch_size());
+      self.y.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      self.y.val.get_excl(txn, node)._set_zero();
+      unsafe { arraydiff_kernel_lst_sq_bwd_f32(
+          _example_len(&x_dim, batch_sz),
+          batch_sz,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.target.val.get(txn, node)._as_ptr(),
+          self.adj.val.get(txn, node)._as_ptr(),
+          self.y.val.get_excl(txn, node)._as_mut_ptr(),
+          match self.clip {
+            false => 0,
+            true  => 1,
+          },
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    // The derivative w.r.t. `x` is the Hessian of the loss, which is exactly
+    // the Gauss-Newton product of the least squares loss.
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { arraydiff_kernel_lst_sq_gauss_newton_bwd_f32(
+          _example_len(&x_dim, batch_sz),
+          batch_sz,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.target.val.get(txn, node)._as_ptr(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          self.adj.val.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+          match self.clip {
+            false => 0,
+            true  => 1,
+          },
+      ) };
+    }
+    if self.adj.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      let len = x_dim.iter().product();
+      let zeros: Vec<f32> = vec![0.0; len];
+      let mut tmp: Vec<f32> = vec![0.0; batch_sz];
+      unsafe { arraydiff_kernel_lst_sq_rfwd_f32(
+          _example_len(&x_dim, batch_sz),
+          batch_sz,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.target.val.get(txn, node)._as_ptr(),
+          self.y.grad.get(txn, node)._as_ptr(),
+          zeros.as_ptr(),
+          tmp.as_mut_ptr(),
+          match self.clip {
+            false => 0,
+            true  => 1,
+          },
+      ) };
+      unsafe { arraydiff_kernel_add_f32(
+          batch_sz,
+          tmp.as_ptr(),
+          self.adj.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
   }
 }
 
@@ -3799,7 +10740,126 @@ pub struct SoftmaxOp<A> {
   x_:       Rc<AVar<AData<A>>>,
   x:        AData<A>,
   prob:     AData<A>,
-  tng:      RefCell<Option<Rc<AVar<AData<A>>>>>,
+}
+
+impl<A> SoftmaxOp<A> {
+  pub fn new(x_: Rc<AVar<AData<A>>>, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<Self> {
+    let node = NodeId::new();
+    let x = x_.data();
+    Rc::new(SoftmaxOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 1),
+      x_:       x_,
+      x:        x,
+      prob:     AData::new(/*clk_horizon,*/ alloc),
+    })
+  }
+}
+
+impl<A> AVar<AData<A>> for SoftmaxOp<A> where SoftmaxOp<A>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.prob
+  }
+}
+
+/// Softmax along the first (contiguous) axis, e.g. over the features of a
+/// `BatchArray1d` or over each column of a `BatchArray3d`.
+pub trait SoftmaxExt<A> {
+  fn softmax(&self) -> Rc<SoftmaxOp<A>>;
+}
+
+impl<Op, S> SoftmaxExt<BatchArray1d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn softmax(&self) -> Rc<SoftmaxOp<BatchArray1d<f32, S>>> {
+    //let clk_horizon = self.data().horizon();
+    SoftmaxOp::new(self.clone(), /*clk_horizon,*/ _batch_array1d_map_alloc(self.data()))
+  }
+}
+
+impl<Op, S> SoftmaxExt<BatchArray3d<f32, S>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray3d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn softmax(&self) -> Rc<SoftmaxOp<BatchArray3d<f32, S>>> {
+    //let clk_horizon = self.data().horizon();
+    SoftmaxOp::new(self.clone(), /*clk_horizon,*/ _batch_array3d_map_alloc(self.data()))
+  }
+}
+
+impl<A> AOp for SoftmaxOp<A> where A: PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.x_._pop(epoch, apply);
+    }
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.prob.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    let num_rows = x_dim[1 .. ].iter().product();
+    if self.prob.val.overwrite(txn, node) {
+      self.prob.val.get_excl(txn, node)._set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_softmax_fwd_f32(
+          x_dim[0],
+          num_rows,
+          self.x.val.get(txn, node)._as_ptr(),
+          self.prob.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    let num_rows = x_dim[1 .. ].iter().product();
+    if self.x.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      unsafe { arraydiff_kernel_softmax_bwd_f32(
+          x_dim[0],
+          num_rows,
+          self.prob.val.get(txn, node)._as_ptr(),
+          self.prob.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, txn: TxnId) {
+    let node = self._id();
+    let x_dim = self.x.val.get(txn, node)._packed_dim();
+    let batch_sz = self.x.val.get(txn, node)._batch_size();
+    let num_rows = x_dim[1 .. ].iter().product();
+    if self.prob.r_val.overwrite(txn, node) {
+      // The softmax Jacobian is symmetric, so the backward kernel applied to
+      // `r_x` gives the forward directional derivative.
+      self.prob.r_val.get_excl(txn, node)._set_batch_size(batch_sz);
+      self.prob.r_val.get_excl(txn, node)._set_zero();
+      unsafe { arraydiff_kernel_softmax_bwd_f32(
+          x_dim[0],
+          num_rows,
+          self.prob.val.get(txn, node)._as_ptr(),
+          self.x.r_val.get(txn, node)._as_ptr(),
+          self.prob.r_val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
+  }
 }
 
 #[derive(Clone, Copy)]
@@ -3820,9 +10880,16 @@ pub enum LRClip {
 #[derive(Clone, Copy)]
 pub struct LRLink{pub lr_clip: LRClip}
 
+#[derive(Clone, Copy)]
 pub struct KL1LossLink;
+
+#[derive(Clone, Copy)]
 pub struct KL2LossLink;
+
+#[derive(Clone, Copy)]
 pub struct LRLossLink;
+
+#[derive(Clone, Copy)]
 pub struct NLLLossLink;
 
 pub trait LikelihoodLossLink {}
@@ -4155,6 +11222,7 @@ pub struct SoftmaxLoss<A, Target, Loss, Link> {
   factor:       AData<A>,
   max_factor:   AData<Loss>,*/
   link:     Link,
+  tng:      RefCell<Option<Rc<AVar<()>>>>,
 }
 
 impl<A, Target, Loss, Link> SoftmaxLoss<A, Target, Loss, Link> where SoftmaxLoss<A, Target, Loss, Link>: AOp, A: 'static, Target: 'static, Loss: 'static, Link: 'static {
@@ -4185,9 +11253,11 @@ impl<A, Target, Loss, Link> SoftmaxLoss<A, Target, Loss, Link> where SoftmaxLoss
       loss:     loss, //AData::new(/*clk_horizon,*/ alloc.clone()),
       //logit:    AData::new(1, alloc.clone()),
       link:     link,
+      tng:      RefCell::new(None),
     });
     *prob_.x_.borrow_mut() = Some(AVar::from(softmax.clone()));
     *loss_.x_.borrow_mut() = Some(AVar::from(softmax.clone()));
+    *loss_.adj.borrow_mut() = softmax._adjoint_hook(prob_.clone());
     (softmax, prob_, loss_)
   }
 
@@ -4200,29 +11270,227 @@ impl<A, Target, Loss, Link> SoftmaxLoss<A, Target, Loss, Link> where SoftmaxLoss
   }*/
 }
 
-impl<A, T, Loss, Link> AVar<()> for SoftmaxLoss<A, T, Loss, Link>
-where A: 'static, T: 'static, Loss: 'static, Link: 'static,
-      Self: AOp,
-{
-  default fn _owned_data(&self) -> &() {
-    unreachable!();
+impl<A, T, Loss, Link> AVar<()> for SoftmaxLoss<A, T, Loss, Link>
+where A: 'static, T: 'static, Loss: 'static, Link: 'static,
+      Self: AOp,
+{
+  default fn _owned_data(&self) -> &() {
+    unreachable!();
+  }
+
+  default fn data(&self) -> () {
+    ()
+  }
+
+  default fn vars(&self) -> VarSet {
+    var_set()
+  }
+
+  default fn tangent(&self) -> Rc<AVar<()>> {
+    if self.tng.borrow().is_none() {
+      *self.tng.borrow_mut() = Some(self._make_tangent());
+    }
+    self.tng.borrow().as_ref().unwrap().clone()
+  }
+}
+
+impl<S, T, Link> AVar<()> for SoftmaxLoss<BatchArray1d<f32, S>, T, Batch<f32>, Link>
+where S: 'static + DerefMut<Target=[f32]>, T: 'static, Link: 'static + SoftmaxLossKernel<T> + Copy,
+      Self: AOp,
+      SoftmaxTangentLoss<BatchArray1d<f32, S>, T, Batch<f32>, Link>: AOp,
+{
+  fn _make_tangent(&self) -> Rc<AVar<()>> {
+    let x_tng_ = self.x_.tangent();
+    let (softmax_tng_, prob_tng_, loss_tng_) = SoftmaxTangentLoss::new(
+        self.x_.clone(), x_tng_, self.target_.clone(), self.prob.clone(), self.link,
+        /*clk_horizon,*/ self.prob.alloc.clone(), self.loss.alloc.clone());
+    // The tangents of the output pass ops are the outputs of the tangent op,
+    // so install them before the pass ops get around to making their own.
+    if let Some(prob_) = Weak::upgrade(&self.prob_) {
+      *prob_.tng.borrow_mut() = Some(prob_tng_);
+    }
+    if let Some(loss_) = Weak::upgrade(&self.loss_) {
+      *loss_.tng.borrow_mut() = Some(loss_tng_);
+    }
+    softmax_tng_
+  }
+}
+
+/// Makes the adjoint of the loss output of a `SoftmaxLoss`, which is installed
+/// on the output pass op. The probabilities output has no adjoint.
+trait SoftmaxLossAdjointHook<A, Loss> {
+  fn _adjoint_hook(&self, prob_: Rc<PassOp<(), A>>) -> Option<Rc<Fn(Rc<AVar<AData<Loss>>>, &mut AdjointMap)>>;
+}
+
+impl<A, T, Loss, Link> SoftmaxLossAdjointHook<A, Loss> for SoftmaxLoss<A, T, Loss, Link> {
+  default fn _adjoint_hook(&self, _prob_: Rc<PassOp<(), A>>) -> Option<Rc<Fn(Rc<AVar<AData<Loss>>>, &mut AdjointMap)>> {
+    None
+  }
+}
+
+impl<S, T, Link> SoftmaxLossAdjointHook<BatchArray1d<f32, S>, Batch<f32>> for SoftmaxLoss<BatchArray1d<f32, S>, T, Batch<f32>, Link>
+where S: 'static + DerefMut<Target=[f32]>, T: 'static, Link: 'static + SoftmaxLossKernel<T> + Copy,
+{
+  fn _adjoint_hook(&self, prob_: Rc<PassOp<(), BatchArray1d<f32, S>>>) -> Option<Rc<Fn(Rc<AVar<AData<Batch<f32>>>>, &mut AdjointMap)>> {
+    // The hook lives in the loss pass op, so it may hold the probabilities
+    // pass op (and through it this op) without a cycle.
+    let x_ = self.x_.clone();
+    let target_ = self.target_.clone();
+    let link = self.link;
+    let alloc = self.x.alloc.clone();
+    Some(Rc::new(move |adj_: Rc<AVar<AData<Batch<f32>>>>, adjs: &mut AdjointMap| {
+      let x_adj_: Rc<SoftmaxAdjointLoss<BatchArray1d<f32, S>, T, Link>> = SoftmaxAdjointLoss::new(x_.clone(), prob_.clone(), target_.clone(), adj_, link, /*clk_horizon,*/ alloc.clone());
+      adjs.accumulate(x_.clone(), x_adj_);
+    }))
+  }
+}
+
+/// The adjoint of the loss of a `SoftmaxLoss` with respect to its input, i.e.
+/// the gradient of the loss w.r.t. the softmax input weighted by the adjoint
+/// of each loss. The probabilities are read from the primal op.
+pub struct SoftmaxAdjointLoss<A, Target, Link> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  x_:       Rc<AVar<AData<A>>>,
+  prob_:    Rc<AVar<AData<A>>>,
+  target_:  Option<Rc<AVar<AData<Target>>>>,
+  adj_:     Rc<AVar<AData<Batch<f32>>>>,
+  x:        AData<A>,
+  prob:     AData<A>,
+  target:   Option<AData<Target>>,
+  adj:      AData<Batch<f32>>,
+  y:        AData<A>,
+  link:     Link,
+}
+
+impl<A, Target, Link> SoftmaxAdjointLoss<A, Target, Link> {
+  pub fn new(x_: Rc<AVar<AData<A>>>, prob_: Rc<AVar<AData<A>>>, target_: Option<Rc<AVar<AData<Target>>>>, adj_: Rc<AVar<AData<Batch<f32>>>>, link: Link, /*clk_horizon: usize,*/ alloc: Rc<Fn(TxnId, NodeId) -> A>) -> Rc<Self> {
+    let node = NodeId::new();
+    let in_degree = match target_ {
+      None      => 3,
+      Some(_)   => 4,
+    };
+    let x = x_.data();
+    let prob = prob_.data();
+    let target = target_.as_ref().map(|t_| t_.data());
+    let adj = adj_.data();
+    Rc::new(SoftmaxAdjointLoss{
+      node_id:  node,
+      stack:    OperatorStack::new(node, in_degree),
+      x_:       x_,
+      prob_:    prob_,
+      target_:  target_,
+      adj_:     adj_,
+      x:        x,
+      prob:     prob,
+      target:   target,
+      adj:      adj,
+      y:        AData::new(/*clk_horizon,*/ alloc),
+      link:     link,
+    })
+  }
+}
+
+impl<A, Target, Link> AVar<AData<A>> for SoftmaxAdjointLoss<A, Target, Link> where SoftmaxAdjointLoss<A, Target, Link>: AOp {
+  default fn _owned_data(&self) -> &AData<A> {
+    &self.y
+  }
+}
+
+impl<S, T, Link> AOp for SoftmaxAdjointLoss<BatchArray1d<f32, S>, T, Link> where S: DerefMut<Target=[f32]>, Link: SoftmaxLossKernel<T> {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.x_._push(epoch, apply);
+      self.prob_._push(epoch, apply);
+      if let Some(ref target_) = self.target_ {
+        target_._push(epoch, apply);
+      }
+      self.adj_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.adj_._pop(epoch, apply);
+      if let Some(ref target_) = self.target_ {
+        target_._pop(epoch, apply);
+      }
+      self.prob_._pop(epoch, apply);
+      self.x_._pop(epoch, apply);
+    }
   }
 
-  default fn data(&self) -> () {
-    ()
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.y.rollover_all(txn, vars);
   }
 
-  default fn vars(&self) -> VarSet {
-    var_set()
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    let target = match self.target {
+      None            => panic!("SoftmaxAdjointLoss requires a target"),
+      Some(ref target) => target,
+    };
+    if self.y.val.overwrite(txn, node) {
+      let x_dim = self.prob.val.get(txn, node).dim();
+      let batch_sz = self.prob.val.get(txn, node).batch_size();
+      assert_eq!(batch_sz, self.adj.val.get(txn, node).batch_size());
+      self.y.val.get_excl(txn, node).set_batch_size(batch_sz);
+      self.y.val.get_excl(txn, node).as_view_mut().set_constant(0.0);
+      unsafe { self.link._loss_bwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          &*target.val.get(txn, node),
+          self.adj.val.get(txn, node)._as_ptr(),
+          self.y.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
   }
 
-  default fn tangent(&self) -> Rc<AVar<()>> {
-    // FIXME
-    unimplemented!();
-    /*if self.tng.borrow().is_none() {
-      *self.tng.borrow_mut() = Some(self._make_tangent());
+  fn _backward(&self, txn: TxnId) {
+    // The derivative w.r.t. the softmax input is the Hessian of the loss,
+    // which is what the Gauss-Newton kernels multiply by; the derivative
+    // w.r.t. the adjoint is the directional derivative of the loss.
+    let node = self._id();
+    let target = match self.target {
+      None            => panic!("SoftmaxAdjointLoss requires a target"),
+      Some(ref target) => target,
+    };
+    let x_dim = self.prob.val.get(txn, node).dim();
+    let batch_sz = self.prob.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad.set_batch_size(batch_sz); grad.as_view_mut().set_constant(0.0); }) {
+      unsafe { self.link._loss_gauss_newton_bwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          &*target.val.get(txn, node),
+          self.y.grad.get(txn, node).as_view().as_ptr(),
+          self.adj.val.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
+    if self.adj.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      let mut tmp: Vec<f32> = vec![0.0; batch_sz];
+      unsafe { self.link._loss_tangent_fwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          &*target.val.get(txn, node),
+          self.y.grad.get(txn, node).as_view().as_ptr(),
+          tmp.as_mut_ptr(),
+      ) };
+      unsafe { arraydiff_kernel_add_f32(
+          batch_sz,
+          tmp.as_ptr(),
+          self.adj.grad.get_mut(txn, node)._as_mut_ptr(),
+      ) };
     }
-    self.tng.borrow().as_ref().unwrap().clone()*/
   }
 }
 
@@ -4274,7 +11542,157 @@ pub fn softmax_nll_loss<Op, A, Target, L>(x_: Rc<Op>, target_: Rc<AVar<AData<Tar
   }
 }*/
 
-impl<S> AOp for SoftmaxLoss<BatchArray1d<f32, S>, BatchArray1d<f32, S>, Batch<f32>, KL2LossLink> where S: DerefMut<Target=[f32]> {
+/// Per-link loss kernels of `SoftmaxLoss`, given the softmax output `y`.
+pub trait SoftmaxLossKernel<Target> {
+  /// Computes the loss of each batch row.
+  unsafe fn _loss_fwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Target, loss: *mut f32);
+  /// Accumulates the gradient of the loss w.r.t. the softmax input.
+  unsafe fn _loss_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Target, df: *const f32, dx: *mut f32);
+  /// Computes the directional derivative of the loss along `x_tng`.
+  unsafe fn _loss_tangent_fwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Target, x_tng: *const f32, loss_tng: *mut f32);
+  /// Accumulates the gradient of the directional derivative w.r.t. the
+  /// softmax input, where `y_tng = J(y) x_tng`.
+  unsafe fn _loss_tangent_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Target, x_tng: *const f32, y_tng: *const f32, dloss_tng: *const f32, dx: *mut f32);
+  /// Accumulates `df H r_x`, for the Hessian `H` of the loss w.r.t. the
+  /// softmax input.
+  unsafe fn _loss_gauss_newton_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Target, r_x: *const f32, df: *const f32, dx: *mut f32);
+  /// Accumulates `df g <g, r_x>`, for the gradient `g` of the loss w.r.t. the
+  /// softmax input.
+  unsafe fn _loss_empirical_fisher_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Target, r_x: *const f32, df: *const f32, dx: *mut f32);
+  /// Accumulates `df2 g^2 + df h` into `dx2`, for the gradient `g` and the
+  /// Hessian diagonal `h` of the loss w.r.t. the softmax input.
+  unsafe fn _loss_bwd2_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Target, df: *const f32, df2: *const f32, dx2: *mut f32);
+}
+
+impl<S> SoftmaxLossKernel<BatchArray1d<f32, S>> for KL2LossLink where S: DerefMut<Target=[f32]> {
+  unsafe fn _loss_fwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &BatchArray1d<f32, S>, loss: *mut f32) {
+    arraydiff_kernel_softmax_kl2_loss_fwd_f32(dim, batch_sz, y, target.as_view().as_ptr(), loss);
+  }
+
+  unsafe fn _loss_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &BatchArray1d<f32, S>, df: *const f32, dx: *mut f32) {
+    arraydiff_kernel_softmax_kl2_loss_bwd_f32(dim, batch_sz, y, target.as_view().as_ptr(), df, dx);
+  }
+
+  unsafe fn _loss_tangent_fwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &BatchArray1d<f32, S>, x_tng: *const f32, loss_tng: *mut f32) {
+    arraydiff_kernel_softmax_kl2_loss_tangent_fwd_f32(dim, batch_sz, y, target.as_view().as_ptr(), x_tng, loss_tng);
+  }
+
+  unsafe fn _loss_tangent_bwd_f32(&self, dim: usize, batch_sz: usize, _y: *const f32, _target: &BatchArray1d<f32, S>, _x_tng: *const f32, y_tng: *const f32, dloss_tng: *const f32, dx: *mut f32) {
+    arraydiff_kernel_softmax_kl2_loss_tangent_bwd_f32(dim, batch_sz, y_tng, dloss_tng, dx);
+  }
+
+  unsafe fn _loss_gauss_newton_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, _target: &BatchArray1d<f32, S>, r_x: *const f32, df: *const f32, dx: *mut f32) {
+    arraydiff_kernel_softmax_gauss_newton_bwd_f32(dim, batch_sz, y, r_x, df, dx);
+  }
+
+  unsafe fn _loss_empirical_fisher_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &BatchArray1d<f32, S>, r_x: *const f32, df: *const f32, dx: *mut f32) {
+    arraydiff_kernel_softmax_kl2_loss_empirical_fisher_bwd_f32(dim, batch_sz, y, target.as_view().as_ptr(), r_x, df, dx);
+  }
+
+  unsafe fn _loss_bwd2_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &BatchArray1d<f32, S>, df: *const f32, df2: *const f32, dx2: *mut f32) {
+    arraydiff_kernel_softmax_kl2_loss_bwd2_f32(dim, batch_sz, y, target.as_view().as_ptr(), df, df2, dx2);
+  }
+}
+
+impl SoftmaxLossKernel<Batch<u32>> for NLLLossLink {
+  unsafe fn _loss_fwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Batch<u32>, loss: *mut f32) {
+    arraydiff_kernel_softmax_nll_loss_fwd_f32(dim, batch_sz, y, target.reshape(batch_sz).as_ptr(), loss);
+  }
+
+  unsafe fn _loss_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Batch<u32>, df: *const f32, dx: *mut f32) {
+    arraydiff_kernel_softmax_nll_loss_bwd_f32(dim, batch_sz, y, target.reshape(batch_sz).as_ptr(), df, dx);
+  }
+
+  unsafe fn _loss_tangent_fwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Batch<u32>, x_tng: *const f32, loss_tng: *mut f32) {
+    arraydiff_kernel_softmax_nll_loss_tangent_fwd_f32(dim, batch_sz, y, target.reshape(batch_sz).as_ptr(), x_tng, loss_tng);
+  }
+
+  unsafe fn _loss_tangent_bwd_f32(&self, dim: usize, batch_sz: usize, _y: *const f32, _target: &Batch<u32>, _x_tng: *const f32, y_tng: *const f32, dloss_tng: *const f32, dx: *mut f32) {
+    arraydiff_kernel_softmax_nll_loss_tangent_bwd_f32(dim, batch_sz, y_tng, dloss_tng, dx);
+  }
+
+  unsafe fn _loss_gauss_newton_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, _target: &Batch<u32>, r_x: *const f32, df: *const f32, dx: *mut f32) {
+    arraydiff_kernel_softmax_gauss_newton_bwd_f32(dim, batch_sz, y, r_x, df, dx);
+  }
+
+  unsafe fn _loss_empirical_fisher_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Batch<u32>, r_x: *const f32, df: *const f32, dx: *mut f32) {
+    arraydiff_kernel_softmax_nll_loss_empirical_fisher_bwd_f32(dim, batch_sz, y, target.reshape(batch_sz).as_ptr(), r_x, df, dx);
+  }
+
+  unsafe fn _loss_bwd2_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Batch<u32>, df: *const f32, df2: *const f32, dx2: *mut f32) {
+    arraydiff_kernel_softmax_nll_loss_bwd2_f32(dim, batch_sz, y, target.reshape(batch_sz).as_ptr(), df, df2, dx2);
+  }
+}
+
+/// Splits likelihood ratio targets into the label indices and the
+/// probabilities of the behavior distribution.
+fn _unzip_lr_target(target: &Batch<(u32, f32)>, batch_sz: usize) -> (Vec<u32>, Vec<f32>) {
+  let mut index = Vec::with_capacity(batch_sz);
+  let mut t = Vec::with_capacity(batch_sz);
+  for idx in 0 .. batch_sz {
+    let (index_i, t_i) = target[idx];
+    index.push(index_i);
+    t.push(t_i);
+  }
+  (index, t)
+}
+
+impl SoftmaxLossKernel<Batch<(u32, f32)>> for LRLossLink {
+  unsafe fn _loss_fwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Batch<(u32, f32)>, loss: *mut f32) {
+    let (index, t) = _unzip_lr_target(target, batch_sz);
+    arraydiff_kernel_softmax_lr_loss_fwd_f32(dim, batch_sz, y, index.as_ptr(), t.as_ptr(), loss, f32::INFINITY);
+  }
+
+  unsafe fn _loss_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Batch<(u32, f32)>, df: *const f32, dx: *mut f32) {
+    let (index, t) = _unzip_lr_target(target, batch_sz);
+    arraydiff_kernel_softmax_lr_loss_bwd_f32(dim, batch_sz, y, index.as_ptr(), t.as_ptr(), df, dx, f32::INFINITY);
+  }
+
+  unsafe fn _loss_tangent_fwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Batch<(u32, f32)>, x_tng: *const f32, loss_tng: *mut f32) {
+    let (index, t) = _unzip_lr_target(target, batch_sz);
+    arraydiff_kernel_softmax_lr_loss_tangent_fwd_f32(dim, batch_sz, y, index.as_ptr(), t.as_ptr(), x_tng, loss_tng, f32::INFINITY);
+  }
+
+  unsafe fn _loss_tangent_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Batch<(u32, f32)>, x_tng: *const f32, y_tng: *const f32, dloss_tng: *const f32, dx: *mut f32) {
+    let (index, t) = _unzip_lr_target(target, batch_sz);
+    arraydiff_kernel_softmax_lr_loss_tangent_bwd_f32(dim, batch_sz, y, index.as_ptr(), t.as_ptr(), x_tng, y_tng, dloss_tng, dx, f32::INFINITY);
+  }
+
+  unsafe fn _loss_gauss_newton_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Batch<(u32, f32)>, r_x: *const f32, df: *const f32, dx: *mut f32) {
+    // The likelihood ratio is not a convex function of the softmax input, so
+    // this product uses an indefinite loss Hessian.
+    let (index, t) = _unzip_lr_target(target, batch_sz);
+    arraydiff_kernel_softmax_lr_loss_gauss_newton_bwd_f32(dim, batch_sz, y, index.as_ptr(), t.as_ptr(), r_x, df, dx, f32::INFINITY);
+  }
+
+  unsafe fn _loss_empirical_fisher_bwd_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Batch<(u32, f32)>, r_x: *const f32, df: *const f32, dx: *mut f32) {
+    let (index, t) = _unzip_lr_target(target, batch_sz);
+    arraydiff_kernel_softmax_lr_loss_empirical_fisher_bwd_f32(dim, batch_sz, y, index.as_ptr(), t.as_ptr(), r_x, df, dx, f32::INFINITY);
+  }
+
+  unsafe fn _loss_bwd2_f32(&self, dim: usize, batch_sz: usize, y: *const f32, target: &Batch<(u32, f32)>, df: *const f32, df2: *const f32, dx2: *mut f32) {
+    let (index, t) = _unzip_lr_target(target, batch_sz);
+    arraydiff_kernel_softmax_lr_loss_bwd2_f32(dim, batch_sz, y, index.as_ptr(), t.as_ptr(), df, df2, dx2, f32::INFINITY);
+  }
+}
+
+impl<Op, S> SoftmaxNLLLossExt<Op, BatchArray1d<f32, S>, Batch<u32>, Batch<f32>> for Rc<Op> where Op: 'static + AVar<AData<BatchArray1d<f32, S>>>, S: 'static + DerefMut<Target=[f32]> + BatchArrayStorage<usize> {
+  fn softmax_nll_loss(x_: Rc<Op>, target_: Rc<AVar<AData<Batch<u32>>>>) -> (Rc<PassOp<(), BatchArray1d<f32, S>>>, Rc<PassOp<(), Batch<f32>>>) {
+    //let clk_horizon = x_.data().horizon();
+    let (_, prob, loss) = SoftmaxLoss::new(x_.clone(), Some(target_.clone()), NLLLossLink, /*clk_horizon,*/ _batch_array1d_map_alloc(x_.data()), {
+      let x = x_.data();
+      Rc::new(move |txn, node| {
+        let batch_sz = x.val.get(txn, node).batch_size();
+        let mut loss = Batch::new();
+        loss.set_batch_size(batch_sz, 0.0);
+        loss
+      })
+    });
+    (prob, loss)
+  }
+}
+
+impl<S, T, Link> AOp for SoftmaxLoss<BatchArray1d<f32, S>, T, Batch<f32>, Link> where S: DerefMut<Target=[f32]>, Link: SoftmaxLossKernel<T> {
   fn _id(&self) -> NodeId {
     self.node_id
   }
@@ -4304,53 +11722,225 @@ impl<S> AOp for SoftmaxLoss<BatchArray1d<f32, S>, BatchArray1d<f32, S>, Batch<f3
   }
 
   fn _forward(&self, txn: TxnId) {
-    unimplemented!();
+    let node = self._id();
+    let target = match self.target {
+      None            => panic!("SoftmaxLoss requires a target"),
+      Some(ref target) => target,
+    };
+    if self.loss.val.overwrite(txn, node) {
+      let x_dim = self.x.val.get(txn, node).dim();
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      self.loss.val.get_excl(txn, node).set_batch_size(batch_sz, 0.0);
+      assert!(self.prob.val.overwrite(txn, node));
+      self.prob.val.get_excl(txn, node).set_batch_size(batch_sz);
+      unsafe { arraydiff_kernel_softmax_fwd_f32(
+          x_dim,
+          batch_sz,
+          self.x.val.get(txn, node).as_view().as_ptr(),
+          self.prob.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+      unsafe { self.link._loss_fwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get_excl(txn, node).as_view().as_ptr(),
+          &*target.val.get(txn, node),
+          self.loss.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
   }
 
   fn _backward(&self, txn: TxnId) {
-    unimplemented!();
+    // Like the CUDA ops, only the gradient of the loss is backpropagated;
+    // the gradient of the probabilities is ignored.
+    let node = self._id();
+    let target = match self.target {
+      None            => panic!("SoftmaxLoss requires a target"),
+      Some(ref target) => target,
+    };
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad.set_batch_size(batch_sz); grad.as_view_mut().set_constant(0.0); }) {
+      assert_eq!(batch_sz, self.loss.grad.get(txn, node).batch_size());
+      unsafe { self.link._loss_bwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          &*target.val.get(txn, node),
+          self.loss.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
   }
-}
 
-impl<S> AOp for SoftmaxLoss<BatchArray1d<f32, S>, Batch<(u32, f32)>, Batch<f32>, LRLossLink> where S: DerefMut<Target=[f32]> {
-  fn _id(&self) -> NodeId {
-    self.node_id
+  fn _has_r_forward(&self) -> bool {
+    true
   }
 
-  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
-    if 1 == self.stack.push(epoch) {
-      self.x_._push(epoch, apply);
-      if let Some(ref target_) = self.target_ {
-        target_._push(epoch, apply);
-      }
-      apply(self);
+  fn _r_forward(&self, txn: TxnId) {
+    // The target is treated as a constant.
+    let node = self._id();
+    let target = match self.target {
+      None            => panic!("SoftmaxLoss requires a target"),
+      Some(ref target) => target,
+    };
+    if self.loss.r_val.overwrite(txn, node) {
+      let x_dim = self.x.val.get(txn, node).dim();
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      self.loss.r_val.get_excl(txn, node).set_batch_size(batch_sz, 0.0);
+      assert!(self.prob.r_val.overwrite(txn, node));
+      self.prob.r_val.get_excl(txn, node).set_batch_size(batch_sz);
+      self.prob.r_val.get_excl(txn, node).as_view_mut().set_constant(0.0);
+      unsafe { arraydiff_kernel_softmax_bwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          self.x.r_val.get(txn, node).as_view().as_ptr(),
+          self.prob.r_val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+      unsafe { self.link._loss_tangent_fwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          &*target.val.get(txn, node),
+          self.x.r_val.get(txn, node).as_view().as_ptr(),
+          self.loss.r_val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
     }
   }
 
-  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
-    if self.stack.degree(epoch) == self.stack.pop(epoch) {
-      apply(self);
-      if let Some(ref target_) = self.target_ {
-        target_._pop(epoch, apply);
-      }
-      self.x_._pop(epoch, apply);
+  fn _backward_gauss_newton(&self, txn: TxnId) {
+    let node = self._id();
+    let target = match self.target {
+      None            => panic!("SoftmaxLoss requires a target"),
+      Some(ref target) => target,
+    };
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad.set_batch_size(batch_sz); grad.as_view_mut().set_constant(0.0); }) {
+      unsafe { self.link._loss_gauss_newton_bwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          &*target.val.get(txn, node),
+          self.x.r_val.get(txn, node).as_view().as_ptr(),
+          self.loss.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
     }
   }
 
-  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
-    self.loss.rollover_all(txn, vars);
+  fn _backward_empirical_fisher(&self, txn: TxnId) {
+    let node = self._id();
+    let target = match self.target {
+      None            => panic!("SoftmaxLoss requires a target"),
+      Some(ref target) => target,
+    };
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad.accumulate(txn, node, |grad| { grad.set_batch_size(batch_sz); grad.as_view_mut().set_constant(0.0); }) {
+      unsafe { self.link._loss_empirical_fisher_bwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          &*target.val.get(txn, node),
+          self.x.r_val.get(txn, node).as_view().as_ptr(),
+          self.loss.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
   }
 
-  fn _forward(&self, txn: TxnId) {
-    unimplemented!();
+  fn _backward2(&self, txn: TxnId) {
+    let node = self._id();
+    let target = match self.target {
+      None            => panic!("SoftmaxLoss requires a target"),
+      Some(ref target) => target,
+    };
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    if self.x.grad2.accumulate(txn, node, |grad2| { grad2.set_batch_size(batch_sz); grad2.as_view_mut().set_constant(0.0); }) {
+      unsafe { self.link._loss_bwd2_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          &*target.val.get(txn, node),
+          self.loss.grad.get(txn, node)._as_ptr(),
+          self.loss.grad2.get(txn, node)._as_ptr(),
+          self.x.grad2.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
   }
+}
 
-  fn _backward(&self, txn: TxnId) {
-    unimplemented!();
+/// The tangent of `SoftmaxLoss`: given the primal probabilities, computes
+/// `prob_tng = J(prob) x_tng` and the directional derivative of the loss.
+pub struct SoftmaxTangentLoss<A, Target, Loss, Link> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  x_:       Rc<AVar<AData<A>>>,
+  x_tng_:   Rc<AVar<AData<A>>>,
+  target_:  Option<Rc<AVar<AData<Target>>>>,
+  x:        AData<A>,
+  x_tng:    AData<A>,
+  target:   Option<AData<Target>>,
+  prob:     AData<A>,
+  prob_tng: AData<A>,
+  loss_tng: AData<Loss>,
+  link:     Link,
+}
+
+impl<A, Target, Loss, Link> SoftmaxTangentLoss<A, Target, Loss, Link> where SoftmaxTangentLoss<A, Target, Loss, Link>: AOp, A: 'static, Target: 'static, Loss: 'static, Link: 'static {
+  pub fn new(x_: Rc<AVar<AData<A>>>, x_tng_: Rc<AVar<AData<A>>>, target_: Option<Rc<AVar<AData<Target>>>>, prob: AData<A>, link: Link, /*clk_horizon: usize,*/ prob_alloc: Rc<Fn(TxnId, NodeId) -> A>, loss_alloc: Rc<Fn(TxnId, NodeId) -> Loss>) -> (Rc<Self>, Rc<PassOp<(), A>>, Rc<PassOp<(), Loss>>) {
+    let node = NodeId::new();
+    let in_degree = match target_ {
+      None      => 2,
+      Some(_)   => 3,
+    };
+    let x = x_.data();
+    let x_tng = x_tng_.data();
+    let target = target_.as_ref().map(|t_| t_.data());
+    let prob_tng = AData::new(/*clk_horizon,*/ prob_alloc.clone());
+    let loss_tng = AData::new(/*clk_horizon,*/ loss_alloc.clone());
+    let prob_tng_ = PassOp::new(None, prob_tng.clone());
+    let loss_tng_ = PassOp::new(None, loss_tng.clone());
+    let softmax_tng = Rc::new(SoftmaxTangentLoss{
+      node_id:  node,
+      stack:    OperatorStack::new(node, in_degree),
+      x_:       x_,
+      x_tng_:   x_tng_,
+      target_:  target_,
+      x:        x,
+      x_tng:    x_tng,
+      target:   target,
+      prob:     prob,
+      prob_tng: prob_tng,
+      loss_tng: loss_tng,
+      link:     link,
+    });
+    *prob_tng_.x_.borrow_mut() = Some(AVar::from(softmax_tng.clone()));
+    *loss_tng_.x_.borrow_mut() = Some(AVar::from(softmax_tng.clone()));
+    (softmax_tng, prob_tng_, loss_tng_)
+  }
+}
+
+impl<A, T, Loss, Link> AVar<()> for SoftmaxTangentLoss<A, T, Loss, Link>
+where A: 'static, T: 'static, Loss: 'static, Link: 'static,
+      Self: AOp,
+{
+  default fn _owned_data(&self) -> &() {
+    unreachable!();
+  }
+
+  default fn data(&self) -> () {
+    ()
+  }
+
+  default fn vars(&self) -> VarSet {
+    var_set()
   }
 }
 
-impl<S> AOp for SoftmaxLoss<BatchArray1d<f32, S>, Batch<u32>, Batch<f32>, NLLLossLink> where S: DerefMut<Target=[f32]> {
+impl<S, T, Link> AOp for SoftmaxTangentLoss<BatchArray1d<f32, S>, T, Batch<f32>, Link> where S: DerefMut<Target=[f32]>, Link: SoftmaxLossKernel<T> {
   fn _id(&self) -> NodeId {
     self.node_id
   }
@@ -4358,6 +11948,7 @@ impl<S> AOp for SoftmaxLoss<BatchArray1d<f32, S>, Batch<u32>, Batch<f32>, NLLLos
   fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
     if 1 == self.stack.push(epoch) {
       self.x_._push(epoch, apply);
+      self.x_tng_._push(epoch, apply);
       if let Some(ref target_) = self.target_ {
         target_._push(epoch, apply);
       }
@@ -4371,19 +11962,82 @@ impl<S> AOp for SoftmaxLoss<BatchArray1d<f32, S>, Batch<u32>, Batch<f32>, NLLLos
       if let Some(ref target_) = self.target_ {
         target_._pop(epoch, apply);
       }
+      self.x_tng_._pop(epoch, apply);
       self.x_._pop(epoch, apply);
     }
   }
 
   fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
-    self.loss.rollover_all(txn, vars);
+    self.loss_tng.rollover_all(txn, vars);
   }
 
   fn _forward(&self, txn: TxnId) {
-    unimplemented!();
+    let node = self._id();
+    let target = match self.target {
+      None            => panic!("SoftmaxTangentLoss requires a target"),
+      Some(ref target) => target,
+    };
+    if self.loss_tng.val.overwrite(txn, node) {
+      let x_dim = self.x.val.get(txn, node).dim();
+      let batch_sz = self.x.val.get(txn, node).batch_size();
+      assert_eq!(batch_sz, self.x_tng.val.get(txn, node).batch_size());
+      assert_eq!(batch_sz, self.prob.val.get(txn, node).batch_size());
+      self.loss_tng.val.get_excl(txn, node).set_batch_size(batch_sz, 0.0);
+      assert!(self.prob_tng.val.overwrite(txn, node));
+      self.prob_tng.val.get_excl(txn, node).set_batch_size(batch_sz);
+      self.prob_tng.val.get_excl(txn, node).as_view_mut().set_constant(0.0);
+      // The softmax Jacobian is symmetric, so the backward kernel applied to
+      // `x_tng` gives `J(prob) x_tng`.
+      unsafe { arraydiff_kernel_softmax_bwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          self.x_tng.val.get(txn, node).as_view().as_ptr(),
+          self.prob_tng.val.get_excl(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+      unsafe { self.link._loss_tangent_fwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          &*target.val.get(txn, node),
+          self.x_tng.val.get(txn, node).as_view().as_ptr(),
+          self.loss_tng.val.get_excl(txn, node)._as_mut_ptr(),
+      ) };
+    }
   }
 
   fn _backward(&self, txn: TxnId) {
-    unimplemented!();
+    // As in `SoftmaxLoss`, only the gradient of the loss tangent is
+    // backpropagated.
+    let node = self._id();
+    let target = match self.target {
+      None            => panic!("SoftmaxTangentLoss requires a target"),
+      Some(ref target) => target,
+    };
+    let x_dim = self.x.val.get(txn, node).dim();
+    let batch_sz = self.x.val.get(txn, node).batch_size();
+    assert_eq!(batch_sz, self.loss_tng.grad.get(txn, node).batch_size());
+    if self.x_tng.grad.accumulate(txn, node, |grad| { grad.set_batch_size(batch_sz); grad.as_view_mut().set_constant(0.0); }) {
+      unsafe { self.link._loss_bwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          &*target.val.get(txn, node),
+          self.loss_tng.grad.get(txn, node)._as_ptr(),
+          self.x_tng.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
+    if self.x.grad.accumulate(txn, node, |grad| { grad.set_batch_size(batch_sz); grad.as_view_mut().set_constant(0.0); }) {
+      unsafe { self.link._loss_tangent_bwd_f32(
+          x_dim,
+          batch_sz,
+          self.prob.val.get(txn, node).as_view().as_ptr(),
+          &*target.val.get(txn, node),
+          self.x_tng.val.get(txn, node).as_view().as_ptr(),
+          self.prob_tng.val.get(txn, node).as_view().as_ptr(),
+          self.loss_tng.grad.get(txn, node)._as_ptr(),
+          self.x.grad.get_mut(txn, node).as_view_mut().as_mut_ptr(),
+      ) };
+    }
   }
 }
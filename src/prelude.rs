@@ -19,10 +19,12 @@ pub use super::{
   NodeId, TxnId, EpochNr, Epoch, Clock, OperatorStack, Var, VarSet, Symbol,
   AOp, AVar,
   AVarOutput, AData,
+  AdjointArray, AdjointMap,
   ArrayData,
   AutodiffSink, // TODO: deprecate.
   GradientSinkExt,
   GaussNewtonSinkExt,
+  HessianDiagSinkExt,
   HessianSinkExt,
   NullIo, ZeroIo, BatchIo,
   //SerialIoBuf, ZeroIo, CursorIoBufExt, CursorIoBuf,
@@ -0,0 +1,2010 @@
+/*
+Copyright 2017 the arraydiff authors
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Property checks for the forward and backward passes of the CPU ops.
+//!
+//! Every test builds a tiny graph over randomized inputs and checks that:
+//!
+//! - the gradient computed by `_backward` passes `gradcheck` on `<w, y>`
+//!   for a random output weighting `w`;
+//! - backward is linear in the output gradient;
+//! - where the graph supports `r_eval`, the R-operator agrees with central
+//!   differences of `y` along a random direction;
+//! - repeating eval and backward within a txn is a no-op, while a fresh txn
+//!   recomputes the values and resets the gradients.
+
+extern crate arraydiff;
+extern crate densearray;
+extern crate rand;
+
+use arraydiff::prelude::*;
+use arraydiff::ops::*;
+use densearray::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand::chacha::{ChaChaRng};
+
+use std::any::{Any};
+use std::cell::{Cell, RefCell};
+use std::ptr;
+use std::rc::{Rc};
+
+const BATCH_SZ: usize = 3;
+const EPS:      f32 = 5.0e-3;
+const GRAD_TOL: f64 = 1.0e-2;
+const LIN_TOL:  f64 = 1.0e-4;
+const R_TOL:    f64 = 1.0e-2;
+
+/// A source whose buffers are written directly by the harness, so that any
+/// `PackedArray` (batched or not) can be an input. Packed sources also load
+/// their value from a `Vec<f32>` reader and store their gradient into a
+/// `Vec<f32>` writer, like a parameter would. The tangent of a source is
+/// another source with the same shape, and a source is a leaf of an adjoint
+/// graph.
+struct TestSrc<A> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  data:     AData<A>,
+  packed:   Option<fn(&A) -> Vec<f32>>,
+  unpack:   Option<fn(&mut A, &[f32]) -> usize>,
+  tng:      RefCell<Option<Rc<AVar<AData<A>>>>>,
+}
+
+impl<A> TestSrc<A> where A: 'static {
+  fn new<F>(alloc: F) -> Rc<TestSrc<A>> where F: 'static + Fn(TxnId, NodeId) -> A {
+    let node = NodeId::new();
+    Rc::new(TestSrc{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 0),
+      data:     AData::new(Rc::new(alloc)),
+      packed:   None,
+      unpack:   None,
+      tng:      RefCell::new(None),
+    })
+  }
+}
+
+impl<A> TestSrc<A> where A: 'static + PackedArray {
+  fn packed<F>(alloc: F) -> Rc<TestSrc<A>> where F: 'static + Fn(TxnId, NodeId) -> A {
+    let node = NodeId::new();
+    Rc::new(TestSrc{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 0),
+      data:     AData::new(Rc::new(alloc)),
+      packed:   Some(read_packed::<A>),
+      unpack:   Some(unpack_into::<A>),
+      tng:      RefCell::new(None),
+    })
+  }
+}
+
+impl<A> AOp for TestSrc<A> where A: 'static {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+    }
+  }
+
+  fn _load_val(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, reader: &mut Any) -> usize {
+    let node = self._id();
+    if let Some(unpack) = self.unpack {
+      if vars.mask(self.data.val.var()) {
+        let reader = reader.downcast_mut::<Vec<f32>>().unwrap();
+        if self.data.val.overwrite(txn, node) {
+          offset += unpack(&mut *self.data.val.get_excl(txn, node), &reader[offset .. ]);
+        }
+      }
+    }
+    offset
+  }
+
+  fn _load_r_val(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, reader: &mut Any) -> usize {
+    let node = self._id();
+    if let Some(unpack) = self.unpack {
+      if vars.mask(self.data.r_val.var()) {
+        let reader = reader.downcast_mut::<Vec<f32>>().unwrap();
+        if self.data.r_val.overwrite(txn, node) {
+          offset += unpack(&mut *self.data.r_val.get_excl(txn, node), &reader[offset .. ]);
+        }
+      }
+    }
+    offset
+  }
+
+  fn _store_val(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, writer: &mut Any) -> usize {
+    // `val_size` stores into a `NullIo` to count the elements.
+    let node = self._id();
+    if let Some(read) = self.packed {
+      if vars.mask(self.data.val.var()) {
+        let val = read(&*self.data.val.get(txn, node));
+        if let Some(writer) = writer.downcast_mut::<Vec<f32>>() {
+          writer[offset .. offset + val.len()].copy_from_slice(&val);
+        }
+        offset += val.len();
+      }
+    }
+    offset
+  }
+
+  fn _store_grad(&self, txn: TxnId, vars: &mut VarSet, mut offset: usize, writer: &mut Any) -> usize {
+    let node = self._id();
+    if let Some(read) = self.packed {
+      if vars.mask(self.data.grad.var()) {
+        let grad = read(&*self.data.grad.get(txn, node));
+        let writer = writer.downcast_mut::<Vec<f32>>().unwrap();
+        writer[offset .. offset + grad.len()].copy_from_slice(&grad);
+        offset += grad.len();
+      }
+    }
+    offset
+  }
+
+  fn _persist(&self, txn: TxnId, vars: &mut VarSet) {
+    self.data.rollover_all(txn, vars);
+  }
+
+  fn _forward(&self, _txn: TxnId) {
+  }
+
+  fn _backward(&self, _txn: TxnId) {
+  }
+
+  fn _has_r_forward(&self) -> bool {
+    true
+  }
+
+  fn _r_forward(&self, _txn: TxnId) {
+  }
+}
+
+impl<A> AVar<AData<A>> for TestSrc<A> where A: 'static {
+  fn _owned_data(&self) -> &AData<A> {
+    &self.data
+  }
+
+  fn _make_tangent(&self) -> Rc<AVar<AData<A>>> {
+    let node = NodeId::new();
+    Rc::new(TestSrc{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 0),
+      data:     AData::new(self.data.alloc.clone()),
+      packed:   self.packed,
+      unpack:   self.unpack,
+      tng:      RefCell::new(None),
+    })
+  }
+
+  fn tangent(&self) -> Rc<AVar<AData<A>>> {
+    if self.tng.borrow().is_none() {
+      *self.tng.borrow_mut() = Some(self._make_tangent());
+    }
+    self.tng.borrow().as_ref().unwrap().clone()
+  }
+
+  fn _adjoint(&self, _adj_: Rc<AVar<AData<A>>>, _adjs: &mut AdjointMap) {
+  }
+}
+
+fn array1d_src(dim: usize) -> Rc<TestSrc<Array1d<f32>>> {
+  TestSrc::packed(move |_, _| Array1d::zeros(dim))
+}
+
+fn array2d_src(dim: (usize, usize)) -> Rc<TestSrc<Array2d<f32>>> {
+  TestSrc::packed(move |_, _| Array2d::zeros(dim))
+}
+
+fn batch_src() -> Rc<TestSrc<Batch<f32>>> {
+  TestSrc::packed(|_, _| {
+    let mut x = Batch::new();
+    x.set_batch_size(BATCH_SZ, 0.0);
+    x
+  })
+}
+
+fn batch_array1d_src(dim: usize) -> Rc<TestSrc<BatchArray1d<f32>>> {
+  TestSrc::packed(move |_, _| {
+    let buf = <Vec<f32> as BatchArrayStorage<usize>>::alloc(dim, BATCH_SZ);
+    BatchArray1d::from_storage(dim, BATCH_SZ, buf)
+  })
+}
+
+fn batch_array3d_src(dim: (usize, usize, usize)) -> Rc<TestSrc<BatchArray3d<f32>>> {
+  TestSrc::packed(move |_, _| {
+    let buf = <Vec<f32> as BatchArrayStorage<usize>>::alloc(dim.flat_len(), BATCH_SZ);
+    BatchArray3d::from_storage(dim, BATCH_SZ, buf)
+  })
+}
+
+fn index_src() -> Rc<TestSrc<Batch<u32>>> {
+  TestSrc::new(|_, _| {
+    let mut t: Batch<u32> = Batch::new();
+    t.set_batch_size(BATCH_SZ, 0);
+    t
+  })
+}
+
+fn write_index(x: &TxnVar<Batch<u32>>, txn: TxnId, node: NodeId, vals: &[u32]) {
+  if x.overwrite(txn, node) {
+    let mut x = x.get_excl(txn, node);
+    for (i, &v) in vals.iter().enumerate() {
+      x[i] = v;
+    }
+  }
+}
+
+fn erase<Op, A>(x_: &Rc<Op>) -> Rc<AVar<AData<A>>> where Op: 'static + AVar<AData<A>> {
+  x_.clone()
+}
+
+fn packed_len<A>(x: &A) -> usize where A: PackedArray {
+  x._packed_dim().iter().product()
+}
+
+fn read_packed<A>(x: &A) -> Vec<f32> where A: PackedArray {
+  let len = packed_len(x);
+  let mut buf = vec![0.0; len];
+  unsafe { ptr::copy_nonoverlapping(x._as_ptr(), buf.as_mut_ptr(), len) };
+  buf
+}
+
+/// Copies the leading entries of `vals` into `x`, returning how many.
+fn unpack_into<A>(x: &mut A, vals: &[f32]) -> usize where A: PackedArray {
+  let len = packed_len(x);
+  unsafe { ptr::copy_nonoverlapping(vals[ .. len].as_ptr(), x._as_mut_ptr(), len) };
+  len
+}
+
+fn write_packed<A>(x: &TxnVar<A>, txn: TxnId, node: NodeId, batch_sz: usize, vals: &[f32]) where A: PackedArray {
+  if x.overwrite(txn, node) {
+    let mut x = x.get_excl(txn, node);
+    x._set_batch_size(batch_sz);
+    assert_eq!(vals.len(), packed_len(&*x));
+    unsafe { ptr::copy_nonoverlapping(vals.as_ptr(), x._as_mut_ptr(), vals.len()) };
+  }
+}
+
+fn test_rng() -> ChaChaRng {
+  ChaChaRng::from_seed(&[0x5eed_u32])
+}
+
+fn uniform(rng: &mut ChaChaRng, len: usize, lo: f32, hi: f32) -> Vec<f32> {
+  (0 .. len).map(|_| rng.gen_range(lo, hi)).collect()
+}
+
+/// Values with magnitude in `[0.1, 1)` and a random sign, which keeps the
+/// finite differences clear of the kinks of piecewise kernels.
+fn signed(rng: &mut ChaChaRng, len: usize) -> Vec<f32> {
+  (0 .. len).map(|_| {
+    let u = rng.gen_range(0.1, 1.0);
+    if rng.gen() { u } else { -u }
+  }).collect()
+}
+
+/// Each row of the `(dim, BATCH_SZ)` batch is a probability distribution.
+fn distributions(rng: &mut ChaChaRng, dim: usize) -> Vec<f32> {
+  let mut p = uniform(rng, dim * BATCH_SZ, 0.1, 1.0);
+  for row in p.chunks_mut(dim) {
+    let total: f32 = row.iter().sum();
+    for v in row.iter_mut() {
+      *v /= total;
+    }
+  }
+  p
+}
+
+fn rel_err(a: f64, b: f64) -> f64 {
+  (a - b).abs() / 1.0_f64.max(a.abs()).max(b.abs())
+}
+
+/// The scalar loss `<w, y>` for a fixed seed `w`, so that `gradcheck` can
+/// check the gradient of any output along `w`.
+struct SeedOp<Y> {
+  node_id:  NodeId,
+  stack:    OperatorStack,
+  y_:       Rc<AOp>,
+  y:        AData<Y>,
+  w:        Vec<f32>,
+  loss:     AData<f32>,
+}
+
+impl<Y> SeedOp<Y> where Y: 'static + PackedArray {
+  fn new<Op>(y_: Rc<Op>, w: Vec<f32>) -> Rc<SeedOp<Y>> where Op: 'static + AVar<AData<Y>> {
+    let node = NodeId::new();
+    let y = y_.data();
+    Rc::new(SeedOp{
+      node_id:  node,
+      stack:    OperatorStack::new(node, 1),
+      y_:       y_,
+      y:        y,
+      w:        w,
+      loss:     AData::new(Rc::new(|_, _| 0.0_f32)),
+    })
+  }
+}
+
+impl<Y> AOp for SeedOp<Y> where Y: 'static + PackedArray {
+  fn _id(&self) -> NodeId {
+    self.node_id
+  }
+
+  fn _push(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if 1 == self.stack.push(epoch) {
+      self.y_._push(epoch, apply);
+      apply(self);
+    }
+  }
+
+  fn _pop(&self, epoch: Epoch, apply: &mut FnMut(&AOp)) {
+    if self.stack.degree(epoch) == self.stack.pop(epoch) {
+      apply(self);
+      self.y_._pop(epoch, apply);
+    }
+  }
+
+  fn _forward(&self, txn: TxnId) {
+    let node = self._id();
+    if self.loss.val.overwrite(txn, node) {
+      let y = read_packed(&*self.y.val.get(txn, node));
+      assert_eq!(self.w.len(), y.len());
+      *self.loss.val.get_excl(txn, node) = y.iter().zip(self.w.iter()).map(|(&y, &w)| y * w).sum();
+    }
+  }
+
+  fn _backward(&self, txn: TxnId) {
+    let node = self._id();
+    let batch_sz = self.y.val.get(txn, node)._batch_size();
+    if self.y.grad.accumulate(txn, node, |grad| { grad._set_batch_size(batch_sz); grad._set_zero(); }) {
+      let df = *self.loss.grad.get(txn, node);
+      let mut grad = self.y.grad.get_mut(txn, node);
+      assert_eq!(self.w.len(), packed_len(&*grad));
+      let grad = unsafe { ::std::slice::from_raw_parts_mut(grad._as_mut_ptr(), self.w.len()) };
+      for (g, &w) in grad.iter_mut().zip(self.w.iter()) {
+        *g += df * w;
+      }
+    }
+  }
+}
+
+impl<Y> AVar<AData<f32>> for SeedOp<Y> where Y: 'static + PackedArray {
+  fn _owned_data(&self) -> &AData<f32> {
+    &self.loss
+  }
+}
+
+struct Input {
+  vals:   Vec<f32>,
+  load:   Box<Fn(TxnId, &[f32])>,
+  load_r: Box<Fn(TxnId, &[f32])>,
+  grad:   Box<Fn(TxnId) -> Vec<f32>>,
+}
+
+/// A graph under test: differentiable inputs, constant inputs (targets) and
+/// a single output.
+struct OpCheck {
+  op:       Rc<AOp>,
+  inputs:   Vec<Input>,
+  params:   VarSet,
+  consts:   Vec<Box<Fn(TxnId)>>,
+  seed_loss: Box<Fn(Vec<f32>) -> Rc<AVar<AData<f32>>>>,
+  read_val: Box<Fn(TxnId) -> Vec<f32>>,
+  read_r_val: Box<Fn(TxnId) -> Vec<f32>>,
+  seed:     Box<Fn(TxnId, &[f32])>,
+}
+
+impl OpCheck {
+  fn new<Op, Y>(y_: Rc<Op>) -> OpCheck where Op: 'static + AVar<AData<Y>>, Y: 'static + PackedArray {
+    let node = NodeId::new();
+    let y = y_.data();
+    let y2 = y.clone();
+    let y3 = y.clone();
+    let y4_ = y_.clone();
+    OpCheck{
+      op:       y_,
+      inputs:   vec![],
+      params:   var_set(),
+      consts:   vec![],
+      seed_loss: Box::new(move |w| {
+        let loss_: Rc<AVar<AData<f32>>> = SeedOp::new(y4_.clone(), w);
+        loss_
+      }),
+      read_val: Box::new(move |txn| read_packed(&*y.val.get(txn, node))),
+      read_r_val: Box::new(move |txn| read_packed(&*y3.r_val.get(txn, node))),
+      seed:     Box::new(move |txn, w| {
+        let batch_sz = y2.val.get(txn, node)._batch_size();
+        write_packed(&y2.grad, txn, node, batch_sz, w);
+      }),
+    }
+  }
+
+  fn input<Op, X>(mut self, x_: &Rc<Op>, batch_sz: usize, vals: Vec<f32>) -> OpCheck where Op: 'static + ?Sized + AVar<AData<X>>, X: 'static + PackedArray {
+    let node = NodeId::new();
+    let x = x_.data();
+    let x2 = x.clone();
+    let x3 = x.clone();
+    self.params.insert_all(&x_.vars());
+    self.inputs.push(Input{
+      vals:   vals,
+      load:   Box::new(move |txn, vals| write_packed(&x.val, txn, node, batch_sz, vals)),
+      load_r: Box::new(move |txn, dirs| write_packed(&x3.r_val, txn, node, batch_sz, dirs)),
+      grad:   Box::new(move |txn| read_packed(&*x2.grad.get(txn, node))),
+    });
+    self
+  }
+
+  fn constant<F>(mut self, load: F) -> OpCheck where F: 'static + Fn(TxnId) {
+    self.consts.push(Box::new(load));
+    self
+  }
+
+  fn load(&self, txn: TxnId, vals: &[Vec<f32>]) {
+    for (input, vals) in self.inputs.iter().zip(vals.iter()) {
+      (input.load)(txn, vals);
+    }
+    self.load_consts(txn);
+  }
+
+  fn load_consts(&self, txn: TxnId) {
+    for load in self.consts.iter() {
+      load(txn);
+    }
+  }
+
+  fn backward(&self, txn: TxnId) {
+    self.op._traverse_bwd(&mut |op| op._backward(txn));
+  }
+
+  fn read_grads(&self, txn: TxnId) -> Vec<Vec<f32>> {
+    self.inputs.iter().map(|input| (input.grad)(txn)).collect()
+  }
+
+  fn value(&self, vals: &[Vec<f32>]) -> Vec<f32> {
+    let txn = txn();
+    self.load(txn, vals);
+    self.op.eval(txn);
+    (self.read_val)(txn)
+  }
+
+  fn grads(&self, txn: TxnId, vals: &[Vec<f32>], w: &[f32]) -> Vec<Vec<f32>> {
+    self.load(txn, vals);
+    self.op.eval(txn);
+    (self.seed)(txn, w);
+    self.backward(txn);
+    self.read_grads(txn)
+  }
+
+  fn run(&self, rng: &mut ChaChaRng) {
+    let vals: Vec<Vec<f32>> = self.inputs.iter().map(|input| input.vals.clone()).collect();
+    let y_len = self.value(&vals).len();
+    let w = uniform(rng, y_len, -1.0, 1.0);
+    self.check_gradient(&vals, &w);
+    self.check_linearity(rng, &vals, &w);
+    self.check_txns(rng, &vals, &w);
+    if self.op.supports_r_eval() {
+      self.check_r_op(rng, &vals);
+    }
+  }
+
+  fn check_gradient(&self, vals: &[Vec<f32>], w: &[f32]) {
+    let sink = GradientSink::new((self.seed_loss)(w.to_vec()));
+    let txn = txn();
+    self.load(txn, vals);
+    let check = gradcheck(&sink, &self.params, EPS, GRAD_TOL as f32, txn, |txn| self.load_consts(txn));
+    let param_len: usize = check.params.iter().map(|p| p.len).sum();
+    assert_eq!(vals.iter().map(|vals| vals.len()).sum::<usize>(), param_len);
+    for p in check.params.iter() {
+      assert!(p.max_rel_err <= check.tol,
+          "input at offset {} elem {}: relative error {} of backward vs. numerical", p.offset, p.argmax, p.max_rel_err);
+    }
+  }
+
+  fn check_r_op(&self, rng: &mut ChaChaRng, vals: &[Vec<f32>]) {
+    let dirs: Vec<Vec<f32>> = vals.iter().map(|vals| uniform(rng, vals.len(), -1.0, 1.0)).collect();
+    let txn = txn();
+    self.load(txn, vals);
+    for (input, dirs) in self.inputs.iter().zip(dirs.iter()) {
+      (input.load_r)(txn, dirs);
+    }
+    self.op.r_eval(txn);
+    let r_y = (self.read_r_val)(txn);
+    let step = |sign: f32| -> Vec<Vec<f32>> {
+      vals.iter().zip(dirs.iter())
+        .map(|(vals, dirs)| vals.iter().zip(dirs.iter()).map(|(&v, &d)| v + sign * EPS * d).collect())
+        .collect()
+    };
+    let y_p = self.value(&step(1.0));
+    let y_m = self.value(&step(-1.0));
+    assert_eq!(y_p.len(), r_y.len());
+    for i in 0 .. r_y.len() {
+      let fd = (y_p[i] as f64 - y_m[i] as f64) / (2.0 * EPS as f64);
+      assert!(rel_err(r_y[i] as f64, fd) <= R_TOL,
+          "elem {}: R-op {} vs. numerical {}", i, r_y[i], fd);
+    }
+  }
+
+  fn check_linearity(&self, rng: &mut ChaChaRng, vals: &[Vec<f32>], w: &[f32]) {
+    let (a, b) = (0.5, -2.0);
+    let v = uniform(rng, w.len(), -1.0, 1.0);
+    let u: Vec<f32> = w.iter().zip(v.iter()).map(|(&w, &v)| a * w + b * v).collect();
+    let grads_w = self.grads(txn(), vals, w);
+    let grads_v = self.grads(txn(), vals, &v);
+    let grads_u = self.grads(txn(), vals, &u);
+    for k in 0 .. grads_u.len() {
+      for i in 0 .. grads_u[k].len() {
+        let expected = a as f64 * grads_w[k][i] as f64 + b as f64 * grads_v[k][i] as f64;
+        assert!(rel_err(grads_u[k][i] as f64, expected) <= LIN_TOL,
+            "input {} elem {}: backward of combined seed {} vs. combined backward {}", k, i, grads_u[k][i], expected);
+      }
+    }
+  }
+
+  fn check_txns(&self, rng: &mut ChaChaRng, vals: &[Vec<f32>], w: &[f32]) {
+    // Repeated eval and backward within a txn must not recompute values or
+    // accumulate gradients twice.
+    let txn1 = txn();
+    self.load(txn1, vals);
+    self.op.eval(txn1);
+    let y1 = (self.read_val)(txn1);
+    self.op.eval(txn1);
+    assert_eq!(y1, (self.read_val)(txn1));
+    (self.seed)(txn1, w);
+    self.backward(txn1);
+    let grads1 = self.read_grads(txn1);
+    self.backward(txn1);
+    assert_eq!(grads1, self.read_grads(txn1));
+
+    // A fresh txn recomputes the output from new inputs.
+    let vals2: Vec<Vec<f32>> = vals.iter()
+      .map(|vals| vals.iter().map(|&v| v * rng.gen_range(0.5, 1.5)).collect())
+      .collect();
+    assert!(y1 != self.value(&vals2));
+
+    // ...and gradients start from zero instead of accumulating over the
+    // previous txns.
+    let txn3 = txn();
+    let grads3 = self.grads(txn3, vals, w);
+    assert_eq!(y1, (self.read_val)(txn3));
+    assert_eq!(grads1, grads3);
+  }
+}
+
+fn check_map_kernels<F>(make: F) where F: Fn(&mut ChaChaRng, &str) -> OpCheck {
+  let mut rng = test_rng();
+  for &name in ["exp", "log", "sqrt", "softplus", "rect", "leaky_rect", "elu", "gelu", "swish", "logistic", "tanh"].iter() {
+    make(&mut rng, name).run(&mut rng);
+  }
+}
+
+macro_rules! map_op {
+  ($x_:expr, $name:expr) => {
+    match $name {
+      "exp"         => OpCheck::new($x_.exp()),
+      "log"         => OpCheck::new($x_.log()),
+      "sqrt"        => OpCheck::new($x_.sqrt()),
+      "softplus"    => OpCheck::new($x_.softplus()),
+      "rect"        => OpCheck::new($x_.rect()),
+      "leaky_rect"  => OpCheck::new($x_.leaky_rect(0.1)),
+      "elu"         => OpCheck::new($x_.elu(0.5)),
+      "gelu"        => OpCheck::new($x_.gelu()),
+      "swish"       => OpCheck::new($x_.swish()),
+      "logistic"    => OpCheck::new($x_.logistic()),
+      "tanh"        => OpCheck::new($x_.tanh()),
+      _ => unreachable!(),
+    }
+  };
+}
+
+/// `log` and `sqrt` are only checked on positive inputs.
+fn map_input(rng: &mut ChaChaRng, name: &str, len: usize) -> Vec<f32> {
+  match name {
+    "log" | "sqrt" => uniform(rng, len, 0.1, 1.0),
+    _ => signed(rng, len),
+  }
+}
+
+#[test]
+fn map_op_array1d() {
+  check_map_kernels(|rng, name| {
+    let x_ = array1d_src(5);
+    let vals = map_input(rng, name, 5);
+    map_op!(x_, name).input(&x_, 1, vals)
+  });
+}
+
+#[test]
+fn map_op_batch_array1d() {
+  check_map_kernels(|rng, name| {
+    let x_ = batch_array1d_src(4);
+    let vals = map_input(rng, name, 4 * BATCH_SZ);
+    map_op!(x_, name).input(&x_, BATCH_SZ, vals)
+  });
+}
+
+#[test]
+fn map_op_batch_array3d() {
+  check_map_kernels(|rng, name| {
+    let x_ = batch_array3d_src((2, 2, 2));
+    let vals = map_input(rng, name, 8 * BATCH_SZ);
+    map_op!(x_, name).input(&x_, BATCH_SZ, vals)
+  });
+}
+
+#[test]
+fn linear_op_inner_prod() {
+  let mut rng = test_rng();
+  let x_ = array1d_src(5);
+  let w_: Rc<AVar<AData<Array1d<f32>>>> = array1d_src(5);
+  let (x, w) = (signed(&mut rng, 5), signed(&mut rng, 5));
+  OpCheck::new(x_.mult(w_.clone()))
+    .input(&x_, 1, x)
+    .input(&w_, 1, w)
+    .run(&mut rng);
+}
+
+#[test]
+fn linear_op_mat_vec() {
+  let mut rng = test_rng();
+  let a_: Rc<AVar<AData<Array2d<f32>>>> = array2d_src((4, 3));
+  let x_: Rc<AVar<AData<Array1d<f32>>>> = array1d_src(3);
+  let b_: Rc<AVar<AData<Array1d<f32>>>> = array1d_src(4);
+  let (a, x, b) = (signed(&mut rng, 12), signed(&mut rng, 3), signed(&mut rng, 4));
+  OpCheck::new(a_.mult(x_.clone()))
+    .input(&a_, 1, a.clone())
+    .input(&x_, 1, x.clone())
+    .run(&mut rng);
+  OpCheck::new(a_.mult_add(x_.clone(), b_.clone()))
+    .input(&a_, 1, a.clone())
+    .input(&x_, 1, x)
+    .input(&b_, 1, b)
+    .run(&mut rng);
+  let u_: Rc<AVar<AData<Array1d<f32>>>> = array1d_src(4);
+  let u = signed(&mut rng, 4);
+  OpCheck::new(a_.t_mult(u_.clone()))
+    .input(&a_, 1, a)
+    .input(&u_, 1, u)
+    .run(&mut rng);
+}
+
+#[test]
+fn linear_op_batch() {
+  let mut rng = test_rng();
+  let a_: Rc<AVar<AData<Array2d<f32>>>> = array2d_src((4, 3));
+  let x_: Rc<AVar<AData<BatchArray1d<f32>>>> = batch_array1d_src(3);
+  let b_: Rc<AVar<AData<Array1d<f32>>>> = array1d_src(4);
+  let (a, x, b) = (signed(&mut rng, 12), signed(&mut rng, 3 * BATCH_SZ), signed(&mut rng, 4));
+  OpCheck::new(a_.mult(x_.clone()))
+    .input(&a_, 1, a.clone())
+    .input(&x_, BATCH_SZ, x.clone())
+    .run(&mut rng);
+  OpCheck::new(a_.mult_add(x_.clone(), b_.clone()))
+    .input(&a_, 1, a.clone())
+    .input(&x_, BATCH_SZ, x)
+    .input(&b_, 1, b)
+    .run(&mut rng);
+  let u_: Rc<AVar<AData<BatchArray1d<f32>>>> = batch_array1d_src(4);
+  let u = signed(&mut rng, 4 * BATCH_SZ);
+  OpCheck::new(a_.t_mult(u_.clone()))
+    .input(&a_, 1, a)
+    .input(&u_, BATCH_SZ, u)
+    .run(&mut rng);
+}
+
+#[test]
+fn linear_op_mat_mat_t() {
+  let mut rng = test_rng();
+  let a_: Rc<AVar<AData<Array2d<f32>>>> = array2d_src((4, 3));
+  let x_: Rc<AVar<AData<Array2d<f32>>>> = array2d_src((2, 3));
+  let (a, x) = (signed(&mut rng, 12), signed(&mut rng, 6));
+  OpCheck::new(a_.mult_t(x_.clone()))
+    .input(&a_, 1, a)
+    .input(&x_, 1, x)
+    .run(&mut rng);
+}
+
+#[test]
+fn join_op_sum() {
+  let mut rng = test_rng();
+  let x1_ = batch_array1d_src(4);
+  let x2_ = batch_array1d_src(4);
+  let x3_ = batch_array1d_src(4);
+  let (x1, x2, x3) = (signed(&mut rng, 4 * BATCH_SZ), signed(&mut rng, 4 * BATCH_SZ), signed(&mut rng, 4 * BATCH_SZ));
+  OpCheck::new(x1_.add(x2_.clone()))
+    .input(&x1_, BATCH_SZ, x1.clone())
+    .input(&x2_, BATCH_SZ, x2.clone())
+    .run(&mut rng);
+  OpCheck::new(sum(vec![erase(&x1_), erase(&x2_), erase(&x3_)]))
+    .input(&x1_, BATCH_SZ, x1)
+    .input(&x2_, BATCH_SZ, x2)
+    .input(&x3_, BATCH_SZ, x3)
+    .run(&mut rng);
+}
+
+#[test]
+fn join_op_axis() {
+  let mut rng = test_rng();
+  let x1_ = array1d_src(2);
+  let x2_ = array1d_src(3);
+  let (x1, x2) = (signed(&mut rng, 2), signed(&mut rng, 3));
+  OpCheck::new(axis_join(vec![x1_.clone(), x2_.clone()]))
+    .input(&x1_, 1, x1)
+    .input(&x2_, 1, x2)
+    .run(&mut rng);
+  let y1_ = batch_array1d_src(2);
+  let y2_ = batch_array1d_src(3);
+  let (y1, y2) = (signed(&mut rng, 2 * BATCH_SZ), signed(&mut rng, 3 * BATCH_SZ));
+  OpCheck::new(axis_join_at(0, vec![erase(&y1_), erase(&y2_)]))
+    .input(&y1_, BATCH_SZ, y1)
+    .input(&y2_, BATCH_SZ, y2)
+    .run(&mut rng);
+  for axis in 0 .. 3 {
+    let z1_ = batch_array3d_src((2, 2, 2));
+    let z2_ = batch_array3d_src((2, 2, 2));
+    let (z1, z2) = (signed(&mut rng, 8 * BATCH_SZ), signed(&mut rng, 8 * BATCH_SZ));
+    OpCheck::new(axis_join_at(axis, vec![erase(&z1_), erase(&z2_)]))
+      .input(&z1_, BATCH_SZ, z1)
+      .input(&z2_, BATCH_SZ, z2)
+      .run(&mut rng);
+  }
+}
+
+#[test]
+fn transform_op() {
+  let mut rng = test_rng();
+  let x_ = batch_array3d_src((2, 3, 2));
+  let x = signed(&mut rng, 12 * BATCH_SZ);
+  OpCheck::new(x_.flatten())
+    .input(&x_, BATCH_SZ, x.clone())
+    .run(&mut rng);
+  OpCheck::new(ReshapeExt::<(usize, usize, usize), _, BatchArray3d<f32>>::reshape(&x_, (3, 2, 2)))
+    .input(&x_, BATCH_SZ, x.clone())
+    .run(&mut rng);
+  OpCheck::new(ReshapeExt::<usize, _, BatchArray1d<f32>>::reshape(&x_, 12))
+    .input(&x_, BATCH_SZ, x)
+    .run(&mut rng);
+  let y_ = batch_array1d_src(1);
+  let y = signed(&mut rng, BATCH_SZ);
+  OpCheck::new(ReshapeExt::<(), _, Batch<f32>>::reshape(&y_, ()))
+    .input(&y_, BATCH_SZ, y)
+    .run(&mut rng);
+}
+
+#[test]
+fn batch_join_op() {
+  let mut rng = test_rng();
+  let x_ = batch_src();
+  let x = signed(&mut rng, BATCH_SZ);
+  OpCheck::new(BatchJoinOp::new(x_.clone(), SumJoinKernel, Rc::new(|_: TxnId, _: NodeId| 0.0_f32)))
+    .input(&x_, BATCH_SZ, x)
+    .run(&mut rng);
+}
+
+#[test]
+fn sequential_join_op() {
+  let mut rng = test_rng();
+  let x_ = batch_src();
+  let x = signed(&mut rng, BATCH_SZ);
+  let alloc = Rc::new(|_: TxnId, _: NodeId| {
+    let mut y: Batch<f32> = Batch::new();
+    y.set_batch_size(BATCH_SZ, 0.0);
+    y
+  });
+  OpCheck::new(SequentialJoinOp::new(x_.clone(), SumJoinKernel, alloc))
+    .input(&x_, BATCH_SZ, x)
+    .run(&mut rng);
+}
+
+#[test]
+fn softmax_nll_loss_op() {
+  let mut rng = test_rng();
+  let x_ = batch_array1d_src(4);
+  let t_: Rc<AVar<AData<Batch<u32>>>> = TestSrc::new(|_, _| {
+    let mut t: Batch<u32> = Batch::new();
+    t.set_batch_size(BATCH_SZ, 0);
+    t
+  });
+  let x = signed(&mut rng, 4 * BATCH_SZ);
+  let labels: Vec<u32> = (0 .. BATCH_SZ).map(|_| rng.gen_range(0, 4)).collect();
+  let (_, loss_) = softmax_nll_loss(x_.clone(), t_.clone());
+  let t = t_.data();
+  let node = NodeId::new();
+  OpCheck::new(loss_)
+    .input(&x_, BATCH_SZ, x)
+    .constant(move |txn| {
+      if t.val.overwrite(txn, node) {
+        let mut t = t.val.get_excl(txn, node);
+        for (i, &label) in labels.iter().enumerate() {
+          t[i] = label;
+        }
+      }
+    })
+    .run(&mut rng);
+}
+
+#[test]
+fn softmax_kl2_loss_op() {
+  let mut rng = test_rng();
+  let x_ = batch_array1d_src(4);
+  let t_: Rc<AVar<AData<BatchArray1d<f32>>>> = batch_array1d_src(4);
+  let x = signed(&mut rng, 4 * BATCH_SZ);
+  let target = distributions(&mut rng, 4);
+  let prob_alloc = Rc::new(|_: TxnId, _: NodeId| {
+    let buf = <Vec<f32> as BatchArrayStorage<usize>>::alloc(4, BATCH_SZ);
+    BatchArray1d::from_storage(4, BATCH_SZ, buf)
+  });
+  let loss_alloc = Rc::new(|_: TxnId, _: NodeId| {
+    let mut loss: Batch<f32> = Batch::new();
+    loss.set_batch_size(BATCH_SZ, 0.0);
+    loss
+  });
+  let (_, _, loss_) = SoftmaxLoss::new(x_.clone(), Some(t_.clone()), KL2LossLink, prob_alloc, loss_alloc);
+  let t = t_.data();
+  let node = NodeId::new();
+  OpCheck::new(loss_)
+    .input(&x_, BATCH_SZ, x)
+    .constant(move |txn| write_packed(&t.val, txn, node, BATCH_SZ, &target))
+    .run(&mut rng);
+}
+
+#[test]
+fn grad_scale_op() {
+  let mut rng = test_rng();
+  for &c in [0.0, 0.5, -1.0].iter() {
+    let x_ = batch_array1d_src(4);
+    let x = signed(&mut rng, 4 * BATCH_SZ);
+    let check = OpCheck::new(x_.scale_gradient(c))
+      .input(&x_, BATCH_SZ, x.clone());
+    let vals = vec![x.clone()];
+    assert_eq!(x, check.value(&vals));
+    let w = uniform(&mut rng, x.len(), -1.0, 1.0);
+    let expected: Vec<f32> = w.iter().map(|&w| c * w).collect();
+    assert_eq!(vec![expected], check.grads(txn(), &vals, &w));
+    check.check_txns(&mut rng, &vals, &w);
+  }
+}
+
+#[test]
+fn grad_scale_op_second_order() {
+  let mut rng = test_rng();
+  let (m, n) = (3, 4);
+  let (a, x, t, v) = (signed(&mut rng, m * n), signed(&mut rng, n * BATCH_SZ), signed(&mut rng, m * BATCH_SZ), signed(&mut rng, m * n));
+  for &c in [0.0, 0.5, -1.0].iter() {
+    // `f = sum_b 0.5 |tanh(a x_b) - t_b|^2`, with the gradient into `a x`
+    // scaled by `c`.
+    let a_src_ = array2d_src((m, n));
+    let a_: Rc<AVar<AData<Array2d<f32>>>> = a_src_.clone();
+    let x_: Rc<AVar<AData<BatchArray1d<f32>>>> = batch_array1d_src(n);
+    let t_ = batch_array1d_src(m);
+    let z_ = a_.mult(x_.clone()).scale_gradient(c);
+    let loss_: Rc<LstSqLoss<BatchArray1d<f32>, Batch<f32>>> = lst_sq_loss(false, z_.clone().tanh(), t_.clone());
+    let total_ = BatchJoinOp::new(loss_, SumJoinKernel, Rc::new(|_: TxnId, _: NodeId| 0.0_f32));
+    let grad_ = GradientSink::new(erase(&total_));
+    let node = NodeId::new();
+    let load = |txn: TxnId, a: &[f32]| {
+      write_packed(&a_.data().val, txn, node, 1, a);
+      write_packed(&x_.data().val, txn, node, BATCH_SZ, &x);
+      write_packed(&t_.data().val, txn, node, BATCH_SZ, &t);
+    };
+    let grad = |a: &[f32]| {
+      let txn = txn();
+      load(txn, a);
+      grad_.eval_gradient(txn);
+      read_packed(&*a_.data().grad.get(txn, node))
+    };
+
+    // The adjoint is the scaled gradient; a zero scale cuts the edge.
+    let adjs = adjoint_graph(total_.clone());
+    if c == 0.0 {
+      assert!(adjs.adjoint(&a_src_).is_none());
+    } else {
+      let expected = grad(&a);
+      let txn = txn();
+      load(txn, &a);
+      assert_close(&expected, &eval_adjoint(&adjs, &a_src_, txn));
+    }
+
+    // `H v` is the central difference of the scaled gradient along `v`.
+    let hvp_ = HessianSink::new(erase(&total_));
+    let hv = {
+      let txn = txn();
+      load(txn, &a);
+      write_packed(&a_.tangent().data().val, txn, node, 1, &v);
+      write_packed(&x_.tangent().data().val, txn, node, BATCH_SZ, &vec![0.0; n * BATCH_SZ]);
+      hvp_.eval_hessian_vector_product(txn);
+      read_packed(&*a_.data().grad.get(txn, node))
+    };
+    let a_p: Vec<f32> = a.iter().zip(v.iter()).map(|(&a, &v)| a + EPS * v).collect();
+    let a_m: Vec<f32> = a.iter().zip(v.iter()).map(|(&a, &v)| a - EPS * v).collect();
+    let (g_p, g_m) = (grad(&a_p), grad(&a_m));
+    for i in 0 .. a.len() {
+      let fd = (g_p[i] as f64 - g_m[i] as f64) / (2.0 * EPS as f64);
+      assert!(rel_err(hv[i] as f64, fd) <= GRAD_TOL,
+          "c {} elem {}: Hessian-vector product {} vs. numerical {}", c, i, hv[i], fd);
+    }
+
+    // Without the tanh the loss is quadratic in `a`, and its Hessian diagonal
+    // is scaled by `c^2`.
+    let quad_: Rc<LstSqLoss<BatchArray1d<f32>, Batch<f32>>> = lst_sq_loss(false, z_.clone(), t_.clone());
+    let quad_total_ = BatchJoinOp::new(quad_, SumJoinKernel, Rc::new(|_: TxnId, _: NodeId| 0.0_f32));
+    let diag_ = HessianDiagSink::new(erase(&quad_total_));
+    let txn = txn();
+    load(txn, &a);
+    diag_.eval_hessian_diagonal(txn);
+    let mut a_expected = vec![0.0; m * n];
+    for x_b in x.chunks(n) {
+      for i in 0 .. m {
+        for j in 0 .. n {
+          a_expected[i + j * m] += c * c * x_b[j] * x_b[j];
+        }
+      }
+    }
+    assert_close(&a_expected, &read_packed(&*a_.data().grad2.get(txn, node)));
+  }
+}
+
+#[test]
+fn dropout_op() {
+  let mut rng = test_rng();
+  let rate = 0.25;
+  let x_ = batch_array1d_src(64);
+  let x = signed(&mut rng, 64 * BATCH_SZ);
+  let vals = vec![x.clone()];
+  let mode = Rc::new(CopyConstant{var: TxnCopyVar::new()});
+  let seed_rng = Rc::new(RefCell::new(ChaChaRng::from_seed(&[7_u32])));
+  let y_ = x_.dropout(rate, mode.clone(), seed_rng);
+
+  // Eval mode is the identity.
+  let eval_mode = mode.clone();
+  OpCheck::new(y_.clone())
+    .input(&x_, BATCH_SZ, x.clone())
+    .constant(move |txn| eval_mode.var.set(txn, true))
+    .run(&mut rng);
+
+  // Train mode zeroes a fraction of the entries and scales up the rest; the
+  // backward pass applies the same mask.
+  let train_mode = mode.clone();
+  let train = OpCheck::new(y_.clone())
+    .input(&x_, BATCH_SZ, x.clone())
+    .constant(move |txn| train_mode.var.set(txn, false));
+  let w = uniform(&mut rng, x.len(), -1.0, 1.0);
+  let txn1 = txn();
+  let grads1 = train.grads(txn1, &vals, &w);
+  let y1 = (train.read_val)(txn1);
+  let scale = 1.0 / (1.0 - rate);
+  let mut num_dropped = 0;
+  for i in 0 .. x.len() {
+    if y1[i] == 0.0 {
+      assert_eq!(0.0, grads1[0][i]);
+      num_dropped += 1;
+    } else {
+      assert!((y1[i] - scale * x[i]).abs() <= 1.0e-6);
+      assert!((grads1[0][i] - scale * w[i]).abs() <= 1.0e-6);
+    }
+  }
+  assert!(num_dropped >= x.len() / 8 && num_dropped <= x.len() / 2,
+      "dropped {} of {} entries at rate {}", num_dropped, x.len(), rate);
+
+  // The R-op applies the mask of the txn too.
+  let dirs = uniform(&mut rng, x.len(), -1.0, 1.0);
+  (train.inputs[0].load_r)(txn1, &dirs);
+  train.op.r_eval(txn1);
+  let r_y1 = (train.read_r_val)(txn1);
+  for i in 0 .. x.len() {
+    if y1[i] == 0.0 {
+      assert_eq!(0.0, r_y1[i]);
+    } else {
+      assert!((r_y1[i] - scale * dirs[i]).abs() <= 1.0e-6);
+    }
+  }
+
+  // Re-evaluating in the same txn keeps the mask; a new txn samples another.
+  train.op.eval(txn1);
+  assert_eq!(y1, (train.read_val)(txn1));
+  let txn2 = txn();
+  let grads2 = train.grads(txn2, &vals, &w);
+  let y2 = (train.read_val)(txn2);
+  assert!(y1 != y2);
+  for i in 0 .. x.len() {
+    assert_eq!(y2[i] == 0.0, grads2[0][i] == 0.0);
+  }
+}
+
+#[test]
+fn dropout_op_seeded() {
+  let mut rng = test_rng();
+  let x_ = batch_array1d_src(64);
+  let x = signed(&mut rng, 64 * BATCH_SZ);
+  let vals = vec![x.clone()];
+  let mode = Rc::new(CopyConstant{var: TxnCopyVar::new()});
+  let masks = |seed: u32| {
+    let seed_rng = Rc::new(RefCell::new(ChaChaRng::from_seed(&[seed])));
+    let train_mode = mode.clone();
+    let check = OpCheck::new(x_.dropout(0.5, mode.clone(), seed_rng))
+      .input(&x_, BATCH_SZ, x.clone())
+      .constant(move |txn| train_mode.var.set(txn, false));
+    (0 .. 3).map(|_| check.value(&vals)).collect::<Vec<_>>()
+  };
+  // The same seed gives the same sequence of masks.
+  assert_eq!(masks(1), masks(1));
+  assert!(masks(1) != masks(2));
+}
+
+#[test]
+fn embedding_op_tied() {
+  let mut rng = test_rng();
+  let (row_len, num_rows) = (3, 5);
+  let w = signed(&mut rng, row_len * num_rows);
+  let vals = vec![w.clone()];
+  // The first lookup repeats a row and the second shares a row with it, so
+  // the table gradient sums over both lookups.
+  let tied = |dense_grad: bool| {
+    let w_ = array2d_src((row_len, num_rows));
+    let i1_ = index_src();
+    let i2_ = index_src();
+    let (y1_, y2_): (Rc<EmbeddingOp<Array2d<f32>, Batch<u32>, BatchArray1d<f32>>>, Rc<EmbeddingOp<Array2d<f32>, Batch<u32>, BatchArray1d<f32>>>) = if dense_grad {
+      (w_.embed_dense(i1_.clone()), w_.embed_dense(i2_.clone()))
+    } else {
+      (w_.embed(i1_.clone()), w_.embed(i2_.clone()))
+    };
+    let (i1, i2) = (i1_.data(), i2_.data());
+    let node = NodeId::new();
+    let check = OpCheck::new(y1_.add(y2_.clone()))
+      .input(&w_, 1, w.clone())
+      .constant(move |txn| write_index(&i1.val, txn, node, &[1, 3, 1]))
+      .constant(move |txn| write_index(&i2.val, txn, node, &[3, 0, 4]));
+    (w_, y1_, y2_, check)
+  };
+
+  // The dense grad is opt-in, and is what the gradient check reads.
+  let (dense_w_, _, _, dense) = tied(true);
+  dense.run(&mut rng);
+  let seed = uniform(&mut rng, row_len * BATCH_SZ, -1.0, 1.0);
+  let stored = {
+    let txn = txn();
+    let grads = dense.grads(txn, &vals, &seed);
+    let mut stored = vec![0.0; row_len * num_rows];
+    assert_eq!(stored.len(), dense.op.store_grad(txn, &mut dense_w_.vars(), 0, &mut stored));
+    assert_eq!(grads[0], stored);
+    stored
+  };
+
+  // By default only the shared sparse rows are kept, and store_grad writes
+  // the table's grad from them.
+  let (sparse_w_, y1_, y2_, sparse) = tied(false);
+  let txn = txn();
+  sparse.load(txn, &vals);
+  sparse.op.eval(txn);
+  (sparse.seed)(txn, &seed);
+  sparse.backward(txn);
+  let node = NodeId::new();
+  assert_eq!(vec![0, 1, 3, 4], y1_.sparse_grad().get(txn, node).rows);
+  assert_eq!(vec![0, 1, 3, 4], y2_.sparse_grad().get(txn, node).rows);
+  let mut rows: Vec<(usize, SparseRowGrad)> = vec![];
+  assert_eq!(stored.len(), sparse.op.store_grad(txn, &mut sparse_w_.vars(), 0, &mut rows));
+  assert_eq!(1, rows.len());
+  assert_eq!(0, rows[0].0);
+  assert_eq!(vec![0, 1, 3, 4], rows[0].1.rows);
+  let mut sparse_stored = vec![0.0; row_len * num_rows];
+  assert_eq!(stored.len(), sparse.op.store_grad(txn, &mut sparse_w_.vars(), 0, &mut sparse_stored));
+  for i in 0 .. stored.len() {
+    assert!((sparse_stored[i] - stored[i]).abs() <= 1.0e-6,
+        "elem {}: sparse {} vs. dense {}", i, sparse_stored[i], stored[i]);
+  }
+}
+
+#[test]
+#[should_panic(expected = "same gradient mode")]
+fn embedding_op_mixed_grad_modes() {
+  let w_ = array2d_src((3, 5));
+  let _: Rc<EmbeddingOp<Array2d<f32>, Batch<u32>, BatchArray1d<f32>>> = w_.embed(index_src());
+  let _: Rc<EmbeddingOp<Array2d<f32>, Batch<u32>, BatchArray1d<f32>>> = w_.embed_dense(index_src());
+}
+
+/// `op(a) op(x)` for single column-major matrices.
+fn naive_mult(a: &[f32], a_dim: (usize, usize), a_trans: Transpose, x: &[f32], x_dim: (usize, usize), x_trans: Transpose) -> Vec<f32> {
+  let at = |i: usize, j: usize| match a_trans {
+    Transpose::N => a[i + j * a_dim.0],
+    Transpose::T => a[j + i * a_dim.0],
+  };
+  let xt = |i: usize, j: usize| match x_trans {
+    Transpose::N => x[i + j * x_dim.0],
+    Transpose::T => x[j + i * x_dim.0],
+  };
+  let (m, k) = match a_trans { Transpose::N => a_dim, Transpose::T => (a_dim.1, a_dim.0) };
+  let n = match x_trans { Transpose::N => x_dim.1, Transpose::T => x_dim.0 };
+  let mut y = vec![0.0; m * n];
+  for j in 0 .. n {
+    for i in 0 .. m {
+      y[i + j * m] = (0 .. k).map(|l| at(i, l) * xt(l, j)).sum();
+    }
+  }
+  y
+}
+
+fn naive_softmax(x: &[f32]) -> Vec<f32> {
+  let x_max = x.iter().fold(x[0], |m, &v| m.max(v));
+  let e: Vec<f32> = x.iter().map(|&v| (v - x_max).exp()).collect();
+  let total: f32 = e.iter().sum();
+  e.iter().map(|&v| v / total).collect()
+}
+
+fn assert_close(expected: &[f32], actual: &[f32]) {
+  assert_eq!(expected.len(), actual.len());
+  for i in 0 .. expected.len() {
+    assert!((expected[i] - actual[i]).abs() <= 1.0e-5,
+        "elem {}: expected {} vs. actual {}", i, expected[i], actual[i]);
+  }
+}
+
+#[test]
+fn batch_mult_op() {
+  let mut rng = test_rng();
+  let (m, k, n, num_mats) = (2, 3, 4, 2);
+  for &a_trans in [Transpose::N, Transpose::T].iter() {
+    for &x_trans in [Transpose::N, Transpose::T].iter() {
+      let a_dim = match a_trans { Transpose::N => (m, k), Transpose::T => (k, m) };
+      let x_dim = match x_trans { Transpose::N => (k, n), Transpose::T => (n, k) };
+      let a_ = batch_array3d_src((a_dim.0, a_dim.1, num_mats));
+      let x_ = batch_array3d_src((x_dim.0, x_dim.1, num_mats));
+      let a = signed(&mut rng, m * k * num_mats * BATCH_SZ);
+      let x = signed(&mut rng, k * n * num_mats * BATCH_SZ);
+      let check = OpCheck::new(a_.batch_mult_scale(0.5, a_trans, erase(&x_), x_trans))
+        .input(&a_, BATCH_SZ, a.clone())
+        .input(&x_, BATCH_SZ, x.clone());
+
+      // Each example holds `num_mats` independent products.
+      let y = check.value(&[a.clone(), x.clone()]);
+      let mut expected = vec![];
+      for (a, x) in a.chunks(m * k).zip(x.chunks(k * n)) {
+        expected.extend(naive_mult(a, a_dim, a_trans, x, x_dim, x_trans).iter().map(|&v| 0.5 * v));
+      }
+      assert_close(&expected, &y);
+      check.run(&mut rng);
+    }
+  }
+}
+
+#[test]
+fn softmax_op() {
+  let mut rng = test_rng();
+  let x_ = batch_array1d_src(4);
+  let x = signed(&mut rng, 4 * BATCH_SZ);
+  let check = OpCheck::new(x_.softmax())
+    .input(&x_, BATCH_SZ, x.clone());
+  let expected: Vec<f32> = x.chunks(4).flat_map(|x| naive_softmax(x)).collect();
+  assert_close(&expected, &check.value(&[x]));
+  check.run(&mut rng);
+
+  // A `BatchArray3d` is normalized along each column.
+  let z_ = batch_array3d_src((4, 2, 2));
+  let z = signed(&mut rng, 16 * BATCH_SZ);
+  let check = OpCheck::new(z_.softmax())
+    .input(&z_, BATCH_SZ, z.clone());
+  let expected: Vec<f32> = z.chunks(4).flat_map(|z| naive_softmax(z)).collect();
+  assert_close(&expected, &check.value(&[z]));
+  check.run(&mut rng);
+}
+
+#[test]
+fn scaled_dot_product_attention_op() {
+  let mut rng = test_rng();
+  let (head_dim, value_dim, num_queries, num_keys, num_heads) = (3, 2, 2, 4, 2);
+  let q_ = batch_array3d_src((head_dim, num_queries, num_heads));
+  let k_ = batch_array3d_src((head_dim, num_keys, num_heads));
+  let v_ = batch_array3d_src((value_dim, num_keys, num_heads));
+  let q = signed(&mut rng, head_dim * num_queries * num_heads * BATCH_SZ);
+  let k = signed(&mut rng, head_dim * num_keys * num_heads * BATCH_SZ);
+  let v = signed(&mut rng, value_dim * num_keys * num_heads * BATCH_SZ);
+  let check = OpCheck::new(scaled_dot_product_attention(head_dim, q_.clone(), k_.clone(), v_.clone()))
+    .input(&q_, BATCH_SZ, q.clone())
+    .input(&k_, BATCH_SZ, k.clone())
+    .input(&v_, BATCH_SZ, v.clone());
+
+  // Each query attends over the keys of its own head and example.
+  let scale = 1.0 / (head_dim as f32).sqrt();
+  let mut expected = vec![];
+  for ((q, k), v) in q.chunks(head_dim * num_queries).zip(k.chunks(head_dim * num_keys)).zip(v.chunks(value_dim * num_keys)) {
+    for j in 0 .. num_queries {
+      let q_j = &q[j * head_dim .. (j + 1) * head_dim];
+      let score: Vec<f32> = k.chunks(head_dim)
+        .map(|k_i| scale * k_i.iter().zip(q_j.iter()).map(|(&k, &q)| k * q).sum::<f32>())
+        .collect();
+      let prob = naive_softmax(&score);
+      for d in 0 .. value_dim {
+        expected.push((0 .. num_keys).map(|i| v[d + i * value_dim] * prob[i]).sum());
+      }
+    }
+  }
+  assert_close(&expected, &check.value(&[q, k, v]));
+  check.run(&mut rng);
+}
+
+#[test]
+fn elem_binary_op() {
+  let mut rng = test_rng();
+  for &name in ["sub", "mul", "div", "max", "min", "pow"].iter() {
+    let x1_ = batch_array1d_src(4);
+    let x2_ = batch_array1d_src(4);
+    let c_: Rc<AVar<AData<Array1d<f32>>>> = array1d_src(4);
+    // `pow` and `div` are only checked on positive operands.
+    let (x1, x2, c) = match name {
+      "div" | "pow" => (uniform(&mut rng, 4 * BATCH_SZ, 0.5, 1.5), uniform(&mut rng, 4 * BATCH_SZ, 0.5, 1.5), uniform(&mut rng, 4, 0.5, 1.5)),
+      _ => (signed(&mut rng, 4 * BATCH_SZ), signed(&mut rng, 4 * BATCH_SZ), signed(&mut rng, 4)),
+    };
+    let (same, bcast) = match name {
+      "sub" => (OpCheck::new(x1_.sub(erase(&x2_))), OpCheck::new(x1_.sub(c_.clone()))),
+      "mul" => (OpCheck::new(x1_.elem_mul(erase(&x2_))), OpCheck::new(x1_.elem_mul(c_.clone()))),
+      "div" => (OpCheck::new(x1_.elem_div(erase(&x2_))), OpCheck::new(x1_.elem_div(c_.clone()))),
+      "max" => (OpCheck::new(x1_.elem_max(erase(&x2_))), OpCheck::new(x1_.elem_max(c_.clone()))),
+      "min" => (OpCheck::new(x1_.elem_min(erase(&x2_))), OpCheck::new(x1_.elem_min(c_.clone()))),
+      "pow" => (OpCheck::new(x1_.pow(erase(&x2_))), OpCheck::new(x1_.pow(c_.clone()))),
+      _ => unreachable!(),
+    };
+    same
+      .input(&x1_, BATCH_SZ, x1.clone())
+      .input(&x2_, BATCH_SZ, x2)
+      .run(&mut rng);
+    bcast
+      .input(&x1_, BATCH_SZ, x1)
+      .input(&c_, 1, c)
+      .run(&mut rng);
+  }
+}
+
+#[test]
+fn reduce_op() {
+  let mut rng = test_rng();
+  for &name in ["sum", "mean", "max", "min", "l2_norm", "logsumexp"].iter() {
+    let x_ = batch_array1d_src(5);
+    let x = signed(&mut rng, 5 * BATCH_SZ);
+    let check = match name {
+      "sum"       => OpCheck::new(ReduceExt::<_, Batch<f32>>::reduce_sum(&x_, vec![0])),
+      "mean"      => OpCheck::new(ReduceExt::<_, Batch<f32>>::reduce_mean(&x_, vec![0])),
+      "max"       => OpCheck::new(ReduceExt::<_, Batch<f32>>::reduce_max(&x_, vec![0])),
+      "min"       => OpCheck::new(ReduceExt::<_, Batch<f32>>::reduce_min(&x_, vec![0])),
+      "l2_norm"   => OpCheck::new(ReduceExt::<_, Batch<f32>>::l2_norm(&x_, vec![0])),
+      "logsumexp" => OpCheck::new(ReduceExt::<_, Batch<f32>>::logsumexp(&x_, vec![0])),
+      _ => unreachable!(),
+    };
+    check.input(&x_, BATCH_SZ, x).run(&mut rng);
+  }
+}
+
+#[test]
+#[should_panic(expected = "broadcast_add: axes [0] are not supported")]
+fn broadcast_add_unsupported_axes() {
+  let a_ = array1d_src(2);
+  let x_ = batch_array3d_src((3, 2, 2));
+  a_.broadcast_add(vec![0], erase(&x_));
+}
+
+#[test]
+fn elem_linear_op() {
+  let mut rng = test_rng();
+  let a_ = array1d_src(2);
+  let b_ = array1d_src(2);
+  let x_ = batch_array3d_src((3, 2, 2));
+  let (a, b, x) = (signed(&mut rng, 2), signed(&mut rng, 2), signed(&mut rng, 12 * BATCH_SZ));
+  OpCheck::new(a_.broadcast_add(vec![0, 1], erase(&x_)))
+    .input(&a_, 1, a.clone())
+    .input(&x_, BATCH_SZ, x.clone())
+    .run(&mut rng);
+  OpCheck::new(a_.elem_mult_add(erase(&x_), erase(&b_)))
+    .input(&a_, 1, a)
+    .input(&x_, BATCH_SZ, x)
+    .input(&b_, 1, b)
+    .run(&mut rng);
+}
+
+#[test]
+fn group_norm_op() {
+  let mut rng = test_rng();
+  for &num_groups in [1, 2].iter() {
+    let x_ = batch_array3d_src((3, 1, 4));
+    let scale_: Rc<AVar<AData<Array1d<f32>>>> = array1d_src(4);
+    let shift_: Rc<AVar<AData<Array1d<f32>>>> = array1d_src(4);
+    let x = signed(&mut rng, 12 * BATCH_SZ);
+    let (scale, shift) = (signed(&mut rng, 4), signed(&mut rng, 4));
+    OpCheck::new(x_.group_norm(num_groups, 1.0e-5, scale_.clone(), shift_.clone()))
+      .input(&x_, BATCH_SZ, x)
+      .input(&scale_, 1, scale)
+      .input(&shift_, 1, shift)
+      .run(&mut rng);
+  }
+}
+
+#[test]
+fn index_op() {
+  let mut rng = test_rng();
+  let x_ = batch_array1d_src(5);
+  let i_ = index_src();
+  let x = signed(&mut rng, 5 * BATCH_SZ);
+  let i = i_.data();
+  let node = NodeId::new();
+  let y_: Rc<IndexOp<_, _, Batch<f32>>> = x_.index(i_.clone());
+  OpCheck::new(y_)
+    .input(&x_, BATCH_SZ, x)
+    .constant(move |txn| write_index(&i.val, txn, node, &[4, 0, 2]))
+    .run(&mut rng);
+}
+
+/// Checks that the output of a branch or switch in a fresh txn is its
+/// `active` input, and that backward passes the seed to that input alone.
+fn check_selected(check: &OpCheck, vals: &[Vec<f32>], active: usize, w: &[f32]) {
+  assert_eq!(vals[active], check.value(vals));
+  let grads = check.grads(txn(), vals, w);
+  for (k, grad) in grads.iter().enumerate() {
+    if k == active {
+      assert_eq!(w, grad.as_slice());
+    } else {
+      assert!(grad.iter().all(|&g| g == 0.0), "inactive input {} has grad {:?}", k, grad);
+    }
+  }
+}
+
+#[test]
+fn branch_op() {
+  let mut rng = test_rng();
+  let off_ = batch_array1d_src(3);
+  let on_ = batch_array1d_src(3);
+  let cond = Rc::new(CopyConstant{var: TxnCopyVar::new()});
+  let y_ = BranchOp::new(cond.clone(), off_.clone(), on_.clone(), off_.data().alloc.clone());
+  let vals = vec![signed(&mut rng, 3 * BATCH_SZ), signed(&mut rng, 3 * BATCH_SZ)];
+  // The condition is read from `flag` in every txn, so it can flip between
+  // txns of the same graph.
+  let flag = Rc::new(Cell::new(false));
+  let flag2 = flag.clone();
+  let check = OpCheck::new(y_)
+    .input(&off_, BATCH_SZ, vals[0].clone())
+    .input(&on_, BATCH_SZ, vals[1].clone())
+    .constant(move |txn| cond.var.set(txn, flag2.get()));
+  let w = uniform(&mut rng, 3 * BATCH_SZ, -1.0, 1.0);
+  for &on in [false, true, false].iter() {
+    flag.set(on);
+    check_selected(&check, &vals, on as usize, &w);
+    check.run(&mut rng);
+  }
+}
+
+#[test]
+fn switch_op() {
+  let mut rng = test_rng();
+  let xs_: Vec<_> = (0 .. 3).map(|_| batch_array1d_src(3)).collect();
+  let cond = Rc::new(CopyConstant{var: TxnCopyVar::new()});
+  let y_ = switch(cond.clone(), xs_.iter().map(|x_| erase(x_)).collect());
+  let vals: Vec<Vec<f32>> = (0 .. 3).map(|_| signed(&mut rng, 3 * BATCH_SZ)).collect();
+  let active = Rc::new(Cell::new(0));
+  let active2 = active.clone();
+  let mut check = OpCheck::new(y_);
+  for (x_, vals) in xs_.iter().zip(vals.iter()) {
+    check = check.input(x_, BATCH_SZ, vals.clone());
+  }
+  let check = check.constant(move |txn| cond.var.set(txn, active2.get()));
+  let w = uniform(&mut rng, 3 * BATCH_SZ, -1.0, 1.0);
+  for &idx in [2, 0, 1, 2].iter() {
+    active.set(idx);
+    check_selected(&check, &vals, idx, &w);
+    check.run(&mut rng);
+  }
+}
+
+#[test]
+fn gather_op() {
+  // The first example gathers one entry twice, so its gradient accumulates.
+  let mut rng = test_rng();
+  let x_ = batch_array1d_src(5);
+  let i_: Rc<TestSrc<Batch<Vec<u32>>>> = TestSrc::new(|_, _| {
+    let mut t: Batch<Vec<u32>> = Batch::new();
+    t.set_batch_size(BATCH_SZ, vec![]);
+    t
+  });
+  let x = signed(&mut rng, 5 * BATCH_SZ);
+  let i = i_.data();
+  let node = NodeId::new();
+  let y_: Rc<IndexOp<_, _, BatchArray1d<f32>>> = x_.index(i_.clone());
+  OpCheck::new(y_)
+    .input(&x_, BATCH_SZ, x)
+    .constant(move |txn| {
+      if i.val.overwrite(txn, node) {
+        let mut t = i.val.get_excl(txn, node);
+        t[0] = vec![1, 1];
+        t[1] = vec![4, 0];
+        t[2] = vec![2, 3];
+      }
+    })
+    .run(&mut rng);
+}
+
+#[test]
+#[should_panic(expected = "indices per example")]
+fn gather_op_changed_k() {
+  // `k` is fixed by the first txn, so a second txn with more indices per
+  // example is rejected instead of mismatching the downstream buffers.
+  let mut rng = test_rng();
+  let x_ = batch_array1d_src(5);
+  let i_: Rc<TestSrc<Batch<Vec<u32>>>> = TestSrc::new(|_, _| {
+    let mut t: Batch<Vec<u32>> = Batch::new();
+    t.set_batch_size(BATCH_SZ, vec![]);
+    t
+  });
+  let y_: Rc<IndexOp<_, _, BatchArray1d<f32>>> = x_.index(i_.clone());
+  let check = OpCheck::new(y_.clone())
+    .input(&x_, BATCH_SZ, signed(&mut rng, 5 * BATCH_SZ));
+  let vals: Vec<Vec<f32>> = check.inputs.iter().map(|input| input.vals.clone()).collect();
+  let i = i_.data();
+  let node = NodeId::new();
+  for &k in [2, 3].iter() {
+    let txn = txn();
+    check.load(txn, &vals);
+    if i.val.overwrite(txn, node) {
+      let mut t = i.val.get_excl(txn, node);
+      for idx in 0 .. BATCH_SZ {
+        t[idx] = (0 .. k as u32).collect();
+      }
+    }
+    y_.eval(txn);
+    check.grads(txn, &vals, &vec![1.0; k * BATCH_SZ]);
+  }
+}
+
+#[test]
+fn layer_norm_op() {
+  let mut rng = test_rng();
+  let x_ = batch_array1d_src(6);
+  let scale_: Rc<AVar<AData<Array1d<f32>>>> = array1d_src(6);
+  let shift_: Rc<AVar<AData<Array1d<f32>>>> = array1d_src(6);
+  let x = signed(&mut rng, 6 * BATCH_SZ);
+  let (scale, shift) = (signed(&mut rng, 6), signed(&mut rng, 6));
+  OpCheck::new(x_.layer_norm(1.0e-5, scale_.clone(), shift_.clone()))
+    .input(&x_, BATCH_SZ, x)
+    .input(&scale_, 1, scale)
+    .input(&shift_, 1, shift)
+    .run(&mut rng);
+}
+
+#[test]
+fn lst_sq_loss_op() {
+  // With the Huber clip, residuals beyond 1 in magnitude have a gradient of
+  // constant magnitude; the residuals stay away from the kink at 1.
+  let mut rng = test_rng();
+  for &huber_clip in [false, true].iter() {
+    let x_ = batch_array1d_src(4);
+    let t_ = batch_array1d_src(4);
+    let target = vec![0.0; 4 * BATCH_SZ];
+    let x: Vec<f32> = signed(&mut rng, 4 * BATCH_SZ).iter().enumerate()
+      .map(|(i, &r)| if i % 2 == 0 { 0.8 * r } else { 2.0 * r.signum() + r })
+      .collect();
+    let t = t_.data();
+    let node = NodeId::new();
+    let loss_: Rc<LstSqLoss<BatchArray1d<f32>, Batch<f32>>> = lst_sq_loss(huber_clip, x_.clone(), t_.clone());
+    OpCheck::new(loss_)
+      .input(&x_, BATCH_SZ, x)
+      .constant(move |txn| write_packed(&t.val, txn, node, BATCH_SZ, &target))
+      .run(&mut rng);
+  }
+}
+
+#[test]
+fn hessian_vector_product() {
+  let mut rng = test_rng();
+  let a_: Rc<AVar<AData<Array2d<f32>>>> = array2d_src((3, 4));
+  let x_: Rc<AVar<AData<BatchArray1d<f32>>>> = batch_array1d_src(4);
+  let t_ = index_src();
+  let (_, loss_) = softmax_nll_loss(a_.mult(x_.clone()).tanh(), erase(&t_));
+  let total_ = BatchJoinOp::new(loss_, SumJoinKernel, Rc::new(|_: TxnId, _: NodeId| 0.0_f32));
+  let grad_ = GradientSink::new(erase(&total_));
+  let hvp_ = HessianSink::new(erase(&total_));
+  let (a, x, v) = (signed(&mut rng, 12), signed(&mut rng, 4 * BATCH_SZ), signed(&mut rng, 12));
+  let node = NodeId::new();
+  let load = |txn: TxnId, a: &[f32]| {
+    write_packed(&a_.data().val, txn, node, 1, a);
+    write_packed(&x_.data().val, txn, node, BATCH_SZ, &x);
+    write_index(&t_.data().val, txn, node, &[2, 0, 1]);
+  };
+  let grad = |a: &[f32]| {
+    let txn = txn();
+    load(txn, a);
+    grad_.eval_gradient(txn);
+    read_packed(&*a_.data().grad.get(txn, node))
+  };
+
+  // The direction is loaded into the tangent sources; `x` is held fixed.
+  let txn = txn();
+  load(txn, &a);
+  write_packed(&a_.tangent().data().val, txn, node, 1, &v);
+  write_packed(&x_.tangent().data().val, txn, node, BATCH_SZ, &vec![0.0; 4 * BATCH_SZ]);
+  hvp_.eval_hessian_vector_product(txn);
+  let hv = read_packed(&*a_.data().grad.get(txn, node));
+  let tng_grad = read_packed(&*a_.tangent().data().grad.get(txn, node));
+
+  // `H v` is the central difference of the gradient along `v`.
+  let a_p: Vec<f32> = a.iter().zip(v.iter()).map(|(&a, &v)| a + EPS * v).collect();
+  let a_m: Vec<f32> = a.iter().zip(v.iter()).map(|(&a, &v)| a - EPS * v).collect();
+  let (g_p, g_m, g) = (grad(&a_p), grad(&a_m), grad(&a));
+  for i in 0 .. a.len() {
+    let fd = (g_p[i] as f64 - g_m[i] as f64) / (2.0 * EPS as f64);
+    assert!(rel_err(hv[i] as f64, fd) <= GRAD_TOL,
+        "elem {}: Hessian-vector product {} vs. numerical {}", i, hv[i], fd);
+    assert!(rel_err(tng_grad[i] as f64, g[i] as f64) <= LIN_TOL,
+        "elem {}: tangent source grad {} vs. gradient {}", i, tng_grad[i], g[i]);
+  }
+}
+
+#[test]
+fn grad_accumulator_micro_batches() {
+  let mut rng = test_rng();
+  let a_: Rc<AVar<AData<Array2d<f32>>>> = array2d_src((2, 4));
+  let x_: Rc<AVar<AData<BatchArray1d<f32>>>> = batch_array1d_src(4);
+  let t_ = batch_array1d_src(2);
+  let loss_: Rc<LstSqLoss<BatchArray1d<f32>, Batch<f32>>> = lst_sq_loss(false, a_.mult(x_.clone()).tanh(), t_.clone());
+  let total_ = BatchJoinOp::new(loss_, SumJoinKernel, Rc::new(|_: TxnId, _: NodeId| 0.0_f32));
+  let grad_ = GradientSink::new(erase(&total_));
+  let (a, x, t) = (signed(&mut rng, 8), signed(&mut rng, 4 * BATCH_SZ), signed(&mut rng, 2 * BATCH_SZ));
+  let node = NodeId::new();
+  // Loads examples `lo .. hi` as one batch.
+  let load = |txn: TxnId, lo: usize, hi: usize| {
+    write_packed(&a_.data().val, txn, node, 1, &a);
+    write_packed(&x_.data().val, txn, node, hi - lo, &x[4 * lo .. 4 * hi]);
+    write_packed(&t_.data().val, txn, node, hi - lo, &t[2 * lo .. 2 * hi]);
+  };
+
+  // The grad of examples `lo .. hi` in a fresh txn.
+  let grad = |lo: usize, hi: usize| {
+    let txn = txn();
+    load(txn, lo, hi);
+    grad_.eval_gradient(txn);
+    read_packed(&*a_.data().grad.get(txn, node))
+  };
+  let combined = grad(0, BATCH_SZ);
+
+  // Micro-batches of 2 and 1 examples sum to the combined batch's grad.
+  let mut acc = GradAccumulator::new(total_.clone(), &a_.vars());
+  for &(lo, hi) in [(0, 2), (2, BATCH_SZ)].iter() {
+    let txn = acc.begin();
+    load(txn, lo, hi);
+    grad_.eval_gradient(txn);
+  }
+  assert_eq!(2, acc.num_steps());
+  assert_close(&combined, &read_packed(&*a_.data().grad.get(acc.txn().unwrap(), node)));
+
+  // A new cycle starts from a zero grad.
+  acc.reset();
+  let txn = acc.begin();
+  load(txn, 2, BATCH_SZ);
+  grad_.eval_gradient(txn);
+  assert_eq!(1, acc.num_steps());
+  let restarted = read_packed(&*a_.data().grad.get(txn, node));
+  assert_close(&grad(2, BATCH_SZ), &restarted);
+}
+
+#[test]
+fn softmax_lr_loss_curvature() {
+  let mut rng = test_rng();
+  let (m, n) = (3, 4);
+  let a_: Rc<AVar<AData<Array2d<f32>>>> = array2d_src((m, n));
+  let x_: Rc<AVar<AData<BatchArray1d<f32>>>> = batch_array1d_src(n);
+  let t_: Rc<AVar<AData<Batch<(u32, f32)>>>> = TestSrc::new(|_, _| {
+    let mut t: Batch<(u32, f32)> = Batch::new();
+    t.set_batch_size(BATCH_SZ, (0, 1.0));
+    t
+  });
+  let prob_alloc = Rc::new(move |_: TxnId, _: NodeId| {
+    let buf = <Vec<f32> as BatchArrayStorage<usize>>::alloc(m, BATCH_SZ);
+    BatchArray1d::from_storage(m, BATCH_SZ, buf)
+  });
+  let loss_alloc = Rc::new(|_: TxnId, _: NodeId| {
+    let mut loss: Batch<f32> = Batch::new();
+    loss.set_batch_size(BATCH_SZ, 0.0);
+    loss
+  });
+  let (_, _, loss_) = SoftmaxLoss::new(a_.mult(x_.clone()), Some(t_.clone()), LRLossLink, prob_alloc, loss_alloc);
+  let gn_ = GaussNewtonSink::new(erase(&loss_));
+  let (a, x, v) = (signed(&mut rng, m * n), signed(&mut rng, n * BATCH_SZ), signed(&mut rng, m * n));
+  let target: Vec<(u32, f32)> = (0 .. BATCH_SZ).map(|_| (rng.gen_range(0, m as u32), rng.gen_range(0.2, 0.8))).collect();
+  let node = NodeId::new();
+  let load = |txn: TxnId| {
+    write_packed(&a_.data().val, txn, node, 1, &a);
+    write_packed(&x_.data().val, txn, node, BATCH_SZ, &x);
+    write_packed(&a_.data().r_val, txn, node, 1, &v);
+    write_packed(&x_.data().r_val, txn, node, BATCH_SZ, &vec![0.0; n * BATCH_SZ]);
+    let t = t_.data();
+    if t.val.overwrite(txn, node) {
+      let mut t = t.val.get_excl(txn, node);
+      for (i, &target_i) in target.iter().enumerate() {
+        t[i] = target_i;
+      }
+    }
+  };
+
+  // Build `J^T H_L J v` and `J^T g g^T J v` explicitly: the Jacobian of the
+  // logits `z = a x` maps `v` to `v x`, and its transpose maps `u` to `u xᵀ`.
+  let mut gn_expected = vec![0.0; m * n];
+  let mut ef_expected = vec![0.0; m * n];
+  for (b, x_b) in x.chunks(n).enumerate() {
+    let z = naive_mult(&a, (m, n), Transpose::N, x_b, (n, 1), Transpose::N);
+    let r_z = naive_mult(&v, (m, n), Transpose::N, x_b, (n, 1), Transpose::N);
+    let y = naive_softmax(&z);
+    let (label, t) = (target[b].0 as usize, target[b].1);
+    let lr = y[label] / t;
+    let e = |i: usize| if i == label { 1.0 } else { 0.0 };
+    let g: Vec<f32> = (0 .. m).map(|i| lr * (e(i) - y[i])).collect();
+    let g_r: f32 = (0 .. m).map(|i| g[i] * r_z[i]).sum();
+    for i in 0 .. m {
+      let h_r: f32 = (0 .. m).map(|k| {
+        let diag = if i == k { y[i] } else { 0.0 };
+        lr * ((e(i) - y[i]) * (e(k) - y[k]) - diag + y[i] * y[k]) * r_z[k]
+      }).sum();
+      for j in 0 .. n {
+        gn_expected[i + j * m] += h_r * x_b[j];
+        ef_expected[i + j * m] += g[i] * g_r * x_b[j];
+      }
+    }
+  }
+
+  let txn1 = txn();
+  load(txn1);
+  gn_.eval_gauss_newton_vector_product(txn1);
+  assert_close(&gn_expected, &read_packed(&*a_.data().grad.get(txn1, node)));
+  let txn2 = txn();
+  load(txn2);
+  gn_.eval_empirical_fisher_vector_product(txn2);
+  assert_close(&ef_expected, &read_packed(&*a_.data().grad.get(txn2, node)));
+}
+
+#[test]
+fn hessian_diagonal_quadratic() {
+  // `f = sum_b 0.5 |c * (a x_b) - t_b|^2` is quadratic in each of `a`, `c`
+  // and `x`, and the Hessian of the loss in the outputs is the identity, so
+  // the Becker-LeCun diagonal is exact.
+  let mut rng = test_rng();
+  let (m, n) = (3, 4);
+  let a_: Rc<AVar<AData<Array2d<f32>>>> = array2d_src((m, n));
+  let c_ = array1d_src(m);
+  let x_: Rc<AVar<AData<BatchArray1d<f32>>>> = batch_array1d_src(n);
+  let t_ = batch_array1d_src(m);
+  let z_ = a_.mult(x_.clone());
+  let loss_: Rc<LstSqLoss<BatchArray1d<f32>, Batch<f32>>> = lst_sq_loss(false, c_.elem_mult(erase(&z_)), t_.clone());
+  let total_ = BatchJoinOp::new(loss_, SumJoinKernel, Rc::new(|_: TxnId, _: NodeId| 0.0_f32));
+  let diag_ = HessianDiagSink::new(erase(&total_));
+  let (a, c, x, t) = (signed(&mut rng, m * n), signed(&mut rng, m), signed(&mut rng, n * BATCH_SZ), signed(&mut rng, m * BATCH_SZ));
+  let node = NodeId::new();
+  let txn = txn();
+  write_packed(&a_.data().val, txn, node, 1, &a);
+  write_packed(&c_.data().val, txn, node, 1, &c);
+  write_packed(&x_.data().val, txn, node, BATCH_SZ, &x);
+  write_packed(&t_.data().val, txn, node, BATCH_SZ, &t);
+  diag_.eval_hessian_diagonal(txn);
+
+  let mut a_expected = vec![0.0; m * n];
+  let mut c_expected = vec![0.0; m];
+  let mut x_expected = vec![0.0; n * BATCH_SZ];
+  for (b, x_b) in x.chunks(n).enumerate() {
+    let z = naive_mult(&a, (m, n), Transpose::N, x_b, (n, 1), Transpose::N);
+    for i in 0 .. m {
+      c_expected[i] += z[i] * z[i];
+      for j in 0 .. n {
+        a_expected[i + j * m] += c[i] * c[i] * x_b[j] * x_b[j];
+        x_expected[j + b * n] += c[i] * c[i] * a[i + j * m] * a[i + j * m];
+      }
+    }
+  }
+  assert_close(&a_expected, &read_packed(&*a_.data().grad2.get(txn, node)));
+  assert_close(&c_expected, &read_packed(&*c_.data().grad2.get(txn, node)));
+  assert_close(&x_expected, &read_packed(&*x_.data().grad2.get(txn, node)));
+}
+
+/// The Hessian-vector product of `0.5 x^T h x`, whose Hessian is `h`.
+struct QuadraticHessianSink {
+  node: NodeId,
+  x_:   Rc<TestSrc<Array1d<f32>>>,
+  h:    Vec<f32>,
+}
+
+impl HessianSinkExt for QuadraticHessianSink {
+  fn eval_hessian_vector_product(&self, txn: TxnId) {
+    let dim = self.x_.data().val.get(txn, self.node).dim();
+    let v = read_packed(&*self.x_.tangent().data().val.get(txn, self.node));
+    let hv = naive_mult(&self.h, (dim, dim), Transpose::N, &v, (dim, 1), Transpose::N);
+    write_packed(&self.x_.data().grad, txn, self.node, 1, &hv);
+  }
+}
+
+#[test]
+fn hutchinson_estimator_quadratic() {
+  let mut rng = test_rng();
+  let dim = 4;
+  let x_ = array1d_src(dim);
+  let m = signed(&mut rng, dim * dim);
+  let h = naive_mult(&m, (dim, dim), Transpose::T, &m, (dim, dim), Transpose::N);
+  let sink = QuadraticHessianSink{node: NodeId::new(), x_: x_.clone(), h: h.clone()};
+  let mut est = HutchinsonEstimator::new(dim);
+  est.add_source(x_.clone());
+  let num_samples = 4000;
+  for _ in 0 .. num_samples {
+    let txn = txn();
+    write_packed(&x_.data().val, txn, sink.node, 1, &vec![0.0; dim]);
+    est.eval_sample(txn, &sink, &mut rng);
+  }
+  assert_eq!(num_samples, est.num_samples());
+  let mut diag = vec![0.0; dim];
+  est.diagonal(&mut diag);
+
+  // Each sample of entry `j` has mean `h_jj` and variance
+  // `sum_{k != j} h_jk^2`; allow four standard errors.
+  for j in 0 .. dim {
+    let var: f32 = (0 .. dim).filter(|&k| k != j).map(|k| h[j + k * dim] * h[j + k * dim]).sum();
+    let tol = 4.0 * (var / num_samples as f32).sqrt();
+    assert!((diag[j] - h[j + j * dim]).abs() <= tol,
+        "elem {}: estimate {} vs. exact {}", j, diag[j], h[j + j * dim]);
+  }
+
+  // A diagonal Hessian is recovered exactly by a single sample.
+  let d: Vec<f32> = (0 .. dim * dim).map(|k| if k % (dim + 1) == 0 { h[k] } else { 0.0 }).collect();
+  let sink = QuadraticHessianSink{node: sink.node, x_: x_.clone(), h: d};
+  est.reset();
+  let txn = txn();
+  write_packed(&x_.data().val, txn, sink.node, 1, &vec![0.0; dim]);
+  est.eval_sample(txn, &sink, &mut rng);
+  est.diagonal(&mut diag);
+  for j in 0 .. dim {
+    assert!((diag[j] - h[j + j * dim]).abs() <= 1.0e-5,
+        "elem {}: single-sample estimate {} vs. exact {}", j, diag[j], h[j + j * dim]);
+  }
+}
+
+/// Evaluates the adjoint of `x_` in `txn`, whose sources are already loaded.
+fn eval_adjoint<Op, X>(adjs: &AdjointMap, x_: &Rc<Op>, txn: TxnId) -> Vec<f32> where Op: AVar<AData<X>>, X: 'static + PackedArray {
+  let adj_ = adjs.adjoint(x_).unwrap();
+  adj_.eval(txn);
+  let adj = adj_.data();
+  let node = NodeId::new();
+  let vals = read_packed(&*adj.val.get(txn, node));
+  vals
+}
+
+/// The batch sum of a loss of `tanh(a x + b)`, with the values of its params
+/// and input and a loader for its target.
+struct OneLayer {
+  a_:       Rc<TestSrc<Array2d<f32>>>,
+  b_:       Rc<TestSrc<Array1d<f32>>>,
+  x_:       Rc<TestSrc<BatchArray1d<f32>>>,
+  y_:       Rc<BatchJoinOp<Batch<f32>, f32, SumJoinKernel>>,
+  vals:     Vec<Vec<f32>>,
+  target:   Rc<Fn(TxnId)>,
+}
+
+impl OneLayer {
+  fn new<F, L>(rng: &mut ChaChaRng, head: F) -> OneLayer
+  where F: Fn(&mut ChaChaRng, Rc<MapOp<BatchArray1d<f32>, TanhMapKernel>>) -> (Rc<L>, Rc<Fn(TxnId)>),
+        L: 'static + AVar<AData<Batch<f32>>>,
+  {
+    let a_ = array2d_src((4, 3));
+    let b_ = array1d_src(4);
+    let x_ = batch_array1d_src(3);
+    let h_ = erase(&a_).mult_add(erase(&x_), erase(&b_)).tanh();
+    let (loss_, target) = head(rng, h_);
+    let y_: Rc<BatchJoinOp<Batch<f32>, f32, SumJoinKernel>> = batch_sum(loss_);
+    let vals = vec![signed(rng, 12), signed(rng, 4), signed(rng, 3 * BATCH_SZ)];
+    OneLayer{
+      a_:       a_,
+      b_:       b_,
+      x_:       x_,
+      y_:       y_,
+      vals:     vals,
+      target:   target,
+    }
+  }
+
+  fn check<Op, Y>(&self, out_: Rc<Op>) -> OpCheck where Op: 'static + AVar<AData<Y>>, Y: 'static + PackedArray {
+    let target = self.target.clone();
+    OpCheck::new(out_)
+      .input(&self.a_, 1, self.vals[0].clone())
+      .input(&self.b_, 1, self.vals[1].clone())
+      .input(&self.x_, BATCH_SZ, self.vals[2].clone())
+      .constant(move |txn| (target)(txn))
+  }
+}
+
+fn nll_head(rng: &mut ChaChaRng, h_: Rc<MapOp<BatchArray1d<f32>, TanhMapKernel>>) -> (Rc<PassOp<(), Batch<f32>>>, Rc<Fn(TxnId)>) {
+  let t_ = index_src();
+  let labels: Vec<u32> = (0 .. BATCH_SZ).map(|_| rng.gen_range(0, 4)).collect();
+  let (_, loss_) = softmax_nll_loss(h_, erase(&t_));
+  let t = t_.data();
+  let node = NodeId::new();
+  (loss_, Rc::new(move |txn| write_index(&t.val, txn, node, &labels)))
+}
+
+fn lst_sq_head(rng: &mut ChaChaRng, h_: Rc<MapOp<BatchArray1d<f32>, TanhMapKernel>>) -> (Rc<LstSqLoss<BatchArray1d<f32>, Batch<f32>>>, Rc<Fn(TxnId)>) {
+  let t_ = batch_array1d_src(4);
+  let target = uniform(rng, 4 * BATCH_SZ, -1.0, 1.0);
+  let loss_ = lst_sq_loss(false, h_, t_.clone());
+  let t = t_.data();
+  let node = NodeId::new();
+  (loss_, Rc::new(move |txn| write_packed(&t.val, txn, node, BATCH_SZ, &target)))
+}
+
+#[test]
+fn adjoint_graph_gradient() {
+  let mut rng = test_rng();
+  let models = vec![OneLayer::new(&mut rng, nll_head), OneLayer::new(&mut rng, lst_sq_head)];
+  for model in models.iter() {
+    let adjs = adjoint_graph(model.y_.clone());
+    let check = model.check(model.y_.clone());
+    let grads = check.grads(txn(), &model.vals, &[1.0]);
+    let txn = txn();
+    check.load(txn, &model.vals);
+    assert_close(&grads[0], &eval_adjoint(&adjs, &model.a_, txn));
+    assert_close(&grads[1], &eval_adjoint(&adjs, &model.b_, txn));
+    assert_close(&grads[2], &eval_adjoint(&adjs, &model.x_, txn));
+  }
+}
+
+#[test]
+fn adjoint_graph_grad_of_grad() {
+  // The backward pass of an adjoint graph is a second derivative of the
+  // loss, which `OpCheck` compares against central differences of the
+  // adjoints. `sum` of one var makes the adjoint a concrete op.
+  let mut rng = test_rng();
+  let models = vec![OneLayer::new(&mut rng, nll_head), OneLayer::new(&mut rng, lst_sq_head)];
+  for model in models.iter() {
+    let adjs = adjoint_graph(model.y_.clone());
+    model.check(sum(vec![adjs.adjoint(&model.x_).unwrap()])).run(&mut rng);
+    model.check(sum(vec![adjs.adjoint(&model.a_).unwrap()])).run(&mut rng);
+    model.check(sum(vec![adjs.adjoint(&model.b_).unwrap()])).run(&mut rng);
+  }
+}
+
+#[test]
+fn adjoint_graph_op_adjoints() {
+  // `<c, f'(z) s>` for `z = (u v^T) w` exercises the adjoints of the outer
+  // product, the tangent map and the inner product.
+  let mut rng = test_rng();
+  let u_ = array1d_src(3);
+  let v_ = array1d_src(4);
+  let w_ = array1d_src(4);
+  let s_ = array1d_src(3);
+  let c_ = array1d_src(3);
+  let p_: Rc<AVar<AData<Array2d<f32>>>> = OuterProdOp::new(erase(&u_), erase(&v_), Rc::new(|_: TxnId, _: NodeId| Array2d::zeros((3, 4))));
+  let z_ = p_.mult(erase(&w_));
+  let h_: Rc<MapTangentOp<Array1d<f32>, TanhMapKernel>> = MapTangentOp::new(TanhMapKernel, erase(&z_), erase(&s_), Rc::new(|_: TxnId, _: NodeId| Array1d::zeros(3)));
+  let y_ = c_.mult(erase(&h_));
+  let vals = vec![signed(&mut rng, 3), signed(&mut rng, 4), signed(&mut rng, 4), signed(&mut rng, 3), signed(&mut rng, 3)];
+  let check = OpCheck::new(y_.clone())
+    .input(&u_, 1, vals[0].clone())
+    .input(&v_, 1, vals[1].clone())
+    .input(&w_, 1, vals[2].clone())
+    .input(&s_, 1, vals[3].clone())
+    .input(&c_, 1, vals[4].clone());
+  let grads = check.grads(txn(), &vals, &[1.0]);
+  let adjs = adjoint_graph(y_);
+  let txn = txn();
+  check.load(txn, &vals);
+  assert_close(&grads[0], &eval_adjoint(&adjs, &u_, txn));
+  assert_close(&grads[1], &eval_adjoint(&adjs, &v_, txn));
+  assert_close(&grads[2], &eval_adjoint(&adjs, &w_, txn));
+  assert_close(&grads[3], &eval_adjoint(&adjs, &s_, txn));
+  assert_close(&grads[4], &eval_adjoint(&adjs, &c_, txn));
+}
+
+/// Compares the columns of `jacobian` for the `k`-th input of `check`, whose
+/// vars are `xs`, with central differences of the output.
+fn check_jacobian<Op, Y>(y_: Rc<Op>, check: &OpCheck, k: usize, xs: VarSet) where Op: AVar<AData<Y>>, Y: PackedArray {
+  let vals: Vec<Vec<f32>> = check.inputs.iter().map(|input| input.vals.clone()).collect();
+  let jac = jacobian(y_, &xs, |txn| check.load(txn, &vals));
+  let y_len = check.value(&vals).len();
+  assert_eq!((y_len, vals[k].len()), jac.dim());
+  let jac = jac.as_slice();
+  for j in 0 .. vals[k].len() {
+    let mut vals_p = vals.clone();
+    let mut vals_m = vals.clone();
+    vals_p[k][j] += EPS;
+    vals_m[k][j] -= EPS;
+    let step = vals_p[k][j] as f64 - vals_m[k][j] as f64;
+    let y_p = check.value(&vals_p);
+    let y_m = check.value(&vals_m);
+    for i in 0 .. y_len {
+      let fd = (y_p[i] as f64 - y_m[i] as f64) / step;
+      assert!(rel_err(jac[i + j * y_len] as f64, fd) <= GRAD_TOL,
+          "row {} col {}: jacobian {} vs. numerical {}", i, j, jac[i + j * y_len], fd);
+    }
+  }
+}
+
+#[test]
+fn jacobian_forward_mode() {
+  // As many inputs as outputs and an R-operator everywhere.
+  let mut rng = test_rng();
+  let x_ = array1d_src(4);
+  let y_ = x_.tanh();
+  assert!(y_.supports_r_eval());
+  let check = OpCheck::new(y_.clone())
+    .input(&x_, 1, signed(&mut rng, 4));
+  check_jacobian(y_, &check, 0, x_.vars());
+}
+
+#[test]
+fn jacobian_reverse_mode() {
+  // More inputs than outputs.
+  let mut rng = test_rng();
+  let c_ = array1d_src(4);
+  let x_ = array1d_src(4);
+  let y_ = c_.mult(erase(&x_.tanh()));
+  let check = OpCheck::new(y_.clone())
+    .input(&x_, 1, signed(&mut rng, 4))
+    .input(&c_, 1, signed(&mut rng, 4));
+  check_jacobian(y_, &check, 0, x_.vars());
+}
+
+#[test]
+fn jacobian_without_r_op() {
+  // Fewer inputs than outputs, but the outer product has no R-operator, so
+  // the Jacobian must fall back to reverse mode.
+  let mut rng = test_rng();
+  let u_ = array1d_src(3);
+  let v_ = array1d_src(2);
+  let y_: Rc<OuterProdOp<Array1d<f32>, Array2d<f32>>> = OuterProdOp::new(erase(&u_), erase(&v_), Rc::new(|_: TxnId, _: NodeId| Array2d::zeros((3, 2))));
+  assert!(!y_.supports_r_eval());
+  let check = OpCheck::new(y_.clone())
+    .input(&u_, 1, signed(&mut rng, 3))
+    .input(&v_, 1, signed(&mut rng, 2));
+  check_jacobian(y_, &check, 0, u_.vars());
+}
+
+/// Runs `gradcheck` on `<c, tanh(x)>` with respect to `x`, where the
+/// perturbed losses are evaluated with `c_fd` in place of `c`.
+fn gradcheck_inner_tanh(x: &[f32], c: &[f32], c_fd: Vec<f32>) -> GradCheck {
+  let x_ = array1d_src(x.len());
+  let c_ = array1d_src(c.len());
+  let y_ = c_.mult(erase(&x_.tanh()));
+  let sink = GradientSink::new(erase(&y_));
+  let node = NodeId::new();
+  let txn = txn();
+  write_packed(&x_.data().val, txn, node, 1, x);
+  write_packed(&c_.data().val, txn, node, 1, c);
+  let c = c_.data();
+  gradcheck(&sink, &x_.vars(), 1.0e-3, GRAD_TOL as f32, txn, |txn| write_packed(&c.val, txn, node, 1, &c_fd))
+}
+
+#[test]
+fn gradcheck_passes() {
+  let mut rng = test_rng();
+  let (x, c) = (signed(&mut rng, 4), signed(&mut rng, 4));
+  let check = gradcheck_inner_tanh(&x, &c, c.clone());
+  assert_eq!(1, check.params.len());
+  assert_eq!(4, check.params[0].len);
+  assert!(check.is_ok(), "max relative error {}", check.max_rel_err());
+}
+
+#[test]
+fn gradcheck_fails_on_small_gradients() {
+  // Doubling `c` in the perturbed losses makes the numerical gradient twice
+  // the analytic one. The gradients are far below 1, where an error relative
+  // to `max(1, |a|, |n|)` would still pass.
+  let mut rng = test_rng();
+  let x = signed(&mut rng, 4);
+  let c: Vec<f32> = signed(&mut rng, 4).iter().map(|&c| 1.0e-3 * c).collect();
+  let c_fd: Vec<f32> = c.iter().map(|&c| 2.0 * c).collect();
+  let check = gradcheck_inner_tanh(&x, &c, c_fd);
+  assert!(!check.is_ok());
+  assert!(check.max_rel_err() >= 0.4, "max relative error {}", check.max_rel_err());
+}